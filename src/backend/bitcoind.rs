@@ -0,0 +1,682 @@
+use anyhow::{anyhow, bail};
+use async_trait::async_trait;
+use bitcoin::consensus::encode::serialize_hex;
+use bitcoin::network::Magic;
+use bitcoin::{BlockHash, OutPoint, Transaction, Txid};
+use bitcoincore_rpc::jsonrpc;
+use bitcoincore_rpc::{Auth, Client, RpcApi};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+use super::{PackageTxResult, TestAccept, TxBackend, TxStatus};
+use crate::config::NetworkConfig;
+use crate::protocol;
+
+/// Error code of Bitcoin Core for unknown transactions, among others
+const RPC_INVALID_ADDRESS_OR_KEY: i32 = -5;
+
+/// Error code of Bitcoin Core while it's starting up
+const RPC_IN_WARMUP: i32 = -28;
+
+/// Error code of Bitcoin Core for transactions that are already confirmed
+const RPC_VERIFY_ALREADY_IN_CHAIN: i32 = -27;
+
+/// Error code of Bitcoin Core for wallet calls when no wallet is loaded
+const RPC_WALLET_NOT_FOUND: i32 = -18;
+
+/// Error code of Bitcoin Core for wallet calls when several wallets are loaded
+const RPC_WALLET_NOT_SPECIFIED: i32 = -19;
+
+/// Error code of Bitcoin Core for unknown methods
+const RPC_METHOD_NOT_FOUND: i32 = -32601;
+
+/// Blocks searched for a confirmed tx the node can't look up by its txid, a day of them
+const MAX_SCANNED_BLOCKS: usize = 144;
+
+/// How long a node gets to answer a call before failing over to the next one
+const RPC_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Attempts of a submission while no node can take it, and the wait before the second attempt,
+/// doubled after each
+const SUBMIT_ATTEMPTS: u32 = 4;
+const SUBMIT_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Bitcoin Core over RPC. With several nodes, calls go to the first healthy node and fail over
+/// to the next one when a node is unreachable.
+#[derive(Debug, Clone)]
+pub struct Bitcoind {
+    name: String,
+    nodes: Arc<Vec<Node>>,
+    /// Whether the nodes have `submitpackage`, found out with the first package
+    submitpackage: Arc<OnceLock<bool>>,
+}
+
+#[derive(Debug)]
+struct Node {
+    url: String,
+    rpc: Arc<Client>,
+    healthy: AtomicBool,
+}
+
+impl Bitcoind {
+    /// Connect to the RPC server at `url`
+    pub fn new(url: &str, auth: Auth) -> anyhow::Result<Self> {
+        Self::with_failover(&[url.to_string()], auth)
+    }
+
+    /// Connect to the RPC servers at `urls`, in order of preference
+    pub fn with_failover(urls: &[String], auth: Auth) -> anyhow::Result<Self> {
+        if urls.is_empty() {
+            bail!("No Bitcoin Core RPC url given");
+        }
+
+        let nodes = urls
+            .iter()
+            .map(|url| {
+                Ok(Node {
+                    url: url.clone(),
+                    rpc: Arc::new(Client::new(url, auth.clone())?),
+                    healthy: AtomicBool::new(true),
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(Self {
+            name: urls.join(","),
+            nodes: Arc::new(nodes),
+            submitpackage: Arc::new(OnceLock::new()),
+        })
+    }
+
+    /// Connect to the Bitcoin Core nodes of `config`, checked to be on the network of `magic`.
+    /// Without credentials, the default cookie file of the network is used
+    pub async fn from_config(magic: Magic, config: &NetworkConfig) -> anyhow::Result<Self> {
+        let auth = match (
+            &config.bitcoin_user,
+            &config.bitcoin_password,
+            &config.bitcoin_cookie,
+        ) {
+            (Some(user), Some(password), _) => Auth::UserPass(user.clone(), password.clone()),
+            (_, _, Some(cookie)) => Auth::CookieFile(cookie.clone()),
+            _ => match default_cookie_file(magic) {
+                Some(cookie) => Auth::CookieFile(cookie),
+                None => Auth::None,
+            },
+        };
+        let bitcoind = Self::with_failover(&config.bitcoin_hosts, auth)?;
+        // don't throw the transactions of one network at a node of another
+        bitcoind.check_chain(magic).await?;
+        Ok(bitcoind)
+    }
+
+    /// Run `f` with the rpc client, which is blocking, off the async runtime. Calls that fail
+    /// to reach a node are retried on the next one.
+    pub async fn rpc<T, F>(&self, f: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: Fn(&Client) -> bitcoincore_rpc::Result<T> + Send + Sync + 'static,
+    {
+        let f = Arc::new(f);
+
+        // healthy nodes first, the others as a last resort
+        let mut nodes: Vec<&Node> = self.nodes.iter().collect();
+        nodes.sort_by_key(|node| !node.healthy.load(Ordering::Relaxed));
+
+        let mut last_error = None;
+        for node in nodes {
+            let rpc = node.rpc.clone();
+            let f = f.clone();
+            let call = tokio::task::spawn_blocking(move || f(&rpc));
+
+            let error = match tokio::time::timeout(RPC_TIMEOUT, call).await {
+                Ok(Ok(Ok(value))) => {
+                    node.set_healthy(true);
+                    return Ok(value);
+                }
+                // the node answered, another one won't answer differently
+                Ok(Ok(Err(e))) if !is_transient(&e) => return Err(e.into()),
+                Ok(Ok(Err(e))) => anyhow!(e),
+                Ok(Err(e)) => anyhow!(e),
+                Err(_) => anyhow!("timed out"),
+            };
+
+            if self.nodes.len() > 1 {
+                warn!(url = %node.url, "Bitcoin Core failed, failing over: {error}");
+            }
+            node.set_healthy(false);
+            last_error = Some(error);
+        }
+
+        Err(last_error.unwrap_or_else(|| anyhow!("No Bitcoin Core node available")))
+    }
+
+    /// [`Self::rpc`] for submissions, which are retried with exponential backoff while no node
+    /// can take them. Rejections aren't retried
+    async fn submit_rpc<T, F>(&self, f: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: Fn(&Client) -> bitcoincore_rpc::Result<T> + Send + Sync + 'static,
+    {
+        let f = Arc::new(f);
+        let mut delay = SUBMIT_RETRY_DELAY;
+        let mut attempt = 1;
+        loop {
+            let call = f.clone();
+            match self.rpc(move |rpc| call(rpc)).await {
+                Err(e) if attempt < SUBMIT_ATTEMPTS && !is_rejection(&e) => {
+                    warn!(backend = %self.name, attempt, retry_secs = delay.as_secs_f64(), "Error submitting, retrying: {e}");
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Look up the raw transaction `txid` in the mempool, the wallet or, with -txindex, the chain
+    pub async fn get_tx(&self, txid: Txid) -> anyhow::Result<Transaction> {
+        let tx = self
+            .rpc(move |rpc| {
+                match rpc.get_raw_transaction(&txid, None) {
+                    Ok(tx) => return Ok(Some(tx)),
+                    Err(e) if !is_rpc_error(&e, RPC_INVALID_ADDRESS_OR_KEY) => return Err(e),
+                    Err(_) => {}
+                }
+                // without -txindex, the wallet still knows its own confirmed transactions
+                match rpc.get_transaction(&txid, None) {
+                    Ok(info) => Ok(Some(info.transaction()?)),
+                    Err(e) if is_rpc_error(&e, RPC_INVALID_ADDRESS_OR_KEY) => Ok(None),
+                    // no wallet loaded, or built without one
+                    Err(e)
+                        if is_rpc_error(&e, RPC_WALLET_NOT_FOUND)
+                            || is_rpc_error(&e, RPC_METHOD_NOT_FOUND) =>
+                    {
+                        Ok(None)
+                    }
+                    Err(e) => Err(e),
+                }
+            })
+            .await?;
+        tx.ok_or_else(|| {
+            anyhow!("Tx {txid} not found in the mempool or the wallet, confirmed txs need -txindex")
+        })
+    }
+
+    /// Make sure every node is on the network of `magic`, failing on the first that isn't. Nodes
+    /// that can't be reached are skipped with a warning
+    pub async fn check_chain(&self, magic: Magic) -> anyhow::Result<()> {
+        for node in self.nodes.iter() {
+            let rpc = node.rpc.clone();
+            let call = tokio::task::spawn_blocking(move || {
+                rpc.call::<serde_json::Value>("getblockchaininfo", &[])
+            });
+            let info = match tokio::time::timeout(RPC_TIMEOUT, call).await {
+                Ok(Ok(Ok(info))) => info,
+                Ok(Ok(Err(e))) => {
+                    warn!(url = %node.url, "Couldn't check the chain of Bitcoin Core: {e}");
+                    continue;
+                }
+                Ok(Err(e)) => return Err(e.into()),
+                Err(_) => {
+                    warn!(url = %node.url, "Couldn't check the chain of Bitcoin Core: timed out");
+                    continue;
+                }
+            };
+
+            let chain = info["chain"].as_str().unwrap_or_default();
+            let expected = match magic {
+                Magic::BITCOIN => "main",
+                Magic::TESTNET => "test",
+                Magic::REGTEST => "regtest",
+                _ => "signet",
+            };
+            if chain != expected {
+                bail!(
+                    "Bitcoin Core at {} is on chain {chain}, not on network {magic}",
+                    node.url
+                );
+            }
+            // only newer nodes tell which signet
+            if let Some(challenge) = info["signet_challenge"].as_str() {
+                let signet = protocol::signet_magic(challenge)?;
+                if signet != magic {
+                    bail!(
+                        "Bitcoin Core at {} is on signet {signet}, not on {magic}",
+                        node.url
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Probe the unhealthy nodes every `interval` and put the ones that respond again back in
+    /// the rotation
+    pub fn spawn_health_checks(&self, interval: Duration) {
+        let nodes = self.nodes.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                for node in nodes.iter() {
+                    if node.healthy.load(Ordering::Relaxed) {
+                        continue;
+                    }
+                    let rpc = node.rpc.clone();
+                    let probe = tokio::task::spawn_blocking(move || rpc.get_block_count());
+                    if let Ok(Ok(Ok(_))) = tokio::time::timeout(RPC_TIMEOUT, probe).await {
+                        info!(url = %node.url, "Bitcoin Core is back");
+                        node.set_healthy(true);
+                    }
+                }
+            }
+        });
+    }
+
+    /// Submit package members one by one, in order
+    async fn submit_members(&self, txs: &[Transaction]) -> Vec<PackageTxResult> {
+        let mut results = vec![];
+        for tx in txs {
+            results.push(PackageTxResult {
+                txid: tx.txid(),
+                error: self.submit_member(tx).await,
+            });
+        }
+        results
+    }
+
+    /// Submit a package member on its own, giving why it's rejected. Members already confirmed,
+    /// such as a parent mined in the meantime, count as accepted
+    async fn submit_member(&self, tx: &Transaction) -> Option<String> {
+        match self.submit_tx(tx).await {
+            Ok(_) => None,
+            Err(e) if is_rpc_error_code(&e, RPC_VERIFY_ALREADY_IN_CHAIN) => None,
+            Err(e) => Some(e.to_string()),
+        }
+    }
+}
+
+impl Node {
+    fn set_healthy(&self, healthy: bool) {
+        self.healthy.store(healthy, Ordering::Relaxed);
+    }
+}
+
+/// Where the wallet of the node has `txid`, [`TxStatus::Missing`] when it has no wallet or doesn't
+/// know the tx, or only as conflicted
+fn wallet_status(rpc: &Client, txid: &Txid) -> bitcoincore_rpc::Result<TxStatus> {
+    let info = match rpc.get_transaction(txid, None) {
+        Ok(result) => result.info,
+        Err(e)
+            if is_rpc_error(&e, RPC_INVALID_ADDRESS_OR_KEY)
+                || is_rpc_error(&e, RPC_WALLET_NOT_FOUND)
+                || is_rpc_error(&e, RPC_WALLET_NOT_SPECIFIED)
+                || is_rpc_error(&e, RPC_METHOD_NOT_FOUND) =>
+        {
+            return Ok(TxStatus::Missing)
+        }
+        Err(e) => return Err(e),
+    };
+    match info.blockhash {
+        Some(block_hash) if info.confirmations > 0 => {
+            let height = match info.blockheight {
+                Some(height) => height,
+                None => rpc.get_block_header_info(&block_hash)?.height as u32,
+            };
+            Ok(TxStatus::Confirmed {
+                block_hash,
+                height,
+                confirmations: info.confirmations as u32,
+            })
+        }
+        // in the mempool of the node, or conflicted
+        _ => Ok(TxStatus::Missing),
+    }
+}
+
+/// Where `tx`, which the node doesn't know by its txid, is in the chain. A tx with a prevout still
+/// unspent isn't confirmed, otherwise it's looked up in the last [`MAX_SCANNED_BLOCKS`] blocks.
+/// [`TxStatus::Missing`] when it isn't there, as when a conflicting tx spent the prevouts
+fn find_in_chain(rpc: &Client, tx: &Transaction) -> bitcoincore_rpc::Result<TxStatus> {
+    for input in &tx.input {
+        let prevout = input.previous_output;
+        if rpc
+            .get_tx_out(&prevout.txid, prevout.vout, Some(true))?
+            .is_some()
+        {
+            return Ok(TxStatus::Missing);
+        }
+    }
+
+    let txid = tx.txid();
+    let tip = rpc.get_block_count()?;
+    for height in (0..=tip).rev().take(MAX_SCANNED_BLOCKS) {
+        let block_hash = rpc.get_block_hash(height)?;
+        let block = rpc.get_block_info(&block_hash)?;
+        if block.tx.contains(&txid) {
+            return Ok(TxStatus::Confirmed {
+                block_hash,
+                height: height as u32,
+                confirmations: (tip - height + 1) as u32,
+            });
+        }
+    }
+    Ok(TxStatus::Missing)
+}
+
+/// Whether `e` is the node answering with error `code`, for errors of [`Bitcoind::rpc`]
+fn is_rpc_error_code(e: &anyhow::Error, code: i32) -> bool {
+    matches!(e.downcast_ref::<bitcoincore_rpc::Error>(), Some(e) if is_rpc_error(e, code))
+}
+
+/// Whether `e` is the node answering with error `code`
+fn is_rpc_error(e: &bitcoincore_rpc::Error, code: i32) -> bool {
+    matches!(e, bitcoincore_rpc::Error::JsonRpc(jsonrpc::Error::Rpc(e)) if e.code == code)
+}
+
+/// Whether `e` tells the node has no `submitpackage`, which was regtest only before Bitcoin Core
+/// 26.0
+fn lacks_submitpackage(e: &anyhow::Error) -> bool {
+    match e.downcast_ref::<bitcoincore_rpc::Error>() {
+        Some(bitcoincore_rpc::Error::JsonRpc(jsonrpc::Error::Rpc(e))) => {
+            e.code == RPC_METHOD_NOT_FOUND || e.message.contains("regression testing")
+        }
+        _ => false,
+    }
+}
+
+/// Whether `e` of [`Bitcoind::rpc`] is the node refusing, rather than failing to answer. Timeouts
+/// aren't errors of the rpc client
+fn is_rejection(e: &anyhow::Error) -> bool {
+    matches!(e.downcast_ref::<bitcoincore_rpc::Error>(), Some(e) if !is_transient(e))
+}
+
+/// Whether `e` may go away by trying again: the node couldn't be reached, is too busy (its work
+/// queue is full, answered over HTTP) or is still starting up
+fn is_transient(e: &bitcoincore_rpc::Error) -> bool {
+    is_unreachable(e) || is_rpc_error(e, RPC_IN_WARMUP)
+}
+
+/// The error of each tx in the `tx-results` of a `submitpackage` result, `None` for the accepted
+/// ones
+fn package_errors(result: &serde_json::Value) -> HashMap<Txid, Option<String>> {
+    let Some(results) = result["tx-results"].as_object() else {
+        return HashMap::new();
+    };
+    results
+        .values()
+        .filter_map(|result| {
+            let txid = result["txid"].as_str()?.parse().ok()?;
+            let error = result["error"].as_str().map(String::from);
+            Some((txid, error))
+        })
+        .collect()
+}
+
+/// Whether `e` is the node refusing a tx it already has in its mempool, which nodes before
+/// Bitcoin Core 0.21 do
+fn is_already_in_mempool(e: &bitcoincore_rpc::Error) -> bool {
+    matches!(
+        e,
+        bitcoincore_rpc::Error::JsonRpc(jsonrpc::Error::Rpc(e))
+            if ["txn-already-in-mempool", "txn-already-known", "already known"]
+                .iter()
+                .any(|reason| e.message.contains(reason))
+    )
+}
+
+/// Whether `e` means the node couldn't be reached, rather than it answering with an error
+fn is_unreachable(e: &bitcoincore_rpc::Error) -> bool {
+    !matches!(
+        e,
+        bitcoincore_rpc::Error::JsonRpc(jsonrpc::Error::Rpc(_))
+            | bitcoincore_rpc::Error::ReturnedError(_)
+    )
+}
+
+#[async_trait]
+impl TxBackend for Bitcoind {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn submit_tx(&self, tx: &Transaction) -> anyhow::Result<Txid> {
+        let tx = tx.clone();
+        let attempts = AtomicU32::new(0);
+        self.submit_rpc(move |rpc| {
+            let retry = attempts.fetch_add(1, Ordering::Relaxed) > 0;
+            match rpc.send_raw_transaction(&tx) {
+                // an attempt that timed out or lost its answer got the tx in after all
+                Err(e) if retry && is_already_in_mempool(&e) => Ok(tx.txid()),
+                result => result,
+            }
+        })
+        .await
+    }
+
+    async fn submit_package(&self, txs: &[Transaction]) -> anyhow::Result<Vec<PackageTxResult>> {
+        if self.submitpackage.get() == Some(&false) {
+            return Ok(self.submit_members(txs).await);
+        }
+
+        let package: Vec<String> = txs.iter().map(serialize_hex).collect();
+        let result = self
+            .submit_rpc(move |rpc| {
+                rpc.call::<serde_json::Value>("submitpackage", &[package.clone().into()])
+            })
+            .await;
+        let result = match result {
+            Ok(result) => result,
+            Err(e) if lacks_submitpackage(&e) => {
+                info!(backend = %self.name, "No submitpackage, submitting package txs one by one: {e}");
+                let _ = self.submitpackage.set(false);
+                return Ok(self.submit_members(txs).await);
+            }
+            // before 28.0 a single failing tx fails the whole package, the others may be fine
+            Err(e) if is_rejection(&e) => {
+                info!(backend = %self.name, "Package rejected, submitting its txs one by one: {e}");
+                return Ok(self.submit_members(txs).await);
+            }
+            Err(e) => return Err(e),
+        };
+        self.submitpackage.get_or_init(|| true);
+        debug!(%result, "Submitted package");
+
+        // the txs that weren't validated because another one failed get another chance on
+        // their own
+        let errors = package_errors(&result);
+        let mut results = vec![];
+        for tx in txs {
+            let txid = tx.txid();
+            let error = match errors.get(&txid) {
+                Some(Some(error)) if error != "package-not-validated" => Some(error.clone()),
+                Some(None) => None,
+                _ => self.submit_member(tx).await,
+            };
+            results.push(PackageTxResult { txid, error });
+        }
+        Ok(results)
+    }
+
+    async fn test_accept(&self, txs: &[Transaction]) -> anyhow::Result<Option<Vec<TestAccept>>> {
+        let txs = txs.to_vec();
+        let results = self
+            .rpc(move |rpc| rpc.test_mempool_accept(&txs.iter().collect::<Vec<_>>()))
+            .await?;
+        Ok(Some(
+            results
+                .into_iter()
+                .map(|result| TestAccept {
+                    txid: result.txid,
+                    allowed: result.allowed,
+                    reject_reason: result.reject_reason,
+                    feerate: match (result.fees, result.vsize) {
+                        (Some(fees), Some(vsize)) if vsize > 0 => {
+                            Some(fees.base.to_sat() as f64 / vsize as f64)
+                        }
+                        _ => None,
+                    },
+                })
+                .collect(),
+        ))
+    }
+
+    async fn tx_status(&self, txid: &Txid) -> anyhow::Result<Option<TxStatus>> {
+        let txid = *txid;
+        let status = self
+            .rpc(move |rpc| {
+                match rpc.get_mempool_entry(&txid) {
+                    Ok(_) => return Ok(TxStatus::InMempool),
+                    Err(e) if !is_rpc_error(&e, RPC_INVALID_ADDRESS_OR_KEY) => return Err(e),
+                    Err(_) => {}
+                }
+
+                // confirmed transactions are only found with -txindex, or in the wallet
+                let info = match rpc.get_raw_transaction_info(&txid, None) {
+                    Ok(info) => info,
+                    Err(e) if is_rpc_error(&e, RPC_INVALID_ADDRESS_OR_KEY) => {
+                        return wallet_status(rpc, &txid)
+                    }
+                    Err(e) => return Err(e),
+                };
+                let Some(block_hash) = info.blockhash else {
+                    return Ok(TxStatus::InMempool);
+                };
+                let header = rpc.get_block_header_info(&block_hash)?;
+                Ok(TxStatus::Confirmed {
+                    block_hash,
+                    height: header.height as u32,
+                    confirmations: info.confirmations.unwrap_or(0),
+                })
+            })
+            .await?;
+        Ok(Some(status))
+    }
+
+    async fn locate_tx(&self, tx: &Transaction) -> anyhow::Result<Option<TxStatus>> {
+        let status = self.tx_status(&tx.txid()).await?;
+        if status != Some(TxStatus::Missing) {
+            return Ok(status);
+        }
+        // without -txindex, a confirmed tx has spent its prevouts in the chain
+        let tx = tx.clone();
+        let status = self.rpc(move |rpc| find_in_chain(rpc, &tx)).await?;
+        Ok(Some(status))
+    }
+
+    async fn get_tx(&self, txid: &Txid) -> anyhow::Result<Option<Transaction>> {
+        let txid = *txid;
+        self.rpc(move |rpc| match rpc.get_raw_transaction(&txid, None) {
+            Ok(tx) => Ok(Some(tx)),
+            // confirmed transactions are only found with -txindex
+            Err(e) if is_rpc_error(&e, RPC_INVALID_ADDRESS_OR_KEY) => Ok(None),
+            Err(e) => Err(e),
+        })
+        .await
+    }
+
+    async fn prevout_value(&self, outpoint: &OutPoint) -> anyhow::Result<Option<u64>> {
+        let outpoint = *outpoint;
+        let output = self
+            .rpc(move |rpc| rpc.get_tx_out(&outpoint.txid, outpoint.vout, Some(true)))
+            .await?;
+        Ok(output.map(|output| output.value.to_sat()))
+    }
+
+    async fn best_block_hash(&self) -> anyhow::Result<Option<BlockHash>> {
+        Ok(Some(self.rpc(|rpc| rpc.get_best_block_hash()).await?))
+    }
+
+    async fn spending_tx(&self, outpoint: &OutPoint) -> anyhow::Result<Option<Txid>> {
+        // only the mempool, since Bitcoin Core 24.0
+        let outpoints = serde_json::json!([{"txid": outpoint.txid, "vout": outpoint.vout}]);
+        let spends: Vec<serde_json::Value> = self
+            .rpc(move |rpc| rpc.call("gettxspendingprevout", std::slice::from_ref(&outpoints)))
+            .await?;
+        Ok(spends
+            .first()
+            .and_then(|spend| spend["spendingtxid"].as_str())
+            .map(str::parse)
+            .transpose()?)
+    }
+
+    async fn is_synced(&self) -> anyhow::Result<Option<bool>> {
+        let info: serde_json::Value = self.rpc(|rpc| rpc.call("getblockchaininfo", &[])).await?;
+        Ok(info["initialblockdownload"].as_bool().map(|ibd| !ibd))
+    }
+
+    async fn health_check(&self) -> anyhow::Result<()> {
+        self.rpc(|rpc| rpc.get_block_count()).await?;
+        Ok(())
+    }
+
+    async fn mempool_min_feerate(&self) -> anyhow::Result<Option<f64>> {
+        let info: serde_json::Value = self.rpc(|rpc| rpc.call("getmempoolinfo", &[])).await?;
+        // BTC/kvB
+        let min_fee = info["mempoolminfee"]
+            .as_f64()
+            .ok_or_else(|| anyhow!("getmempoolinfo without mempoolminfee"))?;
+        Ok(Some(min_fee * 100_000.0))
+    }
+}
+
+/// The cookie file Bitcoin Core writes in its default data directory for the network identified
+/// by `magic`, if it exists
+pub fn default_cookie_file(magic: Magic) -> Option<PathBuf> {
+    let datadir = if cfg!(target_os = "windows") {
+        PathBuf::from(std::env::var_os("APPDATA")?).join("Bitcoin")
+    } else if cfg!(target_os = "macos") {
+        PathBuf::from(std::env::var_os("HOME")?).join("Library/Application Support/Bitcoin")
+    } else {
+        PathBuf::from(std::env::var_os("HOME")?).join(".bitcoin")
+    };
+
+    let network_dir = match magic {
+        Magic::BITCOIN => "",
+        Magic::TESTNET => "testnet3",
+        Magic::REGTEST => "regtest",
+        // custom signets share the signet directory
+        _ => "signet",
+    };
+
+    let cookie = datadir.join(network_dir).join(".cookie");
+    cookie.is_file().then_some(cookie)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rpc_error(code: i32, message: &str) -> bitcoincore_rpc::Error {
+        bitcoincore_rpc::Error::JsonRpc(jsonrpc::Error::Rpc(jsonrpc::error::RpcError {
+            code,
+            message: message.to_string(),
+            data: None,
+        }))
+    }
+
+    #[test]
+    fn already_in_mempool() {
+        assert!(is_already_in_mempool(&rpc_error(
+            -26,
+            "txn-already-in-mempool"
+        )));
+        assert!(is_already_in_mempool(&rpc_error(-26, "txn-already-known")));
+        assert!(is_already_in_mempool(&rpc_error(
+            -27,
+            "Transaction already known"
+        )));
+        assert!(!is_already_in_mempool(&rpc_error(
+            -26,
+            "bad-txns-inputs-missingorspent"
+        )));
+        assert!(!is_already_in_mempool(&rpc_error(
+            RPC_VERIFY_ALREADY_IN_CHAIN,
+            "Transaction already in block chain"
+        )));
+    }
+}
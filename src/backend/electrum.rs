@@ -0,0 +1,65 @@
+use async_trait::async_trait;
+use bitcoin::{Transaction, Txid};
+use electrum_client::{Client, ConfigBuilder, ElectrumApi};
+use std::sync::Arc;
+
+use super::TxBackend;
+
+/// An Electrum server, over plain TCP (`tcp://host:port`) or TLS (`ssl://host:port`)
+#[derive(Clone)]
+pub struct Electrum {
+    url: String,
+    client: Arc<Client>,
+}
+
+impl Electrum {
+    /// Connect to the server at `url`. `validate_domain` can be turned off for servers with a
+    /// self-signed certificate, as is common for Electrum servers.
+    pub fn new(url: &str, validate_domain: bool) -> anyhow::Result<Self> {
+        let config = ConfigBuilder::new()
+            .validate_domain(validate_domain)
+            .timeout(Some(30))
+            .build();
+
+        Ok(Self {
+            url: url.to_string(),
+            client: Arc::new(Client::from_config(url, config)?),
+        })
+    }
+}
+
+impl std::fmt::Debug for Electrum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Electrum").field("url", &self.url).finish()
+    }
+}
+
+#[async_trait]
+impl TxBackend for Electrum {
+    fn name(&self) -> &str {
+        &self.url
+    }
+
+    async fn submit_tx(&self, tx: &Transaction) -> anyhow::Result<Txid> {
+        // the client is blocking
+        let client = self.client.clone();
+        let tx = tx.clone();
+        Ok(tokio::task::spawn_blocking(move || client.transaction_broadcast(&tx)).await??)
+    }
+
+    async fn get_tx(&self, txid: &Txid) -> anyhow::Result<Option<Transaction>> {
+        let client = self.client.clone();
+        let txid = *txid;
+        // servers answer unknown transactions with an error, telling them apart isn't possible
+        Ok(
+            tokio::task::spawn_blocking(move || client.transaction_get(&txid))
+                .await?
+                .ok(),
+        )
+    }
+
+    async fn health_check(&self) -> anyhow::Result<()> {
+        let client = self.client.clone();
+        Ok(tokio::task::spawn_blocking(move || client.ping()).await??)
+    }
+}
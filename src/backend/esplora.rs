@@ -0,0 +1,193 @@
+use anyhow::{anyhow, bail};
+use async_trait::async_trait;
+use bitcoin::consensus::serialize;
+use bitcoin::network::Magic;
+use bitcoin::{BlockHash, OutPoint, Transaction, Txid};
+use hex_string::HexString;
+use reqwest::StatusCode;
+use serde::Deserialize;
+
+use super::{TxBackend, TxStatus};
+use crate::protocol::{self, mutinynet_magic};
+
+/// An Esplora (Blockstream/mempool.space style) HTTP API
+#[derive(Debug, Clone)]
+pub struct Esplora {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl Esplora {
+    /// The API at `url`, e.g. `https://blockstream.info/api`
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// The public mempool.space API for every network it serves
+    pub fn mempool_space() -> Vec<(Magic, Self)> {
+        [
+            (Magic::BITCOIN, "https://mempool.space/api"),
+            (Magic::TESTNET, "https://mempool.space/testnet/api"),
+            (Magic::SIGNET, "https://mempool.space/signet/api"),
+            (mutinynet_magic(), "https://mutinynet.com/api"),
+        ]
+        .into_iter()
+        .map(|(magic, url)| (magic, Self::new(url)))
+        .collect()
+    }
+}
+
+#[async_trait]
+impl TxBackend for Esplora {
+    fn name(&self) -> &str {
+        &self.url
+    }
+
+    async fn submit_tx(&self, tx: &Transaction) -> anyhow::Result<Txid> {
+        let bytes = serialize(tx);
+        let body = HexString::from_bytes(&bytes).as_string();
+
+        let response = self
+            .client
+            .post(format!("{}/tx", self.url))
+            .body(body)
+            .send()
+            .await?;
+
+        // the body holds the txid, or the reason the transaction was rejected
+        let status = response.status();
+        let text = response.text().await?;
+        if !status.is_success() {
+            bail!("{status}: {}", text.trim());
+        }
+        text.trim()
+            .parse()
+            .map_err(|_| anyhow!("Unexpected response: {text}"))
+    }
+
+    async fn tx_status(&self, txid: &Txid) -> anyhow::Result<Option<TxStatus>> {
+        #[derive(Deserialize)]
+        struct Status {
+            confirmed: bool,
+            block_height: Option<u32>,
+            block_hash: Option<BlockHash>,
+        }
+
+        let response = self
+            .client
+            .get(format!("{}/tx/{txid}/status", self.url))
+            .send()
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(Some(TxStatus::Missing));
+        }
+        let status: Status = serde_json::from_str(&response.error_for_status()?.text().await?)?;
+
+        let (true, Some(height), Some(block_hash)) =
+            (status.confirmed, status.block_height, status.block_hash)
+        else {
+            return Ok(Some(TxStatus::InMempool));
+        };
+
+        let tip: u32 = self
+            .client
+            .get(format!("{}/blocks/tip/height", self.url))
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?
+            .trim()
+            .parse()?;
+
+        Ok(Some(TxStatus::Confirmed {
+            block_hash,
+            height,
+            confirmations: tip.saturating_sub(height) + 1,
+        }))
+    }
+
+    async fn best_block_hash(&self) -> anyhow::Result<Option<BlockHash>> {
+        let hash = self
+            .client
+            .get(format!("{}/blocks/tip/hash", self.url))
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        Ok(Some(hash.trim().parse()?))
+    }
+
+    async fn health_check(&self) -> anyhow::Result<()> {
+        self.client
+            .get(format!("{}/blocks/tip/height", self.url))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn get_tx(&self, txid: &Txid) -> anyhow::Result<Option<Transaction>> {
+        let response = self
+            .client
+            .get(format!("{}/tx/{txid}/hex", self.url))
+            .send()
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let hex = response.error_for_status()?.text().await?;
+        Ok(Some(protocol::parse_tx_hex(hex.trim())?))
+    }
+
+    async fn prevout_value(&self, outpoint: &OutPoint) -> anyhow::Result<Option<u64>> {
+        #[derive(Deserialize)]
+        struct Tx {
+            vout: Vec<Output>,
+        }
+        #[derive(Deserialize)]
+        struct Output {
+            value: u64,
+        }
+
+        let response = self
+            .client
+            .get(format!("{}/tx/{}", self.url, outpoint.txid))
+            .send()
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let tx: Tx = serde_json::from_str(&response.error_for_status()?.text().await?)?;
+        Ok(tx
+            .vout
+            .get(outpoint.vout as usize)
+            .map(|output| output.value))
+    }
+
+    async fn spending_tx(&self, outpoint: &OutPoint) -> anyhow::Result<Option<Txid>> {
+        #[derive(Deserialize)]
+        struct Outspend {
+            spent: bool,
+            txid: Option<Txid>,
+        }
+
+        let response = self
+            .client
+            .get(format!(
+                "{}/tx/{}/outspend/{}",
+                self.url, outpoint.txid, outpoint.vout
+            ))
+            .send()
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let outspend: Outspend = serde_json::from_str(&response.error_for_status()?.text().await?)?;
+        Ok(outspend.txid.filter(|_| outspend.spent))
+    }
+}
@@ -0,0 +1,198 @@
+use anyhow::bail;
+use async_trait::async_trait;
+use bitcoin::{BlockHash, OutPoint, Transaction, Txid};
+use futures_util::future::join_all;
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+use super::{PackageTxResult, TestAccept, TxBackend, TxStatus};
+
+/// Submits to several backends at once, so a single flaky backend doesn't block propagation.
+/// A submission succeeds when any of the backends accepted it.
+#[derive(Debug, Clone)]
+pub struct FanOut {
+    backends: Vec<Arc<dyn TxBackend>>,
+}
+
+impl FanOut {
+    /// Fan out to `backends`
+    pub fn new(backends: Vec<Arc<dyn TxBackend>>) -> Self {
+        Self { backends }
+    }
+
+    /// Report the outcome of every backend and keep the successes
+    fn report<T>(&self, results: Vec<anyhow::Result<T>>) -> anyhow::Result<Vec<T>> {
+        let mut successes = vec![];
+        for (backend, result) in self.backends.iter().zip(results) {
+            match result {
+                Ok(value) => {
+                    debug!(backend = backend.name(), "Backend succeeded");
+                    successes.push(value);
+                }
+                Err(e) => warn!(backend = backend.name(), "Backend failed: {e}"),
+            }
+        }
+
+        if successes.is_empty() {
+            bail!("All {} backends failed", self.backends.len());
+        }
+        Ok(successes)
+    }
+}
+
+#[async_trait]
+impl TxBackend for FanOut {
+    fn name(&self) -> &str {
+        "fan-out"
+    }
+
+    async fn submit_tx(&self, tx: &Transaction) -> anyhow::Result<Txid> {
+        let results = join_all(self.backends.iter().map(|backend| backend.submit_tx(tx))).await;
+        let txids = self.report(results)?;
+        Ok(txids[0])
+    }
+
+    async fn submit_package(&self, txs: &[Transaction]) -> anyhow::Result<Vec<PackageTxResult>> {
+        let results = join_all(
+            self.backends
+                .iter()
+                .map(|backend| backend.submit_package(txs)),
+        )
+        .await;
+        let results = self.report(results)?;
+
+        // accepted by any backend counts, otherwise the first rejection tells why
+        Ok(txs
+            .iter()
+            .map(|tx| {
+                let txid = tx.txid();
+                let mut outcomes = results.iter().flatten().filter(|r| r.txid == txid);
+                let error = match outcomes.clone().find(|r| r.error.is_none()) {
+                    Some(_) => None,
+                    None => Some(
+                        outcomes
+                            .find_map(|r| r.error.clone())
+                            .unwrap_or_else(|| "no result".to_string()),
+                    ),
+                };
+                PackageTxResult { txid, error }
+            })
+            .collect())
+    }
+
+    async fn test_accept(&self, txs: &[Transaction]) -> anyhow::Result<Option<Vec<TestAccept>>> {
+        // the first backend that can tell decides
+        for backend in &self.backends {
+            if let Some(results) = backend.test_accept(txs).await? {
+                return Ok(Some(results));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn tx_status(&self, txid: &Txid) -> anyhow::Result<Option<TxStatus>> {
+        // seen anywhere counts
+        let mut status = None;
+        for backend in &self.backends {
+            match backend.tx_status(txid).await {
+                Ok(Some(found @ TxStatus::Confirmed { .. })) => return Ok(Some(found)),
+                Ok(found) => status = seen(status, found),
+                Err(_) => {}
+            }
+        }
+        Ok(status)
+    }
+
+    async fn locate_tx(&self, tx: &Transaction) -> anyhow::Result<Option<TxStatus>> {
+        let mut status = None;
+        for backend in &self.backends {
+            match backend.locate_tx(tx).await {
+                Ok(Some(found @ TxStatus::Confirmed { .. })) => return Ok(Some(found)),
+                Ok(found) => status = seen(status, found),
+                Err(_) => {}
+            }
+        }
+        Ok(status)
+    }
+
+    async fn is_synced(&self) -> anyhow::Result<Option<bool>> {
+        // ready as long as one backend is, or may be
+        let mut syncing = false;
+        for backend in &self.backends {
+            match backend.is_synced().await {
+                Ok(Some(true)) => return Ok(Some(true)),
+                Ok(Some(false)) => syncing = true,
+                Ok(None) | Err(_) => return Ok(None),
+            }
+        }
+        Ok(syncing.then_some(false))
+    }
+
+    async fn health_check(&self) -> anyhow::Result<()> {
+        // healthy as long as one backend is
+        let results = join_all(self.backends.iter().map(|backend| backend.health_check())).await;
+        let mut errors = vec![];
+        for (backend, result) in self.backends.iter().zip(results) {
+            match result {
+                Ok(()) => return Ok(()),
+                Err(e) => errors.push(format!("{}: {e}", backend.name())),
+            }
+        }
+        bail!("all backends failed: {}", errors.join(", "))
+    }
+
+    async fn prevout_value(&self, outpoint: &OutPoint) -> anyhow::Result<Option<u64>> {
+        for backend in &self.backends {
+            if let Ok(Some(value)) = backend.prevout_value(outpoint).await {
+                return Ok(Some(value));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn get_tx(&self, txid: &Txid) -> anyhow::Result<Option<Transaction>> {
+        for backend in &self.backends {
+            if let Ok(Some(tx)) = backend.get_tx(txid).await {
+                return Ok(Some(tx));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn best_block_hash(&self) -> anyhow::Result<Option<BlockHash>> {
+        for backend in &self.backends {
+            if let Ok(Some(hash)) = backend.best_block_hash().await {
+                return Ok(Some(hash));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn spending_tx(&self, outpoint: &OutPoint) -> anyhow::Result<Option<Txid>> {
+        for backend in &self.backends {
+            if let Ok(Some(txid)) = backend.spending_tx(outpoint).await {
+                return Ok(Some(txid));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn mempool_min_feerate(&self) -> anyhow::Result<Option<f64>> {
+        for backend in &self.backends {
+            if let Ok(Some(feerate)) = backend.mempool_min_feerate().await {
+                return Ok(Some(feerate));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// `status` after a backend `found` the tx in the mempool or not at all: in the mempool of one
+/// backend counts over missing from another
+fn seen(status: Option<TxStatus>, found: Option<TxStatus>) -> Option<TxStatus> {
+    match found {
+        Some(TxStatus::InMempool) => found,
+        Some(_) => status.or(found),
+        None => status,
+    }
+}
@@ -0,0 +1,101 @@
+use async_trait::async_trait;
+use bitcoin::{BlockHash, OutPoint, Transaction, Txid};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+
+use super::{PackageTxResult, TestAccept, TxBackend, TxStatus};
+use crate::metrics::Metrics;
+
+/// Records the latency and failures of the calls to another backend
+#[derive(Debug)]
+pub struct Measured {
+    backend: Arc<dyn TxBackend>,
+    metrics: Metrics,
+}
+
+impl Measured {
+    /// Measure the calls to `backend`
+    pub fn new(backend: Arc<dyn TxBackend>, metrics: Metrics) -> Self {
+        Self { backend, metrics }
+    }
+
+    async fn measure<T>(
+        &self,
+        call: &str,
+        f: impl Future<Output = anyhow::Result<T>>,
+    ) -> anyhow::Result<T> {
+        let start = Instant::now();
+        let result = f.await;
+        self.metrics.backend_call(
+            self.backend.name(),
+            call,
+            start.elapsed().as_secs_f64(),
+            result.is_ok(),
+        );
+        result
+    }
+}
+
+#[async_trait]
+impl TxBackend for Measured {
+    fn name(&self) -> &str {
+        self.backend.name()
+    }
+
+    async fn submit_tx(&self, tx: &Transaction) -> anyhow::Result<Txid> {
+        self.measure("submit_tx", self.backend.submit_tx(tx)).await
+    }
+
+    async fn submit_package(&self, txs: &[Transaction]) -> anyhow::Result<Vec<PackageTxResult>> {
+        self.measure("submit_package", self.backend.submit_package(txs))
+            .await
+    }
+
+    async fn test_accept(&self, txs: &[Transaction]) -> anyhow::Result<Option<Vec<TestAccept>>> {
+        self.measure("test_accept", self.backend.test_accept(txs))
+            .await
+    }
+
+    async fn tx_status(&self, txid: &Txid) -> anyhow::Result<Option<TxStatus>> {
+        self.measure("tx_status", self.backend.tx_status(txid))
+            .await
+    }
+
+    async fn locate_tx(&self, tx: &Transaction) -> anyhow::Result<Option<TxStatus>> {
+        self.measure("locate_tx", self.backend.locate_tx(tx)).await
+    }
+
+    async fn get_tx(&self, txid: &Txid) -> anyhow::Result<Option<Transaction>> {
+        self.measure("get_tx", self.backend.get_tx(txid)).await
+    }
+
+    async fn prevout_value(&self, outpoint: &OutPoint) -> anyhow::Result<Option<u64>> {
+        self.measure("prevout_value", self.backend.prevout_value(outpoint))
+            .await
+    }
+
+    async fn best_block_hash(&self) -> anyhow::Result<Option<BlockHash>> {
+        self.measure("best_block_hash", self.backend.best_block_hash())
+            .await
+    }
+
+    async fn spending_tx(&self, outpoint: &OutPoint) -> anyhow::Result<Option<Txid>> {
+        self.measure("spending_tx", self.backend.spending_tx(outpoint))
+            .await
+    }
+
+    async fn is_synced(&self) -> anyhow::Result<Option<bool>> {
+        self.measure("is_synced", self.backend.is_synced()).await
+    }
+
+    async fn health_check(&self) -> anyhow::Result<()> {
+        self.measure("health_check", self.backend.health_check())
+            .await
+    }
+
+    async fn mempool_min_feerate(&self) -> anyhow::Result<Option<f64>> {
+        self.measure("mempool_min_feerate", self.backend.mempool_min_feerate())
+            .await
+    }
+}
@@ -0,0 +1,197 @@
+//! Backends that submit received transactions to the bitcoin network.
+
+use async_trait::async_trait;
+use bitcoin::network::Magic;
+use bitcoin::{BlockHash, OutPoint, Transaction, Txid};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+mod bitcoind;
+mod electrum;
+mod esplora;
+mod fanout;
+mod measured;
+mod p2p;
+
+pub use bitcoind::{default_cookie_file, Bitcoind};
+pub use electrum::Electrum;
+pub use esplora::Esplora;
+pub use fanout::FanOut;
+pub use measured::Measured;
+pub use p2p::P2p;
+
+/// Something that can submit transactions to the bitcoin network
+#[async_trait]
+pub trait TxBackend: Debug + Send + Sync {
+    /// Short name of the backend, used in logs
+    fn name(&self) -> &str;
+
+    /// Submit a single transaction
+    async fn submit_tx(&self, tx: &Transaction) -> anyhow::Result<Txid>;
+
+    /// Submit a package of related transactions, parents before children, telling what happened
+    /// to each of them. Backends without package relay submit them one by one.
+    async fn submit_package(&self, txs: &[Transaction]) -> anyhow::Result<Vec<PackageTxResult>> {
+        Ok(submit_each(self, txs).await)
+    }
+
+    /// Check whether `txs` would be accepted without broadcasting them, `None` when the backend
+    /// can't tell
+    async fn test_accept(&self, _txs: &[Transaction]) -> anyhow::Result<Option<Vec<TestAccept>>> {
+        Ok(None)
+    }
+
+    /// Where the transaction is, `None` when the backend can't tell
+    async fn tx_status(&self, _txid: &Txid) -> anyhow::Result<Option<TxStatus>> {
+        Ok(None)
+    }
+
+    /// Where `tx` is, like [`Self::tx_status`], for backends that can tell more from its inputs
+    /// than from its txid
+    async fn locate_tx(&self, tx: &Transaction) -> anyhow::Result<Option<TxStatus>> {
+        self.tx_status(&tx.txid()).await
+    }
+
+    /// The transaction `txid`, from the mempool or the chain, `None` when the backend doesn't know
+    /// it or can't tell
+    async fn get_tx(&self, _txid: &Txid) -> anyhow::Result<Option<Transaction>> {
+        Ok(None)
+    }
+
+    /// The hash of the block at the tip of the best chain, `None` when the backend can't tell
+    async fn best_block_hash(&self) -> anyhow::Result<Option<BlockHash>> {
+        Ok(None)
+    }
+
+    /// The value in satoshis of the unspent output at `outpoint`, `None` when the backend can't
+    /// tell or doesn't know it
+    async fn prevout_value(&self, _outpoint: &OutPoint) -> anyhow::Result<Option<u64>> {
+        Ok(None)
+    }
+
+    /// The transaction spending `outpoint`, `None` when it's unspent or the backend can't tell
+    async fn spending_tx(&self, _outpoint: &OutPoint) -> anyhow::Result<Option<Txid>> {
+        Ok(None)
+    }
+
+    /// The lowest feerate in sat/vB the mempool currently accepts, `None` when the backend
+    /// can't tell
+    async fn mempool_min_feerate(&self) -> anyhow::Result<Option<f64>> {
+        Ok(None)
+    }
+
+    /// Whether the backend finished its initial block download, `None` when the backend can't
+    /// tell
+    async fn is_synced(&self) -> anyhow::Result<Option<bool>> {
+        Ok(None)
+    }
+
+    /// Check that the backend is reachable, backends without a connection to check are always
+    /// healthy
+    async fn health_check(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// What happened to a transaction of a submitted package
+#[derive(Debug, Clone, PartialEq)]
+pub struct PackageTxResult {
+    /// The transaction
+    pub txid: Txid,
+    /// Why the backend didn't accept it, `None` when it did
+    pub error: Option<String>,
+}
+
+/// Outcome of the dry run of a transaction
+#[derive(Debug, Clone)]
+pub struct TestAccept {
+    /// The transaction
+    pub txid: Txid,
+    /// Whether it would be accepted
+    pub allowed: bool,
+    /// Why it would be rejected
+    pub reject_reason: Option<String>,
+    /// Feerate in sat/vB it would be accepted with, when the backend tells
+    pub feerate: Option<f64>,
+}
+
+/// Where a transaction is according to a backend
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxStatus {
+    /// Unknown to the backend: never seen, dropped from the mempool, or confirmed while the
+    /// backend has no transaction index
+    Missing,
+    /// Waiting in the mempool
+    InMempool,
+    /// Mined
+    Confirmed {
+        /// Block the transaction is in
+        block_hash: BlockHash,
+        /// Height of that block
+        height: u32,
+        /// Number of confirmations
+        confirmations: u32,
+    },
+}
+
+/// Whether a backend rejected a transaction with `error` because it spends outputs the backend
+/// doesn't know, such as those of a parent that isn't in its mempool
+pub fn is_missing_inputs(error: &str) -> bool {
+    ["missing-inputs", "missingorspent", "missing inputs"]
+        .iter()
+        .any(|reason| error.contains(reason))
+}
+
+/// Backends by the magic of the network they serve
+pub type Backends = HashMap<Magic, Arc<dyn TxBackend>>;
+
+/// mempool.space for each network it has an instance for
+pub fn mempool_space() -> Backends {
+    Esplora::mempool_space()
+        .into_iter()
+        .map(|(magic, backend)| (magic, Arc::new(backend) as Arc<dyn TxBackend>))
+        .collect()
+}
+
+/// Submit the transactions of a package one by one with `backend`, in order, for backends without
+/// package relay. Every transaction is tried, also after one is rejected
+pub async fn submit_each<B>(backend: &B, txs: &[Transaction]) -> Vec<PackageTxResult>
+where
+    B: TxBackend + ?Sized,
+{
+    let mut results = vec![];
+    for tx in txs {
+        results.push(PackageTxResult {
+            txid: tx.txid(),
+            error: backend.submit_tx(tx).await.err().map(|e| e.to_string()),
+        });
+    }
+    results
+}
+
+/// Broadcast `txs` with `backend`, as a package when there are several, telling what happened to
+/// each of them. Fails when the backend couldn't take the transactions at all
+pub async fn broadcast_txs(
+    backend: &dyn TxBackend,
+    txs: Vec<Transaction>,
+) -> anyhow::Result<Vec<PackageTxResult>> {
+    let results = match txs.as_slice() {
+        [] => vec![],
+        [tx] => {
+            let txid = backend.submit_tx(tx).await?;
+            vec![PackageTxResult { txid, error: None }]
+        }
+        txs => backend.submit_package(txs).await?,
+    };
+    for result in &results {
+        match &result.error {
+            None => info!(txid = %result.txid, backend = backend.name(), "Broadcasted tx"),
+            Some(e) => {
+                warn!(txid = %result.txid, backend = backend.name(), "Package tx rejected: {e}")
+            }
+        }
+    }
+    Ok(results)
+}
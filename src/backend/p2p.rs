@@ -0,0 +1,257 @@
+use anyhow::{anyhow, bail};
+use async_trait::async_trait;
+use bitcoin::consensus::{deserialize, serialize};
+use bitcoin::network::address::Address;
+use bitcoin::network::constants::ServiceFlags;
+use bitcoin::network::message::{NetworkMessage, RawNetworkMessage};
+use bitcoin::network::message_blockdata::Inventory;
+use bitcoin::network::message_network::VersionMessage;
+use bitcoin::network::Magic;
+use bitcoin::{Transaction, Txid};
+use futures_util::future::join_all;
+use rand::seq::SliceRandom;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{lookup_host, TcpStream};
+use tokio::time::timeout;
+
+use super::{PackageTxResult, TxBackend};
+
+/// Protocol version announced to peers, the last one before wtxid relay
+const PROTOCOL_VERSION: u32 = 70015;
+/// How long a single peer gets to complete the exchange
+const PEER_TIMEOUT: Duration = Duration::from_secs(30);
+/// Largest message accepted from a peer
+const MAX_MESSAGE_SIZE: usize = 4_000_000;
+
+/// Broadcasts directly to bitcoin peers over the P2P protocol, announcing transactions with
+/// `inv` and handing them out on `getdata`
+#[derive(Debug, Clone)]
+pub struct P2p {
+    magic: Magic,
+    peers: Vec<String>,
+    max_peers: usize,
+}
+
+impl P2p {
+    /// Broadcast to `peers` (`host:port`), or to peers discovered through the network's DNS
+    /// seeds when empty. At most `max_peers` are used per broadcast.
+    pub fn new(magic: Magic, peers: Vec<String>, max_peers: usize) -> anyhow::Result<Self> {
+        if peers.is_empty() && dns_seeds(magic).is_empty() {
+            bail!("No DNS seeds known for network {magic}, give peers explicitly");
+        }
+        Ok(Self {
+            magic,
+            peers,
+            max_peers,
+        })
+    }
+
+    async fn peer_addrs(&self) -> anyhow::Result<Vec<SocketAddr>> {
+        let mut addrs = vec![];
+        if self.peers.is_empty() {
+            for seed in dns_seeds(self.magic) {
+                // a single failing seed is fine
+                if let Ok(resolved) = lookup_host((*seed, default_port(self.magic))).await {
+                    addrs.extend(resolved);
+                }
+            }
+        } else {
+            for peer in &self.peers {
+                addrs.extend(lookup_host(peer.as_str()).await?);
+            }
+        }
+
+        addrs.shuffle(&mut rand::thread_rng());
+        addrs.truncate(self.max_peers);
+        if addrs.is_empty() {
+            bail!("No peers found");
+        }
+        Ok(addrs)
+    }
+
+    /// Announce `txs` to all peers, succeeding when at least one peer took them
+    async fn broadcast(&self, txs: &[Transaction]) -> anyhow::Result<()> {
+        let addrs = self.peer_addrs().await?;
+        let results = join_all(addrs.iter().map(|addr| async move {
+            timeout(PEER_TIMEOUT, Peer::announce(self.magic, *addr, txs))
+                .await
+                .map_err(|_| anyhow!("timed out"))
+                .and_then(|result| result)
+        }))
+        .await;
+
+        let mut errors = vec![];
+        for (addr, result) in addrs.iter().zip(results) {
+            if let Err(e) = result {
+                errors.push(format!("{addr}: {e}"));
+            }
+        }
+        if errors.len() == addrs.len() {
+            bail!("No peer accepted the txs: {}", errors.join(", "));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TxBackend for P2p {
+    fn name(&self) -> &str {
+        "p2p"
+    }
+
+    async fn submit_tx(&self, tx: &Transaction) -> anyhow::Result<Txid> {
+        self.broadcast(std::slice::from_ref(tx)).await?;
+        Ok(tx.txid())
+    }
+
+    async fn submit_package(&self, txs: &[Transaction]) -> anyhow::Result<Vec<PackageTxResult>> {
+        // peers don't tell whether they accepted the txs
+        self.broadcast(txs).await?;
+        Ok(txs
+            .iter()
+            .map(|tx| PackageTxResult {
+                txid: tx.txid(),
+                error: None,
+            })
+            .collect())
+    }
+}
+
+struct Peer {
+    magic: Magic,
+    stream: TcpStream,
+}
+
+impl Peer {
+    /// Handshake with the peer at `addr`, announce `txs` and serve the ones it asks for
+    async fn announce(magic: Magic, addr: SocketAddr, txs: &[Transaction]) -> anyhow::Result<()> {
+        let stream = TcpStream::connect(addr).await?;
+        let mut peer = Self { magic, stream };
+
+        peer.handshake(addr).await?;
+
+        let inv = txs
+            .iter()
+            .map(|tx| Inventory::Transaction(tx.txid()))
+            .collect();
+        peer.send(NetworkMessage::Inv(inv)).await?;
+
+        // the peer asks for what it doesn't have yet, a pong tells it's done with the rest
+        let nonce = rand::random();
+        peer.send(NetworkMessage::Ping(nonce)).await?;
+        loop {
+            match peer.receive().await? {
+                Some(NetworkMessage::GetData(items)) => {
+                    for item in items {
+                        let requested = match item {
+                            Inventory::Transaction(txid) | Inventory::WitnessTransaction(txid) => {
+                                txs.iter().find(|tx| tx.txid() == txid)
+                            }
+                            _ => None,
+                        };
+                        if let Some(tx) = requested {
+                            peer.send(NetworkMessage::Tx(tx.clone())).await?;
+                        }
+                    }
+                    // make sure the sent txs are processed before hanging up
+                    peer.send(NetworkMessage::Ping(nonce)).await?;
+                }
+                Some(NetworkMessage::Ping(ping)) => peer.send(NetworkMessage::Pong(ping)).await?,
+                Some(NetworkMessage::Pong(pong)) if pong == nonce => return Ok(()),
+                _ => {}
+            }
+        }
+    }
+
+    async fn handshake(&mut self, addr: SocketAddr) -> anyhow::Result<()> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        let local = SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0));
+        let mut version = VersionMessage::new(
+            ServiceFlags::WITNESS,
+            timestamp,
+            Address::new(&addr, ServiceFlags::NONE),
+            Address::new(&local, ServiceFlags::WITNESS),
+            rand::random(),
+            format!("/nostr-tx-broadcast:{}/", env!("CARGO_PKG_VERSION")),
+            0,
+        );
+        version.version = PROTOCOL_VERSION;
+        self.send(NetworkMessage::Version(version)).await?;
+
+        let (mut got_version, mut got_verack) = (false, false);
+        while !(got_version && got_verack) {
+            match self.receive().await? {
+                Some(NetworkMessage::Version(_)) => {
+                    got_version = true;
+                    self.send(NetworkMessage::Verack).await?;
+                }
+                Some(NetworkMessage::Verack) => got_verack = true,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    async fn send(&mut self, payload: NetworkMessage) -> anyhow::Result<()> {
+        let message = RawNetworkMessage {
+            magic: self.magic,
+            payload,
+        };
+        self.stream.write_all(&serialize(&message)).await?;
+        Ok(())
+    }
+
+    /// The next message of the peer, `None` for messages that fail to decode
+    async fn receive(&mut self) -> anyhow::Result<Option<NetworkMessage>> {
+        let mut header = [0u8; 24];
+        self.stream.read_exact(&mut header).await?;
+        let len = u32::from_le_bytes([header[16], header[17], header[18], header[19]]) as usize;
+        if len > MAX_MESSAGE_SIZE {
+            bail!("Message of {len} bytes too large");
+        }
+
+        let mut data = header.to_vec();
+        data.resize(header.len() + len, 0);
+        self.stream.read_exact(&mut data[header.len()..]).await?;
+
+        match deserialize::<RawNetworkMessage>(&data) {
+            Ok(message) if message.magic != self.magic => bail!("Peer is on another network"),
+            Ok(message) => Ok(Some(message.payload)),
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+fn dns_seeds(magic: Magic) -> &'static [&'static str] {
+    match magic {
+        Magic::BITCOIN => &[
+            "seed.bitcoin.sipa.be",
+            "dnsseed.bluematt.me",
+            "seed.bitcoinstats.com",
+            "seed.bitcoin.jonasschnelli.ch",
+            "seed.btc.petertodd.net",
+            "seed.bitcoin.sprovoost.nl",
+            "dnsseed.emzy.de",
+            "seed.bitcoin.wiz.biz",
+        ],
+        Magic::TESTNET => &[
+            "testnet-seed.bitcoin.jonasschnelli.ch",
+            "seed.tbtc.petertodd.net",
+            "seed.testnet.bitcoin.sprovoost.nl",
+            "testnet-seed.bluematt.me",
+        ],
+        Magic::SIGNET => &["seed.signet.bitcoin.sprovoost.nl"],
+        _ => &[],
+    }
+}
+
+fn default_port(magic: Magic) -> u16 {
+    match magic {
+        Magic::BITCOIN => 8333,
+        Magic::TESTNET => 18333,
+        Magic::REGTEST => 18444,
+        _ => 38333,
+    }
+}
@@ -0,0 +1,106 @@
+//! Relaying transaction events between two sets of relays without broadcasting them, so the
+//! senders publishing to one set reach the broadcasters listening on the other.
+
+use anyhow::bail;
+use nostr::prelude::*;
+use nostr_sdk::relay::pool::RelayPoolNotification;
+use nostr_sdk::Client;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use tracing::{debug, info, warn};
+
+use crate::protocol;
+use crate::seen::SeenCache;
+
+/// What a bridge relays and where
+#[derive(Debug, Clone)]
+pub struct BridgeConfig {
+    /// Relays the events are received from
+    pub from: Vec<String>,
+    /// Relays the events are published to
+    pub to: Vec<String>,
+    /// Event kinds of the transaction events to relay
+    pub kinds: Vec<u64>,
+    /// SOCKS5 proxy the relay connections go through, such as Tor
+    pub proxy: Option<SocketAddr>,
+    /// How many event ids to remember, to publish each event once
+    pub seen_cache_size: NonZeroUsize,
+}
+
+/// Publish the transaction events of `config.from` to `config.to` as they arrive, until
+/// `shutdown` completes
+pub async fn run(
+    keys: &Keys,
+    config: BridgeConfig,
+    shutdown: impl Future<Output = ()>,
+) -> anyhow::Result<()> {
+    if config.from.is_empty() || config.to.is_empty() {
+        bail!("The bridge needs relays to receive from and relays to publish to");
+    }
+    let from = Client::new(keys);
+    for relay in &config.from {
+        from.add_relay(relay.as_str(), config.proxy).await?;
+    }
+    let to = Client::new(keys);
+    for relay in &config.to {
+        to.add_relay(relay.as_str(), config.proxy).await?;
+    }
+    from.connect().await;
+    to.connect().await;
+
+    let kinds = config
+        .kinds
+        .iter()
+        .map(|kind| Kind::Custom(*kind))
+        .collect();
+    from.subscribe(vec![Filter::new().kinds(kinds).since(Timestamp::now())])
+        .await;
+    info!(from = ?config.from, to = ?config.to, "Bridging transaction events");
+
+    let seen = Mutex::new(SeenCache::new(config.seen_cache_size));
+    let notifications = from.handle_notifications(|notification| async {
+        if let RelayPoolNotification::Event(relay, event) = notification {
+            if config.kinds.contains(&event.kind.as_u64()) {
+                bridge(&to, &seen, relay, event).await;
+            }
+        }
+        Ok(())
+    });
+    let result = tokio::select! {
+        result = notifications => result.map_err(Into::into),
+        () = shutdown => {
+            info!("Shutting down");
+            Ok(())
+        }
+    };
+    from.disconnect().await?;
+    to.disconnect().await?;
+    result
+}
+
+/// Publish `event`, received from `relay`, with `to` unless it's invalid, expired or already
+/// published
+async fn bridge(to: &Client, seen: &Mutex<SeenCache>, relay: Url, event: Event) {
+    // before the event is remembered, so forged events can't shadow real ones
+    if let Err(e) = protocol::verify(&event) {
+        warn!(event_id = %event.id, %relay, "Not bridging event: {e}");
+        return;
+    }
+    // relays the bridge publishes to may also be among the ones it receives from
+    if seen.lock().unwrap().seen_event(event.id) {
+        return;
+    }
+    if let Some(expiration) = protocol::expiration(&event) {
+        if expiration <= Timestamp::now() {
+            debug!(event_id = %event.id, %expiration, "Not bridging expired event");
+            return;
+        }
+    }
+    let event_id = event.id;
+    match to.send_event(event).await {
+        Ok(_) => info!(%event_id, from = %relay, "Bridged event"),
+        Err(e) => warn!(%event_id, "Error bridging event: {e}"),
+    }
+}
@@ -0,0 +1,491 @@
+//! Cashu ecash as payment: a token (NUT-00, V3 `cashuA` serialization) sent along with the
+//! transactions is redeemed at the broadcaster's mint by swapping its proofs for fresh ones
+//! (NUT-03), which are kept in a wallet file. Redeeming invalidates the token, so it can't be
+//! spent again, and the mint never learns who paid.
+
+use anyhow::{anyhow, bail, Context};
+use base64::{engine::general_purpose, Engine};
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::secp256k1::{All, PublicKey, Scalar, Secp256k1, SecretKey};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How long the mint may take to answer
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Prefix of V3 tokens
+const TOKEN_PREFIX: &str = "cashuA";
+
+/// Domain separator of hash_to_curve
+const DOMAIN_SEPARATOR: &[u8] = b"Secp256k1_HashToCurve_Cashu_";
+
+/// The only unit taken
+const UNIT: &str = "sat";
+
+/// A proof of ecash, worth `amount` sats of the mint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Proof {
+    pub amount: u64,
+    pub id: String,
+    pub secret: String,
+    #[serde(rename = "C")]
+    pub c: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct MintProofs {
+    mint: String,
+    proofs: Vec<Proof>,
+}
+
+/// A Cashu token
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Token {
+    token: Vec<MintProofs>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    unit: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    memo: Option<String>,
+}
+
+impl Token {
+    /// Decode a `cashuA` token
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        let Some(encoded) = s.trim().strip_prefix(TOKEN_PREFIX) else {
+            bail!("not a V3 Cashu token (cashuA…)");
+        };
+        // url-safe base64, padded or not, though some wallets use the standard alphabet
+        let encoded = encoded
+            .trim_end_matches('=')
+            .replace('+', "-")
+            .replace('/', "_");
+        let json = general_purpose::URL_SAFE_NO_PAD
+            .decode(encoded)
+            .context("invalid base64 in Cashu token")?;
+        let token: Token = serde_json::from_slice(&json).context("invalid Cashu token")?;
+        if token.token.is_empty() {
+            bail!("Cashu token without proofs");
+        }
+        Ok(token)
+    }
+
+    /// Encode as a `cashuA` token
+    pub fn encode(&self) -> anyhow::Result<String> {
+        let json = serde_json::to_vec(self)?;
+        Ok(format!(
+            "{TOKEN_PREFIX}{}",
+            general_purpose::URL_SAFE.encode(json)
+        ))
+    }
+
+    /// Sats the proofs are worth together
+    pub fn amount(&self) -> u64 {
+        self.proofs().map(|proof| proof.amount).sum()
+    }
+
+    fn proofs(&self) -> impl Iterator<Item = &Proof> {
+        self.token.iter().flat_map(|mint| &mint.proofs)
+    }
+}
+
+#[derive(Deserialize)]
+struct Keysets {
+    keysets: Vec<Keyset>,
+}
+
+#[derive(Deserialize)]
+struct Keyset {
+    id: String,
+    unit: String,
+    #[serde(default)]
+    active: bool,
+    #[serde(default)]
+    input_fee_ppk: u64,
+}
+
+#[derive(Deserialize)]
+struct Keys {
+    keysets: Vec<KeysetKeys>,
+}
+
+#[derive(Deserialize)]
+struct KeysetKeys {
+    keys: HashMap<u64, PublicKey>,
+}
+
+#[derive(Serialize)]
+struct BlindedMessage {
+    amount: u64,
+    id: String,
+    #[serde(rename = "B_")]
+    b: String,
+}
+
+#[derive(Deserialize)]
+struct SwapResponse {
+    signatures: Vec<BlindSignature>,
+}
+
+#[derive(Deserialize)]
+struct BlindSignature {
+    amount: u64,
+    #[serde(rename = "C_")]
+    c: PublicKey,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    detail: String,
+}
+
+/// The broadcaster's mint and where the redeemed ecash is kept
+#[derive(Debug)]
+pub struct Wallet {
+    mint: String,
+    file: PathBuf,
+    client: reqwest::Client,
+    /// Held while writing, so the tokens stay one per line
+    writing: Mutex<()>,
+}
+
+impl Wallet {
+    /// Take tokens of the mint at `mint`, appending the redeemed ecash to `file`
+    pub fn new(mint: &str, file: &Path) -> anyhow::Result<Self> {
+        Ok(Self {
+            mint: mint.trim_end_matches('/').to_string(),
+            file: file.to_path_buf(),
+            client: reqwest::Client::builder().timeout(TIMEOUT).build()?,
+            writing: Mutex::new(()),
+        })
+    }
+
+    /// URL of the mint
+    pub fn mint(&self) -> &str {
+        &self.mint
+    }
+
+    /// Redeem `token` if it's worth at least `msat` after the mint's fees, returning the sats kept.
+    /// Nothing is redeemed when it's worth less
+    pub async fn redeem(&self, token: &str, msat: u64) -> anyhow::Result<u64> {
+        let token = Token::parse(token)?;
+        if let Some(unit) = token.unit.as_deref().filter(|unit| *unit != UNIT) {
+            bail!("Cashu token in {unit}, only {UNIT} is taken");
+        }
+        if let Some(other) = token
+            .token
+            .iter()
+            .find(|mint| mint.mint.trim_end_matches('/') != self.mint)
+        {
+            bail!("Cashu token of another mint: {}", other.mint);
+        }
+
+        let keysets: Keysets = self.get("/v1/keysets").await?;
+        let fee_ppk: u64 = token
+            .proofs()
+            .map(|proof| {
+                keysets
+                    .keysets
+                    .iter()
+                    .find(|keyset| keyset.id == proof.id)
+                    .map(|keyset| keyset.input_fee_ppk)
+                    .ok_or_else(|| anyhow!("Cashu token of unknown keyset {}", proof.id))
+            })
+            .sum::<anyhow::Result<_>>()?;
+        let fee = fee_ppk.div_ceil(1000);
+        let worth = token.amount().saturating_sub(fee);
+        if worth.saturating_mul(1000) < msat {
+            bail!("Cashu token worth {worth} sat after fees, {msat} msat needed");
+        }
+
+        let Some(keyset) = keysets
+            .keysets
+            .iter()
+            .find(|keyset| keyset.active && keyset.unit == UNIT)
+        else {
+            bail!("The mint has no active keyset in {UNIT}");
+        };
+        let keys: Keys = self.get(&format!("/v1/keys/{}", keyset.id)).await?;
+        let keys = &keys
+            .keysets
+            .first()
+            .ok_or_else(|| anyhow!("The mint didn't tell the keys of {}", keyset.id))?
+            .keys;
+
+        // the outputs the mint signs, one per power of two in the amount
+        let secp = Secp256k1::new();
+        let mut outputs = vec![];
+        let mut blinding = vec![];
+        for bit in 0..u64::BITS {
+            let output_amount = 1 << bit;
+            if worth & output_amount == 0 {
+                continue;
+            }
+            let secret = hex(&rand::random::<[u8; 32]>());
+            let r = SecretKey::from_slice(&rand::random::<[u8; 32]>())?;
+            let b = blind(&secp, secret.as_bytes(), &r)?;
+            outputs.push(BlindedMessage {
+                amount: output_amount,
+                id: keyset.id.clone(),
+                b: b.to_string(),
+            });
+            blinding.push((secret, r));
+        }
+        let request = serde_json::json!({
+            "inputs": token.proofs().collect::<Vec<_>>(),
+            "outputs": outputs,
+        });
+        let response: SwapResponse = self.post("/v1/swap", &request).await?;
+        if response.signatures.len() != outputs.len() {
+            bail!(
+                "The mint signed {} of {} outputs",
+                response.signatures.len(),
+                outputs.len()
+            );
+        }
+
+        let mut proofs = vec![];
+        for (signature, (secret, r)) in response.signatures.iter().zip(blinding) {
+            let key = keys
+                .get(&signature.amount)
+                .ok_or_else(|| anyhow!("The mint has no key for {} sat", signature.amount))?;
+            let c = unblind(&secp, &signature.c, &r, key)?;
+            proofs.push(Proof {
+                amount: signature.amount,
+                id: keyset.id.clone(),
+                secret,
+                c: c.to_string(),
+            });
+        }
+        self.keep(Token {
+            token: vec![MintProofs {
+                mint: self.mint.clone(),
+                proofs,
+            }],
+            unit: Some(UNIT.to_string()),
+            memo: None,
+        })
+        .with_context(|| {
+            format!(
+                "Redeemed the Cashu token but couldn't keep it in {}",
+                self.file.display()
+            )
+        })?;
+        Ok(worth)
+    }
+
+    /// Append `token` to the wallet file, created readable only by its owner
+    fn keep(&self, token: Token) -> anyhow::Result<()> {
+        let line = format!("{}\n", token.encode()?);
+        let _writing = self.writing.lock().unwrap();
+        let mut options = OpenOptions::new();
+        options.create(true).append(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        options.open(&self.file)?.write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+    async fn get<T: for<'de> Deserialize<'de>>(&self, path: &str) -> anyhow::Result<T> {
+        let response = self
+            .client
+            .get(format!("{}{path}", self.mint))
+            .send()
+            .await?;
+        parse(response).await
+    }
+
+    async fn post<T: for<'de> Deserialize<'de>>(
+        &self,
+        path: &str,
+        body: &serde_json::Value,
+    ) -> anyhow::Result<T> {
+        let response = self
+            .client
+            .post(format!("{}{path}", self.mint))
+            .json(body)
+            .send()
+            .await?;
+        parse(response).await
+    }
+}
+
+async fn parse<T: for<'de> Deserialize<'de>>(response: reqwest::Response) -> anyhow::Result<T> {
+    let status = response.status();
+    let body = response.text().await?;
+    if !status.is_success() {
+        match serde_json::from_str::<ErrorResponse>(&body) {
+            Ok(error) => bail!("The mint refused: {}", error.detail),
+            Err(_) => bail!("The mint answered {status}: {body}"),
+        }
+    }
+    serde_json::from_str(&body).context("Invalid answer from the mint")
+}
+
+/// Map `message` to a point of the curve (NUT-00)
+fn hash_to_curve(message: &[u8]) -> anyhow::Result<PublicKey> {
+    let message_hash = sha256::Hash::hash(&[DOMAIN_SEPARATOR, message].concat());
+    for counter in 0u32..u32::from(u16::MAX) {
+        let hash = sha256::Hash::hash(
+            &[
+                message_hash.as_byte_array().as_slice(),
+                &counter.to_le_bytes(),
+            ]
+            .concat(),
+        );
+        if let Ok(point) =
+            PublicKey::from_slice(&[&[0x02], hash.as_byte_array().as_slice()].concat())
+        {
+            return Ok(point);
+        }
+    }
+    bail!("No point found for the secret")
+}
+
+/// The blinded message `B_ = Y + rG` of `secret` for the mint to sign (NUT-00)
+fn blind(secp: &Secp256k1<All>, secret: &[u8], r: &SecretKey) -> anyhow::Result<PublicKey> {
+    Ok(hash_to_curve(secret)?.combine(&r.public_key(secp))?)
+}
+
+/// The signature `C = C_ - rK` of the secret from the mint's blind signature `c` with its
+/// key `key` (NUT-00)
+fn unblind(
+    secp: &Secp256k1<All>,
+    c: &PublicKey,
+    r: &SecretKey,
+    key: &PublicKey,
+) -> anyhow::Result<PublicKey> {
+    Ok(c.combine(&key.mul_tweak(secp, &Scalar::from(*r))?.negate(secp))?)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::hex::FromHex;
+    use std::str::FromStr;
+
+    // the test vectors of NUT-00
+
+    fn bytes(hex: &str) -> Vec<u8> {
+        Vec::from_hex(hex).unwrap()
+    }
+
+    fn point(hex: &str) -> PublicKey {
+        PublicKey::from_str(hex).unwrap()
+    }
+
+    fn secret_key(hex: &str) -> SecretKey {
+        SecretKey::from_str(hex).unwrap()
+    }
+
+    #[test]
+    fn hash_to_curve_vectors() {
+        let vectors = [
+            (
+                "0000000000000000000000000000000000000000000000000000000000000000",
+                "024cce997d3b518f739663b757deaec95bcd9473c30a14ac2fd04023a739d1a725",
+            ),
+            (
+                "0000000000000000000000000000000000000000000000000000000000000001",
+                "022e7158e11c9506f1aa4248bf531298daa7febd6194f003edcd9b93ade6253acf",
+            ),
+            // takes a few counters to find a point
+            (
+                "0000000000000000000000000000000000000000000000000000000000000002",
+                "026cdbe15362df59cd1dd3c9c11de8aedac2106eca69236ecd9fbe117af897be4f",
+            ),
+        ];
+        for (message, y) in vectors {
+            assert_eq!(hash_to_curve(&bytes(message)).unwrap(), point(y));
+        }
+    }
+
+    #[test]
+    fn blinded_messages() {
+        let secp = Secp256k1::new();
+        let vectors = [
+            (
+                "d341ee4871f1f889041e63cf0d3823c713eea6aff01e80f1719f08f9e5be98f6",
+                "99fce58439fc37412ab3468b73db0569322588f62fb3a49182d67e23d877824a",
+                "033b1a9737a40cc3fd9b6af4b723632b76a67a36782596304612a6c2bfb5197e6d",
+            ),
+            (
+                "f1aaf16c2239746f369572c0784d9dd3d032d952c2d992175873fb58fae31a60",
+                "f78476ea7cc9ade20f9e05e58a804cf19533f03ea805ece5fee88c8e2874ba50",
+                "029bdf2d716ee366eddf599ba252786c1033f47e230248a4612a5670ab931f1763",
+            ),
+        ];
+        for (secret, r, b) in vectors {
+            assert_eq!(
+                blind(&secp, &bytes(secret), &secret_key(r)).unwrap(),
+                point(b)
+            );
+        }
+    }
+
+    #[test]
+    fn unblinded_signature() {
+        let secp = Secp256k1::new();
+        let c = unblind(
+            &secp,
+            &point("02a9acc1e48c25eeeb9289b5031cc57da9fe72f3fe2861d264bdc074209b107ba2"),
+            &secret_key("0000000000000000000000000000000000000000000000000000000000000001"),
+            &point("020000000000000000000000000000000000000000000000000000000000000001"),
+        )
+        .unwrap();
+        assert_eq!(
+            c,
+            point("03c724d7e6a5443b39ac8acf11f40420adc4f99a02e7cc1b57703d9391f6d129cd")
+        );
+    }
+
+    /// Unblinding the mint's signature of a blinded message gives its signature of the secret
+    #[test]
+    fn blind_signature_round_trip() {
+        let secp = Secp256k1::new();
+        let secret = bytes("d341ee4871f1f889041e63cf0d3823c713eea6aff01e80f1719f08f9e5be98f6");
+        let r = secret_key("99fce58439fc37412ab3468b73db0569322588f62fb3a49182d67e23d877824a");
+        let k = secret_key("7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f");
+
+        let b = blind(&secp, &secret, &r).unwrap();
+        let signed = b.mul_tweak(&secp, &Scalar::from(k)).unwrap();
+        let c = unblind(&secp, &signed, &r, &k.public_key(&secp)).unwrap();
+        let expected = hash_to_curve(&secret)
+            .unwrap()
+            .mul_tweak(&secp, &Scalar::from(k))
+            .unwrap();
+        assert_eq!(c, expected);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn wallet_file_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let file = std::env::temp_dir().join(format!("cashu-wallet-{}", std::process::id()));
+        let _ = std::fs::remove_file(&file);
+        let wallet = Wallet::new("https://mint.example", &file).unwrap();
+        let token = Token {
+            token: vec![MintProofs {
+                mint: wallet.mint().to_string(),
+                proofs: vec![],
+            }],
+            unit: Some(UNIT.to_string()),
+            memo: None,
+        };
+        wallet.keep(token).unwrap();
+        let mode = std::fs::metadata(&file).unwrap().permissions().mode();
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
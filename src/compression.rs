@@ -0,0 +1,162 @@
+//! Compression of the transaction payload, so multi-transaction packages fit within the event size
+//! limits of relays. Gzip with flate2, zstd with the reference library.
+
+use anyhow::{bail, Context};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::Deserialize;
+use std::fmt;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+/// Payloads decompressing to more than this are rejected, against decompression bombs
+pub const MAX_DECOMPRESSED: usize = 4 * 1024 * 1024;
+
+/// Compression level of zstd, its default
+const ZSTD_LEVEL: i32 = 3;
+
+/// How the transaction payload of an event is compressed, named in its `compression` tag
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    /// Gzip (RFC 1952)
+    Gzip,
+    /// Zstandard (RFC 8878)
+    Zstd,
+}
+
+impl Compression {
+    /// Name of the compression, as in the tag
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        }
+    }
+
+    /// Compress `data`
+    pub fn compress(&self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(vec![], flate2::Compression::best());
+                encoder.write_all(data)?;
+                Ok(encoder.finish()?)
+            }
+            Compression::Zstd => Ok(zstd::bulk::compress(data, ZSTD_LEVEL)?),
+        }
+    }
+
+    /// Decompress `data`, failing when it decompresses to more than [`MAX_DECOMPRESSED`]
+    pub fn decompress(&self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Compression::Gzip => read_capped(GzDecoder::new(data)).context("invalid gzip data"),
+            Compression::Zstd => {
+                read_capped(zstd::stream::read::Decoder::new(data)?).context("invalid zstd data")
+            }
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "gzip" => Ok(Self::Gzip),
+            "zstd" => Ok(Self::Zstd),
+            _ => bail!("unknown compression {s}, expected gzip or zstd"),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Read `reader` to the end, failing past [`MAX_DECOMPRESSED`] bytes without reading further
+fn read_capped(reader: impl Read) -> anyhow::Result<Vec<u8>> {
+    let mut out = vec![];
+    reader
+        .take(MAX_DECOMPRESSED as u64 + 1)
+        .read_to_end(&mut out)?;
+    if out.len() > MAX_DECOMPRESSED {
+        bail!("decompresses to more than {MAX_DECOMPRESSED} bytes");
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Compression; 2] = [Compression::Gzip, Compression::Zstd];
+
+    /// Bytes that compress somewhat, like transactions sharing scripts
+    fn payload(len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|i| (i * 7 % 251) as u8 ^ (i / 64) as u8)
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        for compression in ALL {
+            for len in [0, 1, 100, 70_000] {
+                let data = payload(len);
+                let compressed = compression.compress(&data).unwrap();
+                assert_eq!(compression.decompress(&compressed).unwrap(), data);
+            }
+        }
+    }
+
+    #[test]
+    fn names() {
+        for compression in ALL {
+            assert_eq!(
+                compression.as_str().parse::<Compression>().unwrap(),
+                compression
+            );
+        }
+        assert!("brotli".parse::<Compression>().is_err());
+    }
+
+    #[test]
+    fn truncated() {
+        for compression in ALL {
+            let compressed = compression.compress(&payload(10_000)).unwrap();
+            for len in [0, 5, compressed.len() / 2, compressed.len() - 1] {
+                assert!(compression.decompress(&compressed[..len]).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn corrupted() {
+        let mut compressed = Compression::Gzip.compress(&payload(1000)).unwrap();
+        // the CRC32 in the trailer
+        let crc = compressed.len() - 8;
+        compressed[crc] ^= 1;
+        assert!(Compression::Gzip.decompress(&compressed).is_err());
+
+        assert!(Compression::Gzip.decompress(b"not gzip at all").is_err());
+        assert!(Compression::Zstd.decompress(b"not zstd at all").is_err());
+    }
+
+    #[test]
+    fn bomb() {
+        let zeros = vec![0; MAX_DECOMPRESSED + 1];
+        for compression in ALL {
+            let compressed = compression.compress(&zeros).unwrap();
+            assert!(compressed.len() < 64 * 1024);
+            assert!(compression.decompress(&compressed).is_err());
+
+            let limit = compression.compress(&zeros[1..]).unwrap();
+            assert_eq!(
+                compression.decompress(&limit).unwrap().len(),
+                MAX_DECOMPRESSED
+            );
+        }
+    }
+}
@@ -0,0 +1,594 @@
+//! The configuration file, a TOML file whose values are overridden by the command line flags.
+//!
+//! ```toml
+//! relays = ["wss://nos.lol", "wss://relay.damus.io"]
+//! # proxy = "socks5://127.0.0.1:9050"
+//! network = "signet"
+//! bitcoin-hosts = ["http://127.0.0.1:38332"]
+//! bitcoin-user = "user"
+//! bitcoin-password = "password"
+//! # or instead of user and password
+//! # bitcoin-cookie = "/home/user/.bitcoin/signet/.cookie"
+//! # instead of or next to a node
+//! esplora-urls = ["https://mempool.space/signet/api"]
+//! electrum-urls = ["ssl://electrum.blockstream.info:60002"]
+//! p2p = true
+//! allow-pubkeys = ["npub1..."]
+//!
+//! # the transactions of other networks go to their own backends
+//! [networks.bitcoin]
+//! esplora-urls = ["https://mempool.space/api"]
+//! [networks.testnet]
+//! bitcoin-hosts = ["http://127.0.0.1:18332"]
+//! bitcoin-cookie = "/home/user/.bitcoin/testnet3/.cookie"
+//! ```
+
+use anyhow::{bail, Context};
+use bitcoin::bip32::DerivationPath;
+use bitcoin::network::Magic;
+use nostr::prelude::XOnlyPublicKey;
+use nostr::Url;
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::SocketAddr;
+use std::num::{NonZeroU32, NonZeroU64, NonZeroUsize};
+use std::path::{Path, PathBuf};
+
+use crate::jitter::Jitter;
+use crate::lnurl::LightningAddress;
+use crate::output::OutputFormat;
+use crate::protocol::{parse_magic, signet_magic};
+use crate::proxy::parse_proxy;
+use crate::pubkeys::parse_pubkey;
+use crate::relays::parse_relay;
+
+/// The values of a configuration file, all optional
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Relays to connect to
+    #[serde(default)]
+    pub relays: Vec<String>,
+    /// Don't fall back to the built-in relays when there are none
+    #[serde(default)]
+    pub no_default_relays: bool,
+    /// File with more relays to connect to, a url per line
+    pub relay_file: Option<PathBuf>,
+    /// Relays only to receive transaction events from
+    #[serde(default)]
+    pub read_relays: Vec<String>,
+    /// Relays only to publish to
+    #[serde(default)]
+    pub write_relays: Vec<String>,
+    /// Event kinds of the transaction events, the first one is published
+    #[serde(default)]
+    pub kinds: Vec<u64>,
+    /// File with the secret key of the nostr identity, created with a new key when missing
+    pub key_file: Option<PathBuf>,
+    /// Derivation path of the nostr key of the mnemonic given on the command line
+    pub derivation_path: Option<DerivationPath>,
+    /// SOCKS5 proxy to connect to the relays through
+    #[serde(default, deserialize_with = "deserialize_proxy")]
+    pub proxy: Option<SocketAddr>,
+    /// Network of the transactions
+    #[serde(default, deserialize_with = "deserialize_magic")]
+    pub network: Option<Magic>,
+    /// Block challenge script of the custom signet to use as network
+    #[serde(default, deserialize_with = "deserialize_signet_challenge")]
+    pub signet_challenge: Option<Magic>,
+    /// Bitcoin Core RPC urls, in order of preference
+    #[serde(default)]
+    pub bitcoin_hosts: Vec<String>,
+    /// Bitcoin Core RPC user
+    pub bitcoin_user: Option<String>,
+    /// Bitcoin Core RPC password
+    pub bitcoin_password: Option<String>,
+    /// Bitcoin Core RPC cookie file
+    pub bitcoin_cookie: Option<PathBuf>,
+    /// Esplora API urls
+    #[serde(default)]
+    pub esplora_urls: Vec<String>,
+    /// Electrum server urls
+    #[serde(default)]
+    pub electrum_urls: Vec<String>,
+    /// Skip the TLS certificate validation of the Electrum server
+    #[serde(default)]
+    pub electrum_skip_tls_verify: bool,
+    /// Broadcast directly to bitcoin peers
+    #[serde(default)]
+    pub p2p: bool,
+    /// Peers to broadcast to, discovered through DNS seeds when empty
+    #[serde(default)]
+    pub p2p_peers: Vec<String>,
+    /// Don't broadcast transactions that fail the dry run
+    #[serde(default)]
+    pub skip_rejected: bool,
+    /// Look for received transactions double spending another one
+    #[serde(default)]
+    pub detect_conflicts: bool,
+    /// Publish an alert for each double spend
+    #[serde(default)]
+    pub conflict_alerts: bool,
+    /// Ask the authors of transactions missing inputs for their parents
+    #[serde(default)]
+    pub request_parents: bool,
+    /// Hold the transactions while Bitcoin Core is in its initial block download
+    #[serde(default)]
+    pub require_synced: bool,
+    /// How many event ids and transactions to remember to skip duplicates
+    pub seen_cache_size: Option<NonZeroUsize>,
+    /// Seconds to wait before reconnecting to a relay that dropped, doubled after each failed
+    /// attempt
+    pub reconnect_delay: Option<u64>,
+    /// Longest wait in seconds between attempts to reconnect to a relay
+    pub max_reconnect_delay: Option<u64>,
+    /// Seconds a relay may stay quiet before it's pinged, 0 not to ping relays
+    pub ping_interval: Option<u64>,
+    /// Seconds a relay may stay silent before the connection is dropped
+    pub silence_timeout: Option<u64>,
+    /// Relays scoring lower than this, between 0 and 1, are demoted, 0 keeps using every relay
+    pub min_relay_score: Option<f64>,
+    /// Seconds a demoted relay isn't used before it's tried again
+    pub relay_demotion: Option<u64>,
+    /// Sqlite database recording the received events and broadcasts
+    pub db: Option<PathBuf>,
+    /// Seconds the database remembers handled event ids
+    pub replay_window: Option<u64>,
+    /// Rebroadcast transactions that drop out of the mempool until they're confirmed
+    #[serde(default)]
+    pub rebroadcast: bool,
+    /// Seconds between checks of the broadcast transactions
+    pub rebroadcast_interval: Option<u64>,
+    /// Rebroadcasts of a transaction before giving up
+    pub rebroadcast_attempts: Option<u32>,
+    /// Confirmations after which transactions are no longer followed
+    pub rebroadcast_depth: Option<u32>,
+    /// bitcoind's ZMQ endpoint publishing `hashblock`
+    pub zmq_block: Option<String>,
+    /// bitcoind's ZMQ endpoint publishing `sequence`
+    pub zmq_tx: Option<String>,
+    /// Publish the broadcast results as replies to the transaction events
+    #[serde(default)]
+    pub reply: bool,
+    /// Send why transactions failed in private messages instead of the replies
+    #[serde(default)]
+    pub dm_failures: bool,
+    /// Only handle the events of these authors
+    #[serde(default, deserialize_with = "deserialize_pubkeys")]
+    pub allow_pubkeys: Vec<XOnlyPublicKey>,
+    /// File with more authors to handle the events of, a pubkey per line
+    pub allow_pubkeys_file: Option<PathBuf>,
+    /// Only subscribe to the events of these authors
+    #[serde(default, deserialize_with = "deserialize_pubkeys")]
+    pub only_authors: Vec<XOnlyPublicKey>,
+    /// Also listen on the relays the allowed authors publish to, from their NIP-65 relay lists
+    #[serde(default)]
+    pub discover_relays: bool,
+    /// Also listen on the relays this broadcaster operator reads from, from its NIP-65 relay list
+    #[serde(default, deserialize_with = "deserialize_pubkey")]
+    pub operator: Option<XOnlyPublicKey>,
+    /// Also listen on public relays of the relay directory
+    #[serde(default)]
+    pub discover_public_relays: bool,
+    /// Directory service listing the public relays online
+    pub relay_directory: Option<String>,
+    /// Most relays to listen on at once
+    pub max_relays: Option<usize>,
+    /// File with authors whose events are ignored, bans are appended to it
+    pub deny_pubkeys_file: Option<PathBuf>,
+    /// Ban authors after this many invalid submissions
+    pub ban_after: Option<NonZeroU32>,
+    /// Keep a reputation per author and temporarily ban authors with a bad one
+    #[serde(default)]
+    pub reputation: bool,
+    /// Reputation score at which authors are temporarily banned, negated
+    pub reputation_threshold: Option<f64>,
+    /// Seconds the first temporary ban lasts
+    pub temp_ban: Option<u64>,
+    /// Seconds a temporary ban lasts at most
+    pub max_temp_ban: Option<u64>,
+    /// Seconds after which reputation scores halve
+    pub reputation_half_life: Option<u64>,
+    /// Minimum NIP-13 proof of work of handled events
+    pub min_pow: Option<u8>,
+    /// Only handle the events of authors with a verified NIP-05 identifier
+    #[serde(default)]
+    pub require_nip05: bool,
+    /// Domains whose NIP-05 identifiers are accepted
+    #[serde(default)]
+    pub nip05_domains: Vec<String>,
+    /// Seconds NIP-05 verifications are remembered
+    pub nip05_cache_ttl: Option<u64>,
+    /// Seconds after which events are too old to handle
+    pub max_event_age: Option<u64>,
+    /// Seconds events may be dated in the future
+    pub max_future_drift: Option<u64>,
+    /// Seconds to wait for the other chunks of a package
+    pub chunk_timeout: Option<u64>,
+    /// Transactions an author may submit per minute
+    pub max_txs_per_minute: Option<NonZeroU32>,
+    /// Transactions an author may submit per hour
+    pub max_txs_per_hour: Option<NonZeroU32>,
+    /// Transactions an author may submit per UTC day
+    pub daily_quota: Option<NonZeroU32>,
+    /// Authors without a daily quota
+    #[serde(default, deserialize_with = "deserialize_pubkeys")]
+    pub quota_exempt_pubkeys: Vec<XOnlyPublicKey>,
+    /// How many events can wait to be broadcast
+    pub queue_size: Option<NonZeroUsize>,
+    /// Events broadcast per second at most
+    pub max_broadcasts_per_second: Option<NonZeroU32>,
+    /// Milliseconds to wait for related events after taking one from the queue
+    pub batch_window: Option<NonZeroU64>,
+    /// Range of the random delay before broadcasting each event
+    #[serde(default, deserialize_with = "deserialize_jitter")]
+    pub broadcast_jitter: Option<Jitter>,
+    /// Longest delay in seconds before broadcasting each event to let other broadcasters go
+    /// first
+    pub cooperative_delay: Option<NonZeroU64>,
+    /// Maximum virtual size of a transaction
+    pub max_tx_vsize: Option<u64>,
+    /// Maximum total weight of the transactions of an event
+    pub max_package_weight: Option<u64>,
+    /// Minimum feerate in sat/vB of the transactions of an event
+    pub min_feerate: Option<f64>,
+    /// Address of the HTTP server with the metrics and health
+    #[serde(alias = "metrics-addr")]
+    pub http_addr: Option<SocketAddr>,
+    /// Also serve the admin endpoints on the HTTP server
+    #[serde(default)]
+    pub admin: bool,
+    /// Serve the endpoints publishing transactions on the HTTP server
+    #[serde(default)]
+    pub submit_api: bool,
+    /// Bearer token of the admin and submitting endpoints
+    pub http_token: Option<String>,
+    /// Unix socket of the admin console
+    pub admin_socket: Option<PathBuf>,
+    /// Address of the web dashboard
+    pub dashboard_addr: Option<SocketAddr>,
+    /// Address of the embedded relay
+    pub local_relay: Option<SocketAddr>,
+    /// Address of the gRPC API
+    pub grpc_addr: Option<SocketAddr>,
+    /// Log level or filter
+    pub log_level: Option<String>,
+    /// Log JSON objects instead of text
+    #[serde(default)]
+    pub log_json: bool,
+    /// File the logs are appended to
+    pub log_file: Option<PathBuf>,
+    /// Run in the background
+    #[serde(default)]
+    pub daemon: bool,
+    /// File with the pid of the listener
+    pub pidfile: Option<PathBuf>,
+    /// Seconds between summaries of what happened
+    pub stats_interval: Option<NonZeroU64>,
+    /// Publish the summaries as text notes
+    #[serde(default)]
+    pub publish_stats: bool,
+    /// Seconds between announcements of the broadcaster
+    pub announce_interval: Option<NonZeroU64>,
+    /// Publish NIP-89 handler information
+    #[serde(default)]
+    pub nip89: bool,
+    /// Take NIP-90 job requests
+    #[serde(default)]
+    pub dvm: bool,
+    /// Millisatoshis a job costs
+    pub dvm_price: Option<u64>,
+    /// Millisatoshis broadcasting the transactions of an event costs
+    pub price: Option<u64>,
+    /// Lightning address of the broadcaster
+    #[serde(default, deserialize_with = "deserialize_lightning_address")]
+    pub lightning_address: Option<LightningAddress>,
+    /// Nostr pubkey of the LNURL server publishing the zap receipts
+    #[serde(default, deserialize_with = "deserialize_pubkey")]
+    pub zapper: Option<XOnlyPublicKey>,
+    /// REST URL of the LND node making the invoices
+    pub lnd_url: Option<String>,
+    /// Macaroon file of the LND node
+    pub lnd_macaroon: Option<PathBuf>,
+    /// TLS certificate of the LND node
+    pub lnd_tls_cert: Option<PathBuf>,
+    /// JSON-RPC socket of the Core Lightning node making the invoices
+    pub cln_socket: Option<PathBuf>,
+    /// URL of the Cashu mint whose tokens are taken
+    pub cashu_mint: Option<String>,
+    /// File the redeemed ecash is appended to
+    pub cashu_wallet: Option<PathBuf>,
+    /// Seconds to wait for the payment of a job
+    pub payment_timeout: Option<u64>,
+    /// What to print on stdout
+    pub output: Option<OutputFormat>,
+    /// The backends of further networks, by network
+    #[serde(default, deserialize_with = "deserialize_networks")]
+    pub networks: Vec<(Magic, NetworkConfig)>,
+}
+
+/// The backends of a network of the `networks` table
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct NetworkConfig {
+    /// Bitcoin Core RPC urls, in order of preference
+    #[serde(default)]
+    pub bitcoin_hosts: Vec<String>,
+    /// Bitcoin Core RPC user
+    pub bitcoin_user: Option<String>,
+    /// Bitcoin Core RPC password
+    pub bitcoin_password: Option<String>,
+    /// Bitcoin Core RPC cookie file
+    pub bitcoin_cookie: Option<PathBuf>,
+    /// Esplora API urls
+    #[serde(default)]
+    pub esplora_urls: Vec<String>,
+    /// Electrum server urls
+    #[serde(default)]
+    pub electrum_urls: Vec<String>,
+    /// Skip the TLS certificate validation of the Electrum server
+    #[serde(default)]
+    pub electrum_skip_tls_verify: bool,
+    /// Broadcast directly to bitcoin peers
+    #[serde(default)]
+    pub p2p: bool,
+    /// Peers to broadcast to, discovered through DNS seeds when empty
+    #[serde(default)]
+    pub p2p_peers: Vec<String>,
+}
+
+impl NetworkConfig {
+    /// Whether any backend is configured
+    pub fn has_backends(&self) -> bool {
+        !self.bitcoin_hosts.is_empty()
+            || !self.esplora_urls.is_empty()
+            || !self.electrum_urls.is_empty()
+            || self.p2p
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if !self.has_backends() {
+            bail!("no backends");
+        }
+        if self.bitcoin_user.is_some() != self.bitcoin_password.is_some() {
+            bail!("`bitcoin-user` and `bitcoin-password` must be set together");
+        }
+        if self.bitcoin_user.is_some() && self.bitcoin_cookie.is_some() {
+            bail!("`bitcoin-user` and `bitcoin-cookie` are mutually exclusive");
+        }
+        if !self.p2p_peers.is_empty() && !self.p2p {
+            bail!("`p2p-peers` is set without `p2p`");
+        }
+        for url in &self.esplora_urls {
+            Url::parse(url).with_context(|| format!("`esplora-urls`: invalid url {url}"))?;
+        }
+        Ok(())
+    }
+}
+
+impl Config {
+    /// Read and validate the configuration file at `path`
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Reading config file {}", path.display()))?;
+        let config: Self = toml::from_str(&contents)
+            .with_context(|| format!("Parsing config file {}", path.display()))?;
+        config
+            .validate()
+            .with_context(|| format!("Invalid config file {}", path.display()))?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        for (key, relays) in [
+            ("relays", &self.relays),
+            ("read-relays", &self.read_relays),
+            ("write-relays", &self.write_relays),
+        ] {
+            for relay in relays {
+                parse_relay(relay).with_context(|| format!("`{key}`"))?;
+            }
+        }
+        if self.network.is_some() && self.signet_challenge.is_some() {
+            bail!("`network` and `signet-challenge` are mutually exclusive");
+        }
+        if self.bitcoin_user.is_some() != self.bitcoin_password.is_some() {
+            bail!("`bitcoin-user` and `bitcoin-password` must be set together");
+        }
+        if self.bitcoin_user.is_some() && self.bitcoin_cookie.is_some() {
+            bail!("`bitcoin-user` and `bitcoin-cookie` are mutually exclusive");
+        }
+        if !self.p2p_peers.is_empty() && !self.p2p {
+            bail!("`p2p-peers` is set without `p2p`");
+        }
+        if let Some(score) = self.min_relay_score {
+            if !(0.0..=1.0).contains(&score) {
+                bail!("`min-relay-score` must be between 0 and 1");
+            }
+        }
+        for url in &self.esplora_urls {
+            Url::parse(url).with_context(|| format!("`esplora-urls`: invalid url {url}"))?;
+        }
+        for (magic, network) in &self.networks {
+            network
+                .validate()
+                .with_context(|| format!("`networks`: network {magic}"))?;
+            if self.networks.iter().filter(|(m, _)| m == magic).count() > 1 {
+                bail!("`networks`: network {magic} is given twice");
+            }
+        }
+        Ok(())
+    }
+}
+
+fn deserialize_networks<'de, D>(deserializer: D) -> Result<Vec<(Magic, NetworkConfig)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    BTreeMap::<String, NetworkConfig>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(network, config)| {
+            parse_magic(&network)
+                .map(|magic| (magic, config))
+                .map_err(|e| serde::de::Error::custom(format!("`networks`: {network}: {e}")))
+        })
+        .collect()
+}
+
+fn deserialize_magic<'de, D>(deserializer: D) -> Result<Option<Magic>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    parse_magic(&s)
+        .map(Some)
+        .map_err(|e| serde::de::Error::custom(format!("`network`: {e}")))
+}
+
+fn deserialize_signet_challenge<'de, D>(deserializer: D) -> Result<Option<Magic>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    signet_magic(&s)
+        .map(Some)
+        .map_err(|e| serde::de::Error::custom(format!("`signet-challenge`: {e}")))
+}
+
+fn deserialize_proxy<'de, D>(deserializer: D) -> Result<Option<SocketAddr>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    parse_proxy(&s)
+        .map(Some)
+        .map_err(|e| serde::de::Error::custom(format!("`proxy`: {e}")))
+}
+
+fn deserialize_pubkey<'de, D>(deserializer: D) -> Result<Option<XOnlyPublicKey>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    parse_pubkey(&s)
+        .map(Some)
+        .map_err(|e| serde::de::Error::custom(format!("invalid pubkey {s}: {e}")))
+}
+
+fn deserialize_lightning_address<'de, D>(
+    deserializer: D,
+) -> Result<Option<LightningAddress>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    s.parse()
+        .map(Some)
+        .map_err(|e| serde::de::Error::custom(format!("`lightning-address`: {e}")))
+}
+
+fn deserialize_jitter<'de, D>(deserializer: D) -> Result<Option<Jitter>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    s.parse()
+        .map(Some)
+        .map_err(|e| serde::de::Error::custom(format!("`broadcast-jitter`: {e}")))
+}
+
+fn deserialize_pubkeys<'de, D>(deserializer: D) -> Result<Vec<XOnlyPublicKey>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|s| {
+            parse_pubkey(s)
+                .map_err(|e| serde::de::Error::custom(format!("`allow-pubkeys`: {s}: {e}")))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The config file `toml`, or the error with its causes
+    fn parse(toml: &str) -> Result<Config, String> {
+        let config: Config = toml::from_str(toml).map_err(|e| e.to_string())?;
+        config.validate().map_err(|e| format!("{e:#}"))?;
+        Ok(config)
+    }
+
+    #[test]
+    fn valid() {
+        let config = parse(
+            r#"
+            relays = ["wss://nos.lol"]
+            network = "signet"
+            bitcoin-hosts = ["http://127.0.0.1:38332"]
+            bitcoin-user = "user"
+            bitcoin-password = "password"
+            min-relay-score = 0.5
+
+            [networks.bitcoin]
+            esplora-urls = ["https://mempool.space/api"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.relays, ["wss://nos.lol"]);
+        assert_eq!(config.network, Some(Magic::SIGNET));
+        assert_eq!(config.networks.len(), 1);
+        assert_eq!(config.networks[0].0, Magic::BITCOIN);
+    }
+
+    #[test]
+    fn names_the_offending_key() {
+        for (toml, key) in [
+            ("relayz = []", "relayz"),
+            (r#"read-relays = ["nos.lol"]"#, "`read-relays`"),
+            (r#"bitcoin-user = "user""#, "`bitcoin-password`"),
+            (
+                r#"bitcoin-user = "user"
+                bitcoin-password = "password"
+                bitcoin-cookie = "/cookie""#,
+                "`bitcoin-cookie`",
+            ),
+            (r#"p2p-peers = ["127.0.0.1:8333"]"#, "`p2p-peers`"),
+            ("min-relay-score = 2.0", "`min-relay-score`"),
+            (r#"esplora-urls = ["not a url"]"#, "`esplora-urls`"),
+            (
+                r#"network = "signet"
+                signet-challenge = "51""#,
+                "`signet-challenge`",
+            ),
+        ] {
+            let error = parse(toml).unwrap_err();
+            assert!(error.contains(key), "{toml}: {error}");
+        }
+    }
+
+    #[test]
+    fn invalid_networks() {
+        let error = parse("[networks.bitcoin]\n").unwrap_err();
+        assert!(
+            error.contains("`networks`") && error.contains("no backends"),
+            "{error}"
+        );
+
+        let error = parse("[networks.moon]\np2p = true\n").unwrap_err();
+        assert!(error.contains("`networks`: moon"), "{error}");
+
+        let error = parse(
+            r#"
+            [networks.bitcoin]
+            esplora-urls = ["not a url"]
+            "#,
+        )
+        .unwrap_err();
+        assert!(error.contains("`esplora-urls`"), "{error}");
+    }
+}
@@ -0,0 +1,102 @@
+//! Double spends: received transactions spending an output that a transaction in the mempool,
+//! or one broadcast earlier, already spends. Merchants accepting unconfirmed payments want to
+//! know.
+
+use bitcoin::{OutPoint, Transaction, Txid};
+use lru::LruCache;
+use nostr::prelude::XOnlyPublicKey;
+use nostr::EventId;
+use std::collections::HashSet;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use tracing::debug;
+
+use crate::backend::TxBackend;
+use crate::protocol::TxConflict;
+
+/// How many outputs spent by broadcast transactions are remembered
+const MAX_SPENDS: usize = 100_000;
+
+/// A double spend found among received transactions
+#[derive(Debug, Clone)]
+pub struct Conflict {
+    /// What conflicts with what
+    pub conflict: TxConflict,
+    /// The event and author of the transaction spending the output first, when it was broadcast
+    /// by this listener
+    pub original: Option<(EventId, XOnlyPublicKey)>,
+}
+
+/// The outputs spent by the transactions broadcast, and by whose events
+#[derive(Debug)]
+pub struct Spends {
+    spent: LruCache<OutPoint, (Txid, EventId, XOnlyPublicKey)>,
+}
+
+impl Default for Spends {
+    fn default() -> Self {
+        Self {
+            spent: LruCache::new(NonZeroUsize::new(MAX_SPENDS).unwrap()),
+        }
+    }
+}
+
+impl Spends {
+    /// Remember the outputs spent by `txs`, broadcast for `event_id` by `author`
+    pub fn record(&mut self, event_id: EventId, author: XOnlyPublicKey, txs: &[Transaction]) {
+        for tx in txs {
+            for input in &tx.input {
+                self.spent
+                    .put(input.previous_output, (tx.txid(), event_id, author));
+            }
+        }
+    }
+
+    /// The broadcast transaction spending `outpoint`, with its event and author
+    pub fn get(&mut self, outpoint: &OutPoint) -> Option<(Txid, EventId, XOnlyPublicKey)> {
+        self.spent.get(outpoint).copied()
+    }
+}
+
+/// The conflicts of `txs` with the transactions broadcast before, as told by `spends`, and with
+/// the transactions `backend` knows
+pub async fn detect(
+    spends: &Mutex<Spends>,
+    backend: &dyn TxBackend,
+    txs: &[Transaction],
+) -> Vec<Conflict> {
+    // outputs of the package itself aren't spent by anyone else yet
+    let package: HashSet<Txid> = txs.iter().map(|tx| tx.txid()).collect();
+    let mut conflicts = vec![];
+    for tx in txs {
+        let txid = tx.txid();
+        for input in &tx.input {
+            let outpoint = input.previous_output;
+            if package.contains(&outpoint.txid) {
+                continue;
+            }
+            let known = spends.lock().unwrap().get(&outpoint);
+            let (conflicting_txid, original) = match known {
+                Some((spender, event_id, author)) => (Some(spender), Some((event_id, author))),
+                None => match backend.spending_tx(&outpoint).await {
+                    Ok(spender) => (spender, None),
+                    Err(e) => {
+                        debug!(%outpoint, backend = backend.name(), "Error looking up spender: {e}");
+                        (None, None)
+                    }
+                },
+            };
+            if let Some(conflicting_txid) = conflicting_txid.filter(|spender| *spender != txid) {
+                conflicts.push(Conflict {
+                    conflict: TxConflict {
+                        txid,
+                        outpoint,
+                        conflicting_txid,
+                    },
+                    original,
+                });
+            }
+        }
+    }
+    conflicts
+}
@@ -0,0 +1,170 @@
+//! The admin console of a listener on a unix socket, a line based protocol to operate it without
+//! opening a network port. Each line is a command, answered by its output and a last line that
+//! is `ok` or `error: <reason>`:
+//!
+//! - `status`: whether broadcasting is paused, the queue, the relays and the backends
+//! - `pause` and `resume`: stop and restart broadcasting
+//! - `flush-queue`: drop the events waiting to be broadcast
+//! - `reload-config`: read the config file again and apply what can change while running
+//! - `help`: the commands
+//! - `quit`: close the connection
+
+use anyhow::bail;
+use futures_util::future::BoxFuture;
+use std::fmt::Write as _;
+use std::fs::{self, Permissions};
+use std::io::ErrorKind;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tracing::{debug, info};
+
+use crate::Listener;
+
+/// Reads the configuration again and applies it to the listener, answering what changed
+pub type Reload = Arc<dyn Fn() -> BoxFuture<'static, anyhow::Result<String>> + Send + Sync>;
+
+const HELP: &str = "\
+status          whether broadcasting is paused, the queue, the relays and the backends
+pause           stop broadcasting, events keep being queued
+resume          broadcast again
+flush-queue     drop the events waiting to be broadcast
+reload-config   read the config file again and apply the changes
+quit            close the connection";
+
+/// Serve the console of `listener` on the unix socket at `path` until an error occurs,
+/// replacing a socket left behind by a previous run
+pub async fn serve(path: &Path, listener: Arc<Listener>, reload: Reload) -> anyhow::Result<()> {
+    let socket = bind(path)?;
+    info!(path = %path.display(), "Serving the admin console");
+    loop {
+        let (stream, _) = socket.accept().await?;
+        let listener = listener.clone();
+        let reload = reload.clone();
+        tokio::spawn(async move {
+            if let Err(e) = session(stream, &listener, &reload).await {
+                debug!("Admin console session failed: {e}");
+            }
+        });
+    }
+}
+
+/// Bind the socket at `path`, accessible only by its owner. Only a socket is replaced, not any
+/// other file given by mistake
+fn bind(path: &Path) -> anyhow::Result<UnixListener> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path)?,
+        Ok(_) => bail!("{} exists and isn't a socket", path.display()),
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    let socket = UnixListener::bind(path)?;
+    fs::set_permissions(path, Permissions::from_mode(0o600))?;
+    Ok(socket)
+}
+
+async fn session(stream: UnixStream, listener: &Listener, reload: &Reload) -> anyhow::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        let command = line.trim();
+        if command.is_empty() {
+            continue;
+        }
+        if command == "quit" {
+            break;
+        }
+        info!(command, "Admin console command");
+        let answer = match run(command, listener, reload).await {
+            Ok(output) if output.is_empty() => "ok\n".to_string(),
+            Ok(output) => format!("{output}\nok\n"),
+            Err(e) => format!("error: {e}\n"),
+        };
+        writer.write_all(answer.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+async fn run(command: &str, listener: &Listener, reload: &Reload) -> anyhow::Result<String> {
+    match command {
+        "status" => status(listener).await,
+        "pause" => {
+            listener.pause();
+            Ok(String::new())
+        }
+        "resume" => {
+            listener.resume();
+            Ok(String::new())
+        }
+        "flush-queue" => Ok(format!("flushed {} events", listener.flush_queue())),
+        "reload-config" => reload().await,
+        "help" => Ok(HELP.to_string()),
+        _ => anyhow::bail!("unknown command {command}, try help"),
+    }
+}
+
+async fn status(listener: &Listener) -> anyhow::Result<String> {
+    let health = listener.health().await;
+    let mut status = String::new();
+    writeln!(status, "healthy: {}", health.healthy)?;
+    writeln!(status, "paused: {}", health.paused)?;
+    writeln!(status, "queued: {}", listener.queue_depth())?;
+    if let Some(secs) = health.last_broadcast_secs {
+        writeln!(status, "last broadcast: {secs}s ago")?;
+    }
+    for relay in &health.relays {
+        writeln!(
+            status,
+            "relay {}: {} (score {:.2}{})",
+            relay.url,
+            relay.status,
+            relay.score,
+            if relay.demoted { ", demoted" } else { "" }
+        )?;
+    }
+    for backend in &health.backends {
+        match &backend.error {
+            None => writeln!(
+                status,
+                "backend {} {}: reachable",
+                backend.magic, backend.backend
+            )?,
+            Some(e) => writeln!(status, "backend {} {}: {e}", backend.magic, backend.backend)?,
+        }
+    }
+    Ok(status.trim_end().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("console-{name}-{}", std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn socket_is_private() {
+        let path = temp_path("socket");
+        let _ = fs::remove_file(&path);
+        let socket = bind(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // a socket left behind is replaced
+        drop(socket);
+        bind(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn keeps_other_files() {
+        let path = temp_path("file");
+        fs::write(&path, "config").unwrap();
+        assert!(bind(&path).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "config");
+        fs::remove_file(&path).unwrap();
+    }
+}
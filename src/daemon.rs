@@ -0,0 +1,122 @@
+//! Running as a classic background service: forking into a new session with the standard streams
+//! on the log file, and a pidfile. The parent only exits once the daemon started, with its exit
+//! code when it failed to.
+
+use anyhow::{bail, Context};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+
+/// The daemonized process, to tell the waiting parent it started
+#[derive(Debug)]
+pub struct Daemon {
+    ready: File,
+}
+
+impl Daemon {
+    /// Let the parent exit successfully
+    pub fn ready(mut self) {
+        let _ = self.ready.write_all(&[1]);
+    }
+}
+
+/// Fork into the background. Only the daemon returns, the parent exits once it calls
+/// [`Daemon::ready`] or exits itself. Stdin is read from /dev/null, stdout and stderr go to
+/// `log_file`, or /dev/null without one. Must be called before any thread is started
+pub fn daemonize(log_file: Option<&Path>) -> anyhow::Result<Daemon> {
+    let output = match log_file {
+        Some(path) => append(path)?,
+        None => OpenOptions::new().write(true).open("/dev/null")?,
+    };
+    let input = File::open("/dev/null")?;
+
+    let mut fds = [0; 2];
+    // SAFETY: fds has room for the two descriptors
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error()).context("Creating pipe");
+    }
+    // SAFETY: pipe returned the two descriptors, owned from here on
+    let (mut waiting, ready) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+
+    // SAFETY: the process is still single threaded
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()).context("Forking"),
+        0 => {
+            drop(waiting);
+            // SAFETY: plain system calls on descriptors that stay open
+            unsafe {
+                if libc::setsid() == -1 {
+                    return Err(io::Error::last_os_error()).context("Starting a new session");
+                }
+                libc::dup2(input.as_raw_fd(), libc::STDIN_FILENO);
+                libc::dup2(output.as_raw_fd(), libc::STDOUT_FILENO);
+                libc::dup2(output.as_raw_fd(), libc::STDERR_FILENO);
+            }
+            Ok(Daemon { ready })
+        }
+        child => {
+            drop(ready);
+            let mut byte = [0];
+            if waiting.read(&mut byte).unwrap_or(0) == 1 {
+                println!("Started in the background with pid {child}");
+                std::process::exit(0);
+            }
+            // the daemon exited before it was ready
+            let mut status = 0;
+            // SAFETY: waiting for our own child
+            unsafe { libc::waitpid(child, &mut status, 0) };
+            let code = if libc::WIFEXITED(status) {
+                libc::WEXITSTATUS(status)
+            } else {
+                1
+            };
+            match log_file {
+                Some(path) => eprintln!("Failed to start, see {}", path.display()),
+                None => eprintln!("Failed to start, run without --daemon to see why"),
+            }
+            std::process::exit(if code == 0 { 1 } else { code });
+        }
+    }
+}
+
+/// Open `path` to append to it
+fn append(path: &Path) -> anyhow::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Opening {}", path.display()))
+}
+
+/// A file with the pid of this process, removed when dropped
+#[derive(Debug)]
+pub struct Pidfile {
+    path: PathBuf,
+}
+
+impl Pidfile {
+    /// Write the pid to `path`, unless the process in an existing pidfile still runs
+    pub fn create(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        if let Ok(existing) = fs::read_to_string(path) {
+            if let Ok(pid) = existing.trim().parse::<libc::pid_t>() {
+                // SAFETY: signal 0 only checks whether the process exists
+                if unsafe { libc::kill(pid, 0) } == 0 {
+                    bail!("Already running with pid {pid}, see {}", path.display());
+                }
+            }
+        }
+        fs::write(path, format!("{}\n", std::process::id()))
+            .with_context(|| format!("Writing {}", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for Pidfile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
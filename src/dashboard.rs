@@ -0,0 +1,70 @@
+//! A small web UI showing what a listener does: its relays, the recently received events, their
+//! outcomes and the transactions being rebroadcast. For operators who don't run Prometheus.
+
+use axum::extract::State;
+use axum::response::Html;
+use axum::routing::get;
+use axum::{Json, Router};
+use nostr::Timestamp;
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::info;
+
+use crate::output;
+use crate::Listener;
+
+const PAGE: &str = include_str!("dashboard.html");
+
+/// Serve the dashboard of `listener` on `addr` until an error occurs: the page at `/` and what
+/// it shows as JSON at `/state`
+pub async fn serve(addr: SocketAddr, listener: Arc<Listener>) -> anyhow::Result<()> {
+    let app = Router::new()
+        .route("/", get(|| async { Html(PAGE) }))
+        .route("/state", get(state))
+        .with_state(listener);
+
+    let tcp = tokio::net::TcpListener::bind(addr).await?;
+    info!("Serving the dashboard on http://{addr}");
+    axum::serve(tcp, app).await?;
+    Ok(())
+}
+
+async fn state(State(listener): State<Arc<Listener>>) -> Json<Value> {
+    let received: Vec<_> = listener
+        .recent_received()
+        .iter()
+        .map(|(at, received)| with_time(output::received_json(received), *at))
+        .collect();
+    let outcomes: Vec<_> = listener
+        .recent_outcomes()
+        .iter()
+        .map(|(at, outcome)| with_time(output::outcome_json(outcome), *at))
+        .collect();
+    let rebroadcasting: Vec<_> = listener
+        .rebroadcasting()
+        .into_iter()
+        .map(|tracked| {
+            json!({
+                "txid": tracked.txid.to_string(),
+                "magic": tracked.magic.to_string(),
+                "event_id": tracked.event_id.to_hex(),
+                "attempts": tracked.attempts,
+                "confirmed": tracked.confirmed,
+            })
+        })
+        .collect();
+    Json(json!({
+        "paused": listener.is_paused(),
+        "relays": listener.relays().await,
+        "totals": listener.metrics().totals(),
+        "received": received,
+        "outcomes": outcomes,
+        "rebroadcasting": rebroadcasting,
+    }))
+}
+
+fn with_time(mut entry: Value, at: Timestamp) -> Value {
+    entry["at"] = at.as_u64().into();
+    entry
+}
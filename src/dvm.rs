@@ -0,0 +1,152 @@
+//! NIP-90 data vending machine jobs: a job request of kind 5333 with the raw transactions as `text`
+//! inputs (`i` tags) and the network as a `network` param, answered with job feedback (kind 7000)
+//! while it's handled and a job result of kind 6333 with the [`TxResult`]s of its transactions.
+
+use bitcoin::network::Magic;
+use bitcoin::Transaction;
+use nostr::prelude::*;
+
+use crate::protocol::{self, TxResult};
+
+/// Event kind of the requests to broadcast transactions
+pub const JOB_REQUEST_KIND: u64 = 5333;
+
+/// Event kind of the results of the requests
+pub const JOB_RESULT_KIND: u64 = JOB_REQUEST_KIND + 1000;
+
+/// Event kind of the feedback on the requests while they're handled
+pub const JOB_FEEDBACK_KIND: u64 = 7000;
+
+const INPUT_TAG: &str = "i";
+const PARAM_TAG: &str = "param";
+const NETWORK_PARAM: &str = "network";
+const BID_TAG: &str = "bid";
+const STATUS_TAG: &str = "status";
+const REQUEST_TAG: &str = "request";
+
+/// How the listener takes jobs
+#[derive(Debug, Clone, Default)]
+pub struct DvmConfig {
+    /// Millisatoshis a job costs, paid with the invoice of the lightning node or by zapping the
+    /// feedback asking for the payment, `None` to handle them for free. Needs a [`PaymentConfig`](crate::payment::PaymentConfig)
+    pub price: Option<u64>,
+}
+
+/// Where a job is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobStatus {
+    /// Waiting for the customer to pay
+    PaymentRequired,
+    /// Queued to be broadcast
+    Processing,
+    /// Not all transactions were broadcast
+    Error,
+    /// All transactions were broadcast
+    Success,
+}
+
+impl JobStatus {
+    /// Name of the status, as in the `status` tag
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobStatus::PaymentRequired => "payment-required",
+            JobStatus::Processing => "processing",
+            JobStatus::Error => "error",
+            JobStatus::Success => "success",
+        }
+    }
+}
+
+/// What a job request asks for
+#[derive(Debug, Clone)]
+pub struct Job {
+    /// Network of the transactions, `None` when missing or invalid
+    pub magic: Option<Magic>,
+    /// The inputs that decode as transactions
+    pub txs: Vec<Transaction>,
+    /// What the customer offers to pay in millisatoshis
+    pub bid: Option<u64>,
+    /// The service provider asked for, `None` for anyone
+    pub provider: Option<XOnlyPublicKey>,
+}
+
+/// The job of a job request
+pub fn job(event: &Event) -> Job {
+    let mut job = Job {
+        magic: None,
+        txs: vec![],
+        bid: None,
+        provider: None,
+    };
+    for tag in &event.tags {
+        if let Tag::PubKey(pubkey, _) = tag {
+            job.provider = Some(*pubkey);
+            continue;
+        }
+        let values = tag.as_vec();
+        match values.as_slice() {
+            [name, data, input_type, ..] if name == INPUT_TAG && input_type == "text" => {
+                job.txs.extend(protocol::decode_tx(data))
+            }
+            [name, param, value, ..] if name == PARAM_TAG && param == NETWORK_PARAM => {
+                job.magic = protocol::parse_magic(value).ok()
+            }
+            [name, msat, ..] if name == BID_TAG => job.bid = msat.parse().ok(),
+            _ => {}
+        }
+    }
+    job
+}
+
+/// Build the feedback on `job`, with `extra` information and the `amount` in millisatoshis asked
+/// for when payment is required, with an invoice paying it or paid by zapping the feedback
+pub fn feedback_event(
+    job: &Event,
+    status: JobStatus,
+    extra: &str,
+    amount: Option<(u64, Option<&str>)>,
+) -> EventBuilder {
+    let mut tags = vec![
+        Tag::Generic(
+            TagKind::Custom(STATUS_TAG.to_string()),
+            vec![status.as_str().to_string(), extra.to_string()],
+        ),
+        Tag::Event(job.id, None, None),
+        Tag::PubKey(job.pubkey, None),
+    ];
+    match amount {
+        Some((msat, Some(bolt11))) => tags.push(Tag::Generic(
+            TagKind::Amount,
+            vec![msat.to_string(), bolt11.to_string()],
+        )),
+        Some((msat, None)) => tags.push(Tag::Amount(msat)),
+        None => {}
+    }
+
+    EventBuilder::new(Kind::Custom(JOB_FEEDBACK_KIND), "", &tags)
+}
+
+/// Build the result of `job`, with the [`TxResult`]s of its transactions as a JSON array in the
+/// content
+pub fn result_event(job: &Event, results: &[TxResult]) -> anyhow::Result<EventBuilder> {
+    let mut tags = vec![
+        Tag::Generic(
+            TagKind::Custom(REQUEST_TAG.to_string()),
+            vec![job.as_json()],
+        ),
+        Tag::Event(job.id, None, None),
+        Tag::PubKey(job.pubkey, None),
+    ];
+    tags.extend(
+        job.tags
+            .iter()
+            .filter(|tag| tag.kind() == TagKind::Custom(INPUT_TAG.to_string()))
+            .cloned(),
+    );
+
+    Ok(EventBuilder::new(
+        Kind::Custom(JOB_RESULT_KIND),
+        serde_json::to_string(results)?,
+        &tags,
+    ))
+}
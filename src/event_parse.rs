@@ -0,0 +1,251 @@
+//! Tolerant parsing of cleartext transaction events. Besides the `transactions` tag of the format,
+//! some implementations put the raw transactions in the content: as is, separated by whitespace
+//! or commas, or as a JSON body such as `{"magic": "f9beb4d9", "transactions": ["<hex>"]}`. Each
+//! transaction can be hex or base64, or all of them compressed together as named in the
+//! `compression` tag.
+
+use bitcoin::network::Magic;
+use bitcoin::Transaction;
+use nostr::Event;
+use serde::Deserialize;
+use std::fmt;
+
+use crate::protocol;
+
+/// Where the transactions of an event were found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// The `transactions` tag
+    Tags,
+    /// Raw transactions in the content
+    Content,
+    /// A JSON body in the content
+    ContentJson,
+    /// The NIP-44 encrypted content
+    Encrypted,
+    /// Chunks of the package in several events
+    Chunks,
+    /// The inputs of a NIP-90 job request
+    Job,
+}
+
+impl Encoding {
+    /// Name of the encoding, as logged
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Tags => "tags",
+            Encoding::Content => "content",
+            Encoding::ContentJson => "content_json",
+            Encoding::Encrypted => "encrypted",
+            Encoding::Chunks => "chunks",
+            Encoding::Job => "job",
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The network and the transactions of an event
+#[derive(Debug, Clone)]
+pub struct Payload {
+    /// Network magic, `None` when missing or invalid
+    pub magic: Option<Magic>,
+    /// The transactions that decode
+    pub txs: Vec<Transaction>,
+    /// Where they were found
+    pub encoding: Encoding,
+}
+
+/// A JSON body in the content, the transactions alone or with their network
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Body {
+    List(Vec<String>),
+    Object {
+        #[serde(alias = "network")]
+        magic: Option<String>,
+        #[serde(default, alias = "txs")]
+        transactions: Vec<String>,
+        #[serde(alias = "hex")]
+        tx: Option<String>,
+    },
+}
+
+/// The transactions of a cleartext event: from its `transactions` tag when it has one, otherwise
+/// from its content. The network is the one in the JSON body, or else the `magic` tag.
+pub fn parse(event: &Event) -> Payload {
+    let tag_magic = protocol::magic(event);
+    let compression = protocol::compression(event).ok().flatten();
+    let content = event.content.trim();
+    if protocol::has_transactions(event) || content.is_empty() {
+        return Payload {
+            magic: tag_magic,
+            txs: protocol::transactions(event),
+            encoding: Encoding::Tags,
+        };
+    }
+
+    match serde_json::from_str::<Body>(content) {
+        Ok(Body::List(txs)) => Payload {
+            magic: tag_magic,
+            txs: protocol::decode_txs(&txs, compression),
+            encoding: Encoding::ContentJson,
+        },
+        Ok(Body::Object {
+            magic,
+            mut transactions,
+            tx,
+        }) => {
+            transactions.extend(tx);
+            Payload {
+                magic: match magic {
+                    Some(magic) => protocol::parse_magic(&magic).ok(),
+                    None => tag_magic,
+                },
+                txs: protocol::decode_txs(&transactions, compression),
+                encoding: Encoding::ContentJson,
+            }
+        }
+        Err(_) => {
+            let txs: Vec<_> = content
+                .split(|c: char| c.is_whitespace() || c == ',')
+                .filter(|tx| !tx.is_empty())
+                .map(String::from)
+                .collect();
+            Payload {
+                magic: tag_magic,
+                txs: protocol::decode_txs(&txs, compression),
+                encoding: Encoding::Content,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::{engine::general_purpose, Engine as _};
+    use bitcoin::absolute::LockTime;
+    use bitcoin::consensus::encode::{serialize, serialize_hex};
+    use bitcoin::hashes::Hash;
+    use bitcoin::{OutPoint, ScriptBuf, TxIn, TxOut, Txid};
+    use nostr::prelude::*;
+
+    /// A transaction spending a made up output, told apart from others by `id`
+    fn tx(id: u32) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: LockTime::from_consensus(id),
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::all_zeros(), id),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: 10_000,
+                script_pubkey: ScriptBuf::new(),
+            }],
+        }
+    }
+
+    /// A cleartext event with `content`, on the network of `magic` when given
+    fn event(content: &str, magic: Option<Magic>) -> Event {
+        let tags: Vec<_> = magic
+            .into_iter()
+            .map(|magic| {
+                Tag::Generic(
+                    TagKind::Custom("magic".to_string()),
+                    vec![magic.to_string()],
+                )
+            })
+            .collect();
+        EventBuilder::new(Kind::from(protocol::BITCOIN_TX_KIND), content, &tags)
+            .to_event(&Keys::generate())
+            .unwrap()
+    }
+
+    #[test]
+    fn tags() {
+        let txs = [tx(1), tx(2)];
+        let event = protocol::tx_event(
+            protocol::BITCOIN_TX_KIND,
+            Magic::BITCOIN,
+            &txs,
+            Default::default(),
+        )
+        .unwrap()
+        .to_event(&Keys::generate())
+        .unwrap();
+        let payload = parse(&event);
+        assert_eq!(payload.encoding, Encoding::Tags);
+        assert_eq!(payload.magic, Some(Magic::BITCOIN));
+        assert_eq!(payload.txs, txs);
+    }
+
+    #[test]
+    fn empty_content() {
+        let payload = parse(&event("  ", Some(Magic::TESTNET)));
+        assert_eq!(payload.encoding, Encoding::Tags);
+        assert_eq!(payload.magic, Some(Magic::TESTNET));
+        assert!(payload.txs.is_empty());
+    }
+
+    #[test]
+    fn raw_content() {
+        let content = format!(
+            "{}, {}\n{}",
+            serialize_hex(&tx(1)),
+            general_purpose::STANDARD.encode(serialize(&tx(2))),
+            serialize_hex(&tx(3)),
+        );
+        let payload = parse(&event(&content, Some(Magic::BITCOIN)));
+        assert_eq!(payload.encoding, Encoding::Content);
+        assert_eq!(payload.magic, Some(Magic::BITCOIN));
+        assert_eq!(payload.txs, [tx(1), tx(2), tx(3)]);
+    }
+
+    #[test]
+    fn json_list() {
+        let content = serde_json::json!([serialize_hex(&tx(1)), serialize_hex(&tx(2))]);
+        let payload = parse(&event(&content.to_string(), Some(Magic::BITCOIN)));
+        assert_eq!(payload.encoding, Encoding::ContentJson);
+        assert_eq!(payload.magic, Some(Magic::BITCOIN));
+        assert_eq!(payload.txs, [tx(1), tx(2)]);
+    }
+
+    #[test]
+    fn json_object() {
+        let content = serde_json::json!({
+            "magic": Magic::TESTNET.to_string(),
+            "transactions": [serialize_hex(&tx(1))],
+            "tx": serialize_hex(&tx(2)),
+        });
+        let payload = parse(&event(&content.to_string(), Some(Magic::BITCOIN)));
+        assert_eq!(payload.encoding, Encoding::ContentJson);
+        assert_eq!(
+            payload.magic,
+            Some(Magic::TESTNET),
+            "the body wins over the tag"
+        );
+        assert_eq!(payload.txs, [tx(1), tx(2)]);
+    }
+
+    #[test]
+    fn json_object_aliases() {
+        let content = serde_json::json!({
+            "network": "not a magic",
+            "txs": [serialize_hex(&tx(1))],
+            "hex": serialize_hex(&tx(2)),
+        });
+        let payload = parse(&event(&content.to_string(), Some(Magic::BITCOIN)));
+        assert_eq!(payload.magic, None);
+        assert_eq!(payload.txs, [tx(1), tx(2)]);
+
+        let content = serde_json::json!({ "txs": [serialize_hex(&tx(1))] });
+        let payload = parse(&event(&content.to_string(), Some(Magic::BITCOIN)));
+        assert_eq!(payload.magic, Some(Magic::BITCOIN), "falls back to the tag");
+    }
+}
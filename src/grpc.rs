@@ -0,0 +1,400 @@
+//! The gRPC API of a listener, for daemons driving it programmatically, see
+//! `proto/broadcaster.proto`. Served with tonic over plaintext HTTP/2 without authentication, so
+//! it's meant to be reachable from the local host only.
+
+use anyhow::bail;
+use bitcoin::consensus::deserialize;
+use bitcoin::network::Magic;
+use bitcoin::{Transaction, Txid};
+use futures_util::stream::{self, Stream};
+use nostr::Timestamp;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+use tracing::{info, warn};
+
+use crate::backend::PackageTxResult;
+use crate::health::Health;
+use crate::listener::{Outcome, ReceivedTxs};
+use crate::protocol::{self, ResultStatus, TxResult};
+use crate::Listener;
+
+/// The messages and services generated from `proto/broadcaster.proto`, with a client to call
+/// the API
+pub mod proto {
+    tonic::include_proto!("nostr_tx_broadcast");
+}
+
+use proto::broadcaster_server::{Broadcaster, BroadcasterServer};
+
+/// Largest request message accepted
+const MAX_MESSAGE_SIZE: usize = 4_000_000;
+
+/// How long to wait before accepting connections again after failing to, such as when out of
+/// file descriptors
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Serve the gRPC API of `listener` on `addr` until an error occurs. `magic` is the network of
+/// submitted transactions that don't name one
+pub async fn serve(addr: SocketAddr, listener: Arc<Listener>, magic: Magic) -> anyhow::Result<()> {
+    let tcp = TcpListener::bind(addr).await?;
+    if !addr.ip().is_loopback() {
+        warn!(%addr, "The gRPC API has no authentication, it should only be reachable locally");
+    }
+    info!("Serving gRPC on http://{addr}");
+    // failing to accept a connection doesn't stop the server
+    let incoming = stream::unfold(tcp, |tcp| async move {
+        loop {
+            match tcp.accept().await {
+                Ok((socket, _)) => return Some((Ok::<_, std::io::Error>(socket), tcp)),
+                Err(e) => {
+                    warn!("Error accepting a gRPC connection: {e}");
+                    tokio::time::sleep(ACCEPT_BACKOFF).await;
+                }
+            }
+        }
+    });
+    let service = BroadcasterServer::new(Service { listener, magic })
+        .max_decoding_message_size(MAX_MESSAGE_SIZE);
+    Server::builder()
+        .add_service(service)
+        .serve_with_incoming(incoming)
+        .await?;
+    Ok(())
+}
+
+/// The `Broadcaster` service of a listener
+struct Service {
+    listener: Arc<Listener>,
+    magic: Magic,
+}
+
+#[tonic::async_trait]
+impl Broadcaster for Service {
+    async fn submit(
+        &self,
+        request: Request<proto::SubmitRequest>,
+    ) -> Result<Response<proto::SubmitResponse>, Status> {
+        let (magic, txs) = submitted(request.into_inner(), self.magic)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let event_id = self
+            .listener
+            .publish_txs(magic, &txs)
+            .await
+            .map_err(|e| Status::unavailable(e.to_string()))?;
+        info!(%event_id, txs = txs.len(), "Published submitted txs");
+        Ok(Response::new(proto::SubmitResponse {
+            event_id: event_id.to_hex(),
+            txids: txs.iter().map(|tx| tx.txid().to_string()).collect(),
+        }))
+    }
+
+    type GetStatusStream = Pin<Box<dyn Stream<Item = Result<proto::Status, Status>> + Send>>;
+
+    async fn get_status(
+        &self,
+        request: Request<proto::GetStatusRequest>,
+    ) -> Result<Response<Self::GetStatusStream>, Status> {
+        let interval = Duration::from_secs(request.into_inner().interval_secs.into());
+        let listener = self.listener.clone();
+        let updates = stream::unfold(true, move |first| {
+            let listener = listener.clone();
+            async move {
+                if !first {
+                    if interval.is_zero() {
+                        return None;
+                    }
+                    tokio::time::sleep(interval).await;
+                }
+                Some((Ok(status(&listener.health().await)), false))
+            }
+        });
+        Ok(Response::new(Box::pin(updates)))
+    }
+
+    async fn list_recent(
+        &self,
+        request: Request<proto::ListRecentRequest>,
+    ) -> Result<Response<proto::ListRecentResponse>, Status> {
+        let limit = match request.into_inner().limit {
+            0 => usize::MAX,
+            n => n as usize,
+        };
+        Ok(Response::new(proto::ListRecentResponse {
+            received: self
+                .listener
+                .recent_received()
+                .iter()
+                .take(limit)
+                .map(|(at, received)| received_message(*at, received))
+                .collect(),
+            outcomes: self
+                .listener
+                .recent_outcomes()
+                .iter()
+                .take(limit)
+                .map(|(at, outcome)| outcome_message(*at, outcome))
+                .collect(),
+        }))
+    }
+
+    async fn rebroadcast_txid(
+        &self,
+        request: Request<proto::RebroadcastTxidRequest>,
+    ) -> Result<Response<proto::RebroadcastTxidResponse>, Status> {
+        let txid = parse_txid(&request.into_inner().txid)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        if !self
+            .listener
+            .rebroadcasting()
+            .iter()
+            .any(|tracked| tracked.txid == txid)
+        {
+            return Err(Status::not_found(format!("Not following tx {txid}")));
+        }
+
+        let results = self
+            .listener
+            .rebroadcast_txid(&txid)
+            .await
+            .map_err(|e| Status::unavailable(e.to_string()))?;
+        let results = results
+            .into_iter()
+            .map(|PackageTxResult { txid, error }| {
+                let result = match error {
+                    None => TxResult::accepted(txid, None, None),
+                    Some(e) => TxResult::failed(txid, ResultStatus::Rejected, e, None),
+                };
+                tx_result(&result)
+            })
+            .collect();
+        Ok(Response::new(proto::RebroadcastTxidResponse { results }))
+    }
+}
+
+/// The network and transactions of a submission, of `default_magic` unless it names a network
+fn submitted(
+    request: proto::SubmitRequest,
+    default_magic: Magic,
+) -> anyhow::Result<(Magic, Vec<Transaction>)> {
+    let magic = match request.network.as_str() {
+        "" => default_magic,
+        network => protocol::parse_magic(network)?,
+    };
+    let txs = request
+        .txs
+        .iter()
+        .map(|tx| deserialize(tx))
+        .collect::<Result<Vec<Transaction>, _>>()?;
+    if txs.is_empty() {
+        bail!("No transactions");
+    }
+    Ok((magic, txs))
+}
+
+fn parse_txid(txid: &str) -> anyhow::Result<Txid> {
+    if txid.is_empty() {
+        bail!("No txid");
+    }
+    Ok(Txid::from_str(txid)?)
+}
+
+fn status(health: &Health) -> proto::Status {
+    proto::Status {
+        healthy: health.healthy,
+        paused: health.paused,
+        relays: health
+            .relays
+            .iter()
+            .map(|relay| proto::Relay {
+                url: relay.url.clone(),
+                status: relay.status.clone(),
+                connected: relay.connected,
+                score: relay.score,
+                demoted: relay.demoted,
+            })
+            .collect(),
+        backends: health
+            .backends
+            .iter()
+            .map(|backend| proto::Backend {
+                magic: backend.magic.clone(),
+                backend: backend.backend.clone(),
+                reachable: backend.reachable,
+                error: backend.error.clone().unwrap_or_default(),
+            })
+            .collect(),
+        last_broadcast_secs: health.last_broadcast_secs,
+    }
+}
+
+fn received_message(at: Timestamp, received: &ReceivedTxs) -> proto::Received {
+    proto::Received {
+        event_id: received.event_id.to_hex(),
+        author: received.author.to_string(),
+        relay: received.relay.to_string(),
+        magic: received.magic.to_string(),
+        txids: received
+            .txs
+            .iter()
+            .map(|tx| tx.txid().to_string())
+            .collect(),
+        at: at.as_u64(),
+        kind: received.kind,
+        encoding: received.encoding.as_str().to_string(),
+    }
+}
+
+fn outcome_message(at: Timestamp, outcome: &Outcome) -> proto::Outcome {
+    proto::Outcome {
+        event_id: outcome.event_id.to_hex(),
+        author: outcome.author.to_string(),
+        results: outcome.results.iter().map(tx_result).collect(),
+        at: at.as_u64(),
+    }
+}
+
+fn tx_result(result: &TxResult) -> proto::TxResult {
+    proto::TxResult {
+        txid: result.txid.to_string(),
+        accepted: result.accepted,
+        error: result.error.clone().unwrap_or_default(),
+        status: result.status.as_str().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health::{BackendHealth, RelayHealth};
+    use bitcoin::absolute::LockTime;
+    use bitcoin::consensus::serialize;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{OutPoint, ScriptBuf, TxIn, TxOut};
+    use prost::Message;
+
+    /// A transaction spending output `vout` of a made up transaction
+    fn tx(vout: u32) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::all_zeros(), vout),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: 10_000,
+                script_pubkey: ScriptBuf::new(),
+            }],
+        }
+    }
+
+    #[test]
+    fn submitted_txs() {
+        let request = proto::SubmitRequest {
+            txs: vec![serialize(&tx(0)), serialize(&tx(1))],
+            network: String::new(),
+        };
+        let (magic, txs) = submitted(request, Magic::BITCOIN).unwrap();
+        assert_eq!(magic, Magic::BITCOIN);
+        assert_eq!(txs, vec![tx(0), tx(1)]);
+
+        let request = proto::SubmitRequest {
+            txs: vec![serialize(&tx(0))],
+            network: "testnet".to_string(),
+        };
+        let (magic, _) = submitted(request, Magic::BITCOIN).unwrap();
+        assert_eq!(magic, Magic::TESTNET);
+    }
+
+    #[test]
+    fn invalid_submissions() {
+        let invalid = [
+            proto::SubmitRequest::default(),
+            proto::SubmitRequest {
+                txs: vec![vec![2, 0, 0]],
+                network: String::new(),
+            },
+            proto::SubmitRequest {
+                txs: vec![serialize(&tx(0))],
+                network: "nonet".to_string(),
+            },
+        ];
+        for request in invalid {
+            assert!(submitted(request, Magic::BITCOIN).is_err());
+        }
+    }
+
+    #[test]
+    fn txids() {
+        let txid = tx(0).txid();
+        assert_eq!(parse_txid(&txid.to_string()).unwrap(), txid);
+        for invalid in ["", "00", "not a txid"] {
+            assert!(parse_txid(invalid).is_err());
+        }
+    }
+
+    #[test]
+    fn status_message() {
+        let health = Health {
+            healthy: false,
+            paused: true,
+            relays: vec![RelayHealth {
+                url: "wss://relay.example/".to_string(),
+                status: "Connected".to_string(),
+                role: "read_write".to_string(),
+                connected: true,
+                score: 0.5,
+                demoted: false,
+                connect_failures: 0,
+                latency_secs: None,
+                duplicate_ratio: 0.0,
+            }],
+            backends: vec![BackendHealth {
+                magic: Magic::BITCOIN.to_string(),
+                backend: "bitcoind".to_string(),
+                reachable: false,
+                error: Some("connection refused".to_string()),
+            }],
+            last_broadcast_secs: Some(0),
+        };
+        let message = status(&health);
+        assert!(message.paused && !message.healthy);
+        assert_eq!(message.relays[0].url, "wss://relay.example/");
+        assert_eq!(message.relays[0].score, 0.5);
+        assert_eq!(message.backends[0].error, "connection refused");
+
+        // an optional 0 is still sent, unlike a missing last broadcast
+        let decoded = proto::Status::decode(message.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(decoded.last_broadcast_secs, Some(0));
+        let never = proto::Status {
+            last_broadcast_secs: None,
+            ..message
+        };
+        let decoded = proto::Status::decode(never.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(decoded.last_broadcast_secs, None);
+    }
+
+    #[test]
+    fn tx_results() {
+        let txid = tx(0).txid();
+        let result = tx_result(&TxResult::failed(
+            txid,
+            ResultStatus::Rejected,
+            "bad-txns-inputs-missingorspent",
+            None,
+        ));
+        assert_eq!(result.txid, txid.to_string());
+        assert!(!result.accepted);
+        assert_eq!(result.error, "bad-txns-inputs-missingorspent");
+        assert_eq!(result.status, ResultStatus::Rejected.as_str());
+
+        let result = tx_result(&TxResult::accepted(txid, None, None));
+        assert!(result.accepted && result.error.is_empty());
+    }
+}
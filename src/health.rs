@@ -0,0 +1,57 @@
+//! The health of a listener: its relay connections, whether its backends are reachable and
+//! when it last broadcast successfully.
+
+use serde::{Deserialize, Serialize};
+
+/// Health report of a listener
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Health {
+    /// Connected to at least one relay, and all backends are reachable
+    pub healthy: bool,
+    /// Broadcasting is paused
+    pub paused: bool,
+    /// The relays
+    pub relays: Vec<RelayHealth>,
+    /// The backends
+    pub backends: Vec<BackendHealth>,
+    /// Seconds since the transactions of an event were last broadcast successfully
+    pub last_broadcast_secs: Option<u64>,
+}
+
+/// Connection status of a relay
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RelayHealth {
+    /// The relay
+    pub url: String,
+    /// Connected, Connecting, Disconnected, …
+    pub status: String,
+    /// read_write, read or write, see [`RelayRole`](crate::listener::RelayRole)
+    #[serde(default)]
+    pub role: String,
+    /// Whether it's connected
+    pub connected: bool,
+    /// Between 0 and 1, see [`RelayStats::score`](crate::scoring::RelayStats::score)
+    pub score: f64,
+    /// Whether it's out of use for scoring too low
+    pub demoted: bool,
+    /// Failed attempts to connect
+    pub connect_failures: u32,
+    /// Average delay in seconds between the creation of the events and their arrival
+    pub latency_secs: Option<f64>,
+    /// Share of the events another relay delivered first
+    pub duplicate_ratio: f64,
+}
+
+/// Reachability of a backend
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackendHealth {
+    /// Magic of the network it serves
+    pub magic: String,
+    /// Name of the backend
+    pub backend: String,
+    /// Whether its health check passed
+    pub reachable: bool,
+    /// Why it didn't
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
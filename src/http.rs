@@ -0,0 +1,247 @@
+//! The HTTP server exposing the metrics and the health of a listener, and optionally the admin
+//! endpoints controlling it and the endpoints publishing transactions for wallets.
+
+use anyhow::bail;
+use axum::extract::{Query, Request, State};
+use axum::http::header::AUTHORIZATION;
+use axum::http::StatusCode;
+use axum::middleware::{self, Next};
+use axum::response::Response;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use bitcoin::network::Magic;
+use bitcoin::Transaction;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::info;
+
+use crate::health::{Health, RelayHealth};
+use crate::protocol;
+use crate::Listener;
+
+/// Serve `/metrics` and `/healthz` of `listener` on `addr` until an error occurs, and with
+/// `admin` the endpoints under `/admin`:
+///
+/// - `GET /admin/relays`: the relays and their status
+/// - `POST /admin/relays` with `{"url": "wss://…"}`: start using a relay
+/// - `DELETE /admin/relays` with `{"url": "wss://…"}`: stop using a relay
+/// - `POST /admin/pause` and `POST /admin/resume`: stop and restart broadcasting
+///
+/// With `submit`, the network of submitted transactions unless `?network=` says otherwise:
+///
+/// - `POST /tx` with a raw transaction in hex: publish it as a transaction event
+/// - `POST /package` with a JSON array of raw transactions in hex: publish them as a package
+///
+/// With `token`, the admin and submitting endpoints require it as a bearer token. Without it
+/// they may only be served on a loopback address
+pub async fn serve(
+    addr: SocketAddr,
+    listener: Arc<Listener>,
+    admin: bool,
+    submit: Option<Magic>,
+    token: Option<String>,
+) -> anyhow::Result<()> {
+    if (admin || submit.is_some()) && token.is_none() && !addr.ip().is_loopback() {
+        bail!("The admin and submitting endpoints need a token on {addr}, give --http-token");
+    }
+    let mut protected = Router::new();
+    if admin {
+        protected = protected
+            .route(
+                "/admin/relays",
+                get(relays).post(add_relay).delete(remove_relay),
+            )
+            .route("/admin/pause", post(pause))
+            .route("/admin/resume", post(resume));
+    }
+    if let Some(magic) = submit {
+        protected = protected
+            .route(
+                "/tx",
+                post(move |listener, network, body: String| {
+                    submit_txs(listener, network, magic, vec![body])
+                }),
+            )
+            .route(
+                "/package",
+                post(move |listener, network, Json(txs): Json<Vec<String>>| {
+                    submit_txs(listener, network, magic, txs)
+                }),
+            );
+    }
+    if let Some(token) = token.filter(|_| admin || submit.is_some()) {
+        let token: Arc<str> = token.into();
+        protected = protected.route_layer(middleware::from_fn(move |request, next| {
+            authorize(token.clone(), request, next)
+        }));
+    }
+    let app = Router::new()
+        .route("/metrics", get(metrics))
+        .route("/healthz", get(healthz))
+        .merge(protected)
+        .with_state(listener);
+
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    info!("Serving metrics and health on http://{addr}");
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+/// Pass on requests with `token` as their bearer token, answer the others 401
+async fn authorize(token: Arc<str>, request: Request, next: Next) -> Result<Response, StatusCode> {
+    let given = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match given {
+        Some(given) if constant_time_eq(given.as_bytes(), token.as_bytes()) => {
+            Ok(next.run(request).await)
+        }
+        _ => Err(StatusCode::UNAUTHORIZED),
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+async fn metrics(State(listener): State<Arc<Listener>>) -> Result<String, (StatusCode, String)> {
+    listener
+        .metrics()
+        .render()
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+/// 200 when healthy, 503 otherwise, with the details either way
+async fn healthz(State(listener): State<Arc<Listener>>) -> (StatusCode, Json<Health>) {
+    let health = listener.health().await;
+    let status = if health.healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(health))
+}
+
+/// Body of the requests adding and removing relays
+#[derive(Deserialize)]
+struct RelayRequest {
+    url: String,
+}
+
+async fn relays(State(listener): State<Arc<Listener>>) -> Json<Vec<RelayHealth>> {
+    Json(listener.relays().await)
+}
+
+async fn add_relay(
+    State(listener): State<Arc<Listener>>,
+    Json(request): Json<RelayRequest>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    listener
+        .add_relay(&request.url)
+        .await
+        .map(|()| StatusCode::NO_CONTENT)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))
+}
+
+async fn remove_relay(
+    State(listener): State<Arc<Listener>>,
+    Json(request): Json<RelayRequest>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    listener
+        .remove_relay(&request.url)
+        .await
+        .map(|()| StatusCode::NO_CONTENT)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))
+}
+
+async fn pause(State(listener): State<Arc<Listener>>) -> StatusCode {
+    listener.pause();
+    StatusCode::NO_CONTENT
+}
+
+async fn resume(State(listener): State<Arc<Listener>>) -> StatusCode {
+    listener.resume();
+    StatusCode::NO_CONTENT
+}
+
+/// Query of the submitting endpoints
+#[derive(Deserialize)]
+struct SubmitQuery {
+    /// Network name or magic, instead of the default
+    network: Option<String>,
+}
+
+/// Publish `txs` as a transaction event, answering its id and the txids
+async fn submit_txs(
+    State(listener): State<Arc<Listener>>,
+    Query(query): Query<SubmitQuery>,
+    default_magic: Magic,
+    txs: Vec<String>,
+) -> Result<Json<Value>, (StatusCode, String)> {
+    let bad_request = |e: anyhow::Error| (StatusCode::BAD_REQUEST, e.to_string());
+    let magic = match query.network {
+        Some(network) => protocol::parse_magic(&network).map_err(bad_request)?,
+        None => default_magic,
+    };
+    let txs = txs
+        .iter()
+        .map(|tx| protocol::parse_tx_hex(tx))
+        .collect::<anyhow::Result<Vec<Transaction>>>()
+        .map_err(bad_request)?;
+    if txs.is_empty() {
+        return Err(bad_request(anyhow::anyhow!("No transactions")));
+    }
+
+    let event_id = listener
+        .publish_txs(magic, &txs)
+        .await
+        .map_err(|e| (StatusCode::BAD_GATEWAY, e.to_string()))?;
+    info!(%event_id, txs = txs.len(), "Published submitted txs");
+    Ok(Json(json!({
+        "event_id": event_id.to_hex(),
+        "txids": txs.iter().map(|tx| tx.txid().to_string()).collect::<Vec<_>>(),
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn bearer_token() {
+        let token: Arc<str> = "secret".into();
+        let app = Router::new()
+            .route("/admin/pause", post(|| async { StatusCode::NO_CONTENT }))
+            .route_layer(middleware::from_fn(move |request, next| {
+                authorize(token.clone(), request, next)
+            }))
+            .route("/healthz", get(|| async { "ok" }));
+        let tcp = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", tcp.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(tcp, app).await });
+
+        let client = reqwest::Client::new();
+        let pause = |auth: Option<&str>| {
+            let mut request = client.post(format!("{url}/admin/pause"));
+            if let Some(auth) = auth {
+                request = request.header("Authorization", auth);
+            }
+            async move { request.send().await.unwrap().status().as_u16() }
+        };
+        assert_eq!(pause(Some("Bearer secret")).await, 204);
+        for auth in [
+            None,
+            Some("Bearer secre"),
+            Some("Bearer secret2"),
+            Some("secret"),
+        ] {
+            assert_eq!(pause(auth).await, 401);
+        }
+        let health = client.get(format!("{url}/healthz")).send().await.unwrap();
+        assert!(health.status().is_success());
+    }
+}
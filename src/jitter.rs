@@ -0,0 +1,127 @@
+//! Delays before broadcasting: random ones, so the time a transaction shows up in the mempool of
+//! the broadcaster's node says less about when it was seen on the relays, and fixed ones per
+//! broadcaster and transaction, so broadcasters listening to the same relays take turns.
+
+use anyhow::{bail, Context};
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::Txid;
+use nostr::prelude::XOnlyPublicKey;
+use rand::Rng;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// A range to draw the delays from uniformly
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Jitter {
+    /// Shortest delay
+    pub min: Duration,
+    /// Longest delay
+    pub max: Duration,
+}
+
+impl Jitter {
+    /// A random delay in the range
+    pub fn sample(&self) -> Duration {
+        match self.min < self.max {
+            true => rand::thread_rng().gen_range(self.min..=self.max),
+            false => self.min,
+        }
+    }
+}
+
+/// `MIN-MAX` or `MAX` in seconds, fractions allowed, such as `0.5-30`
+impl FromStr for Jitter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (min, max) = match s.split_once('-') {
+            Some((min, max)) => (parse_secs(min)?, parse_secs(max)?),
+            None => (Duration::ZERO, parse_secs(s)?),
+        };
+        if min > max {
+            bail!("invalid jitter {s}, the minimum is over the maximum");
+        }
+        Ok(Self { min, max })
+    }
+}
+
+/// The delay of the broadcaster `pubkey` for the transactions `txids`, up to `max`. Derived from
+/// both, so every broadcaster has its own and which one comes first differs from one submission
+/// to the next
+pub fn cooperative_delay(pubkey: &XOnlyPublicKey, txids: &[Txid], max: Duration) -> Duration {
+    let mut data = pubkey.serialize().to_vec();
+    for txid in txids {
+        data.extend_from_slice(txid.as_byte_array());
+    }
+    let hash = sha256::Hash::hash(&data);
+    let mut first = [0; 8];
+    first.copy_from_slice(&hash.as_byte_array()[..8]);
+    let millis = u64::from_le_bytes(first) % (max.as_millis() as u64 + 1);
+    Duration::from_millis(millis)
+}
+
+fn parse_secs(s: &str) -> anyhow::Result<Duration> {
+    let secs: f64 = s
+        .trim()
+        .parse()
+        .with_context(|| format!("invalid number of seconds {s}"))?;
+    Duration::try_from_secs_f64(secs).with_context(|| format!("invalid number of seconds {s}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr::Keys;
+
+    #[test]
+    fn parse() {
+        let jitter: Jitter = "0.5-30".parse().unwrap();
+        assert_eq!(jitter.min, Duration::from_millis(500));
+        assert_eq!(jitter.max, Duration::from_secs(30));
+        let jitter: Jitter = "10".parse().unwrap();
+        assert_eq!(
+            (jitter.min, jitter.max),
+            (Duration::ZERO, Duration::from_secs(10))
+        );
+        assert_eq!(
+            " 1 - 2 ".parse::<Jitter>().unwrap().max,
+            Duration::from_secs(2)
+        );
+
+        assert!("30-10".parse::<Jitter>().is_err());
+        assert!("-1".parse::<Jitter>().is_err());
+        assert!("a-b".parse::<Jitter>().is_err());
+        assert!("".parse::<Jitter>().is_err());
+    }
+
+    #[test]
+    fn sample_in_range() {
+        let jitter: Jitter = "1-2".parse().unwrap();
+        for _ in 0..100 {
+            let delay = jitter.sample();
+            assert!(delay >= jitter.min && delay <= jitter.max);
+        }
+        let fixed: Jitter = "3-3".parse().unwrap();
+        assert_eq!(fixed.sample(), Duration::from_secs(3));
+    }
+
+    #[test]
+    fn cooperative_delays() {
+        let max = Duration::from_secs(86_400);
+        let first = Keys::generate().public_key();
+        let second = Keys::generate().public_key();
+        let txids = [Txid::all_zeros()];
+        let delay = cooperative_delay(&first, &txids, max);
+        assert!(delay <= max);
+        // the same every time
+        assert_eq!(cooperative_delay(&first, &txids, max), delay);
+        // but not for another broadcaster or other txs
+        assert_ne!(cooperative_delay(&second, &txids, max), delay);
+        let other = [Txid::from_byte_array([1; 32])];
+        assert_ne!(cooperative_delay(&first, &other, max), delay);
+        assert_eq!(
+            cooperative_delay(&first, &txids, Duration::ZERO),
+            Duration::ZERO
+        );
+    }
+}
@@ -0,0 +1,58 @@
+//! The nostr identity of the broadcaster, kept across runs so senders can encrypt to it and
+//! recognize its replies.
+
+use anyhow::Context;
+use bip39::Mnemonic;
+use bitcoin::bip32::{DerivationPath, ExtendedPrivKey};
+use bitcoin::Network;
+use nostr::prelude::*;
+use nostr::SECP256K1;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+/// The NIP-06 derivation path of the first nostr key of a seed
+pub const NIP06_PATH: &str = "m/44'/1237'/0'/0/0";
+
+/// Derive the keys at `path` of the seed of a BIP39 `mnemonic` (English words) and
+/// `passphrase`, as NIP-06 does with [`NIP06_PATH`]
+pub fn from_mnemonic(
+    mnemonic: &str,
+    passphrase: &str,
+    path: &DerivationPath,
+) -> anyhow::Result<Keys> {
+    let mnemonic = Mnemonic::parse_normalized(mnemonic.trim())?;
+    let seed = mnemonic.to_seed_normalized(passphrase);
+    let root = ExtendedPrivKey::new_master(Network::Bitcoin, &seed)?;
+    let child = root.derive_priv(SECP256K1, path)?;
+    Ok(Keys::new(child.private_key))
+}
+
+/// Parse a secret key, as nsec or hex
+pub fn parse_secret_key(s: &str) -> anyhow::Result<Keys> {
+    Ok(Keys::from_sk_str(s.trim())?)
+}
+
+/// Read the secret key in the file at `path`, or when there's no such file create it with a new
+/// key, readable only by its owner
+pub fn load_or_create(path: &Path) -> anyhow::Result<Keys> {
+    match fs::read_to_string(path) {
+        Ok(contents) => parse_secret_key(&contents)
+            .with_context(|| format!("Invalid secret key in {}", path.display())),
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            let keys = Keys::generate();
+            let mut options = OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            options.mode(0o600);
+            let mut file = options
+                .open(path)
+                .with_context(|| format!("Creating key file {}", path.display()))?;
+            writeln!(file, "{}", keys.secret_key()?.to_bech32()?)?;
+            Ok(keys)
+        }
+        Err(e) => Err(e).with_context(|| format!("Reading key file {}", path.display())),
+    }
+}
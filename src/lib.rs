@@ -0,0 +1,56 @@
+//! Listen to nostr relays for bitcoin transaction events and broadcast them to the bitcoin
+//! network.
+//!
+//! An implementation of <https://github.com/nostr-protocol/nips/pull/476>.
+
+pub mod backend;
+pub mod bridge;
+pub mod cashu;
+pub mod compression;
+pub mod config;
+pub mod conflicts;
+#[cfg(unix)]
+pub mod console;
+#[cfg(unix)]
+pub mod daemon;
+pub mod dashboard;
+pub mod dvm;
+pub mod event_parse;
+pub mod grpc;
+pub mod health;
+pub mod http;
+pub mod jitter;
+pub mod keys;
+pub mod lightning;
+pub mod listener;
+pub mod lnurl;
+pub mod local_relay;
+pub mod metrics;
+pub mod nip05;
+pub mod nip17;
+pub mod nip44;
+pub mod nip57;
+pub mod nip65;
+pub mod output;
+pub mod package;
+pub mod payment;
+pub mod policy;
+pub mod protocol;
+pub mod proxy;
+pub mod pubkeys;
+pub mod ratelimit;
+pub mod reassembly;
+pub mod reconnect;
+pub mod relays;
+pub mod reputation;
+pub mod scoring;
+pub mod seen;
+pub mod send;
+pub mod store;
+#[cfg(unix)]
+pub mod systemd;
+pub mod tracker;
+pub mod zmq;
+
+pub use backend::{Backends, TxBackend};
+pub use listener::{Listener, ListenerConfig, Outcome, ReceivedTxs};
@@ -0,0 +1,135 @@
+use anyhow::{anyhow, bail, Context};
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use super::{Invoice, InvoiceBackend};
+
+/// How long Core Lightning may take to answer
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The JSON-RPC unix socket of a Core Lightning node, `lightning-rpc` in its network directory
+#[derive(Debug)]
+pub struct Cln {
+    socket: PathBuf,
+    name: String,
+    next_id: AtomicU64,
+}
+
+#[derive(Deserialize)]
+struct InvoiceResponse {
+    bolt11: String,
+    payment_hash: String,
+}
+
+#[derive(Deserialize)]
+struct ListInvoicesResponse {
+    invoices: Vec<ListedInvoice>,
+}
+
+#[derive(Deserialize)]
+struct ListedInvoice {
+    status: String,
+}
+
+impl Cln {
+    /// The node listening on the socket at `socket`
+    pub fn new(socket: &Path) -> Self {
+        Self {
+            socket: socket.to_path_buf(),
+            name: socket.display().to_string(),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Call `method` with `params`, returning its result
+    async fn call(&self, method: &str, params: Value) -> anyhow::Result<Value> {
+        tokio::time::timeout(TIMEOUT, self.call_unlimited(method, params))
+            .await
+            .map_err(|_| anyhow!("Core Lightning didn't answer {method} in time"))?
+    }
+
+    #[cfg(unix)]
+    async fn call_unlimited(&self, method: &str, params: Value) -> anyhow::Result<Value> {
+        let mut stream = tokio::net::UnixStream::connect(&self.socket)
+            .await
+            .with_context(|| format!("Error connecting to {}", self.socket.display()))?;
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": self.next_id.fetch_add(1, Ordering::Relaxed),
+            "method": method,
+            "params": params,
+        });
+        stream.write_all(request.to_string().as_bytes()).await?;
+
+        // the response is a single JSON object, read until it parses
+        let mut response = vec![];
+        let mut buf = [0; 4096];
+        let response: Value = loop {
+            let read = stream.read(&mut buf).await?;
+            if read == 0 {
+                bail!("Core Lightning closed the connection");
+            }
+            response.extend_from_slice(&buf[..read]);
+            if let Ok(value) = serde_json::from_slice(&response) {
+                break value;
+            }
+        };
+        if let Some(error) = response.get("error") {
+            bail!("Core Lightning {method} failed: {error}");
+        }
+        response
+            .get("result")
+            .cloned()
+            .ok_or_else(|| anyhow!("Core Lightning answered {method} without result"))
+    }
+
+    #[cfg(not(unix))]
+    async fn call_unlimited(&self, _method: &str, _params: Value) -> anyhow::Result<Value> {
+        bail!("Core Lightning's socket is only supported on unix")
+    }
+}
+
+#[async_trait]
+impl InvoiceBackend for Cln {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn create_invoice(
+        &self,
+        msat: u64,
+        label: &str,
+        description: &str,
+        expiry: Duration,
+    ) -> anyhow::Result<Invoice> {
+        let params = json!({
+            "amount_msat": msat,
+            "label": label,
+            "description": description,
+            "expiry": expiry.as_secs(),
+        });
+        let result = self.call("invoice", params).await?;
+        let invoice: InvoiceResponse =
+            serde_json::from_value(result).context("Invalid invoice from Core Lightning")?;
+        Ok(Invoice {
+            bolt11: invoice.bolt11,
+            payment_hash: invoice.payment_hash,
+        })
+    }
+
+    async fn is_paid(&self, invoice: &Invoice) -> anyhow::Result<bool> {
+        let params = json!({ "payment_hash": invoice.payment_hash });
+        let result = self.call("listinvoices", params).await?;
+        let listed: ListInvoicesResponse =
+            serde_json::from_value(result).context("Invalid invoices from Core Lightning")?;
+        Ok(listed
+            .invoices
+            .iter()
+            .any(|invoice| invoice.status == "paid"))
+    }
+}
@@ -0,0 +1,114 @@
+use anyhow::{bail, Context};
+use async_trait::async_trait;
+use base64::{engine::general_purpose, Engine as _};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use super::{Invoice, InvoiceBackend};
+
+/// How long LND may take to answer
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The REST API of an LND node
+#[derive(Debug, Clone)]
+pub struct Lnd {
+    url: String,
+    /// Hex of the macaroon, an invoice macaroon is enough
+    macaroon: String,
+    client: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+struct AddInvoiceResponse {
+    r_hash: String,
+    payment_request: String,
+}
+
+#[derive(Deserialize)]
+struct LookupInvoiceResponse {
+    state: String,
+}
+
+impl Lnd {
+    /// The API at `url`, e.g. `https://localhost:8080`, authenticated by the macaroon file at
+    /// `macaroon`, trusting the certificate at `tls_cert` besides the usual roots
+    pub fn new(url: &str, macaroon: &Path, tls_cert: Option<&Path>) -> anyhow::Result<Self> {
+        let macaroon = fs::read(macaroon)
+            .with_context(|| format!("Error reading macaroon {}", macaroon.display()))?;
+        let mut client = reqwest::Client::builder().timeout(TIMEOUT);
+        if let Some(tls_cert) = tls_cert {
+            let pem = fs::read(tls_cert)
+                .with_context(|| format!("Error reading TLS certificate {}", tls_cert.display()))?;
+            client = client.add_root_certificate(reqwest::Certificate::from_pem(&pem)?);
+        }
+        Ok(Self {
+            url: url.trim_end_matches('/').to_string(),
+            macaroon: macaroon.iter().map(|b| format!("{b:02x}")).collect(),
+            client: client.build()?,
+        })
+    }
+
+    async fn request(&self, request: reqwest::RequestBuilder) -> anyhow::Result<String> {
+        let response = request
+            .header("Grpc-Metadata-macaroon", &self.macaroon)
+            .send()
+            .await?;
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            bail!("LND answered {status}: {body}");
+        }
+        Ok(body)
+    }
+}
+
+#[async_trait]
+impl InvoiceBackend for Lnd {
+    fn name(&self) -> &str {
+        &self.url
+    }
+
+    async fn create_invoice(
+        &self,
+        msat: u64,
+        _label: &str,
+        description: &str,
+        expiry: Duration,
+    ) -> anyhow::Result<Invoice> {
+        let body = serde_json::json!({
+            "value_msat": msat.to_string(),
+            "memo": description,
+            "expiry": expiry.as_secs().to_string(),
+        });
+        let response = self
+            .request(
+                self.client
+                    .post(format!("{}/v1/invoices", self.url))
+                    .body(body.to_string()),
+            )
+            .await?;
+        let invoice: AddInvoiceResponse =
+            serde_json::from_str(&response).context("Invalid invoice from LND")?;
+        let hash = general_purpose::STANDARD
+            .decode(&invoice.r_hash)
+            .context("Invalid payment hash from LND")?;
+        Ok(Invoice {
+            bolt11: invoice.payment_request,
+            payment_hash: hash.iter().map(|b| format!("{b:02x}")).collect(),
+        })
+    }
+
+    async fn is_paid(&self, invoice: &Invoice) -> anyhow::Result<bool> {
+        let response = self
+            .request(
+                self.client
+                    .get(format!("{}/v1/invoice/{}", self.url, invoice.payment_hash)),
+            )
+            .await?;
+        let invoice: LookupInvoiceResponse =
+            serde_json::from_str(&response).context("Invalid invoice from LND")?;
+        Ok(invoice.state == "SETTLED")
+    }
+}
@@ -0,0 +1,40 @@
+//! Lightning nodes making the invoices broadcasts are paid with, and telling when they're paid,
+//! without relying on zap receipts.
+
+use async_trait::async_trait;
+use std::fmt::Debug;
+use std::time::Duration;
+
+mod cln;
+mod lnd;
+
+pub use cln::Cln;
+pub use lnd::Lnd;
+
+/// An invoice of a node
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invoice {
+    /// The BOLT11 invoice to pay
+    pub bolt11: String,
+    /// Hex payment hash, identifying the invoice at the node
+    pub payment_hash: String,
+}
+
+/// A lightning node that makes invoices
+#[async_trait]
+pub trait InvoiceBackend: Debug + Send + Sync {
+    /// Short name of the node, used in logs
+    fn name(&self) -> &str;
+
+    /// Make an invoice over `msat` with `description`, unique `label` and `expiry`
+    async fn create_invoice(
+        &self,
+        msat: u64,
+        label: &str,
+        description: &str,
+        expiry: Duration,
+    ) -> anyhow::Result<Invoice>;
+
+    /// Whether `invoice` was paid
+    async fn is_paid(&self, invoice: &Invoice) -> anyhow::Result<bool>;
+}
@@ -0,0 +1,2790 @@
+//! Listening to relays for transaction events.
+
+use anyhow::{bail, Context};
+use bitcoin::network::Magic;
+use bitcoin::{Transaction, Txid};
+use futures_util::future::join_all;
+use lru::LruCache;
+use nostr::prelude::*;
+use nostr_sdk::relay::pool::RelayPoolNotification;
+use nostr_sdk::relay::{Relay, RelayOptions, RelayStatus};
+use nostr_sdk::Client;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::{self, Future};
+use std::net::SocketAddr;
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc, watch, Notify};
+use tokio::time::{Interval, MissedTickBehavior};
+use tracing::{debug, error, info, warn};
+
+use crate::backend::{self, Backends, PackageTxResult, TxBackend, TxStatus};
+use crate::cashu::Wallet;
+use crate::conflicts::{self, Conflict, Spends};
+use crate::dvm::{self, DvmConfig, JobStatus};
+use crate::event_parse::{self, Encoding};
+use crate::health::{BackendHealth, Health, RelayHealth};
+use crate::jitter::{self, Jitter};
+use crate::lightning::Invoice;
+use crate::lnurl;
+use crate::local_relay::LocalRelay;
+use crate::metrics::Metrics;
+use crate::nip05::{Nip05Config, Verifier};
+use crate::nip17;
+use crate::nip57;
+use crate::package;
+use crate::payment::PaymentConfig;
+use crate::policy::Policy;
+use crate::protocol::{
+    self, Announcement, Chunk, ParentRequest, ResultStatus, TxConfirmation, TxEventOptions,
+    TxFailure, TxResult,
+};
+use crate::pubkeys::Denylist;
+use crate::ratelimit::{DailyQuota, RateLimit, RateLimiter};
+use crate::reassembly::{Progress, Reassembly};
+use crate::reconnect::{Action, Keepalive, ReconnectConfig, RelayConnection};
+use crate::relays::{self, parse_relay};
+use crate::reputation::{Conduct, Reputation, ReputationConfig};
+use crate::scoring::ScoringConfig;
+use crate::seen::SeenCache;
+use crate::store::Store;
+use crate::tracker::{Confirmation, Failure, TrackedTx, Tracker, TrackerConfig, Update};
+
+/// How often the relay connections are checked
+const RELAY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Subscription id of the pings of quiet relays
+const PING_SUBSCRIPTION: &str = "ping";
+
+/// How often a backend in its initial block download is asked whether it's done
+const SYNC_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Most ancestors looked up for the txs missing inputs, a package has at most 25 txs
+const MAX_ANCESTORS: usize = 24;
+
+/// How often the lightning node is asked whether the invoices of unpaid submissions were paid
+const INVOICE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How many received events and outcomes are kept for [`Listener::recent_received`] and
+/// [`Listener::recent_outcomes`]
+const RECENT: usize = 50;
+
+/// Configuration of a [`Listener`]
+#[derive(Debug, Clone)]
+pub struct ListenerConfig {
+    /// Relays to subscribe to
+    pub relays: Vec<String>,
+    /// Roles of the relays only read from or only written to, the others are used for both
+    pub relay_roles: HashMap<Url, RelayRole>,
+    /// Relay embedded in the broadcaster whose events are handled like those of the relays, and
+    /// which gets the replies too, see [`local_relay::serve`](crate::local_relay::serve)
+    pub local_relay: Option<Arc<LocalRelay>>,
+    /// Event kinds of the transaction events to handle, the first one is published, see
+    /// [`protocol::BITCOIN_TX_KIND`]
+    pub kinds: Vec<u64>,
+    /// SOCKS5 proxy the relay connections go through, such as Tor
+    pub proxy: Option<SocketAddr>,
+    /// How relays that drop are reconnected
+    pub reconnect: ReconnectConfig,
+    /// When unhealthy relays are demoted, `None` to keep using every relay
+    pub scoring: Option<ScoringConfig>,
+    /// Where received transactions are broadcast to, by network. Transactions of other networks
+    /// are ignored
+    pub backends: Backends,
+    /// Don't broadcast transactions that fail the backend's dry run, instead of only logging why
+    /// they would be rejected
+    pub skip_rejected: bool,
+    /// Look for received transactions double spending a transaction in the mempool or one
+    /// broadcast before, and log them
+    pub detect_conflicts: bool,
+    /// Also publish an alert event for each double spend, tagging the author of the transaction
+    /// it conflicts with when that was broadcast by the listener
+    pub conflict_alerts: bool,
+    /// Ask the author of a transaction missing inputs for its unconfirmed parents when they can't
+    /// be found, with a parent request event
+    pub request_parents: bool,
+    /// Hold the transactions while their backend is in its initial block download, instead of
+    /// broadcasting them to a node that can't judge them yet
+    pub require_synced: bool,
+    /// How many event ids and transactions to remember, to skip duplicates
+    pub seen_cache_size: NonZeroUsize,
+    /// Persistent record of the received events and broadcasts
+    pub store: Option<Store>,
+    /// How long the store remembers handled event ids, so events replayed by relays after a
+    /// restart are skipped
+    pub replay_window: Duration,
+    /// Follow broadcast transactions and rebroadcast them until confirmed
+    pub rebroadcast: Option<TrackerConfig>,
+    /// Publish a reply to each transaction event with the result of broadcasting its transactions,
+    /// and with rebroadcasting also a notification when each of them confirms
+    pub reply: bool,
+    /// Tell authors why their transactions failed in a NIP-17 private message, instead of in the
+    /// public replies
+    pub dm_failures: bool,
+    /// Only handle the events of these authors, `None` to accept everyone
+    pub allowed_authors: Option<HashSet<XOnlyPublicKey>>,
+    /// Only subscribe to the events of these authors, so the relays filter out the others, empty
+    /// to subscribe to everyone's. Without `allowed_authors`, the events of others are ignored
+    pub only_authors: Vec<XOnlyPublicKey>,
+    /// Authors whose events are ignored, banned when they keep submitting invalid transactions
+    pub denylist: Denylist,
+    /// Temporarily ban authors whose submissions keep being invalid or breaking the policy,
+    /// `None` not to keep their reputation
+    pub reputation: Option<ReputationConfig>,
+    /// Minimum NIP-13 proof of work (leading zero bits of the event id) of handled events
+    pub min_pow: u8,
+    /// Only handle the events of authors with a verified NIP-05 identifier, `None` to accept
+    /// everyone
+    pub nip05: Option<Nip05Config>,
+    /// Events created longer ago are ignored, so relays replaying old events don't get their
+    /// transactions broadcast again
+    pub max_event_age: Option<Duration>,
+    /// Events created further in the future are ignored
+    pub max_future_drift: Option<Duration>,
+    /// How long to wait for the other chunks of a package published in several events
+    pub chunk_timeout: Duration,
+    /// Transactions each author may submit, `None` for no limit
+    pub rate_limit: Option<RateLimit>,
+    /// Transactions each author may submit per UTC day, `None` for no quota. Authors on the
+    /// allowlist aren't limited
+    pub daily_quota: Option<NonZeroU32>,
+    /// Authors without a daily quota
+    pub quota_exempt: HashSet<XOnlyPublicKey>,
+    /// How many events can wait to be broadcast, events arriving when it's full are dropped
+    pub queue_size: NonZeroUsize,
+    /// Events broadcast per second at most, over all authors
+    pub max_broadcasts_per_second: Option<NonZeroU32>,
+    /// How long to wait for more events after taking one from the queue, so related txs that
+    /// arrive in separate events, such as a parent and its child, are broadcast together as a
+    /// package. `None` to broadcast each event on its own
+    pub batch_window: Option<Duration>,
+    /// Random delay before each event is broadcast, `None` to broadcast them right away
+    pub broadcast_jitter: Option<Jitter>,
+    /// Longest delay before each event is broadcast to let other broadcasters go first, fixed
+    /// per broadcaster and transactions. Events whose transactions reached the backend's mempool
+    /// meanwhile aren't broadcast. `None` to broadcast them right away
+    pub cooperative_delay: Option<Duration>,
+    /// Limits on the submitted transactions
+    pub policy: Policy,
+    /// Where the listener counts what it does, see [`Metrics::render`]
+    pub metrics: Metrics,
+    /// Log a summary of what happened this often, `None` not to
+    pub stats_interval: Option<Duration>,
+    /// Also publish the summaries as text notes
+    pub publish_stats: bool,
+    /// Publish an announcement of the broadcaster this often, `None` not to, see
+    /// [`protocol::announcement_event`]
+    pub announce_interval: Option<Duration>,
+    /// Hold submissions until they're paid for, `None` for free broadcasting
+    pub payment: Option<PaymentConfig>,
+    /// Take NIP-90 job requests to broadcast transactions too, `None` not to, see [`dvm`]
+    pub dvm: Option<DvmConfig>,
+    /// Publish NIP-89 handler information declaring that the broadcaster handles the transaction
+    /// event kinds, see [`protocol::handler_event`]
+    pub nip89: bool,
+}
+
+/// Transactions received in an event
+#[derive(Debug, Clone)]
+pub struct ReceivedTxs {
+    /// Id of the event that carried the transactions
+    pub event_id: EventId,
+    /// Author of the event
+    pub author: XOnlyPublicKey,
+    /// Relay the event was received from
+    pub relay: Url,
+    /// Kind of the event
+    pub kind: u64,
+    /// Network magic of the transactions
+    pub magic: Magic,
+    /// Where the event carried the transactions
+    pub encoding: Encoding,
+    /// The decoded transactions
+    pub txs: Vec<Transaction>,
+}
+
+/// What happened to the transactions of an event
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    /// Id of the event that carried the transactions
+    pub event_id: EventId,
+    /// Author of the event
+    pub author: XOnlyPublicKey,
+    /// Whether each transaction was accepted
+    pub results: Vec<TxResult>,
+}
+
+/// What the listener uses a relay for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RelayRole {
+    /// Receiving transaction events and publishing results
+    #[default]
+    ReadWrite,
+    /// Only receiving transaction events, an inbox
+    Read,
+    /// Only publishing results, confirmations and alerts, an outbox
+    Write,
+}
+
+impl RelayRole {
+    /// Whether transaction events are received from the relay
+    pub fn reads(self) -> bool {
+        self != RelayRole::Write
+    }
+
+    /// Whether events are published to the relay
+    pub fn writes(self) -> bool {
+        self != RelayRole::Read
+    }
+
+    /// Name of the role, as reported
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RelayRole::ReadWrite => "read_write",
+            RelayRole::Read => "read",
+            RelayRole::Write => "write",
+        }
+    }
+
+    fn options(self) -> RelayOptions {
+        RelayOptions::new(self.reads(), self.writes())
+    }
+}
+
+/// Listens to relays for transaction events and broadcasts their transactions
+pub struct Listener {
+    client: Client,
+    config: ListenerConfig,
+    connections: Mutex<HashMap<Url, RelayConnection>>,
+    delivered: Mutex<LruCache<EventId, ()>>,
+    received: broadcast::Sender<ReceivedTxs>,
+    outcomes: broadcast::Sender<Outcome>,
+    recent_received: Mutex<VecDeque<(Timestamp, ReceivedTxs)>>,
+    recent_outcomes: Mutex<VecDeque<(Timestamp, Outcome)>>,
+    seen: Mutex<SeenCache>,
+    last_seen: Option<Timestamp>,
+    tracker: Option<Tracker>,
+    rate_limiter: Option<Mutex<RateLimiter>>,
+    daily_quota: Option<Mutex<DailyQuota>>,
+    queue: mpsc::Sender<Submission>,
+    queued: tokio::sync::Mutex<mpsc::Receiver<Submission>>,
+    metrics: Metrics,
+    last_broadcast: Mutex<Option<Instant>>,
+    paused: watch::Sender<bool>,
+    /// Wakes up the submitter to drop the queued events
+    flush: Notify,
+    /// Since when the current relay pool notification is being handled
+    handling_since: Mutex<Option<Instant>>,
+    /// The configured policy and allowlist, replaced when the config is reloaded
+    policy: RwLock<Policy>,
+    allowed_authors: RwLock<Option<HashSet<XOnlyPublicKey>>>,
+    reassembly: Mutex<Reassembly>,
+    nip05: Option<Verifier>,
+    reputation: Option<Reputation>,
+    /// The outputs spent by the broadcast transactions, to find double spends
+    spends: Mutex<Spends>,
+    /// The configured relay roles, replaced when the config is reloaded
+    relay_roles: RwLock<HashMap<Url, RelayRole>>,
+    /// The submissions waiting to be paid, by the event to zap
+    unpaid: Mutex<HashMap<EventId, Unpaid>>,
+    /// The txs whose parents were requested, with their author and the parents, so the author's
+    /// answer isn't charged again
+    parents_requested: Mutex<LruCache<Txid, (XOnlyPublicKey, Vec<Txid>)>>,
+}
+
+/// A submission waiting to be paid
+struct Unpaid {
+    submission: Submission,
+    /// Millisatoshis to pay
+    msat: u64,
+    /// Invoice of the node to check, `None` when paid with a zap
+    invoice: Option<Invoice>,
+    since: Instant,
+}
+
+/// An event waiting to be broadcast
+struct Submission {
+    event: Event,
+    magic: Magic,
+    backend: Arc<dyn TxBackend>,
+    txs: Vec<Transaction>,
+    /// Later events with related txs, broadcast together with this one as a package
+    batched: Vec<Submission>,
+}
+
+impl Listener {
+    /// Create a listener using `keys` as its nostr identity
+    pub async fn new(keys: &Keys, config: ListenerConfig) -> anyhow::Result<Self> {
+        if config.relays.is_empty() && config.local_relay.is_none() {
+            bail!("No relay(s) provided");
+        }
+        if config.kinds.is_empty() {
+            bail!("No event kind(s) provided");
+        }
+
+        let client = Client::new(keys);
+        for relay in &config.relays {
+            let role = config
+                .relay_roles
+                .get(&parse_relay(relay)?)
+                .copied()
+                .unwrap_or_default();
+            client
+                .add_relay_with_opts(relay.as_str(), config.proxy, role.options())
+                .await?;
+        }
+
+        let connections = client
+            .relays()
+            .await
+            .into_keys()
+            .map(|url| (url, RelayConnection::new(Timestamp::now())))
+            .collect();
+
+        let (received, _) = broadcast::channel(1024);
+        let (outcomes, _) = broadcast::channel(1024);
+        let mut seen = SeenCache::new(config.seen_cache_size);
+        let mut last_seen = None;
+        if let Some(store) = &config.store {
+            // before the handled events older than the replay window are forgotten
+            last_seen = store.last_seen().context("Error reading store")?;
+            let handled = store
+                .recent_events(config.replay_window, config.seen_cache_size.get())
+                .context("Error reading store")?;
+            debug!(count = handled.len(), "Loaded handled events");
+            for event_id in handled {
+                seen.seen_event(event_id);
+            }
+        }
+        let seen = Mutex::new(seen);
+        let tracker = config
+            .rebroadcast
+            .clone()
+            .map(|tracker| Tracker::new(tracker, config.backends.clone()));
+        let rate_limiter = config
+            .rate_limit
+            .map(|limit| Mutex::new(RateLimiter::new(limit)));
+        let daily_quota = config.daily_quota.map(|limit| {
+            Mutex::new(DailyQuota::new(
+                limit,
+                config.quota_exempt.clone(),
+                config.store.clone(),
+            ))
+        });
+
+        let delivered = Mutex::new(LruCache::new(config.seen_cache_size));
+        let parents_requested = Mutex::new(LruCache::new(config.seen_cache_size));
+        let (queue, queued) = mpsc::channel(config.queue_size.get());
+        let metrics = config.metrics.clone();
+        let policy = RwLock::new(config.policy.clone());
+        let allowed_authors = RwLock::new(config.allowed_authors.clone());
+        let relay_roles = RwLock::new(config.relay_roles.clone());
+        let reassembly = Mutex::new(Reassembly::new(config.chunk_timeout));
+        let nip05 = config
+            .nip05
+            .clone()
+            .map(|nip05| Verifier::new(nip05, config.proxy));
+        let reputation = config
+            .reputation
+            .clone()
+            .map(|reputation| Reputation::new(reputation, config.store.clone()))
+            .transpose()
+            .context("Error reading store")?;
+
+        Ok(Self {
+            client,
+            config,
+            connections: Mutex::new(connections),
+            delivered,
+            received,
+            outcomes,
+            recent_received: Mutex::new(VecDeque::new()),
+            recent_outcomes: Mutex::new(VecDeque::new()),
+            seen,
+            last_seen,
+            tracker,
+            rate_limiter,
+            daily_quota,
+            queue,
+            queued: tokio::sync::Mutex::new(queued),
+            metrics,
+            last_broadcast: Mutex::new(None),
+            paused: watch::channel(false).0,
+            flush: Notify::new(),
+            handling_since: Mutex::new(None),
+            policy,
+            allowed_authors,
+            reassembly,
+            nip05,
+            reputation,
+            spends: Mutex::default(),
+            relay_roles,
+            unpaid: Mutex::default(),
+            parents_requested,
+        })
+    }
+
+    /// The nostr client used by the listener
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// The metrics of the listener
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Check the relay connections and the backends
+    pub async fn health(&self) -> Health {
+        let relays = self.relays().await;
+        let backends = self.backends().await;
+
+        let healthy = (relays.iter().any(|relay| relay.connected)
+            || self.config.local_relay.is_some())
+            && backends.iter().all(|backend| backend.reachable);
+        let last_broadcast_secs = self
+            .last_broadcast
+            .lock()
+            .unwrap()
+            .map(|at| at.elapsed().as_secs());
+
+        Health {
+            healthy,
+            paused: self.is_paused(),
+            relays,
+            backends,
+            last_broadcast_secs,
+        }
+    }
+
+    /// The relays the listener uses and their connection status
+    pub async fn relays(&self) -> Vec<RelayHealth> {
+        let pool = self.client.relays().await;
+        let mut relays = vec![];
+        for url in self.relay_urls() {
+            let status = relay_status(pool.get(&url)).await;
+            let Some((stats, demoted)) = self
+                .connections
+                .lock()
+                .unwrap()
+                .get(&url)
+                .map(|c| (c.stats().clone(), c.is_demoted()))
+            else {
+                continue;
+            };
+            relays.push(RelayHealth {
+                url: url.to_string(),
+                status: status.to_string(),
+                role: self.role(&url).as_str().to_string(),
+                connected: status == RelayStatus::Connected,
+                score: stats.score(),
+                demoted,
+                connect_failures: stats.connect_failures(),
+                latency_secs: stats.latency_secs(),
+                duplicate_ratio: stats.duplicate_ratio(),
+            });
+        }
+        relays
+    }
+
+    /// Check the backends
+    async fn backends(&self) -> Vec<BackendHealth> {
+        let checks = self
+            .config
+            .backends
+            .iter()
+            .map(|(magic, backend)| async move {
+                let result = backend.health_check().await;
+                BackendHealth {
+                    magic: magic.to_string(),
+                    backend: backend.name().to_string(),
+                    reachable: result.is_ok(),
+                    error: result.err().map(|e| e.to_string()),
+                }
+            });
+        join_all(checks).await
+    }
+
+    /// Start using the relay at `url`
+    pub async fn add_relay(&self, url: &str) -> anyhow::Result<()> {
+        let url = parse_relay(url)?;
+        if self.config.proxy.is_none() && relays::is_onion(&url) {
+            bail!("Relay {url} is an onion service, only reachable through a proxy");
+        }
+        if self.connections.lock().unwrap().contains_key(&url) {
+            bail!("Already using relay {url}");
+        }
+        self.client
+            .add_relay_with_opts(url.as_str(), self.config.proxy, self.role(&url).options())
+            .await?;
+        let relay = self
+            .client
+            .relays()
+            .await
+            .remove(&url)
+            .context("Relay missing from the pool")?;
+        let since = Timestamp::now();
+        self.connections
+            .lock()
+            .unwrap()
+            .insert(url.clone(), RelayConnection::new(since));
+        connect(&relay, self.subscriptions(since)).await;
+        info!(relay = %url, "Added relay");
+        Ok(())
+    }
+
+    /// Stop using the relay at `url`
+    pub async fn remove_relay(&self, url: &str) -> anyhow::Result<()> {
+        let url = parse_relay(url)?;
+        if self.connections.lock().unwrap().remove(&url).is_none() {
+            bail!("Not using relay {url}");
+        }
+        self.client.remove_relay(url.as_str()).await?;
+        info!(relay = %url, "Removed relay");
+        Ok(())
+    }
+
+    /// Use exactly `relays`, adding the new ones and removing the others, returning the added and
+    /// removed relays
+    pub async fn set_relays(&self, relays: &[String]) -> anyhow::Result<(Vec<Url>, Vec<Url>)> {
+        let wanted = relays
+            .iter()
+            .map(|relay| parse_relay(relay))
+            .collect::<Result<HashSet<_>, _>>()?;
+        let current: HashSet<_> = self.relay_urls().into_iter().collect();
+
+        let mut added = vec![];
+        for url in wanted.difference(&current) {
+            self.add_relay(url.as_str()).await?;
+            added.push(url.clone());
+        }
+        let mut removed = vec![];
+        for url in current.difference(&wanted) {
+            self.remove_relay(url.as_str()).await?;
+            removed.push(url.clone());
+        }
+        Ok((added, removed))
+    }
+
+    /// Stop broadcasting, events are still received and queued until [`Listener::resume`]
+    pub fn pause(&self) {
+        if !self.paused.send_replace(true) {
+            info!("Paused broadcasting");
+        }
+    }
+
+    /// Broadcast the queued events again after [`Listener::pause`]
+    pub fn resume(&self) {
+        if self.paused.send_replace(false) {
+            info!("Resumed broadcasting");
+        }
+    }
+
+    /// Whether broadcasting is paused
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    /// How long the notification of the relay pool being handled takes so far, `None` while
+    /// waiting for the next one. A long time means the listener hangs
+    pub fn handling_for(&self) -> Option<Duration> {
+        self.handling_since
+            .lock()
+            .unwrap()
+            .map(|since| since.elapsed())
+    }
+
+    /// Wait until a relay is connected
+    pub async fn wait_connected(&self) {
+        if self.config.local_relay.is_some() {
+            return;
+        }
+        loop {
+            for relay in self.client.relays().await.values() {
+                if relay.status().await == RelayStatus::Connected {
+                    return;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+    }
+
+    /// Apply `policy` to the events handled from now on
+    pub fn set_policy(&self, policy: Policy) {
+        *self.policy.write().unwrap() = policy;
+    }
+
+    /// The role of the relay at `url`
+    pub fn role(&self, url: &Url) -> RelayRole {
+        self.relay_roles
+            .read()
+            .unwrap()
+            .get(url)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Use the relays for `roles` from now on, the relays not in it for both reading and writing.
+    /// Relays that start being read from are subscribed to, the others unsubscribed from
+    pub async fn set_relay_roles(&self, roles: HashMap<Url, RelayRole>) {
+        *self.relay_roles.write().unwrap() = roles;
+        for (url, relay) in self.client.relays().await {
+            let role = self.role(&url);
+            let options = relay.opts();
+            let was_read = options.read();
+            options.set_read(role.reads());
+            options.set_write(role.writes());
+            let result = match (was_read, role.reads()) {
+                (false, true) => {
+                    let filters = relay.subscription().await.filters;
+                    relay.subscribe(filters, false).await.map(|_| ())
+                }
+                // allowed while still reading
+                (true, false) => {
+                    options.set_read(true);
+                    let result = relay.unsubscribe(false).await;
+                    options.set_read(false);
+                    result
+                }
+                _ => Ok(()),
+            };
+            if let Err(e) = result {
+                warn!(relay = %url, role = role.as_str(), "Error changing relay role: {e}");
+            }
+        }
+    }
+
+    /// Only handle the events of `authors` from now on, `None` to accept everyone
+    pub fn set_allowed_authors(&self, authors: Option<HashSet<XOnlyPublicKey>>) {
+        *self.allowed_authors.write().unwrap() = authors;
+    }
+
+    /// Read the denylist file again, returning the number of banned authors
+    pub fn reload_denylist(&self) -> anyhow::Result<usize> {
+        self.config.denylist.reload()
+    }
+
+    /// How many events wait to be broadcast
+    pub fn queue_depth(&self) -> usize {
+        self.queue.max_capacity() - self.queue.capacity()
+    }
+
+    /// Drop the events waiting to be broadcast, such as the ones queued while paused, returning
+    /// how many there were
+    pub fn flush_queue(&self) -> usize {
+        let depth = self.queue_depth();
+        self.flush.notify_one();
+        depth
+    }
+
+    /// Stream of the transactions received from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<ReceivedTxs> {
+        self.received.subscribe()
+    }
+
+    /// Stream of the outcomes of the events handled from now on: broadcast, or rejected before
+    /// or by the dry run
+    pub fn outcomes(&self) -> broadcast::Receiver<Outcome> {
+        self.outcomes.subscribe()
+    }
+
+    /// The last received events and when they arrived, the newest first
+    pub fn recent_received(&self) -> Vec<(Timestamp, ReceivedTxs)> {
+        self.recent_received
+            .lock()
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// The last outcomes and when they happened, the newest first
+    pub fn recent_outcomes(&self) -> Vec<(Timestamp, Outcome)> {
+        self.recent_outcomes
+            .lock()
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Publish `txs` as a transaction event of the listener's identity and first kind, for the
+    /// broadcasters listening on its relays, itself included
+    pub async fn publish_txs(&self, magic: Magic, txs: &[Transaction]) -> anyhow::Result<EventId> {
+        let event =
+            protocol::tx_event(self.config.kinds[0], magic, txs, TxEventOptions::default())?
+                .to_event(&self.client.keys())?;
+        self.send(event).await
+    }
+
+    /// The broadcast transactions followed until they're confirmed, none without rebroadcasting
+    pub fn rebroadcasting(&self) -> Vec<TrackedTx> {
+        self.tracker
+            .as_ref()
+            .map(Tracker::tracked)
+            .unwrap_or_default()
+    }
+
+    /// Rebroadcast the followed transaction `txid` now, see [`Tracker::rebroadcast_txid`]
+    pub async fn rebroadcast_txid(&self, txid: &Txid) -> anyhow::Result<Vec<PackageTxResult>> {
+        let Some(tracker) = &self.tracker else {
+            bail!("Rebroadcasting is off, no txs are followed");
+        };
+        tracker.rebroadcast_txid(txid).await
+    }
+
+    /// Connect to the relays and broadcast the received transactions until the relay pool
+    /// shuts down
+    pub async fn run(&self) -> anyhow::Result<()> {
+        self.run_until(future::pending()).await
+    }
+
+    /// [`Listener::run`] until `shutdown` completes, then stop receiving events, broadcast the
+    /// queued ones and disconnect from the relays
+    pub async fn run_until(&self, shutdown: impl Future<Output = ()>) -> anyhow::Result<()> {
+        // each relay subscribes as soon as it connects, unreachable relays don't hold up the
+        // others. Catch up on the events published while the listener wasn't running
+        let since = match self.last_seen {
+            Some(last_seen) => {
+                info!(since = %last_seen, "Catching up on events since the last handled one");
+                last_seen
+            }
+            None => Timestamp::now(),
+        };
+        for (url, relay) in self.client.relays().await {
+            self.connections
+                .lock()
+                .unwrap()
+                .insert(url, RelayConnection::new(since));
+            connect(&relay, self.subscriptions(since)).await;
+        }
+
+        for (magic, backend) in &self.config.backends {
+            info!(%magic, backend = backend.name(), "Broadcasting txs of network");
+            if let Ok(Some(false)) = backend.is_synced().await {
+                warn!(%magic, backend = backend.name(), "Backend is in its initial block download");
+            }
+        }
+        info!("Listening for bitcoin txs");
+        let notifications = self.client.handle_notifications(|notification| async {
+            *self.handling_since.lock().unwrap() = Some(Instant::now());
+            if let RelayPoolNotification::Message(relay, _) = &notification {
+                self.heard(relay);
+            }
+            match notification {
+                // every relay's copy, the pool only notifies the first
+                RelayPoolNotification::Message(relay, RelayMessage::Event { event, .. }) => {
+                    self.delivered(&relay, &event)
+                }
+                RelayPoolNotification::Message(relay, RelayMessage::Auth { challenge }) => {
+                    self.authenticate(&relay, &challenge).await
+                }
+                RelayPoolNotification::Message(relay, RelayMessage::EndOfStoredEvents(id))
+                    if id == SubscriptionId::new(PING_SUBSCRIPTION) =>
+                {
+                    self.send_to(&relay, ClientMessage::close(id)).await
+                }
+                RelayPoolNotification::Event(relay, event) if self.handles(&event) => {
+                    self.handle_event(relay, event).await
+                }
+                RelayPoolNotification::Event(_, event) if event.kind == Kind::Zap => {
+                    self.zapped(event).await
+                }
+                _ => {}
+            }
+            *self.handling_since.lock().unwrap() = None;
+            Ok(())
+        });
+
+        let tracker = async {
+            match &self.tracker {
+                Some(tracker) => tracker.run(|update| self.notify_update(update)).await,
+                None => future::pending().await,
+            }
+        };
+
+        let (stop, stopped) = watch::channel(false);
+        let submitter = self.submit_queued(stopped);
+        tokio::pin!(submitter);
+
+        let result = tokio::select! {
+            result = notifications => result.map_err(Into::into),
+            _ = self.keep_connected() => Ok(()),
+            _ = self.handle_local() => Ok(()),
+            _ = self.summarize() => Ok(()),
+            _ = self.announce() => Ok(()),
+            _ = self.advertise_handler() => Ok(()),
+            _ = self.poll_invoices() => Ok(()),
+            _ = tracker => Ok(()),
+            _ = &mut submitter => Ok(()),
+            _ = shutdown => {
+                info!("Shutting down");
+                Ok(())
+            }
+        };
+
+        // no new events, finish the queued ones, which may still reply through the relays
+        for relay in self.client.relays().await.values() {
+            if relay.opts().read() {
+                if let Err(e) = relay.unsubscribe(false).await {
+                    debug!(relay = %relay.url(), "Error unsubscribing: {e}");
+                }
+            }
+        }
+        let _ = stop.send(true);
+        submitter.await;
+        self.client.disconnect().await?;
+        info!("Disconnected from the relays");
+        result
+    }
+
+    /// Handle the events published to the local relay, like the ones of the relays
+    async fn handle_local(&self) {
+        let Some(local) = &self.config.local_relay else {
+            return future::pending().await;
+        };
+        let mut events = local.subscribe();
+        loop {
+            match events.recv().await {
+                Ok(event) if self.handles(&event) => {
+                    self.handle_event(local.url().clone(), event).await
+                }
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    warn!(missed, "Missed events of the local relay");
+                }
+                Err(broadcast::error::RecvError::Closed) => return future::pending().await,
+            }
+        }
+    }
+
+    /// Reconnect the relays that drop, instead of the relay pool which retries every 20 seconds
+    /// with the original subscription: take them out of the pool and add them back with
+    /// increasing delays, subscribing from the last event received
+    async fn keep_connected(&self) {
+        let start = tokio::time::Instant::now() + RELAY_CHECK_INTERVAL;
+        let mut interval = tokio::time::interval_at(start, RELAY_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let pool = self.client.relays().await;
+            let mut statuses = vec![];
+            for url in self.relay_urls() {
+                let status = relay_status(pool.get(&url)).await;
+                statuses.push((url, status));
+            }
+            let mut connected = statuses
+                .iter()
+                .filter(|(_, status)| *status == RelayStatus::Connected)
+                .count();
+
+            for (url, status) in statuses {
+                let was_connected = status == RelayStatus::Connected;
+                let (action, demoted, keepalive) = {
+                    let mut connections = self.connections.lock().unwrap();
+                    let Some(connection) = connections.get_mut(&url) else {
+                        continue;
+                    };
+                    let action = connection.update(status, &self.config.reconnect);
+                    // only relays read from are asked for something
+                    let keepalive = match self.role(&url).reads() {
+                        true => connection.keepalive(&self.config.reconnect),
+                        false => None,
+                    };
+                    self.metrics
+                        .set_relay_score(url.as_str(), connection.stats().score());
+                    // only demote while another relay is connected
+                    let others = connected - usize::from(was_connected);
+                    let demoted = match &self.config.scoring {
+                        Some(scoring) if others > 0 => connection.demote(scoring),
+                        _ => None,
+                    };
+                    (action, demoted, keepalive)
+                };
+                if let Some(score) = demoted {
+                    if was_connected {
+                        connected -= 1;
+                    }
+                    self.demote(&url, score).await;
+                    continue;
+                }
+                match keepalive {
+                    Some(Keepalive::Ping) => {
+                        debug!(relay = %url, "Pinging quiet relay");
+                        let ping = Filter::new().kinds(vec![Kind::Metadata]).limit(0);
+                        let req = ClientMessage::new_req(
+                            SubscriptionId::new(PING_SUBSCRIPTION),
+                            vec![ping],
+                        );
+                        self.send_to(&url, req).await;
+                    }
+                    Some(Keepalive::Silent(silent)) => {
+                        warn!(relay = %url, silent_secs = silent.as_secs(), "Relay went silent, dropping the connection");
+                        self.metrics.relay_silent(url.as_str());
+                        // reconnected like a relay that dropped
+                        if let Err(e) = self.client.remove_relay(url.as_str()).await {
+                            warn!(relay = %url, "Error removing relay: {e}");
+                        }
+                        continue;
+                    }
+                    None => {}
+                }
+
+                match action {
+                    Action::None => {}
+                    Action::Connected => info!(relay = %url, "Connected to relay"),
+                    Action::Disconnected {
+                        was_connected,
+                        delay,
+                    } => {
+                        let retry_secs = delay.as_secs_f64();
+                        if was_connected {
+                            warn!(relay = %url, retry_secs, "Disconnected from relay");
+                        } else {
+                            info!(relay = %url, retry_secs, "Failed to connect to relay");
+                        }
+                        if let Err(e) = self.client.remove_relay(url.as_str()).await {
+                            warn!(relay = %url, "Error removing relay: {e}");
+                        }
+                    }
+                    Action::Reconnect { since, demoted } => {
+                        if demoted {
+                            info!(relay = %url, %since, "Retrying demoted relay");
+                        } else {
+                            info!(relay = %url, %since, "Reconnecting to relay");
+                        }
+                        if let Err(e) = self.reconnect(&url, since).await {
+                            warn!(relay = %url, "Error reconnecting to relay: {e}");
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Log what happened every stats interval, a heartbeat for operators
+    async fn summarize(&self) {
+        let Some(period) = self.config.stats_interval else {
+            return future::pending().await;
+        };
+        let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        let mut last = self.metrics.totals();
+        loop {
+            interval.tick().await;
+            let totals = self.metrics.totals();
+            let summary = totals.since(&last);
+            last = totals;
+
+            let relays = self.relays().await;
+            let connected = relays.iter().filter(|relay| relay.connected).count();
+            let top_filtered: Vec<_> = summary
+                .top_filtered(3)
+                .iter()
+                .map(|(reason, count)| format!("{reason}={count}"))
+                .collect();
+            info!(
+                period_secs = period.as_secs(),
+                events_received = summary.events_received,
+                unique_txs = summary.txs_received,
+                broadcast_successes = summary.broadcast_successes,
+                broadcast_failures = summary.broadcast_failures(),
+                top_filtered = top_filtered.join(","),
+                relays_connected = connected,
+                relays = relays.len(),
+                "Summary"
+            );
+
+            if self.config.publish_stats {
+                let note = format!(
+                    "Last {} seconds: {}, {connected}/{} relays connected",
+                    period.as_secs(),
+                    summary,
+                    relays.len()
+                );
+                if let Err(e) = self
+                    .publish(Ok(EventBuilder::new_text_note(note, &[])))
+                    .await
+                {
+                    warn!("Error publishing summary: {e}");
+                }
+            }
+        }
+    }
+
+    /// Announce the broadcaster every announce interval, once a relay is connected, with the
+    /// networks, kinds and policy it has at the time
+    async fn announce(&self) {
+        let Some(period) = self.config.announce_interval else {
+            return future::pending().await;
+        };
+        self.wait_connected().await;
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            let policy = self.policy.read().unwrap().clone();
+            let announcement = Announcement {
+                networks: self
+                    .config
+                    .backends
+                    .keys()
+                    .map(|magic| magic.to_string())
+                    .collect(),
+                kinds: self.config.kinds.clone(),
+                encrypted: true,
+                reply: self.config.reply,
+                min_feerate: policy.min_feerate,
+                max_tx_vsize: policy.max_tx_vsize,
+                max_package_weight: policy.max_package_weight,
+                min_pow: self.config.min_pow,
+            };
+            // missing a few announcements doesn't make the broadcaster look gone
+            let expiration = Timestamp::now() + period * 3;
+            match self
+                .publish(protocol::announcement_event(&announcement, expiration))
+                .await
+            {
+                Ok(()) => debug!("Published announcement"),
+                Err(e) => warn!("Error publishing announcement: {e}"),
+            }
+        }
+    }
+
+    /// Publish the NIP-89 handler information once a relay is connected, it's replaceable so
+    /// once per start is enough
+    async fn advertise_handler(&self) {
+        if self.config.nip89 {
+            self.wait_connected().await;
+            let networks: Vec<_> = self
+                .config
+                .backends
+                .keys()
+                .map(|magic| magic.to_string())
+                .collect();
+            let metadata = Metadata::new().name("nostr-tx-broadcast").about(format!(
+                "Broadcasts the bitcoin transactions of the events sent to it, networks {}",
+                networks.join(", ")
+            ));
+            let mut kinds = self.config.kinds.clone();
+            if self.config.dvm.is_some() {
+                kinds.push(dvm::JOB_REQUEST_KIND);
+            }
+            let handler = protocol::handler_event(&kinds, &metadata);
+            match self.publish(Ok(handler)).await {
+                Ok(()) => info!("Published NIP-89 handler information"),
+                Err(e) => warn!("Error publishing NIP-89 handler information: {e}"),
+            }
+        }
+        future::pending().await
+    }
+
+    /// Stop using the relay at `url` for scoring `score`, until it's retried
+    async fn demote(&self, url: &Url, score: f64) {
+        let stats = self
+            .connections
+            .lock()
+            .unwrap()
+            .get(url)
+            .map(|c| c.stats().clone());
+        if let Some(stats) = stats {
+            warn!(
+                relay = %url,
+                score,
+                connect_failures = stats.connect_failures(),
+                latency_secs = stats.latency_secs(),
+                duplicate_ratio = stats.duplicate_ratio(),
+                "Demoting unhealthy relay"
+            );
+        }
+        self.metrics.relay_demoted(url.as_str());
+        if let Err(e) = self.client.remove_relay(url.as_str()).await {
+            warn!(relay = %url, "Error removing relay: {e}");
+        }
+    }
+
+    /// Answer the NIP-42 `challenge` of the relay at `url` with the listener's key, then subscribe
+    /// again, as the relay may have refused the subscription until authenticated
+    async fn authenticate(&self, url: &Url, challenge: &str) {
+        let Some(relay) = self.client.relays().await.remove(url) else {
+            return;
+        };
+        let event = match EventBuilder::auth(challenge, url.clone()).to_event(&self.client.keys()) {
+            Ok(event) => event,
+            Err(e) => {
+                error!(relay = %url, "Error signing the authentication: {e}");
+                return;
+            }
+        };
+        if let Err(e) = relay.send_msg(ClientMessage::new_auth(event), false).await {
+            warn!(relay = %url, "Error authenticating: {e}");
+            return;
+        }
+        info!(relay = %url, "Authenticated to relay");
+        if !self.role(url).reads() {
+            return;
+        }
+        let filters = relay.subscription().await.filters;
+        if let Err(e) = relay.subscribe(filters, false).await {
+            warn!(relay = %url, "Error subscribing after authenticating: {e}");
+        }
+    }
+
+    /// Record that `relay` sent a message, so it isn't taken for silent
+    fn heard(&self, relay: &Url) {
+        if let Some(connection) = self.connections.lock().unwrap().get_mut(relay) {
+            connection.heard();
+        }
+    }
+
+    /// Send `message` to the relay at `url` only
+    async fn send_to(&self, url: &Url, message: ClientMessage) {
+        let Some(relay) = self.client.relays().await.remove(url) else {
+            return;
+        };
+        if let Err(e) = relay.send_msg(message, false).await {
+            debug!(relay = %url, "Error sending message: {e}");
+        }
+    }
+
+    /// Record an event delivered by `relay`, also when another relay delivered it first
+    fn delivered(&self, relay: &Url, event: &Event) {
+        let duplicate = self.delivered.lock().unwrap().put(event.id, ()).is_some();
+        if let Some(connection) = self.connections.lock().unwrap().get_mut(relay) {
+            connection.received(event.created_at, duplicate);
+        }
+    }
+
+    /// Add the relay at `url` back to the pool and subscribe to it from `since`
+    async fn reconnect(&self, url: &Url, since: Timestamp) -> anyhow::Result<()> {
+        self.client
+            .add_relay_with_opts(url.as_str(), self.config.proxy, self.role(url).options())
+            .await?;
+        let relay = self
+            .client
+            .relays()
+            .await
+            .remove(url)
+            .context("Relay missing from the pool")?;
+        connect(&relay, self.subscriptions(since)).await;
+        Ok(())
+    }
+
+    /// The filter of the transaction events of the configured kinds, and authors if any
+    fn subscriptions(&self, since: Timestamp) -> Vec<Filter> {
+        let mut kinds: Vec<Kind> = self
+            .config
+            .kinds
+            .iter()
+            .map(|&kind| Kind::from(kind))
+            .collect();
+        if self.config.dvm.is_some() {
+            kinds.push(Kind::Custom(dvm::JOB_REQUEST_KIND));
+        }
+        let filter = Filter::new().kinds(kinds).since(since);
+        let filter = match self.config.only_authors.is_empty() {
+            true => filter,
+            false => filter.authors(
+                self.config
+                    .only_authors
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect(),
+            ),
+        };
+        let mut filters = vec![filter];
+        if self
+            .config
+            .payment
+            .as_ref()
+            .is_some_and(|payment| payment.zapper.is_some())
+        {
+            // the zaps paying for submissions
+            filters.push(
+                Filter::new()
+                    .kind(Kind::Zap)
+                    .pubkey(self.client.keys().public_key())
+                    .since(since),
+            );
+        }
+        filters
+    }
+
+    /// Whether `event` is a transaction event or a job request the listener handles
+    fn handles(&self, event: &Event) -> bool {
+        let kind = event.kind.as_u64();
+        self.config.kinds.contains(&kind)
+            || (self.config.dvm.is_some() && kind == dvm::JOB_REQUEST_KIND)
+    }
+
+    /// Millisatoshis the submission of `event` costs, `None` when it's free
+    fn price(&self, event: &Event) -> Option<u64> {
+        self.config.payment.as_ref()?;
+        match event.kind == Kind::Custom(dvm::JOB_REQUEST_KIND) {
+            true => self.config.dvm.as_ref().and_then(|dvm| dvm.price),
+            false => self
+                .config
+                .payment
+                .as_ref()
+                .and_then(|payment| payment.price),
+        }
+    }
+
+    /// Whether `submission` is its author's answer to a request for the parents of a tx, carrying
+    /// nothing but the tx and those parents, which was paid for already
+    fn answers_parent_request(&self, submission: &Submission) -> bool {
+        let mut requested = self.parents_requested.lock().unwrap();
+        let answered = submission.txs.iter().find_map(|tx| {
+            let (author, parents) = requested.peek(&tx.txid())?;
+            let answers = *author == submission.event.pubkey
+                && submission
+                    .txs
+                    .iter()
+                    .all(|other| other.txid() == tx.txid() || parents.contains(&other.txid()));
+            answers.then(|| tx.txid())
+        });
+        match answered {
+            Some(txid) => requested.pop(&txid).is_some(),
+            None => false,
+        }
+    }
+
+    /// The relays the listener was configured with, also the ones out of the pool while waiting
+    /// to reconnect
+    fn relay_urls(&self) -> Vec<Url> {
+        self.connections.lock().unwrap().keys().cloned().collect()
+    }
+
+    async fn handle_event(&self, relay: Url, event: Event) {
+        self.metrics.event_received(relay.as_str());
+
+        // before anything is remembered about the event, so forged events can't shadow real ones
+        if let Err(e) = protocol::verify(&event) {
+            warn!(event_id = %event.id, %relay, "Ignoring event: {e}");
+            self.metrics.invalid_event(relay.as_str());
+            self.metrics.event_filtered("invalid_event");
+            return;
+        }
+
+        if self.seen.lock().unwrap().seen_event(event.id) {
+            self.metrics.event_filtered("duplicate_event");
+            return;
+        }
+        if let Some(store) = &self.config.store {
+            if let Err(e) = store.record_seen(&event, self.config.replay_window) {
+                error!("Error writing store: {e}");
+            }
+        }
+
+        let now = Timestamp::now().as_u64();
+        let created_at = event.created_at.as_u64();
+        if let Some(max_age) = self.config.max_event_age {
+            let age_secs = now.saturating_sub(created_at);
+            if age_secs > max_age.as_secs() {
+                debug!(event_id = %event.id, age_secs, "Ignoring stale event");
+                self.metrics.event_filtered("stale");
+                return;
+            }
+        }
+        if let Some(max_drift) = self.config.max_future_drift {
+            let drift_secs = created_at.saturating_sub(now);
+            if drift_secs > max_drift.as_secs() {
+                debug!(event_id = %event.id, drift_secs, "Ignoring event from the future");
+                self.metrics.event_filtered("future");
+                return;
+            }
+        }
+        if let Some(expiration) = protocol::expiration(&event) {
+            if expiration.as_u64() <= now {
+                debug!(event_id = %event.id, %expiration, "Ignoring expired event");
+                self.metrics.event_filtered("expired");
+                return;
+            }
+        }
+
+        // the allowlist, denylist and limits apply to the delegator of delegated events
+        let author = match protocol::author(&event) {
+            Ok(author) => author,
+            Err(e) => {
+                info!(event_id = %event.id, signer = %event.pubkey, "Ignoring event: {e}");
+                self.metrics.event_filtered("delegation");
+                return;
+            }
+        };
+        if author != event.pubkey {
+            debug!(event_id = %event.id, signer = %event.pubkey, delegator = %author, "Delegated event");
+        }
+
+        let allowed = match &*self.allowed_authors.read().unwrap() {
+            Some(allowed) => allowed.contains(&author),
+            // without an allowlist, the authors subscribed to make up one
+            None => {
+                self.config.only_authors.is_empty() || self.config.only_authors.contains(&author)
+            }
+        };
+        if !allowed {
+            let count = self.metrics.event_filtered("not_allowed");
+            debug!(event_id = %event.id, %author, count, "Ignoring event, author not on the allowlist");
+            return;
+        }
+
+        if self.config.denylist.contains(&author) {
+            debug!(event_id = %event.id, %author, "Ignoring event by banned author");
+            self.metrics.event_filtered("banned");
+            return;
+        }
+        if let Some(until) = self
+            .reputation
+            .as_ref()
+            .and_then(|reputation| reputation.banned_until(&author))
+        {
+            debug!(event_id = %event.id, %author, %until, "Ignoring event by temporarily banned author");
+            self.metrics.event_filtered("temp_banned");
+            return;
+        }
+
+        if self.config.min_pow > 0 {
+            let difficulty = protocol::difficulty(&event);
+            if difficulty < self.config.min_pow {
+                debug!(event_id = %event.id, difficulty, min_pow = self.config.min_pow, "Ignoring event with too little proof of work");
+                self.metrics.event_filtered("pow");
+                return;
+            }
+        }
+
+        if let Some(nip05) = &self.nip05 {
+            let Some(identifier) = nip05.verify(&self.client, author).await else {
+                debug!(event_id = %event.id, %author, "Ignoring event, author without verified NIP-05 identifier");
+                self.metrics.event_filtered("nip05");
+                return;
+            };
+            debug!(event_id = %event.id, %identifier, "Verified author");
+        }
+
+        if let Err(e) = protocol::compression(&event) {
+            info!(event_id = %event.id, author = %event.pubkey, "Ignoring event: {e}");
+            self.metrics.event_filtered("compression");
+            return;
+        }
+
+        // a token paying for the broadcast, in encrypted events only
+        let mut cashu = None;
+        let (magic, txs, encoding) = if event.kind == Kind::Custom(dvm::JOB_REQUEST_KIND) {
+            let job = dvm::job(&event);
+            match job.provider {
+                // asked of another service provider
+                Some(provider) if provider != self.client.keys().public_key() => {
+                    self.metrics.event_filtered("other_recipient");
+                    return;
+                }
+                _ => (job.magic, job.txs, Encoding::Job),
+            }
+        } else if let Some(chunk) = protocol::chunk(&event) {
+            let Some(txs) = self.reassemble(&event, &chunk) else {
+                return;
+            };
+            (protocol::magic(&event), txs, Encoding::Chunks)
+        } else {
+            match protocol::recipient(&event) {
+                // meant for another broadcaster
+                Some(recipient) if recipient != self.client.keys().public_key() => {
+                    self.metrics.event_filtered("other_recipient");
+                    return;
+                }
+                Some(_) => match protocol::decrypt(&self.client.keys(), &event) {
+                    Ok(decrypted) => {
+                        cashu = decrypted.cashu;
+                        (Some(decrypted.magic), decrypted.txs, Encoding::Encrypted)
+                    }
+                    Err(e) => {
+                        warn!(event_id = %event.id, author = %event.pubkey, "Error decrypting event: {e}");
+                        self.metrics.event_filtered("decryption");
+                        self.strike(&event);
+                        return;
+                    }
+                },
+                None => {
+                    let payload = event_parse::parse(&event);
+                    (payload.magic, payload.txs, payload.encoding)
+                }
+            }
+        };
+        debug!(event_id = %event.id, %encoding, "Parsed event");
+
+        // calculate network from magic
+        let Some(magic) = magic else {
+            debug!(event_id = %event.id, "Ignoring event of unknown network");
+            self.metrics.event_filtered("unknown_network");
+            return;
+        };
+
+        let Some(backend) = self.config.backends.get(&magic) else {
+            // not silently, an operator may have forgotten to configure the network
+            info!(event_id = %event.id, author = %event.pubkey, %magic, "Ignoring txs of network without backend");
+            self.metrics.event_filtered("no_backend");
+            return;
+        };
+
+        if txs.is_empty() {
+            info!(event_id = %event.id, author = %event.pubkey, "No valid txs in event");
+            self.metrics.event_filtered("invalid_txs");
+            self.strike(&event);
+            return;
+        }
+
+        let txs = match package::sort(&txs) {
+            Ok(sorted) => sorted,
+            Err(e) => {
+                self.reject(&event, &txs, "package", &e.to_string()).await;
+                self.strike(&event);
+                return;
+            }
+        };
+
+        if self.seen.lock().unwrap().seen_txs(&txs) {
+            debug!(event_id = %event.id, "Already seen the txs of event");
+            self.metrics.event_filtered("duplicate_txs");
+            return;
+        }
+
+        let violation = self.policy.read().unwrap().check(&txs);
+        if let Some(violation) = violation {
+            self.reject(&event, &txs, "policy", &violation.to_string())
+                .await;
+            self.rate(&event, Conduct::PolicyReject);
+            return;
+        }
+
+        if let Some(rate_limiter) = &self.rate_limiter {
+            // taken once the event is queued, see enqueue
+            if !rate_limiter.lock().unwrap().allows(author, txs.len()) {
+                let count = self.metrics.event_filtered("rate_limited");
+                info!(event_id = %event.id, %author, count, "Dropping event over the rate limit");
+                return;
+            }
+        }
+
+        // authors on the allowlist are trusted with any number of txs, those only subscribed to
+        // aren't
+        let allowlisted = self
+            .allowed_authors
+            .read()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|allowed| allowed.contains(&author));
+        if let Some(daily_quota) = self.daily_quota.as_ref().filter(|_| !allowlisted) {
+            match daily_quota.lock().unwrap().check(author, txs.len()) {
+                Ok(true) => {}
+                Ok(false) => {
+                    let count = self.metrics.event_filtered("quota");
+                    info!(event_id = %event.id, %author, count, "Dropping event over the daily quota");
+                    return;
+                }
+                Err(e) => error!("Error reading store: {e}"),
+            }
+        }
+
+        if let Some(store) = &self.config.store {
+            match store.all_broadcast(&txs) {
+                Ok(true) => {
+                    debug!(event_id = %event.id, "Already broadcast the txs of event");
+                    self.metrics.event_filtered("already_broadcast");
+                    return;
+                }
+                Ok(false) => {}
+                Err(e) => error!("Error reading store: {e}"),
+            }
+            match store.record_event(&event, &relay, &txs) {
+                Ok(true) => {}
+                Ok(false) => {
+                    debug!(event_id = %event.id, "Already handled event");
+                    self.metrics.event_filtered("duplicate_event");
+                    return;
+                }
+                Err(e) => error!("Error writing store: {e}"),
+            }
+        }
+
+        let received = ReceivedTxs {
+            event_id: event.id,
+            author: event.pubkey,
+            relay,
+            kind: event.kind.as_u64(),
+            magic,
+            encoding,
+            txs: txs.clone(),
+        };
+        remember(&self.recent_received, received.clone());
+        // nobody listening is fine
+        let _ = self.received.send(received);
+
+        let submission = Submission {
+            event,
+            magic,
+            backend: backend.clone(),
+            txs,
+            batched: vec![],
+        };
+        let price = match self.answers_parent_request(&submission) {
+            true => None,
+            false => self.price(&submission.event),
+        };
+        match (price, cashu) {
+            (Some(msat), Some(token)) if self.cashu_wallet().is_some() => {
+                self.redeem(submission, &token, msat).await
+            }
+            (Some(msat), _) => self.request_payment(submission, msat).await,
+            (None, _) => self.enqueue(submission).await,
+        }
+    }
+
+    /// The wallet taking Cashu tokens, `None` when they aren't taken
+    fn cashu_wallet(&self) -> Option<&Wallet> {
+        self.config.payment.as_ref()?.cashu.as_deref()
+    }
+
+    /// Queue `submission` once its Cashu `token` is redeemed for at least `msat`, rejecting it
+    /// otherwise
+    async fn redeem(&self, submission: Submission, token: &str, msat: u64) {
+        let Some(wallet) = self.cashu_wallet() else {
+            return;
+        };
+        let event = &submission.event;
+        match wallet.redeem(token, msat).await {
+            Ok(sat) => {
+                info!(event_id = %event.id, sat, mint = wallet.mint(), "Event paid with ecash");
+                self.enqueue(submission).await;
+            }
+            Err(e) => {
+                self.reject(
+                    event,
+                    &submission.txs,
+                    "unpaid",
+                    &format!("ecash not redeemed: {e}"),
+                )
+                .await
+            }
+        }
+    }
+
+    /// Queue `submission` to be broadcast, telling the customer when it's a job. Its txs count as
+    /// seen, and against the rate limit of the author, from then on
+    async fn enqueue(&self, submission: Submission) {
+        let event = submission.event.clone();
+        let tx_count = submission.txs.len();
+        let txs = submission.txs.clone();
+        match self.queue.try_send(submission) {
+            Ok(()) => {
+                // only now, so events dropped before don't keep others with the same txs out
+                self.seen.lock().unwrap().record_txs(&txs);
+                if let Some(rate_limiter) = &self.rate_limiter {
+                    let author = protocol::author(&event).unwrap_or(event.pubkey);
+                    rate_limiter.lock().unwrap().take(author, tx_count);
+                }
+                let depth = self.queue_depth();
+                self.metrics.set_queue_depth(depth);
+                self.metrics.txs_received(tx_count);
+                debug!(event_id = %event.id, depth, "Queued event");
+                if event.kind == Kind::Custom(dvm::JOB_REQUEST_KIND) {
+                    self.give_feedback(&event, JobStatus::Processing, "queued to be broadcast")
+                        .await;
+                }
+            }
+            Err(_) => {
+                let count = self.metrics.event_filtered("queue_full");
+                warn!(event_id = %event.id, count, "Dropping event, the queue is full");
+                if event.kind == Kind::Custom(dvm::JOB_REQUEST_KIND) {
+                    self.give_feedback(&event, JobStatus::Error, "the queue is full")
+                        .await;
+                }
+            }
+        }
+    }
+
+    /// Ask the author of `submission` to pay `msat`, and hold it until the zap arrives. Jobs are
+    /// paid by zapping the feedback asking for the payment, transaction events with the invoice
+    /// sent in the result event, a zap of the event itself
+    async fn request_payment(&self, submission: Submission, msat: u64) {
+        self.expire_unpaid().await;
+        let event = &submission.event;
+        let requested = match event.kind == Kind::Custom(dvm::JOB_REQUEST_KIND) {
+            true => self.request_job_payment(event, msat).await,
+            false => {
+                self.request_event_payment(event, &submission.txs, msat)
+                    .await
+            }
+        };
+        match requested {
+            Ok((zapped, invoice)) => {
+                info!(event_id = %event.id, %zapped, msat, "Waiting for the payment of event");
+                self.unpaid.lock().unwrap().insert(
+                    zapped,
+                    Unpaid {
+                        submission,
+                        msat,
+                        invoice,
+                        since: Instant::now(),
+                    },
+                );
+            }
+            Err(e) => {
+                warn!(event_id = %event.id, "Error asking for payment: {e}");
+                let results = submission
+                    .txs
+                    .iter()
+                    .map(|tx| {
+                        TxResult::failed(
+                            tx.txid(),
+                            ResultStatus::Error,
+                            "couldn't ask for payment",
+                            None,
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                self.report(event, &results).await;
+            }
+        }
+    }
+
+    /// Publish the feedback asking to pay for `job`, with an invoice of the node if there's one,
+    /// returning the id of the feedback to zap otherwise
+    async fn request_job_payment(
+        &self,
+        job: &Event,
+        msat: u64,
+    ) -> anyhow::Result<(EventId, Option<Invoice>)> {
+        let invoice = self.node_invoice(job, msat).await?;
+        let extra = match invoice {
+            Some(_) => "pay the invoice to have the transactions broadcast",
+            None => "zap this event to have the transactions broadcast",
+        };
+        let feedback = dvm::feedback_event(
+            job,
+            JobStatus::PaymentRequired,
+            extra,
+            Some((
+                msat,
+                invoice.as_ref().map(|invoice| invoice.bolt11.as_str()),
+            )),
+        );
+        let feedback_id = self.send(feedback.to_event(&self.client.keys())?).await?;
+        Ok((feedback_id, invoice))
+    }
+
+    /// Publish an invoice for broadcasting the transactions of `event`: of the node if there's
+    /// one, otherwise of the broadcaster's lightning address zapping `event`. Returns the id of
+    /// `event`
+    async fn request_event_payment(
+        &self,
+        event: &Event,
+        txs: &[Transaction],
+        msat: u64,
+    ) -> anyhow::Result<(EventId, Option<Invoice>)> {
+        let invoice = self.node_invoice(event, msat).await?;
+        let bolt11 = match &invoice {
+            Some(invoice) => invoice.bolt11.clone(),
+            None => {
+                let Some(endpoint) = self
+                    .config
+                    .payment
+                    .as_ref()
+                    .and_then(|payment| payment.endpoint.as_ref())
+                else {
+                    bail!("No lightning address or node to make invoices with");
+                };
+                let keys = self.client.keys();
+                let zap_request = nip57::zap_request(
+                    &keys,
+                    keys.public_key(),
+                    event.id,
+                    msat,
+                    &self.relay_urls(),
+                )?;
+                lnurl::zap_invoice(endpoint, &zap_request, msat).await?
+            }
+        };
+        let results = txs
+            .iter()
+            .map(|tx| {
+                TxResult::failed(
+                    tx.txid(),
+                    ResultStatus::PaymentRequired,
+                    format!("pay {msat} msat to have it broadcast"),
+                    None,
+                )
+            })
+            .collect::<Vec<_>>();
+        self.publish(protocol::invoice_event(event, &results, msat, &bolt11))
+            .await?;
+        Ok((event.id, invoice))
+    }
+
+    /// An invoice of the lightning node over `msat` for `event`, `None` without a node
+    async fn node_invoice(&self, event: &Event, msat: u64) -> anyhow::Result<Option<Invoice>> {
+        let Some(payment) = &self.config.payment else {
+            return Ok(None);
+        };
+        let Some(node) = &payment.invoices else {
+            return Ok(None);
+        };
+        let invoice = node
+            .create_invoice(
+                msat,
+                &format!("nostr-tx-broadcast-{}", event.id),
+                &format!("Broadcasting the transactions of nostr event {}", event.id),
+                payment.timeout,
+            )
+            .await?;
+        debug!(event_id = %event.id, node = node.name(), payment_hash = invoice.payment_hash, "Created invoice");
+        Ok(Some(invoice))
+    }
+
+    /// Check the invoices of the node every [`INVOICE_POLL_INTERVAL`], queueing the submissions
+    /// whose invoice was paid
+    async fn poll_invoices(&self) {
+        let Some(node) = self
+            .config
+            .payment
+            .as_ref()
+            .and_then(|payment| payment.invoices.as_ref())
+        else {
+            return future::pending().await;
+        };
+        let mut interval = tokio::time::interval(INVOICE_POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            self.expire_unpaid().await;
+            let invoices: Vec<(EventId, Invoice)> = self
+                .unpaid
+                .lock()
+                .unwrap()
+                .iter()
+                .filter_map(|(zapped, unpaid)| Some((*zapped, unpaid.invoice.clone()?)))
+                .collect();
+            for (zapped, invoice) in invoices {
+                match node.is_paid(&invoice).await {
+                    Ok(true) => {
+                        let unpaid = self.unpaid.lock().unwrap().remove(&zapped);
+                        if let Some(unpaid) = unpaid {
+                            info!(event_id = %unpaid.submission.event.id, msat = unpaid.msat, "Event paid");
+                            self.enqueue(unpaid.submission).await;
+                        }
+                    }
+                    Ok(false) => {}
+                    Err(e) => {
+                        warn!(
+                            node = node.name(),
+                            payment_hash = invoice.payment_hash,
+                            "Error checking invoice: {e}"
+                        )
+                    }
+                }
+            }
+        }
+    }
+
+    /// Drop the submissions not paid within the payment timeout
+    async fn expire_unpaid(&self) {
+        let Some(payment) = &self.config.payment else {
+            return;
+        };
+        let expired: Vec<Submission> = {
+            let mut unpaid = self.unpaid.lock().unwrap();
+            let expired = unpaid
+                .iter()
+                .filter(|(_, unpaid)| unpaid.since.elapsed() >= payment.timeout)
+                .map(|(zapped, _)| *zapped)
+                .collect::<Vec<_>>();
+            expired
+                .iter()
+                .filter_map(|zapped| unpaid.remove(zapped))
+                .map(|unpaid| unpaid.submission)
+                .collect()
+        };
+        for submission in expired {
+            self.reject(
+                &submission.event,
+                &submission.txs,
+                "unpaid",
+                "payment not received in time",
+            )
+            .await;
+        }
+    }
+
+    /// Queue the submission paid for by the zap receipt `event`
+    async fn zapped(&self, event: Event) {
+        let Some(payment) = &self.config.payment else {
+            return;
+        };
+        self.expire_unpaid().await;
+        let Some(zapper) = payment.zapper else {
+            return;
+        };
+        let receipt = match nip57::receipt(&event, zapper) {
+            Ok(receipt) => receipt,
+            Err(e) => {
+                debug!(event_id = %event.id, "Ignoring zap receipt: {e}");
+                return;
+            }
+        };
+        let submission = {
+            let mut unpaid = self.unpaid.lock().unwrap();
+            match unpaid.get(&receipt.zapped) {
+                Some(pending) if receipt.amount_msat < pending.msat => {
+                    info!(zapped = %receipt.zapped, msat = receipt.amount_msat, "Zap too small to pay for event");
+                    return;
+                }
+                Some(_) => unpaid
+                    .remove(&receipt.zapped)
+                    .map(|unpaid| unpaid.submission),
+                None => None,
+            }
+        };
+        let Some(submission) = submission else {
+            return;
+        };
+        info!(event_id = %submission.event.id, msat = receipt.amount_msat, "Event paid");
+        self.enqueue(submission).await;
+    }
+
+    /// Publish feedback on `job`
+    async fn give_feedback(&self, job: &Event, status: JobStatus, extra: &str) {
+        let feedback = dvm::feedback_event(job, status, extra, None);
+        if let Err(e) = self.publish(Ok(feedback)).await {
+            warn!(event_id = %job.id, "Error publishing job feedback: {e}");
+        }
+    }
+
+    /// Broadcast the queued events, no faster than the global limit and not while paused, until
+    /// `stopped` then drain the queue
+    async fn submit_queued(&self, mut stopped: watch::Receiver<bool>) {
+        let mut paused = self.paused.subscribe();
+        let mut queued = self.queued.lock().await;
+        let mut interval = self.config.max_broadcasts_per_second.map(|limit| {
+            let mut interval =
+                tokio::time::interval(Duration::from_secs_f64(1.0 / limit.get() as f64));
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            interval
+        });
+
+        // events waiting out their jitter, each with when it's due
+        let mut delayed: Vec<(Instant, Submission)> = vec![];
+
+        loop {
+            let due = delayed.iter().map(|(due, _)| *due).min();
+            let ready = tokio::select! {
+                submission = queued.recv(), if !*paused.borrow_and_update() => {
+                    let Some(submission) = submission else {
+                        return;
+                    };
+                    let submissions = match self.config.batch_window {
+                        Some(window) => self.batch(submission, &mut queued, window).await,
+                        None => vec![submission],
+                    };
+                    let mut ready = vec![];
+                    for submission in submissions {
+                        match self.broadcast_delay(&submission) {
+                            Some(delay) => {
+                                debug!(event_id = %submission.event.id, ?delay, "Delaying the broadcast of event");
+                                delayed.push((Instant::now() + delay, submission));
+                            }
+                            None => ready.push(submission),
+                        }
+                    }
+                    ready
+                }
+                _ = tokio::time::sleep_until(due.unwrap_or_else(Instant::now).into()), if due.is_some() && !*paused.borrow() => {
+                    let next = delayed.iter().position(|(at, _)| Some(*at) == due).unwrap_or_default();
+                    vec![delayed.swap_remove(next).1]
+                }
+                _ = paused.changed() => continue,
+                _ = self.flush.notified() => {
+                    let mut flushed = 0;
+                    for submission in delayed
+                        .drain(..)
+                        .map(|(_, submission)| submission)
+                        .chain(std::iter::from_fn(|| queued.try_recv().ok()))
+                    {
+                        debug!(event_id = %submission.event.id, "Dropping queued event");
+                        self.metrics.event_filtered("flushed");
+                        flushed += 1;
+                    }
+                    self.metrics.set_queue_depth(self.queue_depth());
+                    info!(flushed, "Flushed the queue");
+                    continue;
+                }
+                _ = stopped.wait_for(|stopped| *stopped) => break,
+            };
+            for submission in ready {
+                if self.config.require_synced {
+                    // the queue waits, unless shutting down
+                    tokio::select! {
+                        _ = self.wait_synced(submission.backend.as_ref()) => {}
+                        _ = stopped.wait_for(|stopped| *stopped) => {}
+                    }
+                }
+                self.submit_limited(submission, &mut interval).await;
+            }
+        }
+
+        // the delayed ones first, they arrived before those still queued
+        let depth = self.queue_depth() + delayed.len();
+        if depth > 0 && self.is_paused() {
+            warn!(
+                depth,
+                "Not broadcasting the queued events, broadcasting is paused"
+            );
+            return;
+        }
+        if depth > 0 {
+            info!(depth, "Broadcasting the queued events before shutting down");
+        }
+        delayed.sort_by_key(|(due, _)| *due);
+        for (_, submission) in delayed {
+            self.submit_limited(submission, &mut interval).await;
+        }
+        while let Ok(submission) = queued.try_recv() {
+            self.submit_limited(submission, &mut interval).await;
+        }
+    }
+
+    /// `first` and the events queued within `window` after it, those with related txs merged into
+    /// the earliest of them to be broadcast together as a package
+    async fn batch(
+        &self,
+        first: Submission,
+        queued: &mut mpsc::Receiver<Submission>,
+        window: Duration,
+    ) -> Vec<Submission> {
+        let deadline = tokio::time::Instant::now() + window;
+        let mut received = vec![first];
+        while let Ok(Some(submission)) = tokio::time::timeout_at(deadline, queued.recv()).await {
+            received.push(submission);
+        }
+
+        let mut batches: Vec<Submission> = vec![];
+        for submission in received {
+            let related = batches.iter_mut().find(|batch| {
+                batch.magic == submission.magic
+                    && std::iter::once(&batch.txs)
+                        .chain(batch.batched.iter().map(|batched| &batched.txs))
+                        .any(|txs| package::related(txs, &submission.txs))
+            });
+            match related {
+                Some(batch) => {
+                    debug!(event_id = %submission.event.id, batched_with = %batch.event.id, "Batching event with a related one");
+                    batch.batched.push(submission);
+                }
+                None => batches.push(submission),
+            }
+        }
+        batches
+    }
+
+    /// How long to hold `submission` before broadcasting it, `None` when it's broadcast right away
+    fn broadcast_delay(&self, submission: &Submission) -> Option<Duration> {
+        let jitter = self.config.broadcast_jitter.map(|jitter| jitter.sample());
+        let cooperative = self.config.cooperative_delay.map(|max| {
+            let txids: Vec<Txid> = submission.txs.iter().map(Transaction::txid).collect();
+            jitter::cooperative_delay(&self.client.keys().public_key(), &txids, max)
+        });
+        match (jitter, cooperative) {
+            (None, None) => None,
+            (jitter, cooperative) => {
+                Some(jitter.unwrap_or_default() + cooperative.unwrap_or_default())
+            }
+        }
+    }
+
+    /// Whether all of `txs` are in the mempool of `backend` or mined already, broadcast by
+    /// someone else. Backends that can't tell never have them
+    async fn propagating(&self, backend: &dyn TxBackend, txs: &[Transaction]) -> bool {
+        for tx in txs {
+            match backend.tx_status(&tx.txid()).await {
+                Ok(Some(TxStatus::InMempool | TxStatus::Confirmed { .. })) => {}
+                Ok(_) => return false,
+                Err(e) => {
+                    warn!(txid = %tx.txid(), backend = backend.name(), "Error looking up tx: {e}");
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Wait until `backend` finished its initial block download. Backends that can't tell, or fail
+    /// to, are taken as ready
+    async fn wait_synced(&self, backend: &dyn TxBackend) {
+        let mut waiting = false;
+        loop {
+            match backend.is_synced().await {
+                Ok(Some(false)) => {}
+                Ok(_) => break,
+                Err(e) => {
+                    warn!(
+                        backend = backend.name(),
+                        "Error checking whether backend is synced: {e}"
+                    );
+                    break;
+                }
+            }
+            if !waiting {
+                info!(
+                    backend = backend.name(),
+                    "Holding txs until the backend is synced"
+                );
+                waiting = true;
+            }
+            tokio::time::sleep(SYNC_CHECK_INTERVAL).await;
+        }
+        if waiting {
+            info!(
+                backend = backend.name(),
+                "Backend is synced, broadcasting again"
+            );
+        }
+    }
+
+    async fn submit_limited(&self, submission: Submission, interval: &mut Option<Interval>) {
+        if let Some(interval) = interval {
+            interval.tick().await;
+        }
+        self.metrics.set_queue_depth(self.queue_depth());
+        self.submit(submission).await;
+    }
+
+    async fn submit(&self, submission: Submission) {
+        let Submission {
+            event,
+            magic,
+            backend,
+            txs,
+            batched,
+        } = submission;
+        // the events whose txs are broadcast together, each with its own txs
+        let mut events = vec![(event, txs)];
+        events.extend(
+            batched
+                .into_iter()
+                .map(|submission| (submission.event, submission.txs)),
+        );
+        let txs = match events.as_slice() {
+            [(_, txs)] => txs.clone(),
+            _ => {
+                let mut all: Vec<Transaction> = vec![];
+                for tx in events.iter().flat_map(|(_, txs)| txs) {
+                    if !all.iter().any(|other| other.txid() == tx.txid()) {
+                        all.push(tx.clone());
+                    }
+                }
+                let event_ids: Vec<String> =
+                    events.iter().map(|(event, _)| event.id.to_hex()).collect();
+                info!(
+                    event_ids = event_ids.join(","),
+                    "Broadcasting the txs of related events together"
+                );
+                package::sort(&all).unwrap_or(all)
+            }
+        };
+        let event = &events[0].0;
+
+        if self.config.cooperative_delay.is_some() && self.propagating(backend.as_ref(), &txs).await
+        {
+            let count = self.metrics.event_filtered("propagating");
+            info!(event_id = %event.id, count, "Not broadcasting event, its txs are propagating already");
+            let results = txs
+                .iter()
+                .map(|tx| TxResult::propagating(tx.txid()))
+                .collect::<Vec<_>>();
+            self.report_each(&events, &results).await;
+            return;
+        }
+
+        let policy = self.policy.read().unwrap().clone();
+        match policy.check_feerate(backend.as_ref(), &txs).await {
+            Ok(Some(violation)) => {
+                for (event, txs) in &events {
+                    self.reject(event, txs, "feerate", &violation.to_string())
+                        .await;
+                    self.rate(event, Conduct::PolicyReject);
+                }
+                return;
+            }
+            Ok(None) => {}
+            // not knowing what they pay, they can't be let through
+            Err(e) => {
+                warn!(event_id = %event.id, "Error checking the feerate: {e}");
+                let reason = format!("feerate-unknown: {e}");
+                for (event, txs) in &events {
+                    self.reject(event, txs, "feerate", &reason).await;
+                }
+                return;
+            }
+        }
+
+        if self.config.detect_conflicts {
+            for conflict in conflicts::detect(&self.spends, backend.as_ref(), &txs).await {
+                let event = events
+                    .iter()
+                    .find(|(_, txs)| txs.iter().any(|tx| tx.txid() == conflict.conflict.txid))
+                    .map_or(event, |(event, _)| event);
+                self.alert_conflict(event, conflict).await;
+            }
+        }
+
+        // why the dry run would reject txs, to explain a failing broadcast
+        let mut rejections = HashMap::new();
+        // the feerates the dry run would accept txs with
+        let mut feerates = HashMap::new();
+        match backend.test_accept(&txs).await {
+            Ok(Some(results)) => {
+                for result in &results {
+                    if let Some(feerate) = result.feerate {
+                        feerates.insert(result.txid, feerate);
+                    }
+                }
+                for result in results.into_iter().filter(|result| !result.allowed) {
+                    let reason = result
+                        .reject_reason
+                        .unwrap_or_else(|| "unknown reason".to_string());
+                    info!(event_id = %event.id, txid = %result.txid, %reason, "Tx fails the dry run");
+                    rejections.insert(result.txid, reason);
+                }
+                for (event, txs) in &events {
+                    if txs.iter().any(|tx| rejections.contains_key(&tx.txid())) {
+                        self.strike(event);
+                    }
+                }
+                if !rejections.is_empty() && self.config.skip_rejected {
+                    info!(event_id = %event.id, "Not broadcasting txs failing the dry run");
+                    for _ in &events {
+                        self.metrics.event_filtered("dry_run");
+                    }
+                    let results = txs
+                        .iter()
+                        .map(|tx| match rejections.remove(&tx.txid()) {
+                            Some(reason) => TxResult::failed(
+                                tx.txid(),
+                                ResultStatus::Rejected,
+                                reason,
+                                Some(backend.name()),
+                            ),
+                            None => TxResult::failed(
+                                tx.txid(),
+                                ResultStatus::Filtered,
+                                "not broadcast",
+                                None,
+                            ),
+                        })
+                        .collect::<Vec<_>>();
+                    self.report_each(&events, &results).await;
+                    return;
+                }
+            }
+            Ok(None) => {}
+            Err(e) => {
+                warn!(event_id = %event.id, backend = backend.name(), "Error in dry run: {e}")
+            }
+        }
+
+        let result = match backend::broadcast_txs(backend.as_ref(), txs.clone()).await {
+            Ok(results) => Ok(self
+                .adopt_orphans(backend.as_ref(), &events, &txs, results)
+                .await),
+            // a single tx is rejected with an error
+            Err(e) if backend::is_missing_inputs(&e.to_string()) => {
+                let results = txs
+                    .iter()
+                    .map(|tx| PackageTxResult {
+                        txid: tx.txid(),
+                        error: Some(e.to_string()),
+                    })
+                    .collect();
+                let results = self
+                    .adopt_orphans(backend.as_ref(), &events, &txs, results)
+                    .await;
+                match results.iter().all(|result| result.error.is_some()) {
+                    true => Err(e),
+                    false => Ok(results),
+                }
+            }
+            Err(e) => Err(e),
+        };
+        // why each tx wasn't accepted, `None` for the accepted ones
+        let errors: HashMap<Txid, Option<String>> = match &result {
+            Ok(results) => results
+                .iter()
+                .map(|result| (result.txid, result.error.clone()))
+                .collect(),
+            Err(e) => {
+                warn!(event_id = %event.id, backend = backend.name(), "Error broadcasting txs: {e}");
+                txs.iter()
+                    .map(|tx| (tx.txid(), Some(e.to_string())))
+                    .collect()
+            }
+        };
+        // a failing backend is an error, not a verdict on the txs
+        let failure_status = match result {
+            Ok(_) => ResultStatus::Rejected,
+            Err(_) => ResultStatus::Error,
+        };
+        let error = |tx: &Transaction| match errors.get(&tx.txid()) {
+            Some(error) => error.clone(),
+            None => Some("no result".to_string()),
+        };
+
+        for (event, txs) in &events {
+            let accepted: Vec<Transaction> = txs
+                .iter()
+                .filter(|tx| error(tx).is_none())
+                .cloned()
+                .collect();
+            self.metrics.broadcast(accepted.len() == txs.len());
+            if accepted.is_empty() {
+                continue;
+            }
+            *self.last_broadcast.lock().unwrap() = Some(Instant::now());
+            self.rate(event, Conduct::Accepted);
+            if self.config.detect_conflicts {
+                self.spends
+                    .lock()
+                    .unwrap()
+                    .record(event.id, event.pubkey, &accepted);
+            }
+            if let Some(tracker) = &self.tracker {
+                tracker.track(event.id, event.pubkey, magic, &accepted);
+            }
+        }
+
+        let results = txs
+            .iter()
+            .map(|tx| match error(tx) {
+                None => TxResult::accepted(
+                    tx.txid(),
+                    Some(backend.name()),
+                    feerates.get(&tx.txid()).copied(),
+                ),
+                Some(e) => match rejections.remove(&tx.txid()) {
+                    Some(reason) => TxResult::failed(
+                        tx.txid(),
+                        ResultStatus::Rejected,
+                        reason,
+                        Some(backend.name()),
+                    ),
+                    None => TxResult::failed(tx.txid(), failure_status, e, Some(backend.name())),
+                },
+            })
+            .collect::<Vec<_>>();
+        self.report_each(&events, &results).await;
+
+        if let Some(store) = &self.config.store {
+            for (event, txs) in &events {
+                for tx in txs {
+                    let error = error(tx);
+                    if let Err(e) =
+                        store.record_broadcast(event.id, std::slice::from_ref(tx), error.as_deref())
+                    {
+                        error!("Error writing store: {e}");
+                    }
+                }
+            }
+        }
+    }
+
+    /// Broadcast the txs of `results` rejected for missing inputs again, together with their
+    /// unconfirmed ancestors as a package when they can be found, returning the updated results.
+    /// With parent requests, the author of each orphan is asked for its own ancestors not found
+    async fn adopt_orphans(
+        &self,
+        backend: &dyn TxBackend,
+        events: &[(Event, Vec<Transaction>)],
+        txs: &[Transaction],
+        mut results: Vec<PackageTxResult>,
+    ) -> Vec<PackageTxResult> {
+        let orphans: Vec<Transaction> = txs
+            .iter()
+            .filter(|tx| is_orphan(&results, tx.txid()))
+            .cloned()
+            .collect();
+        if orphans.is_empty() {
+            return results;
+        }
+        // looked up for each orphan, as the orphans of a batch may be of different authors
+        let mut ancestors: Vec<Transaction> = vec![];
+        let mut missing: Vec<(Txid, Vec<Txid>)> = vec![];
+        for orphan in &orphans {
+            let (found, not_found) = self
+                .find_ancestors(backend, txs, std::slice::from_ref(orphan))
+                .await;
+            for tx in found {
+                if !ancestors.iter().any(|other| other.txid() == tx.txid()) {
+                    ancestors.push(tx);
+                }
+            }
+            if !not_found.is_empty() {
+                missing.push((orphan.txid(), not_found));
+            }
+        }
+        if ancestors.is_empty() {
+            debug!(
+                orphans = orphans.len(),
+                "Found no parents of the txs missing inputs"
+            );
+        } else {
+            results = self
+                .broadcast_with_ancestors(backend, ancestors, &orphans, results)
+                .await;
+        }
+
+        if self.config.request_parents {
+            for (txid, parents) in &missing {
+                if !is_orphan(&results, *txid) {
+                    continue;
+                }
+                let Some((event, _)) = events
+                    .iter()
+                    .find(|(_, txs)| txs.iter().any(|tx| tx.txid() == *txid))
+                else {
+                    continue;
+                };
+                self.request_parents(event, *txid, parents).await;
+            }
+        }
+        results
+    }
+
+    /// Broadcast `orphans` again as a package with their `ancestors`, merging their results into
+    /// `results`
+    async fn broadcast_with_ancestors(
+        &self,
+        backend: &dyn TxBackend,
+        ancestors: Vec<Transaction>,
+        orphans: &[Transaction],
+        mut results: Vec<PackageTxResult>,
+    ) -> Vec<PackageTxResult> {
+        let package: Vec<Transaction> = ancestors.iter().chain(orphans).cloned().collect();
+        let package = package::sort(&package).unwrap_or(package);
+        info!(
+            orphans = orphans.len(),
+            parents = ancestors.len(),
+            "Broadcasting txs missing inputs again with their parents"
+        );
+        let retried = match backend::broadcast_txs(backend, package).await {
+            Ok(retried) => retried,
+            Err(e) => {
+                warn!(
+                    backend = backend.name(),
+                    "Error broadcasting txs with their parents: {e}"
+                );
+                return results;
+            }
+        };
+        for result in &retried {
+            match results.iter_mut().find(|other| other.txid == result.txid) {
+                Some(other) => *other = result.clone(),
+                None => match &result.error {
+                    None => {
+                        info!(txid = %result.txid, backend = backend.name(), "Broadcasted parent tx")
+                    }
+                    Some(error) => info!(txid = %result.txid, %error, "Parent tx rejected"),
+                },
+            }
+        }
+        results
+    }
+
+    /// The ancestors of `orphans` that aren't in `txs` nor in the mempool or the chain of
+    /// `backend`, found among the txs received before or with `backend`, parents first. At most
+    /// [`MAX_ANCESTORS`]. Also the txids of the ones not found
+    async fn find_ancestors(
+        &self,
+        backend: &dyn TxBackend,
+        txs: &[Transaction],
+        orphans: &[Transaction],
+    ) -> (Vec<Transaction>, Vec<Txid>) {
+        let mut known: HashSet<Txid> = txs.iter().map(Transaction::txid).collect();
+        let mut wanted: VecDeque<Txid> = orphans
+            .iter()
+            .flat_map(|tx| &tx.input)
+            .map(|input| input.previous_output.txid)
+            .filter(|txid| !known.contains(txid))
+            .collect();
+        let mut ancestors = vec![];
+        let mut missing = vec![];
+        while let Some(txid) = wanted.pop_front() {
+            if ancestors.len() >= MAX_ANCESTORS {
+                break;
+            }
+            if !known.insert(txid) {
+                continue;
+            }
+            // nothing to add for the ones the backend has
+            if let Ok(Some(TxStatus::InMempool | TxStatus::Confirmed { .. })) =
+                backend.tx_status(&txid).await
+            {
+                continue;
+            }
+            let stored = match &self.config.store {
+                Some(store) => store.raw_tx(&txid).unwrap_or_else(|e| {
+                    error!("Error reading store: {e}");
+                    None
+                }),
+                None => None,
+            };
+            let tx = match stored {
+                Some(tx) => Some(tx),
+                None => backend.get_tx(&txid).await.unwrap_or_else(|e| {
+                    debug!(%txid, backend = backend.name(), "Error looking up parent tx: {e}");
+                    None
+                }),
+            };
+            let Some(tx) = tx else {
+                debug!(%txid, "Parent tx not found");
+                missing.push(txid);
+                continue;
+            };
+            debug!(%txid, "Found parent tx");
+            wanted.extend(tx.input.iter().map(|input| input.previous_output.txid));
+            ancestors.push(tx);
+        }
+        ancestors.reverse();
+        (ancestors, missing)
+    }
+
+    /// Ask the author of `event` for the `parents` of its tx `txid`, which can't be broadcast
+    /// without them
+    async fn request_parents(&self, event: &Event, txid: Txid, parents: &[Txid]) {
+        let parent_txids: Vec<String> = parents.iter().map(Txid::to_string).collect();
+        info!(
+            event_id = %event.id,
+            %txid,
+            parents = parent_txids.join(","),
+            "Requesting parent txs from the author"
+        );
+        let request = protocol::parent_request_event(
+            event.id,
+            event.pubkey,
+            &ParentRequest {
+                txid,
+                parents: parents.to_vec(),
+            },
+        );
+        if let Err(e) = self.publish(request).await {
+            warn!(%txid, "Error publishing parent request: {e}");
+            return;
+        }
+        self.parents_requested
+            .lock()
+            .unwrap()
+            .put(txid, (event.pubkey, parents.to_vec()));
+    }
+
+    /// Report to each of `events` the results of its txs among `results`
+    async fn report_each(&self, events: &[(Event, Vec<Transaction>)], results: &[TxResult]) {
+        for (event, txs) in events {
+            let results: Vec<TxResult> = txs
+                .iter()
+                .filter_map(|tx| results.iter().find(|result| result.txid == tx.txid()))
+                .cloned()
+                .collect();
+            self.report(event, &results).await;
+        }
+    }
+
+    /// Don't broadcast the transactions of `event` because of `reason`, counted as `filter`
+    async fn reject(&self, event: &Event, txs: &[Transaction], filter: &str, reason: &str) {
+        info!(event_id = %event.id, author = %event.pubkey, %reason, "Rejecting event");
+        self.metrics.event_filtered(filter);
+        let results = txs
+            .iter()
+            .map(|tx| TxResult::failed(tx.txid(), ResultStatus::Filtered, reason, None))
+            .collect::<Vec<_>>();
+        self.report(event, &results).await;
+    }
+
+    /// Add the chunk of a package carried by `event`, returning the transactions of the package
+    /// once its last chunk arrived
+    fn reassemble(&self, event: &Event, chunk: &Chunk) -> Option<Vec<Transaction>> {
+        let Some(data) = protocol::chunk_data(event) else {
+            info!(event_id = %event.id, author = %event.pubkey, "Invalid chunk in event");
+            self.metrics.event_filtered("invalid_txs");
+            self.strike(event);
+            return None;
+        };
+        let compression = protocol::compression(event).ok().flatten();
+        let progress = {
+            let mut reassembly = self.reassembly.lock().unwrap();
+            for _ in 0..reassembly.expire() {
+                self.metrics.event_filtered("chunk_timeout");
+            }
+            reassembly.add(
+                event.pubkey,
+                chunk,
+                protocol::magic(event),
+                compression,
+                data,
+            )
+        };
+        match progress {
+            Ok(Progress::Pending { received, total }) => {
+                debug!(event_id = %event.id, package = %chunk.package_id, received, total, "Waiting for the other chunks of package");
+                None
+            }
+            Ok(Progress::Complete(payload)) => {
+                match protocol::decode_package(&payload, compression) {
+                    Ok(txs) => {
+                        debug!(event_id = %event.id, package = %chunk.package_id, chunks = chunk.total, "Reassembled package");
+                        Some(txs)
+                    }
+                    Err(e) => {
+                        info!(event_id = %event.id, author = %event.pubkey, package = %chunk.package_id, "Invalid package: {e}");
+                        self.metrics.event_filtered("invalid_txs");
+                        self.strike(event);
+                        None
+                    }
+                }
+            }
+            Err(e) => {
+                info!(event_id = %event.id, author = %event.pubkey, "Dropping chunk: {e}");
+                self.metrics.event_filtered("chunk");
+                None
+            }
+        }
+    }
+
+    /// Count an invalid submission against the author of `event`
+    fn strike(&self, event: &Event) {
+        let author = protocol::author(event).unwrap_or(event.pubkey);
+        match self.config.denylist.strike(author) {
+            Ok(true) => {
+                warn!(%author, "Banned author after repeated invalid submissions")
+            }
+            Ok(false) => {}
+            Err(e) => error!(%author, "Error banning author: {e}"),
+        }
+        self.rate(event, Conduct::Invalid);
+    }
+
+    /// Account for the `conduct` of the author of `event` in their reputation
+    fn rate(&self, event: &Event, conduct: Conduct) {
+        let Some(reputation) = &self.reputation else {
+            return;
+        };
+        let author = protocol::author(event).unwrap_or(event.pubkey);
+        if let Some(until) = reputation.record(author, conduct) {
+            warn!(%author, %until, "Temporarily banned author with a bad reputation");
+        }
+    }
+
+    /// Publish what happened to the transactions of `event` to the subscribers, and to its
+    /// author when enabled
+    async fn report(&self, event: &Event, results: &[TxResult]) {
+        let outcome = Outcome {
+            event_id: event.id,
+            author: event.pubkey,
+            results: results.to_vec(),
+        };
+        remember(&self.recent_outcomes, outcome.clone());
+        // nobody listening is fine
+        let _ = self.outcomes.send(outcome);
+
+        // customers of jobs always get the result
+        if event.kind == Kind::Custom(dvm::JOB_REQUEST_KIND) {
+            let (status, extra) = match results.iter().find(|result| !result.accepted) {
+                Some(failed) => (
+                    JobStatus::Error,
+                    format!(
+                        "{} {}: {}",
+                        failed.txid,
+                        failed.status.as_str(),
+                        failed.error.as_deref().unwrap_or("unknown error")
+                    ),
+                ),
+                None => (JobStatus::Success, String::new()),
+            };
+            if let Err(e) = self.publish(dvm::result_event(event, results)).await {
+                warn!(event_id = %event.id, "Error publishing job result: {e}");
+            }
+            self.give_feedback(event, status, &extra).await;
+            return;
+        }
+
+        let (failed, results): (Vec<TxResult>, Vec<TxResult>) = match self.config.dm_failures {
+            true => results.iter().cloned().partition(|result| !result.accepted),
+            false => (vec![], results.to_vec()),
+        };
+        if !failed.is_empty() {
+            let lines: Vec<String> = failed
+                .iter()
+                .map(|result| {
+                    format!(
+                        "{} {}: {}",
+                        result.txid,
+                        result.status.as_str(),
+                        result.error.as_deref().unwrap_or("unknown error")
+                    )
+                })
+                .collect();
+            let message = format!(
+                "Transactions of your event {} weren't broadcast:\n{}",
+                event.id,
+                lines.join("\n")
+            );
+            self.send_private(event.id, event.pubkey, &message).await;
+        }
+
+        if !self.config.reply || results.is_empty() {
+            return;
+        }
+
+        let reply = protocol::result_event(event, &results);
+        if let Err(e) = self.publish(reply).await {
+            warn!(event_id = %event.id, "Error publishing result: {e}");
+        }
+    }
+
+    /// Send `message` about `event_id` to `author` in a NIP-17 private message
+    async fn send_private(&self, event_id: EventId, author: XOnlyPublicKey, message: &str) {
+        let sent = match nip17::gift_wrap(&self.client.keys(), author, message) {
+            Ok(gift_wrap) => self.send(gift_wrap).await,
+            Err(e) => Err(e),
+        };
+        match sent {
+            Ok(_) => debug!(%event_id, %author, "Sent private message"),
+            Err(e) => warn!(%event_id, %author, "Error sending private message: {e}"),
+        }
+    }
+
+    /// Log a double spend by a transaction of `event`, and publish an alert when enabled
+    async fn alert_conflict(&self, event: &Event, conflict: Conflict) {
+        let Conflict { conflict, original } = conflict;
+        warn!(
+            event_id = %event.id,
+            txid = %conflict.txid,
+            conflicting_txid = %conflict.conflicting_txid,
+            outpoint = %conflict.outpoint,
+            original_event_id = original.map(|(event_id, _)| event_id.to_hex()),
+            "Tx double spends another tx"
+        );
+        if !self.config.conflict_alerts {
+            return;
+        }
+
+        let alert = protocol::conflict_event(event.id, &conflict, original);
+        if let Err(e) = self.publish(alert).await {
+            warn!(txid = %conflict.txid, "Error publishing conflict alert: {e}");
+        }
+    }
+
+    /// Tell the author of the event that carried a transaction what happened to it, when replies
+    /// or private messages about failures are enabled
+    async fn notify_update(&self, update: Update) {
+        match update {
+            Update::Confirmed(confirmation) if self.config.reply => {
+                self.notify_confirmation(confirmation).await
+            }
+            Update::Failed(failure) if self.config.dm_failures => {
+                let message = format!(
+                    "Transaction {} of your event {} was given up on: {}",
+                    failure.txid, failure.event_id, failure.reason
+                );
+                self.send_private(failure.event_id, failure.author, &message)
+                    .await;
+            }
+            Update::Failed(failure) if self.config.reply => self.notify_failure(failure).await,
+            _ => {}
+        }
+    }
+
+    async fn notify_confirmation(&self, confirmation: Confirmation) {
+        let notification = protocol::confirmation_event(
+            confirmation.event_id,
+            confirmation.author,
+            &TxConfirmation {
+                txid: confirmation.txid,
+                block_hash: confirmation.block_hash,
+                height: confirmation.height,
+            },
+        );
+        if let Err(e) = self.publish(notification).await {
+            warn!(txid = %confirmation.txid, "Error publishing confirmation: {e}");
+        }
+    }
+
+    async fn notify_failure(&self, failure: Failure) {
+        let notification = protocol::failure_event(
+            failure.event_id,
+            failure.author,
+            &TxFailure {
+                txid: failure.txid,
+                error: failure.reason,
+            },
+        );
+        if let Err(e) = self.publish(notification).await {
+            warn!(txid = %failure.txid, "Error publishing failure: {e}");
+        }
+    }
+
+    async fn publish(&self, builder: anyhow::Result<EventBuilder>) -> anyhow::Result<()> {
+        let event = builder?.to_event(&self.client.keys())?;
+        self.send(event).await?;
+        Ok(())
+    }
+
+    /// Send `event` to the relays written to and the local relay, failing when it reached none of
+    /// them
+    async fn send(&self, event: Event) -> anyhow::Result<EventId> {
+        let mut sent = false;
+        if let Some(local) = &self.config.local_relay {
+            local.publish(event.clone());
+            sent = true;
+        }
+        for (url, relay) in self.client.relays().await {
+            if !relay.opts().write() {
+                continue;
+            }
+            match relay
+                .send_msg(ClientMessage::new_event(event.clone()), false)
+                .await
+            {
+                Ok(()) => sent = true,
+                Err(e) => debug!(relay = %url, event_id = %event.id, "Error sending event: {e}"),
+            }
+        }
+        if !sent {
+            bail!("No relay to publish to");
+        }
+        Ok(event.id)
+    }
+}
+
+/// Whether `txid` was rejected for missing inputs according to `results`
+fn is_orphan(results: &[PackageTxResult], txid: Txid) -> bool {
+    results.iter().any(|result| {
+        result.txid == txid
+            && result
+                .error
+                .as_deref()
+                .is_some_and(backend::is_missing_inputs)
+    })
+}
+
+/// Keep `item` among the most recent ones
+fn remember<T>(recent: &Mutex<VecDeque<(Timestamp, T)>>, item: T) {
+    let mut recent = recent.lock().unwrap();
+    recent.push_front((Timestamp::now(), item));
+    recent.truncate(RECENT);
+}
+
+/// Connect to `relay` in the background, subscribing to `subscription` once connected
+async fn connect(relay: &Relay, subscriptions: Vec<Filter>) {
+    relay.update_subscription_filters(subscriptions).await;
+    relay.connect(false).await;
+}
+
+/// The status of a relay, disconnected when it's out of the pool
+async fn relay_status(relay: Option<&Relay>) -> RelayStatus {
+    match relay {
+        Some(relay) => relay.status().await,
+        None => RelayStatus::Disconnected,
+    }
+}
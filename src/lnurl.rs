@@ -0,0 +1,130 @@
+//! Lightning addresses (LUD-16) and their LNURL pay endpoints (LUD-06), which hand out invoices,
+//! for zaps (NIP-57) when they have a nostr pubkey publishing the zap receipts.
+
+use anyhow::{anyhow, bail, Context};
+use nostr::prelude::*;
+use serde::Deserialize;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// How long the LNURL server may take to answer
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A `name@domain` lightning address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LightningAddress {
+    name: String,
+    domain: String,
+}
+
+impl FromStr for LightningAddress {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.trim().split_once('@') {
+            Some((name, domain)) if !name.is_empty() && !domain.is_empty() => Ok(Self {
+                name: name.to_lowercase(),
+                domain: domain.to_lowercase(),
+            }),
+            _ => bail!("invalid lightning address {s}, expected name@domain"),
+        }
+    }
+}
+
+impl LightningAddress {
+    /// Where its LNURL pay endpoint is described, over http for onion domains
+    fn url(&self) -> String {
+        let scheme = match self.domain.ends_with(".onion") {
+            true => "http",
+            false => "https",
+        };
+        format!(
+            "{scheme}://{}/.well-known/lnurlp/{}",
+            self.domain, self.name
+        )
+    }
+}
+
+/// The LNURL pay endpoint of a lightning address
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PayEndpoint {
+    /// Where invoices are asked for
+    pub callback: String,
+    /// Least millisatoshis it makes invoices for
+    pub min_sendable: u64,
+    /// Most millisatoshis it makes invoices for
+    pub max_sendable: u64,
+    /// Whether it takes zap requests and publishes zap receipts
+    #[serde(default)]
+    pub allows_nostr: bool,
+    /// Nostr pubkey it publishes the zap receipts with
+    #[serde(default)]
+    pub nostr_pubkey: Option<XOnlyPublicKey>,
+}
+
+#[derive(Deserialize)]
+struct Invoice {
+    pr: String,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    reason: String,
+}
+
+/// Look up the LNURL pay endpoint of `address`, which has to take zaps
+pub async fn pay_endpoint(address: &LightningAddress) -> anyhow::Result<PayEndpoint> {
+    let body = get(&address.url(), &[]).await?;
+    let endpoint: PayEndpoint = serde_json::from_str(&body)
+        .with_context(|| format!("Invalid LNURL pay endpoint of {}", address.url()))?;
+    if !endpoint.allows_nostr || endpoint.nostr_pubkey.is_none() {
+        bail!("The lightning address doesn't take zaps");
+    }
+    Ok(endpoint)
+}
+
+/// An invoice of `endpoint` over `msat` for the zap of `zap_request`
+pub async fn zap_invoice(
+    endpoint: &PayEndpoint,
+    zap_request: &Event,
+    msat: u64,
+) -> anyhow::Result<String> {
+    if msat < endpoint.min_sendable || msat > endpoint.max_sendable {
+        bail!(
+            "{msat} msat is outside what the lightning address takes, {} to {} msat",
+            endpoint.min_sendable,
+            endpoint.max_sendable
+        );
+    }
+    let body = get(
+        &endpoint.callback,
+        &[
+            ("amount", msat.to_string()),
+            ("nostr", zap_request.as_json()),
+        ],
+    )
+    .await?;
+    match serde_json::from_str::<Invoice>(&body) {
+        Ok(invoice) => Ok(invoice.pr),
+        Err(_) => match serde_json::from_str::<ErrorResponse>(&body) {
+            Ok(error) => Err(anyhow!(
+                "LNURL server refused the invoice: {}",
+                error.reason
+            )),
+            Err(_) => Err(anyhow!("Invalid invoice from the LNURL server")),
+        },
+    }
+}
+
+async fn get(url: &str, query: &[(&str, String)]) -> anyhow::Result<String> {
+    Ok(reqwest::Client::new()
+        .get(url)
+        .query(query)
+        .timeout(TIMEOUT)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?)
+}
@@ -0,0 +1,259 @@
+//! A small relay embedded in the broadcaster, so senders on the same network or onion service
+//! can publish their transaction events straight to it, without depending on public relays.
+//! It only accepts the transaction events the listener handles, keeps the latest events in memory
+//! and serves them and the broadcaster's replies to the subscriptions of its clients.
+
+use futures_util::{SinkExt, StreamExt};
+use nostr::prelude::*;
+use std::collections::VecDeque;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpStream;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, info};
+
+/// How many events are kept for the subscriptions asking for stored events
+const STORED_EVENTS: usize = 1000;
+
+/// Largest message accepted from a client, enough for the largest compressed package
+const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+/// Subscriptions each client may have open at once
+const MAX_SUBSCRIPTIONS: usize = 20;
+
+/// The events published to the relay, by its clients or by the broadcaster
+pub struct LocalRelay {
+    url: Url,
+    /// Kinds of the events clients may publish
+    kinds: Vec<u64>,
+    stored: Mutex<VecDeque<Event>>,
+    events: broadcast::Sender<Event>,
+}
+
+impl fmt::Debug for LocalRelay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalRelay")
+            .field("url", &self.url)
+            .field("kinds", &self.kinds)
+            .finish_non_exhaustive()
+    }
+}
+
+impl LocalRelay {
+    /// A relay reachable at `addr`, accepting events of `kinds` from its clients
+    pub fn new(addr: SocketAddr, kinds: Vec<u64>) -> anyhow::Result<Self> {
+        let (events, _) = broadcast::channel(1024);
+        Ok(Self {
+            url: Url::parse(&format!("ws://{addr}"))?,
+            kinds,
+            stored: Mutex::new(VecDeque::new()),
+            events,
+        })
+    }
+
+    /// The url of the relay, where its events come from
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Publish `event` to the clients subscribed to it, returning whether it's new
+    pub fn publish(&self, event: Event) -> bool {
+        let mut stored = self.stored.lock().unwrap();
+        if stored.iter().any(|stored| stored.id == event.id) {
+            return false;
+        }
+        stored.push_front(event.clone());
+        stored.truncate(STORED_EVENTS);
+        // nobody listening is fine
+        let _ = self.events.send(event);
+        true
+    }
+
+    /// The events published from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    /// Answer a message of a client, keeping track of its `subscriptions`
+    fn handle(
+        &self,
+        message: &str,
+        subscriptions: &mut Vec<(SubscriptionId, Vec<Filter>)>,
+    ) -> Vec<RelayMessage> {
+        let message = match ClientMessage::from_json(message) {
+            Ok(message) => message,
+            Err(e) => return vec![RelayMessage::new_notice(format!("invalid message: {e}"))],
+        };
+        match message {
+            ClientMessage::Event(event) => {
+                let event_id = event.id;
+                let (accepted, reason) = if !self.kinds.contains(&event.kind.as_u64()) {
+                    (
+                        false,
+                        "blocked: only bitcoin transaction events are accepted".into(),
+                    )
+                } else if let Err(e) = event.verify() {
+                    (false, format!("invalid: {e}"))
+                } else if self.publish(*event) {
+                    debug!(%event_id, "Event published to the local relay");
+                    (true, String::new())
+                } else {
+                    (true, "duplicate: already have this event".into())
+                };
+                vec![RelayMessage::new_ok(event_id, accepted, reason)]
+            }
+            ClientMessage::Req {
+                subscription_id,
+                filters,
+            } => {
+                // a REQ with the id of an open subscription replaces it
+                subscriptions.retain(|(id, _)| *id != subscription_id);
+                if subscriptions.len() >= MAX_SUBSCRIPTIONS {
+                    return vec![RelayMessage::new_notice(format!(
+                        "too many subscriptions, at most {MAX_SUBSCRIPTIONS}"
+                    ))];
+                }
+                let mut replies: Vec<_> = self
+                    .stored(&filters)
+                    .into_iter()
+                    .map(|event| RelayMessage::new_event(subscription_id.clone(), event))
+                    .collect();
+                replies.push(RelayMessage::new_eose(subscription_id.clone()));
+                subscriptions.push((subscription_id, filters));
+                replies
+            }
+            ClientMessage::Count {
+                subscription_id,
+                filters,
+            } => {
+                let count = self.stored(&filters).len();
+                vec![RelayMessage::new_count(subscription_id, count)]
+            }
+            ClientMessage::Close(subscription_id) => {
+                subscriptions.retain(|(id, _)| *id != subscription_id);
+                vec![]
+            }
+            ClientMessage::Auth(_) => {
+                vec![RelayMessage::new_notice("authentication isn't needed")]
+            }
+        }
+    }
+
+    /// The stored events matching `filters`, oldest first, the newest ones when a filter has a
+    /// limit
+    fn stored(&self, filters: &[Filter]) -> Vec<Event> {
+        let stored = self.stored.lock().unwrap();
+        let mut events: Vec<Event> = vec![];
+        for filter in filters {
+            let matching = stored
+                .iter()
+                .filter(|event| matches(filter, event))
+                .take(filter.limit.unwrap_or(usize::MAX));
+            for event in matching {
+                if !events.iter().any(|added| added.id == event.id) {
+                    events.push(event.clone());
+                }
+            }
+        }
+        events.sort_by_key(|event| event.created_at);
+        events
+    }
+}
+
+/// Serve `relay` on `addr` until an error occurs
+pub async fn serve(addr: SocketAddr, relay: Arc<LocalRelay>) -> anyhow::Result<()> {
+    let tcp = tokio::net::TcpListener::bind(addr).await?;
+    info!("Serving the local relay on ws://{addr}");
+    loop {
+        let (stream, peer) = tcp.accept().await?;
+        let relay = relay.clone();
+        tokio::spawn(async move {
+            match connection(&relay, stream).await {
+                Ok(()) => debug!(%peer, "Local relay client disconnected"),
+                Err(e) => debug!(%peer, "Local relay client dropped: {e}"),
+            }
+        });
+    }
+}
+
+/// Talk to a client until it disconnects
+async fn connection(relay: &LocalRelay, stream: TcpStream) -> anyhow::Result<()> {
+    let config = WebSocketConfig {
+        max_message_size: Some(MAX_MESSAGE_SIZE),
+        max_frame_size: Some(MAX_MESSAGE_SIZE),
+        ..Default::default()
+    };
+    let websocket = tokio_tungstenite::accept_async_with_config(stream, Some(config)).await?;
+    let (mut sink, mut source) = websocket.split();
+    let mut subscriptions = vec![];
+    let mut events = relay.subscribe();
+    loop {
+        tokio::select! {
+            message = source.next() => {
+                let Some(message) = message else {
+                    return Ok(());
+                };
+                match message? {
+                    Message::Text(text) => {
+                        for reply in relay.handle(&text, &mut subscriptions) {
+                            sink.send(Message::Text(reply.as_json())).await?;
+                        }
+                    }
+                    Message::Close(_) => return Ok(()),
+                    _ => {}
+                }
+            }
+            event = events.recv() => {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(missed)) => {
+                        debug!(missed, "Local relay client missed events");
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return Ok(()),
+                };
+                for (subscription_id, filters) in &subscriptions {
+                    if filters.iter().any(|filter| matches(filter, &event)) {
+                        let message = RelayMessage::new_event(subscription_id.clone(), event.clone());
+                        sink.send(Message::Text(message.as_json())).await?;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Whether `event` matches `filter`: its ids, authors, kinds, `#e` and `#p` tags, since and until,
+/// the other conditions are ignored
+fn matches(filter: &Filter, event: &Event) -> bool {
+    let prefixed = |prefixes: &Option<Vec<String>>, hex: String| {
+        prefixes
+            .as_ref()
+            .is_none_or(|prefixes| prefixes.iter().any(|prefix| hex.starts_with(prefix)))
+    };
+    let tags_event = filter.events.as_ref().is_none_or(|ids| {
+        event
+            .tags
+            .iter()
+            .any(|tag| matches!(tag, Tag::Event(id, ..) if ids.contains(id)))
+    });
+    let tags_pubkey = filter.pubkeys.as_ref().is_none_or(|pubkeys| {
+        event
+            .tags
+            .iter()
+            .any(|tag| matches!(tag, Tag::PubKey(pubkey, _) if pubkeys.contains(pubkey)))
+    });
+    prefixed(&filter.ids, event.id.to_hex())
+        && prefixed(&filter.authors, event.pubkey.to_string())
+        && filter
+            .kinds
+            .as_ref()
+            .is_none_or(|kinds| kinds.contains(&event.kind))
+        && tags_event
+        && tags_pubkey
+        && filter.since.is_none_or(|since| event.created_at >= since)
+        && filter.until.is_none_or(|until| event.created_at <= until)
+}
@@ -1,120 +1,1738 @@
-use anyhow::anyhow;
-use base64::{engine::general_purpose, Engine as _};
-use bitcoin::consensus::{serialize, Decodable};
+use anyhow::{bail, Context};
+use bitcoin::bip32::DerivationPath;
 use bitcoin::network::Magic;
-use bitcoin::Transaction;
-use hex_string::HexString;
-use nostr::prelude::*;
-use nostr::Keys;
-use nostr_sdk::relay::pool::RelayPoolNotification::*;
-use nostr_sdk::Client;
-use std::str::FromStr;
-
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let my_keys = Keys::generate();
-
-    let client = Client::new(&my_keys);
-    client.add_relay("wss://nostr.wine", None).await?;
-    client.add_relay("wss://nos.lol", None).await?;
-    client.add_relay("wss://nostr.fmt.wiz.biz", None).await?;
-    client.add_relay("wss://nostr.zebedee.cloud", None).await?;
-    client.add_relay("wss://relay.damus.io", None).await?;
+use bitcoin::{Transaction, Txid};
+use clap::{Parser, Subcommand};
+use nostr::prelude::XOnlyPublicKey;
+use nostr::{Keys, Url};
+use nostr_sdk::{Client, Options};
+use nostr_tx_broadcast::backend::{self, Bitcoind, Electrum, Esplora, FanOut, Measured, P2p};
+use nostr_tx_broadcast::bridge::{self, BridgeConfig};
+use nostr_tx_broadcast::cashu::Wallet;
+use nostr_tx_broadcast::compression::Compression;
+use nostr_tx_broadcast::config::{Config, NetworkConfig};
+#[cfg(unix)]
+use nostr_tx_broadcast::console;
+#[cfg(unix)]
+use nostr_tx_broadcast::daemon::{self, Pidfile};
+use nostr_tx_broadcast::dashboard;
+use nostr_tx_broadcast::dvm::{self, DvmConfig};
+use nostr_tx_broadcast::grpc;
+use nostr_tx_broadcast::health::Health;
+use nostr_tx_broadcast::http;
+use nostr_tx_broadcast::jitter::Jitter;
+use nostr_tx_broadcast::keys::{self, parse_secret_key};
+use nostr_tx_broadcast::lightning::{Cln, InvoiceBackend, Lnd};
+use nostr_tx_broadcast::listener::RelayRole;
+use nostr_tx_broadcast::lnurl::{self, LightningAddress};
+use nostr_tx_broadcast::local_relay::{self, LocalRelay};
+use nostr_tx_broadcast::metrics::Metrics;
+use nostr_tx_broadcast::nip05::Nip05Config;
+use nostr_tx_broadcast::nip65::{self, Usage};
+use nostr_tx_broadcast::output::{self, OutputFormat};
+use nostr_tx_broadcast::payment::PaymentConfig;
+use nostr_tx_broadcast::policy::Policy;
+use nostr_tx_broadcast::protocol::{self, TxEncoding};
+use nostr_tx_broadcast::proxy::parse_proxy;
+use nostr_tx_broadcast::pubkeys::{parse_pubkey, read_pubkeys, Denylist};
+use nostr_tx_broadcast::ratelimit::RateLimit;
+use nostr_tx_broadcast::reconnect::ReconnectConfig;
+use nostr_tx_broadcast::relays::{self, parse_relay, read_relays};
+use nostr_tx_broadcast::reputation::ReputationConfig;
+use nostr_tx_broadcast::scoring::ScoringConfig;
+use nostr_tx_broadcast::send::{self, SendConfig};
+use nostr_tx_broadcast::store::Store;
+#[cfg(unix)]
+use nostr_tx_broadcast::systemd;
+use nostr_tx_broadcast::tracker::TrackerConfig;
+use nostr_tx_broadcast::{Backends, Listener, ListenerConfig, TxBackend};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Method, StatusCode};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::future;
+use std::io::{self, Read};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::num::{NonZeroU32, NonZeroU64, NonZeroUsize};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::signal;
+use tracing::{error, info, warn};
+use tracing_subscriber::EnvFilter;
 
-    client.connect().await;
+const RELAYS: [&str; 6] = [
+    "wss://relay.damus.io",
+    "wss://nos.lol",
+    "wss://relay.primal.net",
+    "wss://relay.nostr.band",
+    "wss://nostr.mom",
+    "wss://relay.snort.social",
+];
+
+/// Most relays to listen on with --discover-public-relays, unless --max-relays says otherwise
+const MAX_PUBLIC_RELAYS: usize = 20;
+
+/// How long to wait for the relay lists of the senders and the operator
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often unreachable Bitcoin Core nodes are probed
+const BITCOIND_HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Args {
+    /// TOML config file, its values are overridden by the flags given on the command line
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    /// Relay to connect to, can be given multiple times [default: a built-in list of relays]
+    #[arg(long, global = true)]
+    relays: Vec<String>,
+    /// File with more relays to connect to, a url per line
+    #[arg(long, global = true)]
+    relay_file: Option<PathBuf>,
+    /// Relay only to receive transaction events from, not to publish to, can be given multiple
+    /// times
+    #[arg(long = "read-relay", global = true)]
+    read_relays: Vec<String>,
+    /// Relay only to publish results, confirmations and alerts to, not to receive transaction
+    /// events from, can be given multiple times
+    #[arg(long = "write-relay", global = true)]
+    write_relays: Vec<String>,
+    /// Don't fall back to the built-in list of relays when no --relays are given
+    #[arg(long, global = true)]
+    no_default_relays: bool,
+    /// Event kind of the transaction events, can be given multiple times to listen to several
+    /// kinds, the first one is published [default: 28333]
+    #[arg(long = "kind", global = true)]
+    kinds: Vec<u64>,
+    /// Secret key (nsec or hex) of the nostr identity to listen and send with
+    #[arg(long, global = true, env = "NOSTR_NSEC", hide_env_values = true)]
+    nsec: Option<String>,
+    /// File with the secret key of the nostr identity, created with a new key when missing
+    #[arg(long, global = true, conflicts_with = "nsec")]
+    key_file: Option<PathBuf>,
+    /// BIP39 mnemonic to derive the nostr identity from (NIP-06)
+    #[arg(
+        long,
+        global = true,
+        env = "NOSTR_MNEMONIC",
+        hide_env_values = true,
+        conflicts_with_all = ["nsec", "key_file"]
+    )]
+    mnemonic: Option<String>,
+    /// Passphrase of the mnemonic
+    #[arg(
+        long,
+        global = true,
+        env = "NOSTR_MNEMONIC_PASSPHRASE",
+        hide_env_values = true,
+        requires = "mnemonic"
+    )]
+    mnemonic_passphrase: Option<String>,
+    /// Derivation path of the nostr key of the mnemonic, e.g. m/44'/1237'/1'/0/0 for the
+    /// second account [default: m/44'/1237'/0'/0/0]
+    #[arg(long, global = true)]
+    derivation_path: Option<DerivationPath>,
+    /// SOCKS5 proxy to connect to the relays through, e.g. socks5://127.0.0.1:9050 for Tor
+    #[arg(long, global = true, value_parser = parse_proxy)]
+    proxy: Option<SocketAddr>,
+    /// Network: bitcoin, testnet, signet, regtest, mutinynet or a hex magic. When listening,
+    /// transactions of other networks are ignored, unless the config file has backends for them
+    #[arg(long, alias = "magic", global = true, value_parser = protocol::parse_magic)]
+    network: Option<Magic>,
+    /// Block challenge script (hex) of a custom signet, the network is the signet with the magic
+    /// derived from it
+    #[arg(long, global = true, conflicts_with = "network", value_parser = protocol::signet_magic)]
+    signet_challenge: Option<Magic>,
+    /// Bitcoin Core RPC url to broadcast to. Given multiple times, the next node is used when
+    /// one fails. Several backends can be combined, transactions are submitted to all of them.
+    /// Without any backend mempool.space is used
+    #[arg(long = "bitcoin-host", global = true)]
+    bitcoin_hosts: Vec<String>,
+    /// Bitcoin Core RPC user
+    #[arg(long, global = true, requires = "bitcoin_password")]
+    bitcoin_user: Option<String>,
+    /// Bitcoin Core RPC password
+    #[arg(long, global = true, requires = "bitcoin_user")]
+    bitcoin_password: Option<String>,
+    /// Bitcoin Core RPC cookie file, the default data directory's cookie of the network is used
+    /// when neither this nor a user is given
+    #[arg(long, global = true, conflicts_with = "bitcoin_user")]
+    bitcoin_cookie: Option<PathBuf>,
+    /// Esplora API url to broadcast to, e.g. https://blockstream.info/api, can be given
+    /// multiple times
+    #[arg(long = "esplora-url", global = true)]
+    esplora_urls: Vec<String>,
+    /// Electrum server to broadcast to, tcp://host:port or ssl://host:port, can be given
+    /// multiple times
+    #[arg(long = "electrum-url", global = true)]
+    electrum_urls: Vec<String>,
+    /// Skip the TLS certificate validation of the Electrum servers, for self-signed certificates
+    #[arg(long, global = true, requires = "electrum_urls")]
+    electrum_skip_tls_verify: bool,
+    /// Broadcast directly to bitcoin peers over the P2P protocol
+    #[arg(long, global = true)]
+    p2p: bool,
+    /// Peer (host:port) to broadcast to, can be given multiple times. Peers are discovered
+    /// through the network's DNS seeds when not given
+    #[arg(long = "p2p-peer", global = true, requires = "p2p")]
+    p2p_peers: Vec<String>,
+    /// Maximum number of peers to broadcast each transaction to
+    #[arg(long, global = true, default_value_t = 8, requires = "p2p")]
+    p2p_max_peers: usize,
+    /// Don't broadcast transactions that fail Bitcoin Core's testmempoolaccept dry run. Without
+    /// this the rejection reason is only logged
+    #[arg(long)]
+    skip_rejected: bool,
+    /// Look for received transactions double spending a transaction in the mempool (Bitcoin
+    /// Core 24.0 or later, or Esplora) or one broadcast before, and log both txids
+    #[arg(long)]
+    detect_conflicts: bool,
+    /// Also publish a kind 28336 alert for each double spend, implies --detect-conflicts
+    #[arg(long)]
+    conflict_alerts: bool,
+    /// Publish a kind 28338 request to the author of a transaction missing inputs for the
+    /// unconfirmed parents that can't be found in --db or with the backend
+    #[arg(long)]
+    request_parents: bool,
+    /// Hold the received transactions while Bitcoin Core is in its initial block download,
+    /// instead of broadcasting them to a node that can't judge them yet
+    #[arg(long)]
+    require_synced: bool,
+    /// How many event ids and transactions to remember, so duplicates delivered by the relays
+    /// aren't broadcast again [default: 10000]
+    #[arg(long)]
+    seen_cache_size: Option<NonZeroUsize>,
+    /// Seconds to wait before reconnecting to a relay that dropped, doubled after each failed
+    /// attempt [default: 1]
+    #[arg(long)]
+    reconnect_delay: Option<u64>,
+    /// Longest wait in seconds between attempts to reconnect to a relay [default: 300]
+    #[arg(long)]
+    max_reconnect_delay: Option<u64>,
+    /// Seconds a relay may stay quiet before it's pinged, 0 not to ping relays [default: 60]
+    #[arg(long)]
+    ping_interval: Option<u64>,
+    /// Seconds a relay may stay silent, pings unanswered, before the connection is dropped and
+    /// made again [default: 180]
+    #[arg(long)]
+    silence_timeout: Option<u64>,
+    /// Relays scoring lower than this, between 0 and 1, are demoted while another relay is
+    /// connected. 0 keeps using every relay [default: 0.25]
+    #[arg(long)]
+    min_relay_score: Option<f64>,
+    /// Seconds a demoted relay isn't used before it's tried again [default: 600]
+    #[arg(long)]
+    relay_demotion: Option<u64>,
+    /// Sqlite database recording every received event, transaction and broadcast outcome, so
+    /// transactions aren't broadcast again after a restart
+    #[arg(long)]
+    db: Option<PathBuf>,
+    /// Seconds the database remembers the ids of handled events, so the events relays deliver
+    /// again after a restart are skipped [default: 86400]
+    #[arg(long)]
+    replay_window: Option<u64>,
+    /// Follow broadcast transactions and rebroadcast the ones that drop out of the mempool until
+    /// they're confirmed
+    #[arg(long)]
+    rebroadcast: bool,
+    /// Seconds between checks of the broadcast transactions [default: 60]
+    #[arg(long)]
+    rebroadcast_interval: Option<u64>,
+    /// Rebroadcasts of a transaction before giving up on it [default: 10]
+    #[arg(long)]
+    rebroadcast_attempts: Option<u32>,
+    /// Confirmations after which a transaction is no longer followed [default: 6]
+    #[arg(long)]
+    rebroadcast_depth: Option<u32>,
+    /// bitcoind's ZMQ endpoint publishing `hashblock` (-zmqpubhashblock), e.g.
+    /// tcp://127.0.0.1:28332. With --rebroadcast the transactions are then checked on every block
+    /// instead of every interval
+    #[arg(long)]
+    zmq_block: Option<String>,
+    /// bitcoind's ZMQ endpoint publishing `sequence` (-zmqpubsequence). With --rebroadcast the
+    /// transactions are then checked as soon as one of them is evicted from the mempool
+    #[arg(long)]
+    zmq_tx: Option<String>,
+    /// Publish a reply to each transaction event telling its author whether the transactions
+    /// were accepted, and why not. With --rebroadcast, also publish when each of them confirms, or
+    /// is given up on after dropping out of the mempool
+    #[arg(long)]
+    reply: bool,
+    /// Tell the authors of events why their transactions failed in a NIP-17 private message
+    /// instead of publicly, also without --reply
+    #[arg(long)]
+    dm_failures: bool,
+    /// Only broadcast the transactions of events by this author (hex or npub), can be given
+    /// multiple times. Without an allowlist every author is accepted
+    #[arg(long = "allow-pubkey", value_parser = parse_pubkey)]
+    allow_pubkeys: Vec<XOnlyPublicKey>,
+    /// File with authors to accept, a pubkey per line
+    #[arg(long)]
+    allow_pubkeys_file: Option<PathBuf>,
+    /// Only subscribe to the events of these authors (hex or npub, comma separated), so the
+    /// relays filter out everyone else's. They're the allowlist when there's no other
+    #[arg(long, value_delimiter = ',', value_parser = parse_pubkey)]
+    only_authors: Vec<XOnlyPublicKey>,
+    /// Also listen on the relays the allowed authors publish to, from their NIP-65 relay lists
+    /// found on --relays
+    #[arg(long)]
+    discover_relays: bool,
+    /// Also listen on the relays this broadcaster operator (hex or npub) reads from, from its
+    /// NIP-65 relay list found on --relays
+    #[arg(long, value_parser = parse_pubkey)]
+    operator: Option<XOnlyPublicKey>,
+    /// Also listen on public relays picked at random among the ones --relay-directory lists as
+    /// online, up to --max-relays
+    #[arg(long)]
+    discover_public_relays: bool,
+    /// Directory service listing the public relays online, as a JSON array of urls [default:
+    /// https://api.nostr.watch/v1/online]
+    #[arg(long)]
+    relay_directory: Option<String>,
+    /// Most relays to listen on at once, the relays given come first and the discovered ones
+    /// fill up to it [default: unlimited, 20 with --discover-public-relays]
+    #[arg(long)]
+    max_relays: Option<usize>,
+    /// File with authors (a pubkey per line) whose events are ignored. Banned authors are
+    /// appended to it, so bans survive restarts
+    #[arg(long)]
+    deny_pubkeys_file: Option<PathBuf>,
+    /// Ban an author after this many events with invalid transactions: undecodable, failing to
+    /// decrypt or rejected by the dry run
+    #[arg(long)]
+    ban_after: Option<NonZeroU32>,
+    /// Keep a reputation score per author, lowered by invalid submissions and policy rejects,
+    /// raised by accepted transactions and decaying over time, and temporarily ban authors whose
+    /// score drops too low
+    #[arg(long)]
+    reputation: bool,
+    /// Temporarily ban authors when their reputation drops to minus this, an invalid submission
+    /// costing 1 and a policy reject 0.5 [default: 5]
+    #[arg(long)]
+    reputation_threshold: Option<f64>,
+    /// Seconds the first temporary ban of an author lasts, each next one twice as long
+    /// [default: 600]
+    #[arg(long)]
+    temp_ban: Option<u64>,
+    /// Seconds a temporary ban lasts at most [default: 86400]
+    #[arg(long)]
+    max_temp_ban: Option<u64>,
+    /// Seconds after which reputation scores and past bans halve [default: 3600]
+    #[arg(long)]
+    reputation_half_life: Option<u64>,
+    /// Ignore events without this much NIP-13 proof of work, the number of leading zero bits of
+    /// the event id
+    #[arg(long)]
+    min_pow: Option<u8>,
+    /// Ignore the events of authors without a NIP-05 identifier (name@domain in their metadata)
+    /// that their domain vouches for
+    #[arg(long)]
+    require_nip05: bool,
+    /// Only accept NIP-05 identifiers of these domains, implies --require-nip05
+    #[arg(long, value_delimiter = ',')]
+    nip05_domains: Vec<String>,
+    /// Seconds to remember whether an author's NIP-05 identifier verified [default: 3600]
+    #[arg(long)]
+    nip05_cache_ttl: Option<u64>,
+    /// Ignore events created more than this many seconds ago, such as old events replayed by a
+    /// relay
+    #[arg(long)]
+    max_event_age: Option<u64>,
+    /// Ignore events created more than this many seconds in the future
+    #[arg(long)]
+    max_future_drift: Option<u64>,
+    /// Seconds to wait for the other chunks of a package published in several events
+    /// [default: 60]
+    #[arg(long)]
+    chunk_timeout: Option<u64>,
+    /// Transactions an author may submit per minute, the events of authors over the limit are
+    /// dropped
+    #[arg(long)]
+    max_txs_per_minute: Option<NonZeroU32>,
+    /// Transactions an author may submit per hour
+    #[arg(long)]
+    max_txs_per_hour: Option<NonZeroU32>,
+    /// Transactions an author may submit per UTC day, counted in --db when given. Authors on the
+    /// allowlist of --allow-pubkey have no quota
+    #[arg(long)]
+    daily_quota: Option<NonZeroU32>,
+    /// Author (hex or npub) without a daily quota, can be given multiple times
+    #[arg(long = "quota-exempt-pubkey", value_parser = parse_pubkey)]
+    quota_exempt_pubkeys: Vec<XOnlyPublicKey>,
+    /// How many events can wait to be broadcast, events arriving when the queue is full are
+    /// dropped [default: 1000]
+    #[arg(long)]
+    queue_size: Option<NonZeroUsize>,
+    /// Events broadcast per second at most, over all authors, so a burst can't overwhelm the
+    /// backend
+    #[arg(long)]
+    max_broadcasts_per_second: Option<NonZeroU32>,
+    /// Milliseconds to wait for more events after taking one from the queue, such as 250, so a
+    /// parent and its child arriving in separate events are broadcast together as a package
+    #[arg(long)]
+    batch_window: Option<NonZeroU64>,
+    /// Wait a random time in this range before broadcasting each event, `MIN-MAX` or `MAX` in
+    /// seconds such as 1-30, so the time the txs reach the node's mempool says less about when
+    /// they were seen on the relays
+    #[arg(long)]
+    broadcast_jitter: Option<Jitter>,
+    /// Wait up to this many seconds before broadcasting each event, a time fixed per
+    /// broadcaster and txs, and skip the event when its txs reached the node's mempool
+    /// meanwhile, so broadcasters listening to the same relays share the load
+    #[arg(long)]
+    cooperative_delay: Option<NonZeroU64>,
+    /// Reject events with a transaction of a larger virtual size, in vbytes
+    #[arg(long)]
+    max_tx_vsize: Option<u64>,
+    /// Reject events whose transactions weigh more together, in weight units
+    #[arg(long)]
+    max_package_weight: Option<u64>,
+    /// Don't broadcast events whose transactions together pay less than this feerate in sat/vB,
+    /// or less than the node's mempool minimum fee. Needs a backend that can look up the spent
+    /// outputs, Bitcoin Core or Esplora: transactions whose feerate can't be found aren't
+    /// broadcast
+    #[arg(long)]
+    min_feerate: Option<f64>,
+    /// Address (e.g. 127.0.0.1:9100) of the HTTP server with Prometheus metrics at /metrics and
+    /// the health at /healthz. The status command asks the listener serving it
+    #[arg(long, alias = "metrics-addr", global = true)]
+    http_addr: Option<SocketAddr>,
+    /// Also serve the admin endpoints controlling the listener under /admin on --http-addr, which
+    /// should then only be reachable locally
+    #[arg(long)]
+    admin: bool,
+    /// Also serve POST /tx and POST /package on --http-addr, publishing the raw transactions in
+    /// their body as transaction events of --network, so wallets can submit them over HTTP
+    #[arg(long)]
+    submit_api: bool,
+    /// Bearer token the admin and submitting endpoints of --http-addr require in the
+    /// Authorization header, needed to serve them on an address that isn't loopback. The
+    /// add-relay, remove-relay, pause and resume commands send it
+    #[arg(
+        long,
+        global = true,
+        env = "NOSTR_TX_BROADCAST_HTTP_TOKEN",
+        hide_env_values = true
+    )]
+    http_token: Option<String>,
+    /// Unix socket of a line based admin console (status, pause, resume, flush-queue,
+    /// reload-config), to operate the listener without a network port, e.g. with
+    /// `socat - UNIX-CONNECT:<path>`
+    #[arg(long)]
+    admin_socket: Option<PathBuf>,
+    /// Address (e.g. 127.0.0.1:8080) of a web dashboard showing the relays, the recently
+    /// received events and their outcomes, and the transactions being rebroadcast
+    #[arg(long)]
+    dashboard_addr: Option<SocketAddr>,
+    /// Address (e.g. 0.0.0.0:4848) of a relay embedded in the broadcaster, so senders on the same
+    /// network or onion service can publish their transaction events to it directly. It gets the
+    /// replies too, and with --no-default-relays it's the only relay
+    #[arg(long)]
+    local_relay: Option<SocketAddr>,
+    /// Address (e.g. 127.0.0.1:50051) of the gRPC API other daemons drive the listener with, see
+    /// proto/broadcaster.proto. Submitted transactions are of --network unless they name another
+    #[arg(long)]
+    grpc_addr: Option<SocketAddr>,
+    /// Log level, or a filter like `info,nostr_tx_broadcast=debug` [default: info]
+    #[arg(long)]
+    log_level: Option<String>,
+    /// Log JSON objects instead of text
+    #[arg(long)]
+    log_json: bool,
+    /// Append the logs to this file instead of writing them to stderr
+    #[arg(long)]
+    log_file: Option<PathBuf>,
+    /// Run in the background, detached from the terminal, with stdout and stderr going to
+    /// --log-file. Exits once the listener started, or with an error code when it failed to
+    #[arg(long)]
+    daemon: bool,
+    /// Write the pid of the listener to this file, refusing to start while the process in it runs
+    #[arg(long)]
+    pidfile: Option<PathBuf>,
+    /// Log a summary of the received events, broadcasts, top rejection reasons and relays this
+    /// many seconds, a heartbeat when running headless
+    #[arg(long)]
+    stats_interval: Option<NonZeroU64>,
+    /// Also publish the summaries as text notes (kind 1) to the relays
+    #[arg(long, requires = "stats_interval")]
+    publish_stats: bool,
+    /// Publish an announcement of the broadcaster (kind 18333) with its networks, kinds and
+    /// policy this many seconds, so senders can discover it
+    #[arg(long)]
+    announce_interval: Option<NonZeroU64>,
+    /// Publish NIP-89 handler information (kind 31990) declaring that the broadcaster handles
+    /// the transaction event kinds, for nostr clients to offer it
+    #[arg(long)]
+    nip89: bool,
+    /// Also take NIP-90 job requests (kind 5333) to broadcast transactions, answered with job
+    /// feedback and a job result (kind 6333)
+    #[arg(long)]
+    dvm: bool,
+    /// Millisatoshis a job costs, asked for in the feedback, which has to be zapped before the
+    /// transactions are broadcast
+    #[arg(long, requires = "dvm")]
+    dvm_price: Option<u64>,
+    /// Millisatoshis broadcasting the transactions of an event costs, answered with an invoice
+    /// of the lightning node or --lightning-address which has to be paid before they're broadcast
+    #[arg(long)]
+    price: Option<u64>,
+    /// Lightning address (name@domain) of the broadcaster the payments go to, its LNURL server
+    /// has to take zaps
+    #[arg(long)]
+    lightning_address: Option<LightningAddress>,
+    /// Nostr pubkey (hex or npub) of the LNURL server of the broadcaster's lightning address,
+    /// which publishes the zap receipts [default: the nostrPubkey of --lightning-address]
+    #[arg(long, value_parser = parse_pubkey)]
+    zapper: Option<XOnlyPublicKey>,
+    /// REST URL of the LND node making the invoices instead of --lightning-address, such as
+    /// https://localhost:8080, checking itself whether they're paid
+    #[arg(long, requires = "lnd_macaroon", conflicts_with = "cln_socket")]
+    lnd_url: Option<String>,
+    /// Macaroon file of --lnd-url allowed to make and read invoices, such as invoice.macaroon
+    #[arg(long, requires = "lnd_url")]
+    lnd_macaroon: Option<PathBuf>,
+    /// TLS certificate of --lnd-url, when it's self-signed as LND's tls.cert
+    #[arg(long, requires = "lnd_url")]
+    lnd_tls_cert: Option<PathBuf>,
+    /// JSON-RPC socket of the Core Lightning node making the invoices instead of
+    /// --lightning-address, such as ~/.lightning/bitcoin/lightning-rpc
+    #[arg(long)]
+    cln_socket: Option<PathBuf>,
+    /// URL of the Cashu mint whose tokens pay for encrypted events, redeemed into --cashu-wallet
+    #[arg(long, requires = "cashu_wallet")]
+    cashu_mint: Option<String>,
+    /// File the ecash redeemed at --cashu-mint is appended to, a token per line
+    #[arg(long, requires = "cashu_mint")]
+    cashu_wallet: Option<PathBuf>,
+    /// Seconds to wait for the payment of an event or job before dropping it [default: 600]
+    #[arg(long)]
+    payment_timeout: Option<u64>,
+    /// What to print on stdout: `text` prints nothing besides the logs on stderr, `json` prints
+    /// a JSON object per line for every received event and every outcome [default: text]
+    #[arg(long)]
+    output: Option<OutputFormat>,
+    /// The backends of further networks, from the config file
+    #[arg(skip)]
+    networks: Vec<(Magic, NetworkConfig)>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+impl Args {
+    /// Fill in the values not given on the command line from the config file
+    fn merge(&mut self, config: Config) {
+        if self.relays.is_empty() {
+            self.relays = config.relays;
+        }
+        self.relay_file = self.relay_file.take().or(config.relay_file);
+        if self.read_relays.is_empty() {
+            self.read_relays = config.read_relays;
+        }
+        if self.write_relays.is_empty() {
+            self.write_relays = config.write_relays;
+        }
+        self.no_default_relays |= config.no_default_relays;
+        if self.kinds.is_empty() {
+            self.kinds = config.kinds;
+        }
+        if self.nsec.is_none() && self.mnemonic.is_none() {
+            self.key_file = self.key_file.take().or(config.key_file);
+        }
+        self.derivation_path = self.derivation_path.take().or(config.derivation_path);
+        self.proxy = self.proxy.or(config.proxy);
+        if self.network.is_none() && self.signet_challenge.is_none() {
+            self.network = config.network;
+            self.signet_challenge = config.signet_challenge;
+        }
+        // backends given on the command line replace the ones of the file
+        if self.bitcoin_hosts.is_empty()
+            && self.esplora_urls.is_empty()
+            && self.electrum_urls.is_empty()
+            && !self.p2p
+        {
+            self.bitcoin_hosts = config.bitcoin_hosts;
+            self.esplora_urls = config.esplora_urls;
+            self.electrum_urls = config.electrum_urls;
+            self.electrum_skip_tls_verify |= config.electrum_skip_tls_verify;
+            self.p2p = config.p2p;
+            self.p2p_peers = config.p2p_peers;
+        }
+        self.skip_rejected |= config.skip_rejected;
+        self.detect_conflicts |= config.detect_conflicts;
+        self.conflict_alerts |= config.conflict_alerts;
+        self.request_parents |= config.request_parents;
+        self.require_synced |= config.require_synced;
+        self.seen_cache_size = self.seen_cache_size.or(config.seen_cache_size);
+        self.reconnect_delay = self.reconnect_delay.or(config.reconnect_delay);
+        self.max_reconnect_delay = self.max_reconnect_delay.or(config.max_reconnect_delay);
+        self.ping_interval = self.ping_interval.or(config.ping_interval);
+        self.silence_timeout = self.silence_timeout.or(config.silence_timeout);
+        self.min_relay_score = self.min_relay_score.or(config.min_relay_score);
+        self.relay_demotion = self.relay_demotion.or(config.relay_demotion);
+        self.db = self.db.take().or(config.db);
+        self.replay_window = self.replay_window.or(config.replay_window);
+        self.rebroadcast |= config.rebroadcast;
+        self.rebroadcast_interval = self.rebroadcast_interval.or(config.rebroadcast_interval);
+        self.rebroadcast_attempts = self.rebroadcast_attempts.or(config.rebroadcast_attempts);
+        self.rebroadcast_depth = self.rebroadcast_depth.or(config.rebroadcast_depth);
+        self.zmq_block = self.zmq_block.take().or(config.zmq_block);
+        self.zmq_tx = self.zmq_tx.take().or(config.zmq_tx);
+        self.reply |= config.reply;
+        self.dm_failures |= config.dm_failures;
+        if self.allow_pubkeys.is_empty() && self.allow_pubkeys_file.is_none() {
+            self.allow_pubkeys = config.allow_pubkeys;
+            self.allow_pubkeys_file = config.allow_pubkeys_file;
+        }
+        if self.only_authors.is_empty() {
+            self.only_authors = config.only_authors;
+        }
+        self.discover_relays |= config.discover_relays;
+        self.operator = self.operator.or(config.operator);
+        self.discover_public_relays |= config.discover_public_relays;
+        self.relay_directory = self.relay_directory.take().or(config.relay_directory);
+        self.max_relays = self.max_relays.or(config.max_relays);
+        self.deny_pubkeys_file = self.deny_pubkeys_file.take().or(config.deny_pubkeys_file);
+        self.ban_after = self.ban_after.or(config.ban_after);
+        self.reputation |= config.reputation;
+        self.reputation_threshold = self.reputation_threshold.or(config.reputation_threshold);
+        self.temp_ban = self.temp_ban.or(config.temp_ban);
+        self.max_temp_ban = self.max_temp_ban.or(config.max_temp_ban);
+        self.reputation_half_life = self.reputation_half_life.or(config.reputation_half_life);
+        self.min_pow = self.min_pow.or(config.min_pow);
+        self.require_nip05 |= config.require_nip05;
+        if self.nip05_domains.is_empty() {
+            self.nip05_domains = config.nip05_domains;
+        }
+        self.nip05_cache_ttl = self.nip05_cache_ttl.or(config.nip05_cache_ttl);
+        self.max_event_age = self.max_event_age.or(config.max_event_age);
+        self.max_future_drift = self.max_future_drift.or(config.max_future_drift);
+        self.chunk_timeout = self.chunk_timeout.or(config.chunk_timeout);
+        self.max_txs_per_minute = self.max_txs_per_minute.or(config.max_txs_per_minute);
+        self.max_txs_per_hour = self.max_txs_per_hour.or(config.max_txs_per_hour);
+        self.daily_quota = self.daily_quota.or(config.daily_quota);
+        if self.quota_exempt_pubkeys.is_empty() {
+            self.quota_exempt_pubkeys = config.quota_exempt_pubkeys;
+        }
+        self.queue_size = self.queue_size.or(config.queue_size);
+        self.max_broadcasts_per_second = self
+            .max_broadcasts_per_second
+            .or(config.max_broadcasts_per_second);
+        self.batch_window = self.batch_window.or(config.batch_window);
+        self.broadcast_jitter = self.broadcast_jitter.or(config.broadcast_jitter);
+        self.cooperative_delay = self.cooperative_delay.or(config.cooperative_delay);
+        self.max_tx_vsize = self.max_tx_vsize.or(config.max_tx_vsize);
+        self.max_package_weight = self.max_package_weight.or(config.max_package_weight);
+        self.min_feerate = self.min_feerate.or(config.min_feerate);
+        self.http_addr = self.http_addr.or(config.http_addr);
+        self.dashboard_addr = self.dashboard_addr.or(config.dashboard_addr);
+        self.local_relay = self.local_relay.or(config.local_relay);
+        self.grpc_addr = self.grpc_addr.or(config.grpc_addr);
+        self.admin |= config.admin;
+        self.submit_api |= config.submit_api;
+        self.http_token = self.http_token.take().or(config.http_token);
+        self.admin_socket = self.admin_socket.take().or(config.admin_socket);
+        self.log_level = self.log_level.take().or(config.log_level);
+        self.log_json |= config.log_json;
+        self.log_file = self.log_file.take().or(config.log_file);
+        self.daemon |= config.daemon;
+        self.pidfile = self.pidfile.take().or(config.pidfile);
+        self.stats_interval = self.stats_interval.or(config.stats_interval);
+        self.publish_stats |= config.publish_stats;
+        self.announce_interval = self.announce_interval.or(config.announce_interval);
+        self.nip89 |= config.nip89;
+        self.dvm |= config.dvm;
+        self.dvm_price = self.dvm_price.or(config.dvm_price);
+        self.price = self.price.or(config.price);
+        self.lightning_address = self.lightning_address.take().or(config.lightning_address);
+        self.zapper = self.zapper.or(config.zapper);
+        self.lnd_url = self.lnd_url.take().or(config.lnd_url);
+        self.lnd_macaroon = self.lnd_macaroon.take().or(config.lnd_macaroon);
+        self.lnd_tls_cert = self.lnd_tls_cert.take().or(config.lnd_tls_cert);
+        self.cln_socket = self.cln_socket.take().or(config.cln_socket);
+        self.cashu_mint = self.cashu_mint.take().or(config.cashu_mint);
+        self.cashu_wallet = self.cashu_wallet.take().or(config.cashu_wallet);
+        self.payment_timeout = self.payment_timeout.or(config.payment_timeout);
+        self.output = self.output.or(config.output);
+        self.networks = config.networks;
+        // credentials only make sense as a pair
+        if self.bitcoin_user.is_none() && self.bitcoin_cookie.is_none() {
+            self.bitcoin_user = config.bitcoin_user;
+            self.bitcoin_password = config.bitcoin_password;
+            self.bitcoin_cookie = config.bitcoin_cookie;
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Listen for transaction events and broadcast them (the default)
+    Listen,
+    /// Publish raw transactions as a transaction event
+    Send(Box<SendArgs>),
+    /// Publish the transaction events received from --relays to other relays, without
+    /// broadcasting them, to bridge senders and broadcasters that use different relays
+    Bridge {
+        /// Relays to publish the events to, comma separated or repeated
+        #[arg(long, required = true, value_delimiter = ',')]
+        to: Vec<String>,
+    },
+    /// Print the relays, their scores and the backends of the listener serving --http-addr
+    Status,
+    /// Make the listener serving --http-addr with --admin use another relay
+    AddRelay {
+        /// The relay url
+        url: String,
+    },
+    /// Make the listener serving --http-addr with --admin stop using a relay
+    RemoveRelay {
+        /// The relay url
+        url: String,
+    },
+    /// Make the listener serving --http-addr with --admin stop broadcasting, it keeps queueing
+    /// the received events
+    Pause,
+    /// Make the listener serving --http-addr with --admin broadcast again
+    Resume,
+}
+
+/// The arguments of the send command
+#[derive(clap::Args)]
+struct SendArgs {
+    /// Raw transaction hex, pass several to publish a package, or `-` to read them from
+    /// stdin in hex or binary
+    #[arg(required_unless_present_any = ["tx_file", "txid", "package"])]
+    txs: Vec<String>,
+    /// File with the raw transactions to publish, in hex separated by whitespace or binary
+    #[arg(long)]
+    tx_file: Option<PathBuf>,
+    /// Publish a transaction of the Bitcoin Core node of --bitcoin-host, from its mempool,
+    /// its wallet or with -txindex the chain. Given multiple times, a package
+    #[arg(long)]
+    txid: Vec<Txid>,
+    /// Publish related transactions of the Bitcoin Core node of --bitcoin-host together as a
+    /// package, comma separated in any order, parents are put before their children
+    #[arg(
+        long,
+        value_delimiter = ',',
+        conflicts_with_all = ["txs", "tx_file", "txid"]
+    )]
+    package: Vec<Txid>,
+    /// Public key (hex or npub) of a broadcaster to encrypt the transactions to, so only it
+    /// can read and broadcast them
+    #[arg(long, visible_alias = "to", value_parser = parse_pubkey)]
+    encrypt_to: Option<XOnlyPublicKey>,
+    /// Look up the broadcasters announcing themselves on the relays for the network, failing
+    /// when none takes the transactions
+    #[arg(long)]
+    discover: bool,
+    /// Encrypt the transactions to the most recently announced of the discovered broadcasters
+    /// taking encrypted events, and wait for its result when it replies
+    #[arg(long, requires = "discover", conflicts_with_all = ["encrypt_to", "chunk_size"])]
+    encrypt: bool,
+    /// Cashu token (cashuA…) of the broadcaster's mint paying for the broadcast, sent only in
+    /// encrypted events as anyone seeing it could spend it
+    #[arg(long)]
+    cashu: Option<String>,
+    /// How to encode the transactions in the event: base64, or hex for older listeners
+    #[arg(long, default_value = "base64")]
+    tx_encoding: TxEncoding,
+    /// Compress the transactions together with gzip or zstd, for packages too large for the
+    /// relays
+    #[arg(long)]
+    compress: Option<Compression>,
+    /// Split packages whose payload is larger than this many bytes into several events,
+    /// which listeners reassemble
+    #[arg(long, conflicts_with = "encrypt_to")]
+    chunk_size: Option<NonZeroUsize>,
+    /// Seconds after which relays may drop the event (NIP-40) and listeners ignore it, 0 for
+    /// no expiration
+    #[arg(long, default_value_t = 3600)]
+    expiration: u64,
+    /// Public key (hex or npub) of a broadcaster to wait for the result of, exiting with 0 when
+    /// it broadcast the transactions, 3 when it didn't reply in time and 4 when it couldn't
+    /// broadcast them. Without it, exits with 5 once the relays accepted the events
+    #[arg(long, value_parser = parse_pubkey)]
+    wait_for: Option<XOnlyPublicKey>,
+    /// Seconds to wait for a relay to accept the events, exiting with 2 when none did, and
+    /// for the result of --wait-for
+    #[arg(long, default_value_t = 30)]
+    timeout: u64,
+    /// Only succeed when this many relays accepted each event, publishing to more of the default
+    /// relays when the ones of --relays don't make it
+    #[arg(long, default_value = "1")]
+    min_relays: NonZeroUsize,
+    /// Mine a NIP-13 proof of work of this many leading zero bits into the event ids, for relays
+    /// and broadcasters that require it
+    #[arg(long, default_value_t = 0)]
+    pow: u8,
+}
+
+fn main() -> anyhow::Result<ExitCode> {
+    let args = load_args()?;
+    let listening = matches!(args.command, None | Some(Command::Listen));
+    if args.daemon && !listening {
+        bail!("--daemon is only for listening");
+    }
+
+    // before the runtime starts its threads
+    #[cfg(unix)]
+    let (ready, _pidfile) = {
+        let ready: Box<dyn FnOnce()> = match args.daemon {
+            true => {
+                let daemon = daemon::daemonize(args.log_file.as_deref())?;
+                Box::new(move || daemon.ready())
+            }
+            false => Box::new(|| {}),
+        };
+        let pidfile = args.pidfile.as_ref().map(Pidfile::create).transpose()?;
+        (ready, pidfile)
+    };
+    #[cfg(not(unix))]
+    if args.daemon || args.pidfile.is_some() {
+        bail!("--daemon and --pidfile are only supported on unix");
+    }
+    #[cfg(not(unix))]
+    let ready: Box<dyn FnOnce()> = Box::new(|| {});
+
+    init_logging(
+        args.log_level.as_deref().unwrap_or("info"),
+        args.log_json,
+        args.log_file.as_deref(),
+    )?;
+    tokio::runtime::Runtime::new()?.block_on(run(args, ready))
+}
+
+async fn run(args: Args, ready: Box<dyn FnOnce()>) -> anyhow::Result<ExitCode> {
+    match args.command {
+        None | Some(Command::Listen) => listen(&identity(&args)?, args, ready).await,
+        Some(Command::Send(ref send_args)) => {
+            let config = send_config(&args, send_args);
+            let mut txs = read_txs(&send_args.txs, send_args.tx_file.as_deref())?;
+            if !send_args.txid.is_empty() || !send_args.package.is_empty() {
+                let node = send::fetching_node(config.magic, config.node.as_ref()).await?;
+                txs.extend(send::fetch_txs(&node, &send_args.txid).await?);
+                if !send_args.package.is_empty() {
+                    txs = send::fetch_package(&node, &send_args.package).await?;
+                }
+            }
+            if txs.is_empty() {
+                bail!("No txs to send");
+            }
+            let sent = send::run(&identity(&args)?, &config, &txs).await?;
+            return Ok(sent.exit_code());
+        }
+        Some(Command::Bridge { ref to }) => {
+            for relay in to {
+                check_relay(&args, relay)?;
+            }
+            let config = BridgeConfig {
+                from: args.relays.clone(),
+                to: to.clone(),
+                kinds: args.kinds.clone(),
+                proxy: args.proxy,
+                seen_cache_size: args
+                    .seen_cache_size
+                    .unwrap_or(NonZeroUsize::new(10_000).unwrap()),
+            };
+            bridge::run(&identity(&args)?, config, shutdown_signal()).await
+        }
+        Some(Command::Status) => status(&args).await,
+        Some(Command::AddRelay { ref url }) => {
+            let body = serde_json::json!({ "url": url });
+            admin(&args, Method::POST, "relays", Some(body)).await
+        }
+        Some(Command::RemoveRelay { ref url }) => {
+            let body = serde_json::json!({ "url": url });
+            admin(&args, Method::DELETE, "relays", Some(body)).await
+        }
+        Some(Command::Pause) => admin(&args, Method::POST, "pause", None).await,
+        Some(Command::Resume) => admin(&args, Method::POST, "resume", None).await,
+    }
+    .map(|()| ExitCode::SUCCESS)
+}
+
+/// The identity given by --nsec, --key-file or --mnemonic, or else a new one
+fn identity(args: &Args) -> anyhow::Result<Keys> {
+    if let Some(nsec) = &args.nsec {
+        return parse_secret_key(nsec).context("Invalid --nsec");
+    }
+    if let Some(mnemonic) = &args.mnemonic {
+        let path = match &args.derivation_path {
+            Some(path) => path.clone(),
+            None => keys::NIP06_PATH.parse()?,
+        };
+        let passphrase = args.mnemonic_passphrase.as_deref().unwrap_or_default();
+        return keys::from_mnemonic(mnemonic, passphrase, &path).context("Invalid --mnemonic");
+    }
+    if let Some(path) = &args.key_file {
+        return keys::load_or_create(path);
+    }
+    warn!("Using a new identity, give --key-file to keep it across runs");
+    Ok(Keys::generate())
+}
+
+/// Log to stderr, keeping stdout for the output of the commands
+fn init_logging(level: &str, json: bool, file: Option<&Path>) -> anyhow::Result<()> {
+    let filter = EnvFilter::try_new(level)?;
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+    match (file, json) {
+        (Some(path), true) => {
+            let file = Mutex::new(open_append(path)?);
+            subscriber.with_writer(file).json().init();
+        }
+        (Some(path), false) => {
+            let file = Mutex::new(open_append(path)?);
+            subscriber.with_writer(file).with_ansi(false).init();
+        }
+        (None, true) => subscriber.with_writer(io::stderr).json().init(),
+        (None, false) => subscriber.with_writer(io::stderr).init(),
+    }
+    Ok(())
+}
+
+/// Open `path` to append to it
+fn open_append(path: &Path) -> anyhow::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Opening {}", path.display()))
+}
+
+async fn listen(keys: &Keys, args: Args, ready: Box<dyn FnOnce()>) -> anyhow::Result<()> {
+    let metrics = Metrics::new()?;
+    let http_addr = args.http_addr;
+    let admin = args.admin;
+    // of the transactions submitted over HTTP and gRPC
+    let magic = args.network.unwrap_or(Magic::BITCOIN);
+    let submit = args.submit_api.then_some(magic);
+    let http_token = args.http_token.clone();
+    let dashboard_addr = args.dashboard_addr;
+    let local_addr = args.local_relay;
+    let grpc_addr = args.grpc_addr;
+    let admin_socket = args.admin_socket.clone();
+    let output = args.output.unwrap_or_default();
+    let allowed_authors = allowed_authors(&args)?;
+    let relays = relays(keys, &args, allowed_authors.as_ref()).await?;
+    let local_relay = match args.local_relay {
+        Some(addr) => {
+            let mut kinds = args.kinds.clone();
+            if args.dvm {
+                kinds.push(dvm::JOB_REQUEST_KIND);
+            }
+            Some(Arc::new(LocalRelay::new(addr, kinds)?))
+        }
+        None => None,
+    };
+    if relays.is_empty() && local_relay.is_none() {
+        bail!("No relays to listen on, give them with --relays or --local-relay, or leave out --no-default-relays");
+    }
+    let config = ListenerConfig {
+        backends: backends(&args, &metrics).await?,
+        allowed_authors,
+        only_authors: args.only_authors.clone(),
+        denylist: Denylist::open(args.deny_pubkeys_file.clone(), args.ban_after)?,
+        reputation: args.reputation.then(|| ReputationConfig {
+            ban_threshold: args.reputation_threshold.unwrap_or(5.0),
+            ban_duration: Duration::from_secs(args.temp_ban.unwrap_or(600)),
+            max_ban_duration: Duration::from_secs(args.max_temp_ban.unwrap_or(86_400)),
+            half_life: Duration::from_secs(args.reputation_half_life.unwrap_or(3600)),
+        }),
+        min_pow: args.min_pow.unwrap_or(0),
+        nip05: (args.require_nip05 || !args.nip05_domains.is_empty()).then(|| Nip05Config {
+            domains: args.nip05_domains.clone(),
+            cache_ttl: Duration::from_secs(args.nip05_cache_ttl.unwrap_or(3600)),
+        }),
+        max_event_age: args.max_event_age.map(Duration::from_secs),
+        max_future_drift: args.max_future_drift.map(Duration::from_secs),
+        chunk_timeout: Duration::from_secs(args.chunk_timeout.unwrap_or(60)),
+        rate_limit: (args.max_txs_per_minute.is_some() || args.max_txs_per_hour.is_some())
+            .then_some(RateLimit {
+                per_minute: args.max_txs_per_minute,
+                per_hour: args.max_txs_per_hour,
+            }),
+        daily_quota: args.daily_quota,
+        quota_exempt: args.quota_exempt_pubkeys.iter().copied().collect(),
+        queue_size: args.queue_size.unwrap_or(NonZeroUsize::new(1000).unwrap()),
+        max_broadcasts_per_second: args.max_broadcasts_per_second,
+        batch_window: args
+            .batch_window
+            .map(|millis| Duration::from_millis(millis.get())),
+        broadcast_jitter: args.broadcast_jitter,
+        cooperative_delay: args
+            .cooperative_delay
+            .map(|secs| Duration::from_secs(secs.get())),
+        policy: policy(&args),
+        metrics,
+        stats_interval: args
+            .stats_interval
+            .map(|secs| Duration::from_secs(secs.get())),
+        publish_stats: args.publish_stats,
+        announce_interval: args
+            .announce_interval
+            .map(|secs| Duration::from_secs(secs.get())),
+        nip89: args.nip89,
+        payment: payment_config(&args).await?,
+        dvm: dvm_config(&args)?,
+        relays,
+        relay_roles: relay_roles(&args),
+        local_relay: local_relay.clone(),
+        kinds: args.kinds.clone(),
+        proxy: args.proxy,
+        reconnect: ReconnectConfig {
+            initial_delay: Duration::from_secs(args.reconnect_delay.unwrap_or(1)),
+            max_delay: Duration::from_secs(args.max_reconnect_delay.unwrap_or(300)),
+            ping_interval: match args.ping_interval.unwrap_or(60) {
+                0 => None,
+                secs => Some(Duration::from_secs(secs)),
+            },
+            silence_timeout: Duration::from_secs(args.silence_timeout.unwrap_or(180)),
+        },
+        scoring: {
+            let min_score = args.min_relay_score.unwrap_or(0.25);
+            (min_score > 0.0).then(|| ScoringConfig {
+                min_score,
+                demotion: Duration::from_secs(args.relay_demotion.unwrap_or(600)),
+            })
+        },
+        skip_rejected: args.skip_rejected,
+        detect_conflicts: args.detect_conflicts || args.conflict_alerts,
+        conflict_alerts: args.conflict_alerts,
+        request_parents: args.request_parents,
+        require_synced: args.require_synced,
+        seen_cache_size: args
+            .seen_cache_size
+            .unwrap_or(NonZeroUsize::new(10_000).unwrap()),
+        store: args.db.as_ref().map(Store::open).transpose()?,
+        replay_window: Duration::from_secs(args.replay_window.unwrap_or(86_400)),
+        rebroadcast: args.rebroadcast.then(|| TrackerConfig {
+            interval: Duration::from_secs(args.rebroadcast_interval.unwrap_or(60)),
+            max_attempts: args.rebroadcast_attempts.unwrap_or(10),
+            depth: args.rebroadcast_depth.unwrap_or(6),
+            zmq_block: args.zmq_block.clone(),
+            zmq_tx: args.zmq_tx.clone(),
+        }),
+        reply: args.reply,
+        dm_failures: args.dm_failures,
+    };
+
+    info!(pubkey = %keys.public_key(), "Broadcaster pubkey");
+    let listener = Arc::new(Listener::new(keys, config).await?);
+    ready();
+    if output == OutputFormat::Json {
+        tokio::spawn(output::print_json(
+            listener.subscribe(),
+            listener.outcomes(),
+        ));
+    }
+    let run = listener.run_until(shutdown_signal());
+    let http = async {
+        match http_addr {
+            Some(addr) => http::serve(addr, listener.clone(), admin, submit, http_token).await,
+            None => future::pending().await,
+        }
+    };
+    let local = async {
+        match (local_addr, local_relay) {
+            (Some(addr), Some(relay)) => local_relay::serve(addr, relay).await,
+            _ => future::pending().await,
+        }
+    };
+    let dashboard = async {
+        match dashboard_addr {
+            Some(addr) => dashboard::serve(addr, listener.clone()).await,
+            None => future::pending().await,
+        }
+    };
+    let grpc = async {
+        match grpc_addr {
+            Some(addr) => grpc::serve(addr, listener.clone(), magic).await,
+            None => future::pending().await,
+        }
+    };
+    let console = async {
+        match admin_socket {
+            #[cfg(unix)]
+            Some(path) => {
+                let reload_listener = listener.clone();
+                let keys = keys.clone();
+                let reload: console::Reload = Arc::new(move || {
+                    let listener = reload_listener.clone();
+                    let keys = keys.clone();
+                    Box::pin(async move { reload(&keys, &listener).await })
+                });
+                console::serve(&path, listener.clone(), reload).await
+            }
+            #[cfg(not(unix))]
+            Some(_) => Err(anyhow::anyhow!("The admin console needs unix sockets")),
+            None => future::pending().await,
+        }
+    };
+    let result = tokio::select! {
+        result = run => result,
+        result = http => result,
+        result = dashboard => result,
+        result = local => result,
+        result = grpc => result,
+        result = console => result,
+        () = supervise(&listener) => Ok(()),
+        () = reload_on_hangup(keys, &listener) => Ok(()),
+    };
+    #[cfg(unix)]
+    if let Err(e) = systemd::notify("STOPPING=1") {
+        warn!("Error notifying systemd: {e}");
+    }
+    result
+}
+
+/// The command line arguments, completed by the config file
+fn load_args() -> anyhow::Result<Args> {
+    let mut args = Args::parse();
+    if let Some(path) = &args.config {
+        let config = Config::load(path)?;
+        args.merge(config);
+    }
+    if let Some(path) = &args.relay_file {
+        let mut relays = args.relays.clone();
+        relays::add_new(
+            &mut relays,
+            read_relays(path)?
+                .iter()
+                .filter_map(|url| parse_relay(url).ok()),
+            usize::MAX,
+        );
+        args.relays = relays;
+    }
+    for relay in args.read_relays.iter().chain(&args.write_relays) {
+        let url = parse_relay(relay)?;
+        relays::add_new(&mut args.relays, [url], usize::MAX);
+    }
+    if args.relays.is_empty() && !args.no_default_relays {
+        args.relays = RELAYS.map(String::from).to_vec();
+    }
+    for relay in &args.relays {
+        check_relay(&args, relay)?;
+    }
+    if args.kinds.is_empty() {
+        args.kinds = vec![protocol::BITCOIN_TX_KIND];
+    }
+    if let Some(magic) = args.signet_challenge {
+        args.network = Some(magic);
+    }
+    Ok(args)
+}
+
+/// Tell systemd when the listener is ready, once a relay is connected, and keep its watchdog happy
+/// while the relay notifications don't hang
+#[cfg(unix)]
+async fn supervise(listener: &Listener) {
+    if std::env::var_os("NOTIFY_SOCKET").is_none() {
+        return future::pending().await;
+    }
+    listener.wait_connected().await;
+    if let Err(e) = systemd::notify("READY=1") {
+        warn!("Error notifying systemd: {e}");
+    }
+
+    let Some(timeout) = systemd::watchdog() else {
+        return future::pending().await;
+    };
+    let mut interval = tokio::time::interval(timeout / 2);
+    loop {
+        interval.tick().await;
+        if let Some(handling) = listener
+            .handling_for()
+            .filter(|handling| *handling > timeout)
+        {
+            warn!(
+                handling_secs = handling.as_secs(),
+                "Handling a relay notification hangs, not notifying the watchdog"
+            );
+            continue;
+        }
+        if let Err(e) = systemd::notify("WATCHDOG=1") {
+            warn!("Error notifying the systemd watchdog: {e}");
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn supervise(_listener: &Listener) {
+    future::pending().await
+}
+
+/// Read the config file again and apply what can change while running: the relays, the policy,
+/// the allowlist and the denylist file. The backends and the queued events are kept.
+async fn reload(keys: &Keys, listener: &Listener) -> anyhow::Result<String> {
+    let args = load_args()?;
+    let allowed_authors = allowed_authors(&args)?;
+    let relays = relays(keys, &args, allowed_authors.as_ref()).await?;
+    let banned = listener.reload_denylist()?;
+    listener.set_relay_roles(relay_roles(&args)).await;
+    let (added, removed) = listener.set_relays(&relays).await?;
+    let policy = policy(&args);
+    let mut changes = vec![];
+    changes.extend(added.iter().map(|url| format!("added relay {url}")));
+    changes.extend(removed.iter().map(|url| format!("removed relay {url}")));
+    changes.push(match &allowed_authors {
+        Some(allowed) => format!("allowing {} authors", allowed.len()),
+        None => "allowing all authors".to_string(),
+    });
+    changes.push(format!("denying {banned} authors"));
+    changes.push(format!("policy {policy:?}"));
+    info!(
+        added = added.len(),
+        removed = removed.len(),
+        allowed = allowed_authors.as_ref().map(HashSet::len),
+        banned,
+        ?policy,
+        "Reloaded the config"
+    );
+    listener.set_allowed_authors(allowed_authors);
+    listener.set_policy(policy);
+    Ok(changes.join("\n"))
+}
+
+/// Reload the config on SIGHUP
+#[cfg(unix)]
+async fn reload_on_hangup(keys: &Keys, listener: &Listener) {
+    let mut hangup = match signal::unix::signal(signal::unix::SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            error!("Error waiting for SIGHUP: {e}");
+            return future::pending().await;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        info!("Reloading the config on SIGHUP");
+        if let Err(e) = reload(keys, listener).await {
+            error!("Error reloading the config: {e:#}");
+        }
+    }
+    future::pending().await
+}
+
+#[cfg(not(unix))]
+async fn reload_on_hangup(_keys: &Keys, _listener: &Listener) {
+    future::pending().await
+}
+
+/// The limits on submitted transactions
+fn policy(args: &Args) -> Policy {
+    Policy {
+        max_tx_vsize: args.max_tx_vsize,
+        max_package_weight: args.max_package_weight,
+        min_feerate: args.min_feerate,
+    }
+}
+
+/// How NIP-90 jobs are taken, `None` with --dvm off
+fn dvm_config(args: &Args) -> anyhow::Result<Option<DvmConfig>> {
+    if !args.dvm {
+        if args.dvm_price.is_some() {
+            bail!("--dvm-price needs --dvm");
+        }
+        return Ok(None);
+    }
+    Ok(Some(DvmConfig {
+        price: args.dvm_price,
+    }))
+}
+
+/// How submissions are paid for, `None` when broadcasting is free
+async fn payment_config(args: &Args) -> anyhow::Result<Option<PaymentConfig>> {
+    if args.price.is_none() && args.dvm_price.is_none() {
+        return Ok(None);
+    }
+    let invoices = invoice_backend(args)?;
+    let cashu = match (&args.cashu_mint, &args.cashu_wallet) {
+        (Some(mint), Some(wallet)) => {
+            info!(mint, "Taking Cashu tokens in encrypted events");
+            Some(Arc::new(Wallet::new(mint, wallet)?))
+        }
+        (Some(_), None) => bail!("--cashu-mint needs --cashu-wallet"),
+        (None, Some(_)) => bail!("--cashu-wallet needs --cashu-mint"),
+        (None, None) => None,
+    };
+    let endpoint = match &args.lightning_address {
+        Some(address) => Some(
+            lnurl::pay_endpoint(address)
+                .await
+                .context("Error looking up --lightning-address")?,
+        ),
+        None if args.price.is_some() && invoices.is_none() && cashu.is_none() => {
+            bail!("--price needs --lightning-address, a lightning node or --cashu-mint")
+        }
+        None => None,
+    };
+    let zapper = args
+        .zapper
+        .or_else(|| endpoint.as_ref().and_then(|endpoint| endpoint.nostr_pubkey));
+    match (&invoices, zapper) {
+        (Some(node), _) => info!(node = node.name(), "Taking payments, invoiced by the node"),
+        (None, Some(zapper)) => info!(%zapper, "Taking payments, zapped to the LNURL server"),
+        (None, None) if cashu.is_some() && args.dvm_price.is_none() => {}
+        (None, None) => bail!(
+            "Payments need a lightning node, --lightning-address or the --zapper publishing the zap receipts"
+        ),
+    }
+    Ok(Some(PaymentConfig {
+        price: args.price,
+        endpoint,
+        invoices,
+        zapper,
+        cashu,
+        timeout: Duration::from_secs(args.payment_timeout.unwrap_or(600)),
+    }))
+}
 
-    let bitcoin_tx_kind = Kind::Custom(28333);
-    let subscription = Filter::new()
-        .kinds(vec![bitcoin_tx_kind])
-        .since(Timestamp::now());
-
-    client.subscribe(vec![subscription]).await;
-
-    println!("Listening for bitcoin txs...");
-    client
-        .handle_notifications(|notification| async {
-            if let Event(_, event) = notification {
-                if event.kind == bitcoin_tx_kind {
-                    // calculate network from magic
-                    let magic = event
-                        .tags
-                        .clone()
-                        .into_iter()
-                        .find(|t| t.kind() == TagKind::Custom("magic".to_string()))
-                        .and_then(|t| {
-                            if let Tag::Generic(_, magic) = t {
-                                magic.first().and_then(|m| Magic::from_str(m).ok())
-                            } else {
-                                None
-                            }
-                        });
-
-                    // get transactions
-                    let txs: Vec<Transaction> = event
-                        .tags
-                        .clone()
-                        .into_iter()
-                        .find(|t| t.kind() == TagKind::Custom("transactions".to_string()))
-                        .map(|t| {
-                            if let Tag::Generic(_, txs) = t {
-                                txs.iter().filter_map(|tx| {
-                                    general_purpose::STANDARD.decode(tx).ok().and_then(|decoded| {
-                                        Transaction::consensus_decode(&mut decoded.as_slice()).ok()
-                                    })
-                                }).collect()
-                            } else {
-                                vec![]
-                            }
-                        }).unwrap_or_default();
-
-                    match magic {
-                        Some(magic) => {
-                            if let Err(e) = broadcast_txs(txs, magic).await {
-                                println!("Error broadcasting txs: {e}");
-                            }
-                        }
-                        None => {
-                            println!("Network: unknown");
-                        }
+/// The lightning node making the invoices, `None` without --lnd-url or --cln-socket
+fn invoice_backend(args: &Args) -> anyhow::Result<Option<Arc<dyn InvoiceBackend>>> {
+    match (&args.lnd_url, &args.lnd_macaroon, &args.cln_socket) {
+        (Some(_), _, Some(_)) => bail!("Only one of --lnd-url and --cln-socket"),
+        (Some(url), Some(macaroon), None) => Ok(Some(Arc::new(Lnd::new(
+            url,
+            macaroon,
+            args.lnd_tls_cert.as_deref(),
+        )?))),
+        (Some(_), None, None) => bail!("--lnd-url needs --lnd-macaroon"),
+        (None, _, Some(socket)) => Ok(Some(Arc::new(Cln::new(socket)))),
+        (None, _, None) => Ok(None),
+    }
+}
+
+/// Ctrl-C, or SIGTERM on unix
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = signal::ctrl_c().await {
+            error!("Error waiting for Ctrl-C: {e}");
+            future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                error!("Error waiting for SIGTERM: {e}");
+                future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+/// The allowlist of the flags and the file, `None` when none is given
+fn allowed_authors(args: &Args) -> anyhow::Result<Option<HashSet<XOnlyPublicKey>>> {
+    if args.allow_pubkeys.is_empty() && args.allow_pubkeys_file.is_none() {
+        return Ok(None);
+    }
+    let mut allowed: HashSet<_> = args.allow_pubkeys.iter().copied().collect();
+    if let Some(path) = &args.allow_pubkeys_file {
+        allowed.extend(read_pubkeys(path)?);
+    }
+    Ok(Some(allowed))
+}
+
+/// The roles of the relays of --read-relay and --write-relay, relays given as both are used for
+/// both
+fn relay_roles(args: &Args) -> HashMap<Url, RelayRole> {
+    let mut roles = HashMap::new();
+    for relay in &args.read_relays {
+        if let Ok(url) = parse_relay(relay) {
+            roles.insert(url, RelayRole::Read);
+        }
+    }
+    for relay in &args.write_relays {
+        if let Ok(url) = parse_relay(relay) {
+            let role = match roles.get(&url) {
+                Some(RelayRole::Read) => RelayRole::ReadWrite,
+                _ => RelayRole::Write,
+            };
+            roles.insert(url, role);
+        }
+    }
+    roles
+}
+
+/// The relays of --relays, the ones found in the NIP-65 relay lists of the allowed authors and
+/// the operator, and the public ones of the relay directory, up to --max-relays
+async fn relays(
+    keys: &Keys,
+    args: &Args,
+    allowed_authors: Option<&HashSet<XOnlyPublicKey>>,
+) -> anyhow::Result<Vec<String>> {
+    let max = match args.max_relays {
+        Some(max) => max,
+        None if args.discover_public_relays => MAX_PUBLIC_RELAYS,
+        None => usize::MAX,
+    };
+    let mut relays = discover_relays(keys, args, allowed_authors).await?;
+    if relays.len() > max {
+        warn!(
+            relays = relays.len(),
+            max, "More relays than --max-relays, leaving out the last ones"
+        );
+        relays.truncate(max);
+    }
+
+    if args.discover_public_relays && relays.len() < max {
+        let directory = args.relay_directory.as_deref().unwrap_or(relays::DIRECTORY);
+        if args.proxy.is_some() {
+            // the directory would see the broadcaster's address
+            warn!("Not asking the relay directory for public relays without going through --proxy");
+        } else {
+            match relays::fetch_online(directory).await {
+                Ok(online) => {
+                    let online = reachable(args, online);
+                    for url in relays::add_new(&mut relays, online, max) {
+                        info!(relay = %url, "Discovered public relay");
                     }
                 }
+                Err(e) => warn!(directory, "Error fetching public relays: {e:#}"),
             }
-            Ok(())
-        })
-        .await?;
+        }
+    }
+    Ok(relays)
+}
+
+/// The relays of --relays and the ones found in the NIP-65 relay lists of the allowed authors and
+/// the operator
+async fn discover_relays(
+    keys: &Keys,
+    args: &Args,
+    allowed_authors: Option<&HashSet<XOnlyPublicKey>>,
+) -> anyhow::Result<Vec<String>> {
+    let mut relays = args.relays.clone();
+    let senders: Vec<_> = match allowed_authors {
+        _ if !args.discover_relays => vec![],
+        Some(allowed) => allowed.iter().copied().collect(),
+        None => args.only_authors.clone(),
+    };
+    if args.discover_relays && senders.is_empty() {
+        bail!("--discover-relays needs allowed authors, see --allow-pubkey");
+    }
+    if senders.is_empty() && args.operator.is_none() {
+        return Ok(relays);
+    }
+
+    let client = Client::with_opts(keys, Options::new().wait_for_connection(true));
+    for relay in &args.relays {
+        client.add_relay(relay.as_str(), args.proxy).await?;
+    }
+    client.connect().await;
+    let mut discovered = vec![];
+    if !senders.is_empty() {
+        discovered
+            .extend(nip65::discover(&client, &senders, Usage::Write, DISCOVERY_TIMEOUT).await?);
+    }
+    if let Some(operator) = args.operator {
+        discovered
+            .extend(nip65::discover(&client, &[operator], Usage::Read, DISCOVERY_TIMEOUT).await?);
+    }
+    client.disconnect().await?;
+
+    if discovered.is_empty() {
+        warn!("No relays found in the relay lists");
+    }
+    let discovered = reachable(args, discovered);
+    for url in relays::add_new(&mut relays, discovered, usize::MAX) {
+        info!(relay = %url, "Discovered relay");
+    }
+    Ok(relays)
+}
+
+/// Check that `relay` is a relay url that can be connected to, through --proxy for onion services
+fn check_relay(args: &Args, relay: &str) -> anyhow::Result<()> {
+    let url = parse_relay(relay)?;
+    if args.proxy.is_none() && relays::is_onion(&url) {
+        bail!("Relay {relay} is an onion service, connect to it through Tor with --proxy");
+    }
     Ok(())
 }
 
-async fn broadcast_txs(txs: Vec<Transaction>, magic: Magic) -> anyhow::Result<()> {
-    if txs.is_empty() {
-        return Ok(());
+/// The discovered relays that can be connected to, leaving out the onion services without --proxy
+fn reachable(args: &Args, urls: Vec<Url>) -> Vec<Url> {
+    if args.proxy.is_some() {
+        return urls;
+    }
+    urls.into_iter()
+        .filter(|url| {
+            let onion = relays::is_onion(url);
+            if onion {
+                warn!(relay = %url, "Leaving out onion relay, it needs --proxy");
+            }
+            !onion
+        })
+        .collect()
+}
+
+async fn backends(args: &Args, metrics: &Metrics) -> anyhow::Result<Backends> {
+    let measured =
+        |backend| Arc::new(Measured::new(backend, metrics.clone())) as Arc<dyn TxBackend>;
+
+    let mut backends = Backends::new();
+    let network = args.network.unwrap_or(Magic::BITCOIN);
+    let config = network_config(args);
+    if config.has_backends() {
+        backends.insert(
+            network,
+            backend(network, &config, args.p2p_max_peers, measured).await?,
+        );
+    }
+    for (magic, config) in &args.networks {
+        if backends.contains_key(magic) {
+            bail!("The backends of network {magic} are configured twice");
+        }
+        backends.insert(
+            *magic,
+            backend(*magic, config, args.p2p_max_peers, measured).await?,
+        );
+    }
+    if !backends.is_empty() {
+        return Ok(backends);
     }
 
-    let client = reqwest::Client::builder().build()?;
+    let mut backends = backend::mempool_space();
+    if let Some(network) = args.network {
+        backends.retain(|magic, _| *magic == network);
+    }
+    Ok(backends
+        .into_iter()
+        .map(|(magic, backend)| (magic, measured(backend)))
+        .collect())
+}
+
+/// The backends of --network given on the command line, or at the top of the config file
+fn network_config(args: &Args) -> NetworkConfig {
+    NetworkConfig {
+        bitcoin_hosts: args.bitcoin_hosts.clone(),
+        bitcoin_user: args.bitcoin_user.clone(),
+        bitcoin_password: args.bitcoin_password.clone(),
+        bitcoin_cookie: args.bitcoin_cookie.clone(),
+        esplora_urls: args.esplora_urls.clone(),
+        electrum_urls: args.electrum_urls.clone(),
+        electrum_skip_tls_verify: args.electrum_skip_tls_verify,
+        p2p: args.p2p,
+        p2p_peers: args.p2p_peers.clone(),
+    }
+}
 
-    let mutinynet = Magic::from_bytes([0xA5, 0xDF, 0x2D, 0xCB]);
+/// The backends of `config` combined into one for `network`, each wrapped by `measured`
+async fn backend(
+    network: Magic,
+    config: &NetworkConfig,
+    p2p_max_peers: usize,
+    measured: impl Fn(Arc<dyn TxBackend>) -> Arc<dyn TxBackend>,
+) -> anyhow::Result<Arc<dyn TxBackend>> {
+    let mut backends: Vec<Arc<dyn TxBackend>> = vec![];
+    if !config.bitcoin_hosts.is_empty() {
+        let bitcoind = Bitcoind::from_config(network, config).await?;
+        bitcoind.spawn_health_checks(BITCOIND_HEALTH_CHECK_INTERVAL);
+        backends.push(Arc::new(bitcoind));
+    }
+    for url in &config.esplora_urls {
+        backends.push(Arc::new(Esplora::new(url)));
+    }
+    for url in &config.electrum_urls {
+        backends.push(Arc::new(Electrum::new(
+            url,
+            !config.electrum_skip_tls_verify,
+        )?));
+    }
+    if config.p2p {
+        backends.push(Arc::new(P2p::new(
+            network,
+            config.p2p_peers.clone(),
+            p2p_max_peers,
+        )?));
+    }
 
-    let url = match magic {
-        Magic::BITCOIN => Ok("https://mempool.space/api/tx"),
-        Magic::TESTNET => Ok("https://mempool.space/testnet/api/tx"),
-        Magic::SIGNET => Ok("https://mempool.space/signet/api/tx"),
-        magic if magic == mutinynet => Ok("https://mutinynet.com/api/tx"),
-        magic => Err(anyhow!("Magic: {magic} is unknown")),
-    }?;
+    let mut backends: Vec<_> = backends.into_iter().map(measured).collect();
+    Ok(match backends.len() {
+        1 => backends.remove(0),
+        _ => Arc::new(FanOut::new(backends)),
+    })
+}
 
+/// The transactions to send: the raw ones in hex given as arguments, the ones read from stdin
+/// for `-`, and the ones of `tx_file`
+fn read_txs(txs: &[String], tx_file: Option<&Path>) -> anyhow::Result<Vec<Transaction>> {
+    let mut read = vec![];
     for tx in txs {
-        let bytes = serialize(&tx);
-        let body = HexString::from_bytes(&bytes).as_string();
+        if tx == "-" {
+            let mut bytes = vec![];
+            io::stdin()
+                .read_to_end(&mut bytes)
+                .context("Reading stdin")?;
+            read.extend(protocol::parse_raw_txs(&bytes).context("Invalid txs on stdin")?);
+        } else {
+            read.push(protocol::parse_tx_hex(tx).with_context(|| format!("Invalid tx {tx}"))?);
+        }
+    }
+    if let Some(path) = tx_file {
+        let bytes = fs::read(path).with_context(|| format!("Reading {}", path.display()))?;
+        read.extend(
+            protocol::parse_raw_txs(&bytes)
+                .with_context(|| format!("Invalid txs in {}", path.display()))?,
+        );
+    }
+    Ok(read)
+}
 
-        client
-            .post(url)
-            .body(body)
-            .send()
-            .await?
-            .error_for_status()?;
+/// The Bitcoin Core node of --bitcoin-host, or of the --network section of the config file, to
+/// fetch transactions from
+fn fetching_node(args: &Args) -> Option<NetworkConfig> {
+    let network = args.network.unwrap_or(Magic::BITCOIN);
+    let given = network_config(args);
+    let configured = args.networks.iter().find(|(magic, _)| *magic == network);
+    let config = match configured {
+        Some((_, config)) if given.bitcoin_hosts.is_empty() => config.clone(),
+        _ => given,
+    };
+    (!config.bitcoin_hosts.is_empty()).then_some(config)
+}
 
-        println!("Broadcasted tx: {}", tx.txid());
+/// What the send command publishes and waits for
+fn send_config(args: &Args, send: &SendArgs) -> SendConfig {
+    let spare_relays = match args.no_default_relays {
+        true => vec![],
+        false => RELAYS.map(String::from).to_vec(),
+    };
+    SendConfig {
+        relays: args.relays.clone(),
+        spare_relays,
+        proxy: args.proxy,
+        magic: args.network.unwrap_or(Magic::BITCOIN),
+        kind: args.kinds[0],
+        encrypt_to: send.encrypt_to,
+        discover: send.discover,
+        encrypt: send.encrypt,
+        cashu: send.cashu.clone(),
+        tx_encoding: send.tx_encoding,
+        compression: send.compress,
+        chunk_size: send.chunk_size,
+        expiration: (send.expiration > 0).then(|| Duration::from_secs(send.expiration)),
+        pow: send.pow,
+        wait_for: send.wait_for,
+        timeout: Duration::from_secs(send.timeout),
+        min_relays: send.min_relays,
+        node: fetching_node(args),
     }
+}
+
+/// The HTTP server of the listener, at --http-addr
+fn listener_url(args: &Args) -> anyhow::Result<String> {
+    let Some(mut addr) = args.http_addr else {
+        bail!("--http-addr of the listener is required");
+    };
+    if addr.ip().is_unspecified() {
+        addr.set_ip(Ipv4Addr::LOCALHOST.into());
+    }
+    Ok(format!("http://{addr}"))
+}
+
+/// Send a request to an admin endpoint of the listener
+async fn admin(
+    args: &Args,
+    method: Method,
+    endpoint: &str,
+    body: Option<serde_json::Value>,
+) -> anyhow::Result<()> {
+    let url = format!("{}/admin/{endpoint}", listener_url(args)?);
+    let mut request = reqwest::Client::new().request(method, &url);
+    if let Some(token) = &args.http_token {
+        request = request.bearer_auth(token);
+    }
+    if let Some(body) = body {
+        request = request
+            .header(CONTENT_TYPE, "application/json")
+            .body(body.to_string());
+    }
+    let response = request
+        .send()
+        .await
+        .with_context(|| format!("Requesting {url}"))?;
 
+    let status = response.status();
+    if status == StatusCode::NOT_FOUND {
+        bail!("The listener doesn't serve the admin endpoints, start it with --admin");
+    }
+    if status == StatusCode::UNAUTHORIZED {
+        bail!("The listener wants the token it was started with, give --http-token");
+    }
+    if !status.is_success() {
+        bail!("{status}: {}", response.text().await?);
+    }
+    Ok(())
+}
+
+async fn status(args: &Args) -> anyhow::Result<()> {
+    let url = listener_url(args)?;
+    // the body tells the details also when unhealthy
+    let body = reqwest::get(format!("{url}/healthz"))
+        .await
+        .with_context(|| format!("Querying the listener at {url}"))?
+        .text()
+        .await?;
+    let health: Health = serde_json::from_str(&body)?;
+
+    println!("Healthy: {}", if health.healthy { "yes" } else { "no" });
+    if health.paused {
+        println!("Broadcasting is paused");
+    }
+    if let Some(secs) = health.last_broadcast_secs {
+        println!("Last broadcast: {secs}s ago");
+    }
+    println!();
+    println!(
+        "{:<40} {:<13} {:<10} {:>5} {:>8} {:>8} {:>10}",
+        "RELAY", "STATUS", "ROLE", "SCORE", "FAILURES", "LATENCY", "DUPLICATES"
+    );
+    for relay in &health.relays {
+        let status = if relay.demoted {
+            "Demoted"
+        } else {
+            &relay.status
+        };
+        let latency = relay
+            .latency_secs
+            .map(|secs| format!("{secs:.1}s"))
+            .unwrap_or_else(|| "-".to_string());
+        println!(
+            "{:<40} {:<13} {:<10} {:>5.2} {:>8} {:>8} {:>9.0}%",
+            relay.url,
+            status,
+            relay.role,
+            relay.score,
+            relay.connect_failures,
+            latency,
+            relay.duplicate_ratio * 100.0
+        );
+    }
+    println!();
+    println!("{:<10} {:<40} REACHABLE", "NETWORK", "BACKEND");
+    for backend in &health.backends {
+        let reachable = match &backend.error {
+            None => "yes".to_string(),
+            Some(e) => format!("no: {e}"),
+        };
+        println!(
+            "{:<10} {:<40} {}",
+            backend.magic, backend.backend, reachable
+        );
+    }
     Ok(())
 }
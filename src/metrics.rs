@@ -0,0 +1,282 @@
+//! Prometheus metrics of the listener and the backends.
+
+use prometheus::core::Collector;
+use prometheus::{
+    Encoder, GaugeVec, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder,
+};
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// The counters of a listener, cheap to clone
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    registry: Registry,
+    events_received: IntCounterVec,
+    events_filtered: IntCounterVec,
+    invalid_events: IntCounterVec,
+    queue_depth: IntGauge,
+    txs_received: IntCounter,
+    broadcasts: IntCounter,
+    broadcast_successes: IntCounter,
+    backend_failures: IntCounterVec,
+    backend_latency: HistogramVec,
+    relay_score: GaugeVec,
+    relay_demotions: IntCounterVec,
+    relay_silences: IntCounterVec,
+}
+
+impl Metrics {
+    /// Fresh metrics in their own registry
+    pub fn new() -> anyhow::Result<Self> {
+        let registry = Registry::new();
+        let metrics = Self {
+            events_received: IntCounterVec::new(
+                Opts::new("events_received_total", "Transaction events received"),
+                &["relay"],
+            )?,
+            events_filtered: IntCounterVec::new(
+                Opts::new(
+                    "events_filtered_total",
+                    "Transaction events not broadcast, by reason",
+                ),
+                &["reason"],
+            )?,
+            invalid_events: IntCounterVec::new(
+                Opts::new(
+                    "invalid_events_total",
+                    "Events with a wrong id or signature, by the relay delivering them",
+                ),
+                &["relay"],
+            )?,
+            queue_depth: IntGauge::new("queue_depth", "Events waiting to be broadcast")?,
+            txs_received: IntCounter::new(
+                "txs_received_total",
+                "Unique transactions queued to be broadcast",
+            )?,
+            broadcasts: IntCounter::new("broadcasts_total", "Events whose txs were broadcast")?,
+            broadcast_successes: IntCounter::new(
+                "broadcast_successes_total",
+                "Events whose txs were accepted",
+            )?,
+            backend_failures: IntCounterVec::new(
+                Opts::new("backend_failures_total", "Failed backend calls"),
+                &["backend", "call"],
+            )?,
+            backend_latency: HistogramVec::new(
+                HistogramOpts::new("backend_latency_seconds", "Duration of backend calls"),
+                &["backend", "call"],
+            )?,
+            relay_score: GaugeVec::new(
+                Opts::new("relay_score", "Score of the relays, between 0 and 1"),
+                &["relay"],
+            )?,
+            relay_demotions: IntCounterVec::new(
+                Opts::new(
+                    "relay_demotions_total",
+                    "Relays demoted for scoring too low",
+                ),
+                &["relay"],
+            )?,
+            relay_silences: IntCounterVec::new(
+                Opts::new(
+                    "relay_silences_total",
+                    "Connections to relays dropped for going silent",
+                ),
+                &["relay"],
+            )?,
+            registry,
+        };
+
+        let registry = &metrics.registry;
+        registry.register(Box::new(metrics.events_received.clone()))?;
+        registry.register(Box::new(metrics.events_filtered.clone()))?;
+        registry.register(Box::new(metrics.invalid_events.clone()))?;
+        registry.register(Box::new(metrics.queue_depth.clone()))?;
+        registry.register(Box::new(metrics.txs_received.clone()))?;
+        registry.register(Box::new(metrics.broadcasts.clone()))?;
+        registry.register(Box::new(metrics.broadcast_successes.clone()))?;
+        registry.register(Box::new(metrics.backend_failures.clone()))?;
+        registry.register(Box::new(metrics.backend_latency.clone()))?;
+        registry.register(Box::new(metrics.relay_score.clone()))?;
+        registry.register(Box::new(metrics.relay_demotions.clone()))?;
+        registry.register(Box::new(metrics.relay_silences.clone()))?;
+        Ok(metrics)
+    }
+
+    /// All metrics in the Prometheus text format
+    pub fn render(&self) -> anyhow::Result<String> {
+        let mut buffer = vec![];
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+
+    /// The counters added up over their labels, to summarize what happened between two calls
+    pub fn totals(&self) -> Totals {
+        let sum_by = |counter: &IntCounterVec, label: &str| {
+            let mut sums = BTreeMap::new();
+            for family in counter.collect() {
+                for metric in family.get_metric() {
+                    let value = metric
+                        .get_label()
+                        .iter()
+                        .find(|pair| pair.get_name() == label)
+                        .map(|pair| pair.get_value().to_string())
+                        .unwrap_or_default();
+                    *sums.entry(value).or_default() += metric.get_counter().get_value() as u64;
+                }
+            }
+            sums
+        };
+        Totals {
+            events_received: sum_by(&self.events_received, "relay").values().sum(),
+            txs_received: self.txs_received.get(),
+            broadcasts: self.broadcasts.get(),
+            broadcast_successes: self.broadcast_successes.get(),
+            events_filtered: sum_by(&self.events_filtered, "reason"),
+        }
+    }
+
+    /// Count an event received from `relay`
+    pub fn event_received(&self, relay: &str) {
+        self.events_received.with_label_values(&[relay]).inc();
+    }
+
+    /// Count an event that isn't broadcast because of `reason`, returning how many were so far
+    pub fn event_filtered(&self, reason: &str) -> u64 {
+        let counter = self.events_filtered.with_label_values(&[reason]);
+        counter.inc();
+        counter.get()
+    }
+
+    /// Count an event with a wrong id or signature delivered by `relay`
+    pub fn invalid_event(&self, relay: &str) {
+        self.invalid_events.with_label_values(&[relay]).inc();
+    }
+
+    /// Set how many events wait to be broadcast
+    pub fn set_queue_depth(&self, depth: usize) {
+        self.queue_depth.set(depth as i64);
+    }
+
+    /// Count transactions queued to be broadcast
+    pub fn txs_received(&self, count: usize) {
+        self.txs_received.inc_by(count as u64);
+    }
+
+    /// Count a broadcast of the txs of an event and whether it succeeded
+    pub fn broadcast(&self, success: bool) {
+        self.broadcasts.inc();
+        if success {
+            self.broadcast_successes.inc();
+        }
+    }
+
+    /// Record a call to a backend that took `seconds`
+    pub fn backend_call(&self, backend: &str, call: &str, seconds: f64, success: bool) {
+        self.backend_latency
+            .with_label_values(&[backend, call])
+            .observe(seconds);
+        if !success {
+            self.backend_failures
+                .with_label_values(&[backend, call])
+                .inc();
+        }
+    }
+
+    /// Set the score of `relay`
+    pub fn set_relay_score(&self, relay: &str, score: f64) {
+        self.relay_score.with_label_values(&[relay]).set(score);
+    }
+
+    /// Count a demotion of `relay`
+    pub fn relay_demoted(&self, relay: &str) {
+        self.relay_demotions.with_label_values(&[relay]).inc();
+    }
+
+    /// Count a connection to `relay` dropped for going silent
+    pub fn relay_silent(&self, relay: &str) {
+        self.relay_silences.with_label_values(&[relay]).inc();
+    }
+}
+
+/// What the counters of [`Metrics`] added up to at some point, see [`Metrics::totals`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Totals {
+    /// Transaction events received, duplicates included
+    pub events_received: u64,
+    /// Unique transactions queued to be broadcast
+    pub txs_received: u64,
+    /// Events whose txs were broadcast
+    pub broadcasts: u64,
+    /// Events whose txs were accepted
+    pub broadcast_successes: u64,
+    /// Events not broadcast, by reason
+    pub events_filtered: BTreeMap<String, u64>,
+}
+
+impl Totals {
+    /// What was counted after `earlier`
+    pub fn since(&self, earlier: &Totals) -> Totals {
+        let events_filtered = self
+            .events_filtered
+            .iter()
+            .map(|(reason, count)| {
+                let before = earlier.events_filtered.get(reason).copied().unwrap_or(0);
+                (reason.clone(), count.saturating_sub(before))
+            })
+            .filter(|(_, count)| *count > 0)
+            .collect();
+        Totals {
+            events_received: self.events_received.saturating_sub(earlier.events_received),
+            txs_received: self.txs_received.saturating_sub(earlier.txs_received),
+            broadcasts: self.broadcasts.saturating_sub(earlier.broadcasts),
+            broadcast_successes: self
+                .broadcast_successes
+                .saturating_sub(earlier.broadcast_successes),
+            events_filtered,
+        }
+    }
+
+    /// Broadcasts that weren't accepted
+    pub fn broadcast_failures(&self) -> u64 {
+        self.broadcasts.saturating_sub(self.broadcast_successes)
+    }
+
+    /// The `n` most common reasons events weren't broadcast, most common first
+    pub fn top_filtered(&self, n: usize) -> Vec<(&str, u64)> {
+        let mut reasons: Vec<_> = self
+            .events_filtered
+            .iter()
+            .map(|(reason, count)| (reason.as_str(), *count))
+            .collect();
+        reasons.sort_by_key(|(_, count)| Reverse(*count));
+        reasons.truncate(n);
+        reasons
+    }
+}
+
+impl fmt::Display for Totals {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} events received, {} unique txs, {} broadcasts ({} accepted, {} failed)",
+            self.events_received,
+            self.txs_received,
+            self.broadcasts,
+            self.broadcast_successes,
+            self.broadcast_failures()
+        )?;
+        let top = self.top_filtered(3);
+        if !top.is_empty() {
+            let top: Vec<_> = top
+                .iter()
+                .map(|(reason, count)| format!("{reason} {count}"))
+                .collect();
+            write!(f, ", filtered: {}", top.join(", "))?;
+        }
+        Ok(())
+    }
+}
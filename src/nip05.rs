@@ -0,0 +1,119 @@
+//! NIP-05 identifiers: `name@domain` in the kind 0 metadata of a pubkey, vouched for by the
+//! domain's `/.well-known/nostr.json`, as a lightweight reputation gate for senders.
+
+use anyhow::{anyhow, Context};
+use lru::LruCache;
+use nostr::nips::nip05;
+use nostr::prelude::*;
+use nostr_sdk::Client;
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// How many authors' verifications are remembered
+const CACHE_SIZE: usize = 10_000;
+
+/// How long fetching the metadata and asking the domain may take each
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Which identifiers are accepted
+#[derive(Debug, Clone, Default)]
+pub struct Nip05Config {
+    /// Domains whose identifiers are accepted, empty to accept any domain
+    pub domains: Vec<String>,
+    /// How long a verification, failed or not, is remembered
+    pub cache_ttl: Duration,
+}
+
+/// Verifies the identifiers of authors, remembering the outcome for a while
+#[derive(Debug)]
+pub struct Verifier {
+    config: Nip05Config,
+    proxy: Option<SocketAddr>,
+    cache: Mutex<LruCache<XOnlyPublicKey, (Option<String>, Instant)>>,
+}
+
+impl Verifier {
+    /// Verifier asking the domains through the SOCKS5 `proxy`, if any
+    pub fn new(config: Nip05Config, proxy: Option<SocketAddr>) -> Self {
+        Self {
+            config,
+            proxy,
+            cache: Mutex::new(LruCache::new(NonZeroUsize::new(CACHE_SIZE).unwrap())),
+        }
+    }
+
+    /// The verified identifier of `pubkey`, `None` when it has none, of a domain not accepted, or
+    /// its domain doesn't vouch for it
+    pub async fn verify(&self, client: &Client, pubkey: XOnlyPublicKey) -> Option<String> {
+        if let Some((identifier, since)) = self.cache.lock().unwrap().get(&pubkey) {
+            if since.elapsed() < self.config.cache_ttl {
+                return identifier.clone();
+            }
+        }
+
+        let identifier = match self.check(client, pubkey).await {
+            Ok(identifier) => Some(identifier),
+            Err(e) => {
+                debug!(%pubkey, "No verified NIP-05 identifier: {e:#}");
+                None
+            }
+        };
+        self.cache
+            .lock()
+            .unwrap()
+            .put(pubkey, (identifier.clone(), Instant::now()));
+        identifier
+    }
+
+    async fn check(&self, client: &Client, pubkey: XOnlyPublicKey) -> anyhow::Result<String> {
+        let identifier = identifier(client, pubkey)
+            .await?
+            .ok_or_else(|| anyhow!("no identifier in the metadata"))?;
+        let domain = domain(&identifier);
+        if !self.config.domains.is_empty()
+            && !self
+                .config
+                .domains
+                .iter()
+                .any(|accepted| accepted.eq_ignore_ascii_case(domain))
+        {
+            return Err(anyhow!("{identifier} isn't of an accepted domain"));
+        }
+        tokio::time::timeout(TIMEOUT, nip05::verify(pubkey, &identifier, self.proxy))
+            .await
+            .with_context(|| format!("{domain} didn't answer in time"))?
+            .with_context(|| format!("{domain} doesn't vouch for {identifier}"))?;
+        Ok(identifier)
+    }
+}
+
+/// The identifier in the latest metadata of `pubkey` found on the relays of `client`, a bare
+/// domain standing for `_@domain`
+pub async fn identifier(client: &Client, pubkey: XOnlyPublicKey) -> anyhow::Result<Option<String>> {
+    let filter = Filter::new()
+        .kind(Kind::Metadata)
+        .author(pubkey.to_string());
+    let events = client.get_events_of(vec![filter], Some(TIMEOUT)).await?;
+    let Some(latest) = events
+        .into_iter()
+        .filter(|event| event.pubkey == pubkey && event.verify().is_ok())
+        .max_by_key(|event| event.created_at)
+    else {
+        return Ok(None);
+    };
+    let metadata = Metadata::from_json(&latest.content).context("Invalid metadata")?;
+    Ok(metadata
+        .nip05
+        .map(|identifier| match identifier.contains('@') {
+            true => identifier,
+            false => format!("_@{identifier}"),
+        }))
+}
+
+/// The domain of `identifier`
+fn domain(identifier: &str) -> &str {
+    identifier.rsplit('@').next().unwrap_or(identifier)
+}
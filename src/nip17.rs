@@ -0,0 +1,88 @@
+//! NIP-17 private direct messages: a kind 14 message, sealed in a kind 13 event encrypted by the
+//! sender and gift wrapped in a kind 1059 event encrypted by a throwaway key, so relays see
+//! neither the sender nor the content. Timestamps are randomized to not tell when it was sent.
+
+use nostr::prelude::*;
+use rand::Rng;
+
+use crate::nip44;
+
+/// Kind of the message itself, never published unwrapped
+pub const MESSAGE_KIND: u64 = 14;
+
+/// Kind of the message sealed by the sender
+pub const SEAL_KIND: u64 = 13;
+
+/// Kind of the gift wrap the seal is published in
+pub const GIFT_WRAP_KIND: u64 = 1059;
+
+/// How far in the past the timestamps of the seal and the gift wrap may be set
+const MAX_TWEAK: u64 = 2 * 24 * 60 * 60;
+
+/// Gift wrap the private `message` from `keys` to `receiver`
+pub fn gift_wrap(keys: &Keys, receiver: XOnlyPublicKey, message: &str) -> anyhow::Result<Event> {
+    let rumor = EventBuilder::new(
+        Kind::Custom(MESSAGE_KIND),
+        message,
+        &[Tag::PubKey(receiver, None)],
+    )
+    .to_unsigned_event(keys.public_key());
+
+    let content = nip44::encrypt(&keys.secret_key()?, &receiver, &rumor.as_json())?;
+    let seal = sign_at(
+        keys,
+        Kind::Custom(SEAL_KIND),
+        content,
+        vec![],
+        tweaked_now(),
+    )?;
+
+    let wrapper = Keys::generate();
+    let content = nip44::encrypt(&wrapper.secret_key()?, &receiver, &seal.as_json())?;
+    sign_at(
+        &wrapper,
+        Kind::Custom(GIFT_WRAP_KIND),
+        content,
+        vec![Tag::PubKey(receiver, None)],
+        tweaked_now(),
+    )
+}
+
+/// Unwrap a gift wrapped message to `keys`, returning its sender and content
+pub fn unwrap(keys: &Keys, gift_wrap: &Event) -> anyhow::Result<(XOnlyPublicKey, String)> {
+    let sk = keys.secret_key()?;
+    let seal = Event::from_json(nip44::decrypt(&sk, &gift_wrap.pubkey, &gift_wrap.content)?)?;
+    seal.verify()?;
+    let rumor = UnsignedEvent::from_json(nip44::decrypt(&sk, &seal.pubkey, &seal.content)?)?;
+    anyhow::ensure!(
+        rumor.pubkey == seal.pubkey,
+        "message not sealed by its author"
+    );
+    Ok((rumor.pubkey, rumor.content))
+}
+
+/// Sign an event created at `created_at`
+fn sign_at(
+    keys: &Keys,
+    kind: Kind,
+    content: String,
+    tags: Vec<Tag>,
+    created_at: Timestamp,
+) -> anyhow::Result<Event> {
+    let pubkey = keys.public_key();
+    let event = UnsignedEvent {
+        id: EventId::new(&pubkey, created_at, &kind, &tags, &content),
+        pubkey,
+        created_at,
+        kind,
+        tags,
+        content,
+    };
+    Ok(event.sign(keys)?)
+}
+
+/// Now, set back randomly by up to two days
+fn tweaked_now() -> Timestamp {
+    let tweak = rand::thread_rng().gen_range(0..MAX_TWEAK);
+    Timestamp::from(Timestamp::now().as_u64() - tweak)
+}
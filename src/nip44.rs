@@ -0,0 +1,302 @@
+//! NIP-44 (version 2) encryption, which the nostr crate doesn't implement yet: ECDH between the
+//! two keys, ChaCha20 with padded plaintext, and an HMAC-SHA256 over nonce and ciphertext.
+
+use anyhow::bail;
+use base64::{engine::general_purpose, Engine as _};
+use bitcoin::hashes::hmac::{Hmac, HmacEngine};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::secp256k1::{ecdh, Parity, PublicKey, SecretKey, XOnlyPublicKey};
+use chacha20::cipher::{KeyIvInit, StreamCipher};
+use chacha20::ChaCha20;
+use rand::RngCore;
+
+const VERSION: u8 = 2;
+const SALT: &[u8] = b"nip44-v2";
+const MIN_PLAINTEXT_LEN: usize = 1;
+const MAX_PLAINTEXT_LEN: usize = 65535;
+/// Bounds of the base64 payload, from the smallest to the largest padded plaintext
+const MIN_PAYLOAD_LEN: usize = 132;
+const MAX_PAYLOAD_LEN: usize = 87472;
+
+/// Encrypt `plaintext` from `sk` to `pk`, returning the base64 payload
+pub fn encrypt(sk: &SecretKey, pk: &XOnlyPublicKey, plaintext: &str) -> anyhow::Result<String> {
+    let mut nonce = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    encrypt_with_nonce(&conversation_key(sk, pk), &nonce, plaintext)
+}
+
+/// Decrypt a base64 `payload` sent by `pk` to `sk`
+pub fn decrypt(sk: &SecretKey, pk: &XOnlyPublicKey, payload: &str) -> anyhow::Result<String> {
+    decrypt_with_key(&conversation_key(sk, pk), payload)
+}
+
+fn decrypt_with_key(conversation_key: &[u8; 32], payload: &str) -> anyhow::Result<String> {
+    if payload.starts_with('#') {
+        bail!("unsupported encryption version");
+    }
+    if !(MIN_PAYLOAD_LEN..=MAX_PAYLOAD_LEN).contains(&payload.len()) {
+        bail!("invalid payload length {}", payload.len());
+    }
+    let payload = general_purpose::STANDARD.decode(payload)?;
+    // version, nonce, at least 32 padded bytes plus the length, mac
+    if payload.len() < 1 + 32 + 34 + 32 {
+        bail!("payload too short");
+    }
+    if payload[0] != VERSION {
+        bail!("unsupported encryption version {}", payload[0]);
+    }
+
+    let (nonce, rest) = payload[1..].split_at(32);
+    let (ciphertext, mac) = rest.split_at(rest.len() - 32);
+    let (chacha_key, chacha_nonce, hmac_key) = message_keys(conversation_key, nonce);
+
+    let expected = hmac(&hmac_key, &[nonce, ciphertext]);
+    if !constant_time_eq(&expected, mac) {
+        bail!("invalid mac");
+    }
+
+    let mut padded = ciphertext.to_vec();
+    ChaCha20::new(&chacha_key.into(), &chacha_nonce.into()).apply_keystream(&mut padded);
+
+    let len = u16::from_be_bytes([padded[0], padded[1]]) as usize;
+    if len < MIN_PLAINTEXT_LEN || padded.len() != 2 + padded_len(len) {
+        bail!("invalid padding");
+    }
+    Ok(String::from_utf8(padded[2..2 + len].to_vec())?)
+}
+
+fn encrypt_with_nonce(
+    conversation_key: &[u8; 32],
+    nonce: &[u8; 32],
+    plaintext: &str,
+) -> anyhow::Result<String> {
+    let plaintext = plaintext.as_bytes();
+    if !(MIN_PLAINTEXT_LEN..=MAX_PLAINTEXT_LEN).contains(&plaintext.len()) {
+        bail!("plaintext of {} bytes can't be encrypted", plaintext.len());
+    }
+
+    let mut padded = vec![0u8; 2 + padded_len(plaintext.len())];
+    padded[..2].copy_from_slice(&(plaintext.len() as u16).to_be_bytes());
+    padded[2..2 + plaintext.len()].copy_from_slice(plaintext);
+
+    let (chacha_key, chacha_nonce, hmac_key) = message_keys(conversation_key, nonce);
+    ChaCha20::new(&chacha_key.into(), &chacha_nonce.into()).apply_keystream(&mut padded);
+    let mac = hmac(&hmac_key, &[nonce, &padded]);
+
+    let mut payload = Vec::with_capacity(1 + 32 + padded.len() + 32);
+    payload.push(VERSION);
+    payload.extend_from_slice(nonce);
+    payload.extend_from_slice(&padded);
+    payload.extend_from_slice(&mac);
+    Ok(general_purpose::STANDARD.encode(payload))
+}
+
+/// HKDF-extract of the shared x coordinate
+fn conversation_key(sk: &SecretKey, pk: &XOnlyPublicKey) -> [u8; 32] {
+    let pk = PublicKey::from_x_only_public_key(*pk, Parity::Even);
+    let shared = ecdh::shared_secret_point(&pk, sk);
+    hmac(SALT, &[&shared[..32]])
+}
+
+/// HKDF-expand of the conversation key into the ChaCha20 key and nonce and the HMAC key
+fn message_keys(conversation_key: &[u8; 32], nonce: &[u8]) -> ([u8; 32], [u8; 12], [u8; 32]) {
+    let mut okm = Vec::with_capacity(96);
+    let mut block: Vec<u8> = vec![];
+    for counter in 1..=3u8 {
+        block = hmac(conversation_key, &[&block, nonce, &[counter]]).to_vec();
+        okm.extend_from_slice(&block);
+    }
+
+    let mut chacha_key = [0u8; 32];
+    let mut chacha_nonce = [0u8; 12];
+    let mut hmac_key = [0u8; 32];
+    chacha_key.copy_from_slice(&okm[..32]);
+    chacha_nonce.copy_from_slice(&okm[32..44]);
+    hmac_key.copy_from_slice(&okm[44..76]);
+    (chacha_key, chacha_nonce, hmac_key)
+}
+
+fn padded_len(len: usize) -> usize {
+    if len <= 32 {
+        return 32;
+    }
+    let next_power = 1 << (usize::BITS - (len - 1).leading_zeros());
+    let chunk = if next_power <= 256 {
+        32
+    } else {
+        next_power / 8
+    };
+    chunk * ((len - 1) / chunk + 1)
+}
+
+fn hmac(key: &[u8], data: &[&[u8]]) -> [u8; 32] {
+    let mut engine = HmacEngine::<sha256::Hash>::new(key);
+    for data in data {
+        engine.input(data);
+    }
+    Hmac::<sha256::Hash>::from_engine(engine).to_byte_array()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::hex::FromHex;
+    use serde_json::Value;
+
+    /// The official test vectors of NIP-44
+    const VECTORS: &str = include_str!("nip44.vectors.json");
+
+    fn vectors(kind: &str, name: &str) -> Vec<Value> {
+        let vectors: Value = serde_json::from_str(VECTORS).unwrap();
+        vectors["v2"][kind][name].as_array().unwrap().clone()
+    }
+
+    fn bytes32(value: &Value) -> [u8; 32] {
+        <[u8; 32]>::from_hex(value.as_str().unwrap()).unwrap()
+    }
+
+    fn secret_key(value: &Value) -> SecretKey {
+        SecretKey::from_slice(&bytes32(value)).unwrap()
+    }
+
+    fn public_key(value: &Value) -> XOnlyPublicKey {
+        XOnlyPublicKey::from_slice(&bytes32(value)).unwrap()
+    }
+
+    #[test]
+    fn conversation_keys() {
+        for vector in vectors("valid", "get_conversation_key") {
+            let key = conversation_key(&secret_key(&vector["sec1"]), &public_key(&vector["pub2"]));
+            assert_eq!(
+                key,
+                bytes32(&vector["conversation_key"]),
+                "{}",
+                vector["note"]
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_conversation_keys() {
+        for vector in vectors("invalid", "get_conversation_key") {
+            let sec1 = SecretKey::from_slice(&bytes32(&vector["sec1"]));
+            let pub2 = XOnlyPublicKey::from_slice(&bytes32(&vector["pub2"]));
+            assert!(sec1.is_err() || pub2.is_err(), "{}", vector["note"]);
+        }
+    }
+
+    #[test]
+    fn message_keys_are_hkdf_expand() {
+        for vector in vectors("valid", "encrypt_decrypt") {
+            let conversation_key = bytes32(&vector["conversation_key"]);
+            let nonce = bytes32(&vector["nonce"]);
+            let mut expected = [0u8; 76];
+            hkdf::Hkdf::<sha2::Sha256>::from_prk(&conversation_key)
+                .unwrap()
+                .expand(&nonce, &mut expected)
+                .unwrap();
+
+            let (chacha_key, chacha_nonce, hmac_key) = message_keys(&conversation_key, &nonce);
+            assert_eq!(chacha_key, expected[..32]);
+            assert_eq!(chacha_nonce, expected[32..44]);
+            assert_eq!(hmac_key, expected[44..]);
+        }
+    }
+
+    #[test]
+    fn padded_lengths() {
+        for vector in vectors("valid", "calc_padded_len") {
+            let len = vector[0].as_u64().unwrap() as usize;
+            let padded = vector[1].as_u64().unwrap() as usize;
+            assert_eq!(padded_len(len), padded, "{len}");
+        }
+    }
+
+    #[test]
+    fn encrypt_decrypt() {
+        for vector in vectors("valid", "encrypt_decrypt") {
+            let sec1 = secret_key(&vector["sec1"]);
+            let sec2 = secret_key(&vector["sec2"]);
+            let secp = bitcoin::secp256k1::Secp256k1::new();
+            let pub1 = sec1.x_only_public_key(&secp).0;
+            let pub2 = sec2.x_only_public_key(&secp).0;
+            let key = conversation_key(&sec1, &pub2);
+            assert_eq!(key, bytes32(&vector["conversation_key"]));
+            assert_eq!(conversation_key(&sec2, &pub1), key);
+
+            let plaintext = vector["plaintext"].as_str().unwrap();
+            let ciphertext = vector["ciphertext"].as_str().unwrap();
+            let nonce = bytes32(&vector["nonce"]);
+            assert_eq!(
+                encrypt_with_nonce(&key, &nonce, plaintext).unwrap(),
+                ciphertext
+            );
+            assert_eq!(decrypt(&sec2, &pub1, ciphertext).unwrap(), plaintext);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        let (sk1, pk1) = secp.generate_keypair(&mut rand::thread_rng());
+        let (sk2, pk2) = secp.generate_keypair(&mut rand::thread_rng());
+        let plaintext = "a".repeat(MAX_PLAINTEXT_LEN);
+        let payload = encrypt(&sk1, &pk2.x_only_public_key().0, &plaintext).unwrap();
+        assert_eq!(
+            decrypt(&sk2, &pk1.x_only_public_key().0, &payload).unwrap(),
+            plaintext
+        );
+    }
+
+    #[test]
+    fn invalid_plaintext_lengths() {
+        let key = [1u8; 32];
+        for len in vectors("invalid", "encrypt_msg_lengths") {
+            let plaintext = "a".repeat(len.as_u64().unwrap() as usize);
+            assert!(encrypt_with_nonce(&key, &[2u8; 32], &plaintext).is_err());
+        }
+    }
+
+    #[test]
+    fn invalid_payload_lengths() {
+        let key = [1u8; 32];
+        for len in vectors("invalid", "decrypt_msg_lengths") {
+            let mut payload = vec![VERSION];
+            payload.resize(len.as_u64().unwrap() as usize, 0);
+            let payload = general_purpose::STANDARD.encode(payload);
+            assert!(decrypt_with_key(&key, &payload).is_err());
+        }
+    }
+
+    #[test]
+    fn invalid_payloads() {
+        for vector in vectors("invalid", "decrypt") {
+            let key = bytes32(&vector["conversation_key"]);
+            let ciphertext = vector["ciphertext"].as_str().unwrap();
+            assert!(
+                decrypt_with_key(&key, ciphertext).is_err(),
+                "{}",
+                vector["note"]
+            );
+        }
+    }
+
+    #[test]
+    fn forged_mac_short_plaintext() {
+        // a valid mac over a ciphertext whose length prefix claims more than it pads to
+        let key = [1u8; 32];
+        let nonce = [2u8; 32];
+        let (chacha_key, chacha_nonce, hmac_key) = message_keys(&key, &nonce);
+        let mut padded = vec![0u8; 34];
+        padded[..2].copy_from_slice(&1000u16.to_be_bytes());
+        ChaCha20::new(&chacha_key.into(), &chacha_nonce.into()).apply_keystream(&mut padded);
+        let mac = hmac(&hmac_key, &[&nonce, &padded]);
+        let payload = [&[VERSION][..], &nonce, &padded, &mac].concat();
+        let payload = general_purpose::STANDARD.encode(payload);
+        assert!(decrypt_with_key(&key, &payload).is_err());
+    }
+}
@@ -0,0 +1,94 @@
+//! NIP-57 zaps: a kind 9734 zap request sent to the LNURL server of a lightning address for an
+//! invoice, and the kind 9735 zap receipt it publishes once the invoice is paid, tagging the zapped
+//! event and carrying the paid BOLT11 invoice and the zap request.
+
+use anyhow::{anyhow, bail, Context};
+use nostr::prelude::*;
+
+/// A paid zap
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Receipt {
+    /// The zapped event
+    pub zapped: EventId,
+    /// Amount paid in millisatoshis, from the invoice
+    pub amount_msat: u64,
+    /// Who zapped, the author of the zap request
+    pub sender: Option<XOnlyPublicKey>,
+}
+
+/// Build the request to zap `zapped` to `recipient` with `msat`, asking for the receipt to be
+/// published to `relays`
+pub fn zap_request(
+    keys: &Keys,
+    recipient: XOnlyPublicKey,
+    zapped: EventId,
+    msat: u64,
+    relays: &[Url],
+) -> anyhow::Result<Event> {
+    let tags = vec![
+        Tag::Relays(relays.iter().cloned().map(UncheckedUrl::from).collect()),
+        Tag::Amount(msat),
+        Tag::PubKey(recipient, None),
+        Tag::Event(zapped, None, None),
+    ];
+    Ok(EventBuilder::new(Kind::ZapRequest, "", &tags).to_event(keys)?)
+}
+
+/// The zap of a receipt, checking it's published by `zapper`, the nostr pubkey of the LNURL server
+/// that takes the zaps
+pub fn receipt(event: &Event, zapper: XOnlyPublicKey) -> anyhow::Result<Receipt> {
+    if event.kind != Kind::Zap {
+        bail!("not a zap receipt");
+    }
+    if event.pubkey != zapper {
+        bail!("zap receipt of another LNURL server: {}", event.pubkey);
+    }
+    event.verify()?;
+
+    let mut zapped = None;
+    let mut bolt11 = None;
+    let mut description = None;
+    for tag in &event.tags {
+        match tag {
+            Tag::Event(event_id, ..) => zapped = Some(*event_id),
+            Tag::Bolt11(invoice) => bolt11 = Some(invoice.as_str()),
+            Tag::Description(request) => description = Some(request.as_str()),
+            _ => {}
+        }
+    }
+    let zapped = zapped.context("zap receipt without zapped event")?;
+    let bolt11 = bolt11.context("zap receipt without invoice")?;
+    let amount_msat =
+        invoice_amount(bolt11).ok_or_else(|| anyhow!("invoice without amount: {bolt11}"))?;
+    let sender = description
+        .and_then(|request| Event::from_json(request).ok())
+        .map(|request| request.pubkey);
+    Ok(Receipt {
+        zapped,
+        amount_msat,
+        sender,
+    })
+}
+
+/// The amount of a BOLT11 invoice in millisatoshis, read from its human readable part
+pub fn invoice_amount(bolt11: &str) -> Option<u64> {
+    let bolt11 = bolt11.to_lowercase();
+    // the data part can't contain a 1, so the last one is the separator
+    let hrp = bolt11.get(..bolt11.rfind('1')?)?.strip_prefix("ln")?;
+    // after the currency prefix such as bc or tb
+    let amount = hrp.trim_start_matches(|c: char| c.is_ascii_alphabetic());
+    let (digits, multiplier) = match amount.char_indices().last()? {
+        (i, c) if c.is_ascii_alphabetic() => (&amount[..i], Some(c)),
+        _ => (amount, None),
+    };
+    let value: u64 = digits.parse().ok()?;
+    // millisatoshis per unit
+    match multiplier {
+        None => value.checked_mul(100_000_000_000),
+        Some('m') => value.checked_mul(100_000_000),
+        Some('u') => value.checked_mul(100_000),
+        Some('n') => value.checked_mul(100),
+        Some('p') if value.is_multiple_of(10) => Some(value / 10),
+        _ => None,
+    }
+}
@@ -0,0 +1,71 @@
+//! NIP-65 relay lists: the relays a pubkey reads from and writes to, published as a kind 10002
+//! event, to find the relays senders publish to without listing them by hand.
+
+use nostr::nips::nip65;
+use nostr::prelude::*;
+use nostr_sdk::Client;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::relays::parse_relay;
+
+/// What a relay of a relay list is used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Usage {
+    /// The pubkey reads its mentions and messages there
+    Read,
+    /// The pubkey publishes its events there
+    Write,
+}
+
+/// The relays of the relay list `event` used for `usage`, relays without a marker are used for
+/// both
+pub fn relays(event: &Event, usage: Usage) -> Vec<Url> {
+    nip65::get_relay_list(event.clone())
+        .into_iter()
+        .filter(|(_, marker)| {
+            matches!(
+                (marker.as_deref(), usage),
+                (None, _) | (Some("read"), Usage::Read) | (Some("write"), Usage::Write)
+            )
+        })
+        .filter_map(|(url, _)| parse_relay(&url).ok())
+        .collect()
+}
+
+/// Fetch the latest relay lists of `pubkeys` from the relays of `client`, waiting at most
+/// `timeout`, and give their relays used for `usage`
+pub async fn discover(
+    client: &Client,
+    pubkeys: &[XOnlyPublicKey],
+    usage: Usage,
+    timeout: Duration,
+) -> anyhow::Result<Vec<Url>> {
+    let authors = pubkeys.iter().map(|pubkey| pubkey.to_string()).collect();
+    let filter = Filter::new().kind(Kind::RelayList).authors(authors);
+    let events = client.get_events_of(vec![filter], Some(timeout)).await?;
+
+    // relay lists are replaceable, only the latest counts
+    let mut latest: HashMap<XOnlyPublicKey, Event> = HashMap::new();
+    for event in events {
+        if event.verify().is_err() {
+            continue;
+        }
+        match latest.get(&event.pubkey) {
+            Some(known) if known.created_at >= event.created_at => {}
+            _ => {
+                latest.insert(event.pubkey, event);
+            }
+        }
+    }
+
+    let mut discovered = vec![];
+    for event in latest.values() {
+        for url in relays(event, usage) {
+            if !discovered.contains(&url) {
+                discovered.push(url);
+            }
+        }
+    }
+    Ok(discovered)
+}
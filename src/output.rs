@@ -0,0 +1,79 @@
+//! Machine readable output of what the listener does, a JSON object per line on stdout.
+
+use anyhow::bail;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::str::FromStr;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::listener::{Outcome, ReceivedTxs};
+
+/// What's printed on stdout while listening
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Nothing, everything is in the logs
+    #[default]
+    Text,
+    /// A JSON object per received event and per outcome
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => bail!("unknown output format {s}, expected text or json"),
+        }
+    }
+}
+
+/// Print a JSON object per line for every received event and outcome, until the listener is
+/// dropped
+pub async fn print_json(
+    mut received: broadcast::Receiver<ReceivedTxs>,
+    mut outcomes: broadcast::Receiver<Outcome>,
+) {
+    loop {
+        let line = tokio::select! {
+            received = received.recv() => match received {
+                Ok(received) => received_json(&received),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return,
+            },
+            outcome = outcomes.recv() => match outcome {
+                Ok(outcome) => outcome_json(&outcome),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return,
+            },
+        };
+        println!("{line}");
+    }
+}
+
+/// The JSON object of a received event
+pub fn received_json(received: &ReceivedTxs) -> Value {
+    json!({
+        "type": "received",
+        "event_id": received.event_id.to_hex(),
+        "author": received.author.to_string(),
+        "relay": received.relay.to_string(),
+        "kind": received.kind,
+        "magic": received.magic.to_string(),
+        "encoding": received.encoding.as_str(),
+        "txids": received.txs.iter().map(|tx| tx.txid().to_string()).collect::<Vec<_>>(),
+    })
+}
+
+/// The JSON object of an outcome
+pub fn outcome_json(outcome: &Outcome) -> Value {
+    json!({
+        "type": "outcome",
+        "event_id": outcome.event_id.to_hex(),
+        "author": outcome.author.to_string(),
+        "results": outcome.results,
+    })
+}
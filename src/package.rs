@@ -0,0 +1,247 @@
+//! Ordering the transactions of a package. Events may carry them in any order, but mempools only
+//! accept a package with every parent before the transactions spending it.
+
+use bitcoin::{OutPoint, Transaction, Txid};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// Why the transactions of an event don't form a package
+#[derive(Debug, Clone, PartialEq)]
+pub enum PackageError {
+    /// A transaction is in the package more than once
+    DuplicateTx {
+        /// The transaction
+        txid: Txid,
+    },
+    /// A transaction spends an output its parent in the package doesn't have
+    MissingOutput {
+        /// The spending transaction
+        txid: Txid,
+        /// The missing output
+        outpoint: OutPoint,
+    },
+    /// The transactions spend each other in a circle
+    Cycle {
+        /// The transactions that can't be ordered
+        txids: Vec<Txid>,
+    },
+}
+
+impl fmt::Display for PackageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PackageError::DuplicateTx { txid } => {
+                write!(f, "package-duplicate-tx: {txid} is included more than once")
+            }
+            PackageError::MissingOutput { txid, outpoint } => {
+                write!(
+                    f,
+                    "package-missing-output: {txid} spends {outpoint}, which doesn't exist"
+                )
+            }
+            PackageError::Cycle { txids } => {
+                let txids: Vec<_> = txids.iter().map(Txid::to_string).collect();
+                write!(f, "package-cycle: {}", txids.join(", "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for PackageError {}
+
+/// `txs` with parents before children, keeping the order of the event where it doesn't matter
+pub fn sort(txs: &[Transaction]) -> Result<Vec<Transaction>, PackageError> {
+    let mut index = HashMap::new();
+    for (i, tx) in txs.iter().enumerate() {
+        let txid = tx.txid();
+        if index.insert(txid, i).is_some() {
+            return Err(PackageError::DuplicateTx { txid });
+        }
+    }
+
+    // the parents of each transaction within the package
+    let mut parents: Vec<HashSet<usize>> = vec![HashSet::new(); txs.len()];
+    for (i, tx) in txs.iter().enumerate() {
+        for input in &tx.input {
+            let outpoint = input.previous_output;
+            let Some(&parent) = index.get(&outpoint.txid) else {
+                continue;
+            };
+            if outpoint.vout as usize >= txs[parent].output.len() {
+                return Err(PackageError::MissingOutput {
+                    txid: tx.txid(),
+                    outpoint,
+                });
+            }
+            parents[i].insert(parent);
+        }
+    }
+
+    match order(&parents) {
+        Ok(sorted) => Ok(sorted.into_iter().map(|i| txs[i].clone()).collect()),
+        Err(cycle) => Err(PackageError::Cycle {
+            txids: cycle.into_iter().map(|i| txs[i].txid()).collect(),
+        }),
+    }
+}
+
+/// The indices of `parents` with each after its parents, keeping their order where it doesn't
+/// matter, or the ones that can't be ordered
+fn order(parents: &[HashSet<usize>]) -> Result<Vec<usize>, Vec<usize>> {
+    let mut sorted = Vec::with_capacity(parents.len());
+    let mut placed = vec![false; parents.len()];
+    while sorted.len() < parents.len() {
+        let next = (0..parents.len())
+            .find(|&i| !placed[i] && parents[i].iter().all(|&parent| placed[parent]));
+        let Some(next) = next else {
+            return Err((0..parents.len()).filter(|&i| !placed[i]).collect());
+        };
+        placed[next] = true;
+        sorted.push(next);
+    }
+    Ok(sorted)
+}
+
+/// Whether a transaction of `a` spends an output of a transaction of `b`, or the other way round,
+/// so they have to be submitted together as a package
+pub fn related(a: &[Transaction], b: &[Transaction]) -> bool {
+    let spends = |children: &[Transaction], parents: &[Transaction]| {
+        let parents: HashSet<Txid> = parents.iter().map(Transaction::txid).collect();
+        children.iter().any(|child| {
+            child
+                .input
+                .iter()
+                .any(|input| parents.contains(&input.previous_output.txid))
+        })
+    };
+    spends(a, b) || spends(b, a)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{ScriptBuf, TxIn, TxOut};
+
+    /// A transaction spending `inputs` with `outputs` outputs, told apart from others by `id`
+    fn tx(id: u32, inputs: &[OutPoint], outputs: usize) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: LockTime::from_consensus(id),
+            input: inputs
+                .iter()
+                .map(|&previous_output| TxIn {
+                    previous_output,
+                    ..Default::default()
+                })
+                .collect(),
+            output: vec![
+                TxOut {
+                    value: 10_000,
+                    script_pubkey: ScriptBuf::new(),
+                };
+                outputs
+            ],
+        }
+    }
+
+    /// A transaction spending a made up output
+    fn confirmed_parent(id: u32) -> Transaction {
+        tx(id, &[OutPoint::new(Txid::all_zeros(), id)], 2)
+    }
+
+    fn txids(txs: &[Transaction]) -> Vec<Txid> {
+        txs.iter().map(Transaction::txid).collect()
+    }
+
+    #[test]
+    fn parents_first() {
+        let grandparent = confirmed_parent(1);
+        let parent = tx(2, &[OutPoint::new(grandparent.txid(), 0)], 1);
+        let child = tx(
+            3,
+            &[
+                OutPoint::new(parent.txid(), 0),
+                OutPoint::new(grandparent.txid(), 1),
+            ],
+            1,
+        );
+        let sorted = sort(&[child.clone(), parent.clone(), grandparent.clone()]).unwrap();
+        assert_eq!(sorted, vec![grandparent, parent, child]);
+    }
+
+    #[test]
+    fn keeps_the_order_of_the_event() {
+        let (a, b, c) = (
+            confirmed_parent(1),
+            confirmed_parent(2),
+            confirmed_parent(3),
+        );
+        let unrelated = [b.clone(), a.clone(), c.clone()];
+        assert_eq!(sort(&unrelated).unwrap(), unrelated);
+
+        let child = tx(4, &[OutPoint::new(c.txid(), 0)], 1);
+        let sorted = sort(&[child.clone(), b.clone(), c.clone(), a.clone()]).unwrap();
+        assert_eq!(txids(&sorted), txids(&[b, c, child, a]));
+
+        assert_eq!(sort(&[]).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn duplicate_tx() {
+        let parent = confirmed_parent(1);
+        let child = tx(2, &[OutPoint::new(parent.txid(), 0)], 1);
+        assert_eq!(
+            sort(&[parent.clone(), child, parent.clone()]),
+            Err(PackageError::DuplicateTx {
+                txid: parent.txid()
+            })
+        );
+    }
+
+    #[test]
+    fn missing_output() {
+        let parent = confirmed_parent(1);
+        let outpoint = OutPoint::new(parent.txid(), 2);
+        let child = tx(2, &[outpoint], 1);
+        assert_eq!(
+            sort(&[parent, child.clone()]),
+            Err(PackageError::MissingOutput {
+                txid: child.txid(),
+                outpoint
+            })
+        );
+    }
+
+    // txids commit to the inputs, so transactions spending each other in a circle can't be made
+    #[test]
+    fn cycle() {
+        let parents = |edges: &[&[usize]]| -> Vec<HashSet<usize>> {
+            edges
+                .iter()
+                .map(|parents| parents.iter().copied().collect())
+                .collect()
+        };
+        assert_eq!(order(&parents(&[&[1], &[], &[0]])), Ok(vec![1, 0, 2]));
+        assert_eq!(order(&parents(&[&[], &[2], &[1]])), Err(vec![1, 2]));
+        assert_eq!(order(&parents(&[&[0]])), Err(vec![0]));
+
+        let error = PackageError::Cycle {
+            txids: vec![Txid::all_zeros()],
+        };
+        assert!(error.to_string().starts_with("package-cycle: 0000"));
+    }
+
+    #[test]
+    fn related_packages() {
+        let parent = vec![confirmed_parent(1)];
+        let child = vec![tx(2, &[OutPoint::new(parent[0].txid(), 0)], 1)];
+        let unrelated = vec![confirmed_parent(3)];
+
+        assert!(related(&parent, &child));
+        assert!(related(&[unrelated[0].clone(), child[0].clone()], &parent));
+        assert!(!related(&parent, &unrelated));
+        assert!(!related(&[parent[0].clone(), child[0].clone()], &[]));
+    }
+}
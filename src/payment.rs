@@ -0,0 +1,32 @@
+//! Charging for broadcasts: submissions are held until they're paid for, either with an invoice of
+//! the broadcaster's own lightning node, checked with the node, or with a zap to the broadcaster's
+//! lightning address, seen in the zap receipts its LNURL server publishes. Encrypted transaction
+//! events may instead carry a Cashu token, redeemed at the broadcaster's mint.
+
+use nostr::prelude::XOnlyPublicKey;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::cashu::Wallet;
+use crate::lightning::InvoiceBackend;
+use crate::lnurl::PayEndpoint;
+
+/// How submissions are paid for
+#[derive(Debug, Clone)]
+pub struct PaymentConfig {
+    /// Millisatoshis each transaction event costs, `None` for free. Jobs have their own price, see
+    /// [`DvmConfig`](crate::dvm::DvmConfig)
+    pub price: Option<u64>,
+    /// The LNURL pay endpoint of the broadcaster's lightning address, making the invoices sent
+    /// with the transaction events' payment requests
+    pub endpoint: Option<PayEndpoint>,
+    /// Lightning node making the invoices instead, paid ones are found by asking it
+    pub invoices: Option<Arc<dyn InvoiceBackend>>,
+    /// Nostr pubkey of the LNURL server publishing the zap receipts, only its receipts count. `None`
+    /// to not take zaps, with a node only
+    pub zapper: Option<XOnlyPublicKey>,
+    /// Mint whose Cashu tokens are taken, and where the redeemed ones are kept
+    pub cashu: Option<Arc<Wallet>>,
+    /// How long to wait for the payment before dropping the submission
+    pub timeout: Duration,
+}
@@ -0,0 +1,139 @@
+//! Policies on the submitted transactions. Sizes are checked before submissions are queued so
+//! oversized ones don't reach a backend, the feerate needs the backend to look up prevouts.
+
+use anyhow::anyhow;
+use bitcoin::{Transaction, Txid};
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::backend::TxBackend;
+
+/// Limits on the submitted transactions, nothing is limited by default
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    /// Maximum virtual size of a single transaction
+    pub max_tx_vsize: Option<u64>,
+    /// Maximum total weight of the transactions of an event
+    pub max_package_weight: Option<u64>,
+    /// Minimum feerate in sat/vB of the transactions of an event together, also raised to the
+    /// backend's mempool minimum when checked
+    pub min_feerate: Option<f64>,
+}
+
+/// Why a submission breaks the policy
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+    /// A transaction is too large
+    TxTooLarge {
+        /// The transaction
+        txid: Txid,
+        /// Its virtual size
+        vsize: u64,
+        /// The maximum
+        max: u64,
+    },
+    /// The transactions together are too heavy
+    PackageTooHeavy {
+        /// Their total weight
+        weight: u64,
+        /// The maximum
+        max: u64,
+    },
+    /// The transactions together pay too little
+    FeerateTooLow {
+        /// Their feerate in sat/vB
+        feerate: f64,
+        /// The minimum
+        min: f64,
+    },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::TxTooLarge { txid, vsize, max } => {
+                write!(f, "tx-too-large: {txid} has vsize {vsize}, more than {max}")
+            }
+            Violation::PackageTooHeavy { weight, max } => {
+                write!(f, "package-too-heavy: weight {weight}, more than {max}")
+            }
+            Violation::FeerateTooLow { feerate, min } => {
+                write!(
+                    f,
+                    "feerate-too-low: {feerate:.2} sat/vB, less than {min:.2}"
+                )
+            }
+        }
+    }
+}
+
+impl Policy {
+    /// The first way `txs` break the policy, if any
+    pub fn check(&self, txs: &[Transaction]) -> Option<Violation> {
+        if let Some(max) = self.max_tx_vsize {
+            for tx in txs {
+                let vsize = tx.vsize() as u64;
+                if vsize > max {
+                    return Some(Violation::TxTooLarge {
+                        txid: tx.txid(),
+                        vsize,
+                        max,
+                    });
+                }
+            }
+        }
+
+        if let Some(max) = self.max_package_weight {
+            let weight = txs.iter().map(|tx| tx.weight().to_wu()).sum();
+            if weight > max {
+                return Some(Violation::PackageTooHeavy { weight, max });
+            }
+        }
+
+        None
+    }
+
+    /// Whether `txs` pay too little, looking up the outputs they spend with `backend`. Outputs
+    /// of the transactions themselves are taken from the package.
+    pub async fn check_feerate(
+        &self,
+        backend: &dyn TxBackend,
+        txs: &[Transaction],
+    ) -> anyhow::Result<Option<Violation>> {
+        let Some(min_feerate) = self.min_feerate else {
+            return Ok(None);
+        };
+
+        let package: HashMap<Txid, &Transaction> = txs.iter().map(|tx| (tx.txid(), tx)).collect();
+        let mut fee: i64 = 0;
+        for tx in txs {
+            for input in &tx.input {
+                let outpoint = input.previous_output;
+                let value = match package.get(&outpoint.txid) {
+                    Some(parent) => parent
+                        .output
+                        .get(outpoint.vout as usize)
+                        .map(|output| output.value),
+                    None => backend.prevout_value(&outpoint).await?,
+                };
+                fee += value.ok_or_else(|| anyhow!("unknown prevout {outpoint}"))? as i64;
+            }
+            fee -= tx
+                .output
+                .iter()
+                .map(|output| output.value as i64)
+                .sum::<i64>();
+        }
+
+        let vsize: usize = txs.iter().map(|tx| tx.vsize()).sum();
+        let feerate = fee as f64 / vsize as f64;
+        let min = match backend.mempool_min_feerate().await? {
+            Some(mempool_min) => min_feerate.max(mempool_min),
+            None => min_feerate,
+        };
+        if feerate < min {
+            return Ok(Some(Violation::FeerateTooLow { feerate, min }));
+        }
+        Ok(None)
+    }
+}
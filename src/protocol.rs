@@ -0,0 +1,1058 @@
+//! The transaction event format: a kind 28333 event (or another kind chosen by the deployment) carrying the network `magic` and the
+//! base64 encoded `transactions` in its tags, or NIP-44 encrypted to the broadcaster `p` tagged, answered by a kind 28334 result event and a kind
+//! 28335 event for each transaction that confirms.
+
+use anyhow::{anyhow, bail};
+use base64::{engine::general_purpose, Engine as _};
+use bitcoin::consensus::{deserialize, deserialize_partial, serialize, Decodable};
+use bitcoin::hashes::hex::FromHex;
+use bitcoin::hashes::{sha256, sha256d, Hash};
+use bitcoin::network::Magic;
+use bitcoin::{BlockHash, Network, OutPoint, ScriptBuf, Transaction, Txid};
+use nostr::nips::nip13;
+use nostr::nips::nip26::{DelegationTag, EventProperties};
+use nostr::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::compression::Compression;
+use crate::nip44;
+
+/// Default event kind of bitcoin transaction events
+pub const BITCOIN_TX_KIND: u64 = 28333;
+
+/// Event kind of the replies telling the sender what happened to their transactions
+pub const BROADCAST_RESULT_KIND: u64 = 28334;
+
+/// Event kind of the notifications that a broadcast transaction confirmed
+pub const CONFIRMATION_KIND: u64 = 28335;
+
+/// Event kind of the notifications that a broadcast transaction left the mempool and was given up
+/// on
+pub const FAILURE_KIND: u64 = 28337;
+
+/// Event kind of the alerts that a received transaction double spends another one
+pub const CONFLICT_KIND: u64 = 28336;
+
+/// Event kind of the requests for the unconfirmed parents of a received transaction, which
+/// couldn't be broadcast without them
+pub const PARENT_REQUEST_KIND: u64 = 28338;
+
+/// Event kind of the announcements of broadcasters, replaceable so each broadcaster has one
+pub const ANNOUNCEMENT_KIND: u64 = 18333;
+
+/// Event kind of NIP-89 handler information, telling clients which kinds an application handles
+pub const HANDLER_KIND: u64 = 31990;
+
+/// `d` tag of the broadcaster's NIP-89 handler information, each broadcaster has one
+const HANDLER_IDENTIFIER: &str = "nostr-tx-broadcast";
+
+const MAGIC_TAG: &str = "magic";
+/// Tag of the result events with the kind of the transaction event they answer
+const KIND_TAG: &str = "k";
+const TRANSACTIONS_TAG: &str = "transactions";
+const COMPRESSION_TAG: &str = "compression";
+const CHUNK_TAG: &str = "chunk";
+
+/// Most chunks a package can be split into
+pub const MAX_CHUNKS: usize = 256;
+
+/// How the transactions of a transaction event are encoded, the listener takes both
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TxEncoding {
+    /// Base64, a third shorter than hex
+    #[default]
+    Base64,
+    /// Hex, as `sendrawtransaction` takes it
+    Hex,
+}
+
+impl FromStr for TxEncoding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "base64" => Ok(Self::Base64),
+            "hex" => Ok(Self::Hex),
+            _ => bail!("unknown transaction encoding {s}, expected base64 or hex"),
+        }
+    }
+}
+
+/// How a transaction event is built
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TxEventOptions {
+    /// Base64 or hex
+    pub encoding: TxEncoding,
+    /// Compress the transactions together into a single value, named in the `compression` tag
+    pub compression: Option<Compression>,
+    /// NIP-40 expiration, after which relays may drop the event and listeners ignore it
+    pub expiration: Option<Timestamp>,
+}
+
+/// A part of a package too large for a single event, from its `chunk` tag
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    /// Shared by the chunks of a package, the hex SHA256 of its whole payload
+    pub package_id: String,
+    /// Position of the chunk, from 0
+    pub index: usize,
+    /// Number of chunks of the package
+    pub total: usize,
+}
+
+/// The magic of mutinynet, a custom signet
+pub fn mutinynet_magic() -> Magic {
+    Magic::from_bytes([0xA5, 0xDF, 0x2D, 0xCB])
+}
+
+/// The magic of the custom signet with the block `challenge` script (hex), the first four bytes
+/// of the double SHA256 of the serialized script
+pub fn signet_magic(challenge: &str) -> anyhow::Result<Magic> {
+    let challenge = ScriptBuf::from_hex(challenge.trim())
+        .map_err(|e| anyhow!("invalid signet challenge: {e}"))?;
+    let hash = sha256d::Hash::hash(&serialize(&challenge));
+    Ok(Magic::from_bytes(hash[..4].try_into().unwrap()))
+}
+
+/// Parse a network name (bitcoin, testnet, signet, regtest or mutinynet) or a hex magic
+pub fn parse_magic(s: &str) -> anyhow::Result<Magic> {
+    if s == "mutinynet" {
+        return Ok(mutinynet_magic());
+    }
+    match Network::from_str(s) {
+        Ok(network) => Ok(network.magic()),
+        Err(_) => Magic::from_str(s).map_err(|_| anyhow!("unknown network or magic: {s}")),
+    }
+}
+
+/// The network magic of an event, if present and valid
+pub fn magic(event: &Event) -> Option<Magic> {
+    generic_tag(event, MAGIC_TAG)
+        .and_then(|values| values.first().and_then(|m| Magic::from_str(m).ok()))
+}
+
+/// Whether an event has the `transactions` tag
+pub fn has_transactions(event: &Event) -> bool {
+    generic_tag(event, TRANSACTIONS_TAG).is_some()
+}
+
+/// The transactions of an event, skipping the ones that fail to decode
+pub fn transactions(event: &Event) -> Vec<Transaction> {
+    let compression = compression(event).ok().flatten();
+    generic_tag(event, TRANSACTIONS_TAG)
+        .map(|txs| decode_txs(txs, compression))
+        .unwrap_or_default()
+}
+
+/// The NIP-40 expiration of an event, if it has one
+pub fn expiration(event: &Event) -> Option<Timestamp> {
+    event.tags.iter().find_map(|t| match t {
+        Tag::Expiration(timestamp) => Some(*timestamp),
+        _ => None,
+    })
+}
+
+/// Check that the id of an event is the hash of its contents and that its author signed it,
+/// rather than trusting the relay delivering it
+pub fn verify(event: &Event) -> anyhow::Result<()> {
+    let id = EventId::new(
+        &event.pubkey,
+        event.created_at,
+        &event.kind,
+        &event.tags,
+        &event.content,
+    );
+    if id != event.id {
+        bail!(
+            "id {} doesn't match its contents, which hash to {id}",
+            event.id
+        );
+    }
+    event
+        .verify()
+        .map_err(|e| anyhow!("invalid signature: {e}"))
+}
+
+/// Who an event speaks for: the delegator of its NIP-26 `delegation` tag, when the delegation is
+/// signed by the delegator and its conditions allow the event's kind and creation time, or else
+/// its author. Fails for an invalid delegation.
+pub fn author(event: &Event) -> anyhow::Result<XOnlyPublicKey> {
+    let Some(tag) = event
+        .tags
+        .iter()
+        .map(Tag::as_vec)
+        .find(|t| t.first().map(String::as_str) == Some("delegation"))
+    else {
+        return Ok(event.pubkey);
+    };
+    let delegation = DelegationTag::try_from(tag)?;
+    delegation
+        .validate(event.pubkey, &EventProperties::from_event(event))
+        .map_err(|e| anyhow!("invalid delegation: {e}"))?;
+    Ok(delegation.delegator_pubkey())
+}
+
+/// The chunk an event carries, `None` for events with whole packages or an invalid `chunk` tag
+pub fn chunk(event: &Event) -> Option<Chunk> {
+    let [package_id, index, total] = generic_tag(event, CHUNK_TAG)?.as_slice() else {
+        return None;
+    };
+    let (index, total) = (index.parse().ok()?, total.parse().ok()?);
+    (index < total && total <= MAX_CHUNKS).then(|| Chunk {
+        package_id: package_id.clone(),
+        index,
+        total,
+    })
+}
+
+/// The part of the package payload carried by a chunk event
+pub fn chunk_data(event: &Event) -> Option<Vec<u8>> {
+    generic_tag(event, TRANSACTIONS_TAG)?
+        .first()
+        .and_then(|value| decode_bytes(value))
+}
+
+/// Decode the transactions of the reassembled payload of a chunked package
+pub fn decode_package(
+    payload: &[u8],
+    compression: Option<Compression>,
+) -> anyhow::Result<Vec<Transaction>> {
+    Ok(match compression {
+        Some(compression) => decode_concatenated(&compression.decompress(payload)?),
+        None => decode_concatenated(payload),
+    })
+}
+
+/// The compression of the transactions of an event, named in its `compression` tag, failing
+/// for the ones not supported
+pub fn compression(event: &Event) -> anyhow::Result<Option<Compression>> {
+    generic_tag(event, COMPRESSION_TAG)
+        .and_then(|values| values.first())
+        .map(|name| name.parse())
+        .transpose()
+}
+
+/// The NIP-13 proof of work of an event: the leading zero bits of its id, 0 when the id doesn't
+/// match the event
+pub fn difficulty(event: &Event) -> u8 {
+    let id = EventId::new(
+        &event.pubkey,
+        event.created_at,
+        &event.kind,
+        &event.tags,
+        &event.content,
+    );
+    if id != event.id {
+        return 0;
+    }
+    nip13::get_leading_zero_bits(id.inner())
+}
+
+/// Sign the event of `builder` with a NIP-13 proof of work of at least `difficulty` leading zero
+/// bits, mined on every core
+pub fn mine(builder: EventBuilder, keys: &Keys, difficulty: u8) -> anyhow::Result<Event> {
+    let unsigned = builder.to_unsigned_event(keys.public_key());
+    if difficulty == 0 {
+        return Ok(unsigned.sign(keys)?);
+    }
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get()) as u128;
+    let found = AtomicBool::new(false);
+    let mined = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|first| {
+                let (unsigned, found) = (&unsigned, &found);
+                scope.spawn(move || {
+                    let mut tags = unsigned.tags.clone();
+                    let mut nonce = first;
+                    while !found.load(Ordering::Relaxed) {
+                        tags.push(Tag::POW { nonce, difficulty });
+                        let id = EventId::new(
+                            &unsigned.pubkey,
+                            unsigned.created_at,
+                            &unsigned.kind,
+                            &tags,
+                            &unsigned.content,
+                        );
+                        if nip13::get_leading_zero_bits(id.inner()) >= difficulty {
+                            found.store(true, Ordering::Relaxed);
+                            return Some((id, tags));
+                        }
+                        tags.pop();
+                        nonce += threads;
+                    }
+                    None
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .filter_map(|worker| worker.join().ok().flatten())
+            .next()
+    });
+    let (id, tags) = mined.ok_or_else(|| anyhow!("Mining failed"))?;
+    let event = UnsignedEvent {
+        id,
+        tags,
+        ..unsigned
+    };
+    Ok(event.sign(keys)?)
+}
+
+/// The broadcaster an encrypted transaction event is meant for, `None` for cleartext events
+pub fn recipient(event: &Event) -> Option<XOnlyPublicKey> {
+    if event.content.is_empty() {
+        return None;
+    }
+    event.tags.iter().find_map(|t| match t {
+        Tag::PubKey(pubkey, _) => Some(*pubkey),
+        _ => None,
+    })
+}
+
+/// What an encrypted transaction event carries
+#[derive(Debug, Clone)]
+pub struct Decrypted {
+    /// Network of the transactions
+    pub magic: Magic,
+    /// The transactions that decode
+    pub txs: Vec<Transaction>,
+    /// Cashu token paying for the broadcast, only ever sent encrypted as anyone seeing it can
+    /// spend it
+    pub cashu: Option<String>,
+}
+
+/// Decrypt an encrypted transaction event with the broadcaster's `keys`
+pub fn decrypt(keys: &Keys, event: &Event) -> anyhow::Result<Decrypted> {
+    let plaintext = nip44::decrypt(&keys.secret_key()?, &event.pubkey, &event.content)?;
+    let payload: EncryptedPayload = serde_json::from_str(&plaintext)?;
+    Ok(Decrypted {
+        magic: parse_magic(&payload.magic)?,
+        txs: decode_txs(&payload.transactions, compression(event)?),
+        cashu: payload.cashu,
+    })
+}
+
+/// Decode a raw transaction in hex or base64, telling them apart by their characters
+pub fn decode_tx(s: &str) -> Option<Transaction> {
+    let s = s.trim();
+    if s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        if let Ok(tx) = parse_tx_hex(s) {
+            return Some(tx);
+        }
+    }
+    let decoded = general_purpose::STANDARD.decode(s).ok()?;
+    Transaction::consensus_decode(&mut decoded.as_slice()).ok()
+}
+
+/// Decode the transactions of an event, each in hex or base64, or with `compression` all of them
+/// compressed together, skipping the ones that fail to decode
+pub fn decode_txs(txs: &[String], compression: Option<Compression>) -> Vec<Transaction> {
+    let Some(compression) = compression else {
+        return txs.iter().filter_map(|tx| decode_tx(tx)).collect();
+    };
+    let mut decoded = vec![];
+    for value in txs {
+        let Some(bytes) = decode_bytes(value) else {
+            continue;
+        };
+        let Ok(bytes) = compression.decompress(&bytes) else {
+            continue;
+        };
+        decoded.extend(decode_concatenated(&bytes));
+    }
+    decoded
+}
+
+/// Decode serialized transactions following each other, up to the first that fails to decode
+fn decode_concatenated(bytes: &[u8]) -> Vec<Transaction> {
+    let mut reader = bytes;
+    let mut decoded = vec![];
+    while !reader.is_empty() {
+        match Transaction::consensus_decode(&mut reader) {
+            Ok(tx) => decoded.push(tx),
+            Err(_) => break,
+        }
+    }
+    decoded
+}
+
+/// Decode hex or base64
+fn decode_bytes(s: &str) -> Option<Vec<u8>> {
+    let s = s.trim();
+    if s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        if let Ok(bytes) = Vec::<u8>::from_hex(s) {
+            return Some(bytes);
+        }
+    }
+    general_purpose::STANDARD.decode(s).ok()
+}
+
+/// Decode a raw transaction in hex, as `sendrawtransaction` takes it
+pub fn parse_tx_hex(hex: &str) -> anyhow::Result<Transaction> {
+    let bytes = Vec::<u8>::from_hex(hex.trim())?;
+    Ok(deserialize(&bytes)?)
+}
+
+/// Decode raw transactions, in hex separated by whitespace or in binary one after the other
+pub fn parse_raw_txs(bytes: &[u8]) -> anyhow::Result<Vec<Transaction>> {
+    let is_hex = bytes
+        .iter()
+        .all(|byte| byte.is_ascii_hexdigit() || byte.is_ascii_whitespace());
+    if is_hex {
+        return std::str::from_utf8(bytes)?
+            .split_whitespace()
+            .map(parse_tx_hex)
+            .collect();
+    }
+    let mut txs = vec![];
+    let mut remaining = bytes;
+    while !remaining.is_empty() {
+        let (tx, size) = deserialize_partial::<Transaction>(remaining)?;
+        txs.push(tx);
+        remaining = &remaining[size..];
+    }
+    Ok(txs)
+}
+
+/// Build a transaction event of `kind` for `txs` on the network identified by `magic`
+pub fn tx_event(
+    kind: u64,
+    magic: Magic,
+    txs: &[Transaction],
+    options: TxEventOptions,
+) -> anyhow::Result<EventBuilder> {
+    let mut tags = vec![
+        Tag::Generic(
+            TagKind::Custom(MAGIC_TAG.to_string()),
+            vec![magic.to_string()],
+        ),
+        Tag::Generic(
+            TagKind::Custom(TRANSACTIONS_TAG.to_string()),
+            encode_txs(txs, options)?,
+        ),
+    ];
+    tags.extend(option_tags(options));
+
+    Ok(EventBuilder::new(Kind::from(kind), "", &tags))
+}
+
+/// Build a transaction event of `kind` for `txs` encrypted with `keys` to the broadcaster `to`,
+/// hiding the transactions and their network from the relays, with a `cashu` token paying for
+/// the broadcast
+pub fn encrypted_tx_event(
+    keys: &Keys,
+    to: XOnlyPublicKey,
+    kind: u64,
+    magic: Magic,
+    txs: &[Transaction],
+    options: TxEventOptions,
+    cashu: Option<&str>,
+) -> anyhow::Result<EventBuilder> {
+    let payload = EncryptedPayload {
+        magic: magic.to_string(),
+        transactions: encode_txs(txs, options)?,
+        cashu: cashu.map(String::from),
+    };
+    let content = nip44::encrypt(&keys.secret_key()?, &to, &serde_json::to_string(&payload)?)?;
+    let mut tags = vec![Tag::PubKey(to, None)];
+    tags.extend(option_tags(options));
+
+    Ok(EventBuilder::new(Kind::from(kind), content, &tags))
+}
+
+/// Build the transaction events of `kind` for `txs`: a single one when the payload fits in
+/// `chunk_size` bytes, otherwise chunks of the payload of all the transactions together, which
+/// listeners reassemble
+pub fn chunked_tx_events(
+    kind: u64,
+    magic: Magic,
+    txs: &[Transaction],
+    options: TxEventOptions,
+    chunk_size: usize,
+) -> anyhow::Result<Vec<EventBuilder>> {
+    let raw: Vec<u8> = txs.iter().flat_map(serialize).collect();
+    let payload = match options.compression {
+        Some(compression) => compression.compress(&raw)?,
+        None => raw,
+    };
+    if payload.len() <= chunk_size {
+        return Ok(vec![tx_event(kind, magic, txs, options)?]);
+    }
+    let total = payload.len().div_ceil(chunk_size);
+    if total > MAX_CHUNKS {
+        bail!("the package needs {total} chunks of {chunk_size} bytes, at most {MAX_CHUNKS} are taken");
+    }
+
+    let package_id = sha256::Hash::hash(&payload).to_string();
+    Ok(payload
+        .chunks(chunk_size)
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut tags = vec![
+                Tag::Generic(
+                    TagKind::Custom(MAGIC_TAG.to_string()),
+                    vec![magic.to_string()],
+                ),
+                Tag::Generic(
+                    TagKind::Custom(TRANSACTIONS_TAG.to_string()),
+                    vec![encode_bytes(chunk, options.encoding)],
+                ),
+                Tag::Generic(
+                    TagKind::Custom(CHUNK_TAG.to_string()),
+                    vec![package_id.clone(), index.to_string(), total.to_string()],
+                ),
+            ];
+            tags.extend(option_tags(options));
+            EventBuilder::new(Kind::from(kind), "", &tags)
+        })
+        .collect())
+}
+
+/// The tags of the compression and the expiration of `options`
+fn option_tags(options: TxEventOptions) -> Vec<Tag> {
+    let compression = options.compression.map(|compression| {
+        Tag::Generic(
+            TagKind::Custom(COMPRESSION_TAG.to_string()),
+            vec![compression.to_string()],
+        )
+    });
+    let expiration = options.expiration.map(Tag::Expiration);
+    compression.into_iter().chain(expiration).collect()
+}
+
+/// The content of an encrypted transaction event, the same values as the tags of a cleartext one
+/// and maybe a Cashu token
+#[derive(Serialize, Deserialize)]
+struct EncryptedPayload {
+    magic: String,
+    transactions: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cashu: Option<String>,
+}
+
+/// What happened to one transaction of an event
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TxResult {
+    /// The transaction
+    pub txid: Txid,
+    /// Whether it was accepted into a mempool
+    pub accepted: bool,
+    /// What happened to it, in more detail than `accepted`
+    pub status: ResultStatus,
+    /// Why it wasn't accepted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Feerate in sat/vB it entered the mempool with, when the backend told
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub feerate: Option<f64>,
+    /// Name of the backend it was submitted to, `None` when it wasn't or it's not known which
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend: Option<String>,
+    /// When the outcome was known, in seconds since the epoch
+    pub timestamp: u64,
+}
+
+impl TxResult {
+    /// `txid` was accepted by `backend`, `None` when it's not known which
+    pub fn accepted(txid: Txid, backend: Option<&str>, feerate: Option<f64>) -> Self {
+        Self {
+            txid,
+            accepted: true,
+            status: ResultStatus::Accepted,
+            error: None,
+            feerate,
+            backend: backend.map(String::from),
+            timestamp: Timestamp::now().as_u64(),
+        }
+    }
+
+    /// `txid` is in the mempool already, broadcast by someone else, so it wasn't submitted
+    pub fn propagating(txid: Txid) -> Self {
+        Self {
+            status: ResultStatus::Propagating,
+            ..Self::accepted(txid, None, None)
+        }
+    }
+
+    /// `txid` wasn't accepted because of `error`, by `backend` if it got that far
+    pub fn failed(
+        txid: Txid,
+        status: ResultStatus,
+        error: impl Into<String>,
+        backend: Option<&str>,
+    ) -> Self {
+        Self {
+            txid,
+            accepted: false,
+            status,
+            error: Some(error.into()),
+            feerate: None,
+            backend: backend.map(String::from),
+            timestamp: Timestamp::now().as_u64(),
+        }
+    }
+}
+
+/// Why a transaction was or wasn't accepted, for clients to act on without parsing the error
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResultStatus {
+    /// In the mempool of the backend
+    Accepted,
+    /// In the mempool or mined already, broadcast by someone else, so not submitted
+    Propagating,
+    /// The backend, or its dry run, rejected it: invalid, too cheap, conflicting, ...
+    Rejected,
+    /// Not submitted because of the broadcaster's policy: filters, limits, feerate, ...
+    Filtered,
+    /// The backend couldn't be reached or failed otherwise, submitting it again may work
+    Error,
+    /// Not submitted until it's paid for, the result event carries the invoice
+    PaymentRequired,
+}
+
+impl ResultStatus {
+    /// Name of the status, as in the JSON results
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ResultStatus::Accepted => "accepted",
+            ResultStatus::Propagating => "propagating",
+            ResultStatus::Rejected => "rejected",
+            ResultStatus::Filtered => "filtered",
+            ResultStatus::Error => "error",
+            ResultStatus::PaymentRequired => "payment_required",
+        }
+    }
+}
+
+/// Build the reply to a transaction event, tagging the event, its author and its kind, with the
+/// [`TxResult`]s of its transactions as a JSON array in the content
+pub fn result_event(event: &Event, results: &[TxResult]) -> anyhow::Result<EventBuilder> {
+    let tags = vec![
+        Tag::Event(event.id, None, None),
+        Tag::PubKey(event.pubkey, None),
+        Tag::Generic(
+            TagKind::Custom(KIND_TAG.to_string()),
+            vec![event.kind.as_u64().to_string()],
+        ),
+    ];
+
+    Ok(EventBuilder::new(
+        Kind::Custom(BROADCAST_RESULT_KIND),
+        serde_json::to_string(results)?,
+        &tags,
+    ))
+}
+
+/// Build the reply asking to pay `msat` with the `bolt11` invoice before the transactions of
+/// `event` are broadcast, a result event with the [`TxResult`]s of its transactions tagging the
+/// `amount` and the invoice (`bolt11`) too
+pub fn invoice_event(
+    event: &Event,
+    results: &[TxResult],
+    msat: u64,
+    bolt11: &str,
+) -> anyhow::Result<EventBuilder> {
+    let tags = vec![
+        Tag::Event(event.id, None, None),
+        Tag::PubKey(event.pubkey, None),
+        Tag::Generic(
+            TagKind::Custom(KIND_TAG.to_string()),
+            vec![event.kind.as_u64().to_string()],
+        ),
+        Tag::Amount(msat),
+        Tag::Bolt11(bolt11.to_string()),
+    ];
+
+    Ok(EventBuilder::new(
+        Kind::Custom(BROADCAST_RESULT_KIND),
+        serde_json::to_string(results)?,
+        &tags,
+    ))
+}
+
+/// The invoice of a result event asking for payment
+pub fn invoice(event: &Event) -> Option<&str> {
+    event.tags.iter().find_map(|tag| match tag {
+        Tag::Bolt11(bolt11) => Some(bolt11.as_str()),
+        _ => None,
+    })
+}
+
+/// A transaction of an event made it into a block
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxConfirmation {
+    /// The transaction
+    pub txid: Txid,
+    /// Block the transaction is in
+    pub block_hash: BlockHash,
+    /// Height of that block
+    pub height: u32,
+}
+
+/// Build the notification that a transaction of `event_id` by `author` confirmed, with the
+/// [`TxConfirmation`] as JSON content
+pub fn confirmation_event(
+    event_id: EventId,
+    author: XOnlyPublicKey,
+    confirmation: &TxConfirmation,
+) -> anyhow::Result<EventBuilder> {
+    let tags = vec![Tag::Event(event_id, None, None), Tag::PubKey(author, None)];
+
+    Ok(EventBuilder::new(
+        Kind::Custom(CONFIRMATION_KIND),
+        serde_json::to_string(confirmation)?,
+        &tags,
+    ))
+}
+
+/// A transaction of an event left the mempool and couldn't be rebroadcast
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxFailure {
+    /// The transaction
+    pub txid: Txid,
+    /// Why it couldn't be rebroadcast
+    pub error: String,
+}
+
+/// Build the notification that a transaction of `event_id` by `author` was given up on, with the
+/// [`TxFailure`] as JSON content
+pub fn failure_event(
+    event_id: EventId,
+    author: XOnlyPublicKey,
+    failure: &TxFailure,
+) -> anyhow::Result<EventBuilder> {
+    let tags = vec![Tag::Event(event_id, None, None), Tag::PubKey(author, None)];
+
+    Ok(EventBuilder::new(
+        Kind::Custom(FAILURE_KIND),
+        serde_json::to_string(failure)?,
+        &tags,
+    ))
+}
+
+/// A received transaction spends an output already spent by another one
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxConflict {
+    /// The received transaction
+    pub txid: Txid,
+    /// The output both spend
+    pub outpoint: OutPoint,
+    /// The transaction that spent it first
+    pub conflicting_txid: Txid,
+}
+
+/// Build the alert that a transaction of `event_id` double spends a transaction, with the
+/// [`TxConflict`] as JSON content, tagging the event and author of the transaction it conflicts
+/// with when known
+pub fn conflict_event(
+    event_id: EventId,
+    conflict: &TxConflict,
+    original: Option<(EventId, XOnlyPublicKey)>,
+) -> anyhow::Result<EventBuilder> {
+    let mut tags = vec![Tag::Event(event_id, None, None)];
+    if let Some((original_event_id, original_author)) = original {
+        tags.push(Tag::Event(original_event_id, None, None));
+        tags.push(Tag::PubKey(original_author, None));
+    }
+
+    Ok(EventBuilder::new(
+        Kind::Custom(CONFLICT_KIND),
+        serde_json::to_string(conflict)?,
+        &tags,
+    ))
+}
+
+/// A transaction of an event spends outputs of transactions the broadcaster can't find
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ParentRequest {
+    /// The transaction missing inputs
+    pub txid: Txid,
+    /// Its unconfirmed ancestors the broadcaster needs
+    pub parents: Vec<Txid>,
+}
+
+/// Build the request to `author` for the parents of a transaction of `event_id`, with the
+/// [`ParentRequest`] as JSON content
+pub fn parent_request_event(
+    event_id: EventId,
+    author: XOnlyPublicKey,
+    request: &ParentRequest,
+) -> anyhow::Result<EventBuilder> {
+    let tags = vec![Tag::Event(event_id, None, None), Tag::PubKey(author, None)];
+
+    Ok(EventBuilder::new(
+        Kind::Custom(PARENT_REQUEST_KIND),
+        serde_json::to_string(request)?,
+        &tags,
+    ))
+}
+
+/// The request of a parent request event
+pub fn parent_request(event: &Event) -> anyhow::Result<ParentRequest> {
+    Ok(serde_json::from_str(&event.content)?)
+}
+
+/// What a broadcaster announces about itself
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Announcement {
+    /// Magic of each network it broadcasts to, in hex
+    pub networks: Vec<String>,
+    /// Event kinds of the transaction events it handles
+    pub kinds: Vec<u64>,
+    /// Whether it takes transaction events encrypted to it
+    pub encrypted: bool,
+    /// Whether it replies with a result event
+    pub reply: bool,
+    /// Minimum feerate in sat/vB of the transactions of an event
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_feerate: Option<f64>,
+    /// Maximum virtual size of a single transaction
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tx_vsize: Option<u64>,
+    /// Maximum total weight of the transactions of an event
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_package_weight: Option<u64>,
+    /// Minimum NIP-13 proof of work of the transaction events
+    #[serde(default)]
+    pub min_pow: u8,
+}
+
+impl Announcement {
+    /// Whether the broadcaster broadcasts to the network of `magic`
+    pub fn supports(&self, magic: Magic) -> bool {
+        self.networks.contains(&magic.to_string())
+    }
+}
+
+/// Build the announcement of a broadcaster, tagging each network (`magic`) and kind (`k`), with the
+/// [`Announcement`] as JSON content. It expires at `expiration`, so broadcasters that stop
+/// announcing themselves are forgotten
+pub fn announcement_event(
+    announcement: &Announcement,
+    expiration: Timestamp,
+) -> anyhow::Result<EventBuilder> {
+    let mut tags: Vec<Tag> = announcement
+        .networks
+        .iter()
+        .map(|magic| Tag::Generic(TagKind::Custom(MAGIC_TAG.to_string()), vec![magic.clone()]))
+        .collect();
+    tags.extend(announcement.kinds.iter().map(|kind| {
+        Tag::Generic(
+            TagKind::Custom(KIND_TAG.to_string()),
+            vec![kind.to_string()],
+        )
+    }));
+    tags.push(Tag::Expiration(expiration));
+
+    Ok(EventBuilder::new(
+        Kind::Custom(ANNOUNCEMENT_KIND),
+        serde_json::to_string(announcement)?,
+        &tags,
+    ))
+}
+
+/// The announcement of an announcement event
+pub fn announcement(event: &Event) -> anyhow::Result<Announcement> {
+    Ok(serde_json::from_str(&event.content)?)
+}
+
+/// Build the NIP-89 handler information of a broadcaster handling transaction events of
+/// `kinds`, tagging each kind (`k`), with its `metadata` as content
+pub fn handler_event(kinds: &[u64], metadata: &Metadata) -> EventBuilder {
+    let mut tags = vec![Tag::Identifier(HANDLER_IDENTIFIER.to_string())];
+    tags.extend(kinds.iter().map(|kind| {
+        Tag::Generic(
+            TagKind::Custom(KIND_TAG.to_string()),
+            vec![kind.to_string()],
+        )
+    }));
+
+    EventBuilder::new(Kind::Custom(HANDLER_KIND), metadata.as_json(), &tags)
+}
+
+/// The transaction results of a result event
+pub fn results(event: &Event) -> anyhow::Result<Vec<TxResult>> {
+    Ok(serde_json::from_str(&event.content)?)
+}
+
+fn encode_bytes(bytes: &[u8], encoding: TxEncoding) -> String {
+    match encoding {
+        TxEncoding::Base64 => general_purpose::STANDARD.encode(bytes),
+        TxEncoding::Hex => bytes.iter().map(|b| format!("{b:02x}")).collect(),
+    }
+}
+
+fn encode_txs(txs: &[Transaction], options: TxEventOptions) -> anyhow::Result<Vec<String>> {
+    let encode = |bytes: &[u8]| encode_bytes(bytes, options.encoding);
+    Ok(match options.compression {
+        Some(compression) => {
+            let raw: Vec<u8> = txs.iter().flat_map(serialize).collect();
+            vec![encode(&compression.compress(&raw)?)]
+        }
+        None => txs.iter().map(|tx| encode(&serialize(tx))).collect(),
+    })
+}
+
+fn generic_tag<'a>(event: &'a Event, name: &str) -> Option<&'a Vec<String>> {
+    event.tags.iter().find_map(|t| match t {
+        Tag::Generic(TagKind::Custom(kind), values) if kind == name => Some(values),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reassembly::{Progress, Reassembly};
+    use bitcoin::absolute::LockTime;
+    use bitcoin::{TxIn, TxOut};
+    use std::time::Duration;
+
+    /// A transaction spending a made up output, told apart from others by `id`
+    fn tx(id: u32) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: LockTime::from_consensus(id),
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::all_zeros(), id),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: 10_000,
+                script_pubkey: ScriptBuf::new(),
+            }],
+        }
+    }
+
+    fn event(tags: Vec<Tag>) -> Event {
+        EventBuilder::new(Kind::from(BITCOIN_TX_KIND), "", &tags)
+            .to_event(&Keys::generate())
+            .unwrap()
+    }
+
+    fn chunk_tag(values: &[&str]) -> Tag {
+        Tag::Generic(
+            TagKind::Custom(CHUNK_TAG.to_string()),
+            values.iter().map(|value| value.to_string()).collect(),
+        )
+    }
+
+    #[test]
+    fn decode_hex_and_base64() {
+        let txs = [tx(1), tx(2)];
+        let values = vec![
+            encode_bytes(&serialize(&txs[0]), TxEncoding::Hex),
+            encode_bytes(&serialize(&txs[1]), TxEncoding::Base64),
+            "not a transaction".to_string(),
+            "00".to_string(),
+        ];
+        assert_eq!(decode_txs(&values, None), txs);
+    }
+
+    #[test]
+    fn decode_compressed() {
+        let txs = [tx(1), tx(2), tx(3)];
+        let raw: Vec<u8> = txs.iter().flat_map(serialize).collect();
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            for encoding in [TxEncoding::Hex, TxEncoding::Base64] {
+                let compressed = compression.compress(&raw).unwrap();
+                let values = vec![
+                    encode_bytes(&compressed, encoding),
+                    // not compressed, skipped
+                    encode_bytes(&serialize(&tx(4)), encoding),
+                ];
+                assert_eq!(decode_txs(&values, Some(compression)), txs);
+            }
+        }
+    }
+
+    #[test]
+    fn concatenated() {
+        let txs = [tx(1), tx(2)];
+        let mut bytes: Vec<u8> = txs.iter().flat_map(serialize).collect();
+        assert_eq!(decode_concatenated(&bytes), txs);
+        bytes.extend_from_slice(&[2, 0, 0]);
+        assert_eq!(decode_concatenated(&bytes), txs);
+        assert!(decode_concatenated(&[]).is_empty());
+        assert!(decode_concatenated(&[0xff; 10]).is_empty());
+    }
+
+    #[test]
+    fn chunk_tags() {
+        let parsed = chunk(&event(vec![chunk_tag(&["id", "1", "3"])]));
+        assert_eq!(
+            parsed,
+            Some(Chunk {
+                package_id: "id".to_string(),
+                index: 1,
+                total: 3,
+            })
+        );
+
+        let max = MAX_CHUNKS.to_string();
+        let past_max = (MAX_CHUNKS + 1).to_string();
+        let invalid: [&[&str]; 6] = [
+            &["id", "3", "3"],
+            &["id", "0", &past_max],
+            &["id", "-1", "3"],
+            &["id", "one", "3"],
+            &["id", "1"],
+            &["id", "1", "3", "extra"],
+        ];
+        for values in invalid {
+            assert_eq!(chunk(&event(vec![chunk_tag(values)])), None, "{values:?}");
+        }
+        assert!(chunk(&event(vec![chunk_tag(&["id", "0", &max])])).is_some());
+        assert_eq!(chunk(&event(vec![])), None);
+    }
+
+    #[test]
+    fn chunked_round_trip() {
+        let txs: Vec<Transaction> = (1..=10).map(tx).collect();
+        for compression in [None, Some(Compression::Zstd)] {
+            let options = TxEventOptions {
+                compression,
+                ..Default::default()
+            };
+            let events: Vec<Event> =
+                chunked_tx_events(BITCOIN_TX_KIND, Magic::BITCOIN, &txs, options, 20)
+                    .unwrap()
+                    .into_iter()
+                    .map(|builder| builder.to_event(&Keys::generate()).unwrap())
+                    .collect();
+            assert!(events.len() > 1);
+
+            // the chunks of a package come from one author, here from the first chunk's
+            let author = events[0].pubkey;
+            let mut reassembly = Reassembly::new(Duration::from_secs(60));
+            let mut payload = None;
+            for event in events.iter().rev() {
+                let chunk = chunk(event).unwrap();
+                let data = chunk_data(event).unwrap();
+                let magic = Some(Magic::BITCOIN);
+                match reassembly
+                    .add(author, &chunk, magic, compression, data)
+                    .unwrap()
+                {
+                    Progress::Complete(complete) => payload = Some(complete),
+                    Progress::Pending { .. } => {}
+                }
+            }
+            let payload = payload.unwrap();
+            assert_eq!(decode_package(&payload, compression).unwrap(), txs);
+        }
+    }
+
+    #[test]
+    fn propagating_result() {
+        let result = TxResult::propagating(Txid::all_zeros());
+        assert!(result.accepted);
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["status"], "propagating");
+        assert!(json.get("backend").is_none());
+    }
+}
@@ -0,0 +1,18 @@
+//! The SOCKS5 proxy the relay connections can go through, such as Tor.
+
+use anyhow::{anyhow, bail};
+use std::net::{SocketAddr, ToSocketAddrs};
+
+/// Parse a `socks5://host:port` proxy url, or a bare `host:port`. Relay hostnames are resolved by
+/// the proxy, so `.onion` relays work through Tor
+pub fn parse_proxy(s: &str) -> anyhow::Result<SocketAddr> {
+    let addr = match s.split_once("://") {
+        Some(("socks5" | "socks5h", addr)) => addr,
+        Some((scheme, _)) => bail!("unsupported proxy scheme {scheme}, only socks5 is"),
+        None => s,
+    };
+    let addr = addr.trim_end_matches('/');
+    addr.to_socket_addrs()?
+        .next()
+        .ok_or_else(|| anyhow!("proxy {addr} doesn't resolve"))
+}
@@ -0,0 +1,126 @@
+//! Lists of nostr public keys, to decide whose events are handled.
+
+use anyhow::Context;
+use nostr::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Parse a public key given as hex or npub
+pub fn parse_pubkey(s: &str) -> anyhow::Result<XOnlyPublicKey> {
+    Ok(Keys::from_pk_str(s.trim())?.public_key())
+}
+
+/// Read a file with a public key per line, skipping empty lines and `#` comments
+pub fn read_pubkeys(path: impl AsRef<Path>) -> anyhow::Result<Vec<XOnlyPublicKey>> {
+    let path = path.as_ref();
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Reading pubkey file {}", path.display()))?;
+    contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            parse_pubkey(line)
+                .with_context(|| format!("Invalid pubkey {line} in {}", path.display()))
+        })
+        .collect()
+}
+
+/// Authors whose events are ignored, optionally persisted in a file and grown at runtime by
+/// banning authors after repeated invalid submissions
+#[derive(Debug, Clone)]
+pub struct Denylist {
+    path: Option<PathBuf>,
+    ban_after: Option<NonZeroU32>,
+    state: Arc<Mutex<DenyState>>,
+}
+
+#[derive(Debug, Default)]
+struct DenyState {
+    banned: HashSet<XOnlyPublicKey>,
+    /// Invalid submissions per author not banned yet
+    strikes: HashMap<XOnlyPublicKey, u32>,
+}
+
+impl Denylist {
+    /// A denylist starting with the pubkeys in the file at `path`, if it exists, and saving
+    /// bans to it. Authors are banned after `ban_after` invalid submissions, or never.
+    pub fn open(path: Option<PathBuf>, ban_after: Option<NonZeroU32>) -> anyhow::Result<Self> {
+        let banned = match &path {
+            Some(path) if path.exists() => read_pubkeys(path)?.into_iter().collect(),
+            _ => HashSet::new(),
+        };
+        Ok(Self {
+            path,
+            ban_after,
+            state: Arc::new(Mutex::new(DenyState {
+                banned,
+                strikes: HashMap::new(),
+            })),
+        })
+    }
+
+    /// Read the file again, for bans added to it by hand. Strikes are kept. Returns the number
+    /// of banned pubkeys.
+    pub fn reload(&self) -> anyhow::Result<usize> {
+        let banned: HashSet<_> = match &self.path {
+            Some(path) if path.exists() => read_pubkeys(path)?.into_iter().collect(),
+            Some(_) => HashSet::new(),
+            None => return Ok(self.state.lock().unwrap().banned.len()),
+        };
+        let mut state = self.state.lock().unwrap();
+        state.strikes.retain(|pubkey, _| !banned.contains(pubkey));
+        state.banned = banned;
+        Ok(state.banned.len())
+    }
+
+    /// Whether `pubkey` is banned
+    pub fn contains(&self, pubkey: &XOnlyPublicKey) -> bool {
+        self.state.lock().unwrap().banned.contains(pubkey)
+    }
+
+    /// Ban `pubkey`, appending it to the file. Returns whether it wasn't banned yet.
+    pub fn ban(&self, pubkey: XOnlyPublicKey, reason: &str) -> anyhow::Result<bool> {
+        let mut state = self.state.lock().unwrap();
+        if !state.banned.insert(pubkey) {
+            return Ok(false);
+        }
+        state.strikes.remove(&pubkey);
+
+        if let Some(path) = &self.path {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("Opening pubkey file {}", path.display()))?;
+            writeln!(file, "{pubkey} # {reason}")
+                .with_context(|| format!("Writing pubkey file {}", path.display()))?;
+        }
+        Ok(true)
+    }
+
+    /// Count an invalid submission by `pubkey`, banning it when that's one too many. Returns
+    /// whether it got banned.
+    pub fn strike(&self, pubkey: XOnlyPublicKey) -> anyhow::Result<bool> {
+        let Some(ban_after) = self.ban_after else {
+            return Ok(false);
+        };
+        let strikes = {
+            let mut state = self.state.lock().unwrap();
+            let strikes = state.strikes.entry(pubkey).or_default();
+            *strikes += 1;
+            *strikes
+        };
+        if strikes < ban_after.get() {
+            return Ok(false);
+        }
+        self.ban(
+            pubkey,
+            &format!("banned after {strikes} invalid submissions"),
+        )
+    }
+}
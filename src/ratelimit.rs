@@ -0,0 +1,294 @@
+//! Limiting how many transactions each author can submit, with a token bucket per author, and
+//! with a quota per day.
+
+use lru::LruCache;
+use nostr::prelude::XOnlyPublicKey;
+use nostr::Timestamp;
+use std::collections::HashSet;
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::time::{Duration, Instant};
+
+use crate::store::Store;
+
+/// How many authors' buckets are remembered, the least recently active are forgotten
+const MAX_AUTHORS: usize = 10_000;
+
+/// Transactions an author may submit, bursts up to the limit are allowed
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RateLimit {
+    /// Transactions per minute
+    pub per_minute: Option<NonZeroU32>,
+    /// Transactions per hour
+    pub per_hour: Option<NonZeroU32>,
+}
+
+/// Token buckets of the authors
+#[derive(Debug)]
+pub struct RateLimiter {
+    limit: RateLimit,
+    authors: LruCache<XOnlyPublicKey, Vec<Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    capacity: f64,
+    tokens: f64,
+    /// Tokens added per second
+    rate: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(limit: NonZeroU32, period: Duration, now: Instant) -> Self {
+        let capacity = limit.get() as f64;
+        Self {
+            capacity,
+            tokens: capacity,
+            rate: capacity / period.as_secs_f64(),
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.updated = now;
+    }
+
+    /// The tokens `txs` take. A package larger than the bucket takes all of it, so it passes
+    /// once the bucket is full
+    fn cost(&self, txs: usize) -> f64 {
+        (txs as f64).min(self.capacity)
+    }
+}
+
+impl RateLimiter {
+    /// Limit every author to `limit`
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            authors: LruCache::new(NonZeroUsize::new(MAX_AUTHORS).unwrap()),
+        }
+    }
+
+    /// Whether `author` is within its limits to submit `txs` transactions, without taking them
+    /// from its buckets
+    pub fn allows(&mut self, author: XOnlyPublicKey, txs: usize) -> bool {
+        self.allows_at(author, txs, Instant::now())
+    }
+
+    /// Take `txs` tokens from the buckets of `author`, once its transactions are submitted. The
+    /// buckets may go below empty, when other transactions of the author were let through since
+    /// [`Self::allows`]
+    pub fn take(&mut self, author: XOnlyPublicKey, txs: usize) {
+        self.take_at(author, txs, Instant::now())
+    }
+
+    fn allows_at(&mut self, author: XOnlyPublicKey, txs: usize, now: Instant) -> bool {
+        self.buckets(author, now)
+            .iter()
+            .all(|bucket| bucket.tokens >= bucket.cost(txs))
+    }
+
+    fn take_at(&mut self, author: XOnlyPublicKey, txs: usize, now: Instant) {
+        for bucket in self.buckets(author, now) {
+            bucket.tokens -= bucket.cost(txs);
+        }
+    }
+
+    /// The buckets of `author`, refilled up to `now`
+    fn buckets(&mut self, author: XOnlyPublicKey, now: Instant) -> &mut Vec<Bucket> {
+        let limit = self.limit;
+        let buckets = self.authors.get_or_insert_mut(author, || {
+            [
+                limit
+                    .per_minute
+                    .map(|l| Bucket::new(l, Duration::from_secs(60), now)),
+                limit
+                    .per_hour
+                    .map(|l| Bucket::new(l, Duration::from_secs(60 * 60), now)),
+            ]
+            .into_iter()
+            .flatten()
+            .collect()
+        });
+        for bucket in buckets.iter_mut() {
+            bucket.refill(now);
+        }
+        buckets
+    }
+}
+
+/// Transactions an author may submit per UTC day, counted in the store when there is one so the
+/// quota holds over restarts
+#[derive(Debug)]
+pub struct DailyQuota {
+    limit: NonZeroU32,
+    exempt: HashSet<XOnlyPublicKey>,
+    store: Option<Store>,
+    /// The day and the transactions submitted on it, by author
+    used: LruCache<XOnlyPublicKey, (u64, u32)>,
+}
+
+impl DailyQuota {
+    /// Limit every author but the `exempt` ones to `limit` transactions a day
+    pub fn new(limit: NonZeroU32, exempt: HashSet<XOnlyPublicKey>, store: Option<Store>) -> Self {
+        Self {
+            limit,
+            exempt,
+            store,
+            used: LruCache::new(NonZeroUsize::new(MAX_AUTHORS).unwrap()),
+        }
+    }
+
+    /// Count `txs` against the quota of `author`, returning whether it's within it. Nothing is
+    /// counted when it isn't.
+    pub fn check(&mut self, author: XOnlyPublicKey, txs: usize) -> anyhow::Result<bool> {
+        let day = Timestamp::now().as_u64() / (24 * 60 * 60);
+        self.check_on(author, txs, day)
+    }
+
+    fn check_on(&mut self, author: XOnlyPublicKey, txs: usize, day: u64) -> anyhow::Result<bool> {
+        if self.exempt.contains(&author) {
+            return Ok(true);
+        }
+        let used = match self.used.get(&author) {
+            Some(&(used_day, used)) if used_day == day => used,
+            _ => match &self.store {
+                Some(store) => store.quota_used(author, day)?,
+                None => 0,
+            },
+        };
+
+        let txs = u32::try_from(txs).unwrap_or(u32::MAX);
+        if used.saturating_add(txs) > self.limit.get() {
+            return Ok(false);
+        }
+        if let Some(store) = &self.store {
+            store.add_quota_used(author, day, txs)?;
+        }
+        self.used.put(author, (day, used + txs));
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr::Keys;
+
+    fn per_minute(limit: u32) -> RateLimiter {
+        RateLimiter::new(RateLimit {
+            per_minute: NonZeroU32::new(limit),
+            per_hour: None,
+        })
+    }
+
+    #[test]
+    fn daily_quota() {
+        let mut quota = DailyQuota::new(NonZeroU32::new(3).unwrap(), HashSet::new(), None);
+        let author = Keys::generate().public_key();
+        assert!(quota.check_on(author, 2, 100).unwrap());
+        assert!(!quota.check_on(author, 2, 100).unwrap());
+        // nothing counted for the refused ones
+        assert!(quota.check_on(author, 1, 100).unwrap());
+        assert!(!quota.check_on(author, 1, 100).unwrap());
+        // a new day
+        assert!(quota.check_on(author, 3, 101).unwrap());
+    }
+
+    #[test]
+    fn quota_exempt() {
+        let author = Keys::generate().public_key();
+        let exempt = HashSet::from([author]);
+        let mut quota = DailyQuota::new(NonZeroU32::new(1).unwrap(), exempt, None);
+        assert!(quota.check_on(author, 5, 100).unwrap());
+        assert!(!quota
+            .check_on(Keys::generate().public_key(), 5, 100)
+            .unwrap());
+    }
+
+    #[test]
+    fn quota_over_restarts() {
+        let path = std::env::temp_dir().join(format!("quota-{}.db", std::process::id()));
+        let author = Keys::generate().public_key();
+        let limit = NonZeroU32::new(3).unwrap();
+        let mut quota = DailyQuota::new(limit, HashSet::new(), Some(Store::open(&path).unwrap()));
+        assert!(quota.check_on(author, 2, 100).unwrap());
+
+        let mut quota = DailyQuota::new(limit, HashSet::new(), Some(Store::open(&path).unwrap()));
+        assert!(!quota.check_on(author, 2, 100).unwrap());
+        assert!(quota.check_on(author, 1, 100).unwrap());
+        assert!(quota.check_on(author, 3, 101).unwrap());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn burst() {
+        let mut limiter = per_minute(3);
+        let author = Keys::generate().public_key();
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert!(limiter.allows_at(author, 1, now));
+            limiter.take_at(author, 1, now);
+        }
+        assert!(!limiter.allows_at(author, 1, now));
+        // others have their own buckets
+        assert!(limiter.allows_at(Keys::generate().public_key(), 3, now));
+    }
+
+    #[test]
+    fn refill() {
+        let mut limiter = per_minute(2);
+        let author = Keys::generate().public_key();
+        let now = Instant::now();
+        limiter.take_at(author, 2, now);
+        assert!(!limiter.allows_at(author, 1, now));
+        // a token every 30 seconds
+        assert!(limiter.allows_at(author, 1, now + Duration::from_secs(30)));
+        assert!(!limiter.allows_at(author, 2, now + Duration::from_secs(30)));
+        // never more than the capacity
+        assert!(limiter.allows_at(author, 2, now + Duration::from_secs(3600)));
+        assert_eq!(limiter.authors.get(&author).unwrap()[0].tokens, 2.0);
+    }
+
+    #[test]
+    fn checking_takes_nothing() {
+        let mut limiter = per_minute(1);
+        let author = Keys::generate().public_key();
+        let now = Instant::now();
+        assert!(limiter.allows_at(author, 1, now));
+        assert!(limiter.allows_at(author, 1, now));
+        limiter.take_at(author, 1, now);
+        assert!(!limiter.allows_at(author, 1, now));
+    }
+
+    #[test]
+    fn package_larger_than_the_bucket() {
+        let mut limiter = per_minute(2);
+        let author = Keys::generate().public_key();
+        let now = Instant::now();
+        assert!(limiter.allows_at(author, 5, now));
+        limiter.take_at(author, 5, now);
+        assert!(!limiter.allows_at(author, 1, now));
+        assert!(limiter.allows_at(author, 5, now + Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn forgets_the_least_recently_active() {
+        let mut limiter = per_minute(1);
+        limiter.authors = LruCache::new(NonZeroUsize::new(2).unwrap());
+        let [first, second, third] = [(); 3].map(|()| Keys::generate().public_key());
+        let now = Instant::now();
+        limiter.take_at(first, 1, now);
+        limiter.take_at(second, 1, now);
+        // the first is the most recently active now
+        assert!(!limiter.allows_at(first, 1, now));
+        limiter.take_at(third, 1, now);
+        assert_eq!(limiter.authors.len(), 2);
+        assert!(!limiter.authors.contains(&second));
+        // forgotten, so with a full bucket again
+        assert!(limiter.allows_at(second, 1, now));
+        assert!(!limiter.authors.contains(&first));
+    }
+}
@@ -0,0 +1,253 @@
+//! Reassembling packages too large for a single event from their chunks, published as separate
+//! events that can arrive in any order.
+
+use anyhow::{bail, ensure};
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::network::Magic;
+use nostr::prelude::XOnlyPublicKey;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::compression::Compression;
+use crate::protocol::Chunk;
+
+/// Most packages waiting for chunks at once
+const MAX_PENDING: usize = 1000;
+
+/// What adding a chunk did
+#[derive(Debug)]
+pub enum Progress {
+    /// The package misses chunks still
+    Pending {
+        /// Chunks received
+        received: usize,
+        /// Chunks of the package
+        total: usize,
+    },
+    /// The last chunk arrived, this is the whole payload
+    Complete(Vec<u8>),
+}
+
+/// The chunks of the packages that aren't complete yet
+#[derive(Debug)]
+pub struct Reassembly {
+    timeout: Duration,
+    pending: HashMap<(XOnlyPublicKey, String), Pending>,
+}
+
+#[derive(Debug)]
+struct Pending {
+    magic: Option<Magic>,
+    compression: Option<Compression>,
+    chunks: Vec<Option<Vec<u8>>>,
+    since: Instant,
+}
+
+impl Reassembly {
+    /// Reassembly dropping the packages not complete `timeout` after their first chunk
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Add the `data` of `chunk` by `author`, in an event of network `magic` compressed with
+    /// `compression`, which all chunks of a package must agree on
+    pub fn add(
+        &mut self,
+        author: XOnlyPublicKey,
+        chunk: &Chunk,
+        magic: Option<Magic>,
+        compression: Option<Compression>,
+        data: Vec<u8>,
+    ) -> anyhow::Result<Progress> {
+        ensure!(
+            chunk.index < chunk.total,
+            "chunk {} of package {} is past its {} chunks",
+            chunk.index,
+            chunk.package_id,
+            chunk.total
+        );
+        let key = (author, chunk.package_id.clone());
+        if !self.pending.contains_key(&key) && self.pending.len() >= MAX_PENDING {
+            bail!("too many incomplete packages");
+        }
+        let pending = self.pending.entry(key.clone()).or_insert_with(|| Pending {
+            magic,
+            compression,
+            chunks: vec![None; chunk.total],
+            since: Instant::now(),
+        });
+        ensure!(
+            pending.chunks.len() == chunk.total
+                && pending.magic == magic
+                && pending.compression == compression,
+            "chunk {} doesn't match the other chunks of package {}",
+            chunk.index,
+            chunk.package_id
+        );
+        pending.chunks[chunk.index].get_or_insert(data);
+
+        let received = pending.chunks.iter().flatten().count();
+        if received < chunk.total {
+            return Ok(Progress::Pending {
+                received,
+                total: chunk.total,
+            });
+        }
+        let pending = self.pending.remove(&key).unwrap();
+        let payload: Vec<u8> = pending.chunks.into_iter().flatten().flatten().collect();
+        ensure!(
+            sha256::Hash::hash(&payload).to_string() == chunk.package_id,
+            "the chunks of package {} don't hash to its id",
+            chunk.package_id
+        );
+        Ok(Progress::Complete(payload))
+    }
+
+    /// Drop the packages that didn't complete in time, returning how many
+    pub fn expire(&mut self) -> usize {
+        let before = self.pending.len();
+        let timeout = self.timeout;
+        self.pending
+            .retain(|_, pending| pending.since.elapsed() < timeout);
+        before - self.pending.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr::Keys;
+
+    const PAYLOAD: &[u8] = b"the serialized transactions of a package";
+
+    fn author() -> XOnlyPublicKey {
+        Keys::generate().public_key()
+    }
+
+    fn package_id(payload: &[u8]) -> String {
+        sha256::Hash::hash(payload).to_string()
+    }
+
+    fn chunk(index: usize, total: usize) -> Chunk {
+        Chunk {
+            package_id: package_id(PAYLOAD),
+            index,
+            total,
+        }
+    }
+
+    /// The `index`th of `total` parts of the payload
+    fn data(index: usize, total: usize) -> Vec<u8> {
+        let size = PAYLOAD.len().div_ceil(total);
+        PAYLOAD.chunks(size).nth(index).unwrap().to_vec()
+    }
+
+    fn add(
+        reassembly: &mut Reassembly,
+        author: XOnlyPublicKey,
+        index: usize,
+        total: usize,
+    ) -> anyhow::Result<Progress> {
+        reassembly.add(
+            author,
+            &chunk(index, total),
+            Some(Magic::BITCOIN),
+            None,
+            data(index, total),
+        )
+    }
+
+    #[test]
+    fn chunks_in_any_order() {
+        let mut reassembly = Reassembly::new(Duration::from_secs(60));
+        let author = author();
+        for (index, received) in [(2, 1), (0, 2), (2, 2)] {
+            let progress = add(&mut reassembly, author, index, 3).unwrap();
+            assert!(
+                matches!(progress, Progress::Pending { received: r, total: 3 } if r == received)
+            );
+        }
+        let progress = add(&mut reassembly, author, 1, 3).unwrap();
+        assert!(matches!(progress, Progress::Complete(payload) if payload == PAYLOAD));
+    }
+
+    #[test]
+    fn authors_are_kept_apart() {
+        let mut reassembly = Reassembly::new(Duration::from_secs(60));
+        add(&mut reassembly, author(), 0, 2).unwrap();
+        let progress = add(&mut reassembly, author(), 1, 2).unwrap();
+        assert!(matches!(
+            progress,
+            Progress::Pending {
+                received: 1,
+                total: 2
+            }
+        ));
+    }
+
+    #[test]
+    fn out_of_range_chunk() {
+        let mut reassembly = Reassembly::new(Duration::from_secs(60));
+        let author = author();
+        assert!(reassembly
+            .add(author, &chunk(2, 2), None, None, vec![0])
+            .is_err());
+        add(&mut reassembly, author, 0, 2).unwrap();
+        assert!(reassembly
+            .add(author, &chunk(5, 2), None, None, vec![0])
+            .is_err());
+    }
+
+    #[test]
+    fn mismatched_chunks() {
+        let mut reassembly = Reassembly::new(Duration::from_secs(60));
+        let author = author();
+        add(&mut reassembly, author, 0, 3).unwrap();
+
+        // another number of chunks, network or compression than the first chunk
+        assert!(add(&mut reassembly, author, 1, 4).is_err());
+        let mut add_with = |magic, compression| {
+            reassembly.add(author, &chunk(1, 3), magic, compression, data(1, 3))
+        };
+        assert!(add_with(Some(Magic::TESTNET), None).is_err());
+        assert!(add_with(None, None).is_err());
+        assert!(add_with(Some(Magic::BITCOIN), Some(Compression::Gzip)).is_err());
+
+        add(&mut reassembly, author, 1, 3).unwrap();
+        let progress = add(&mut reassembly, author, 2, 3).unwrap();
+        assert!(matches!(progress, Progress::Complete(payload) if payload == PAYLOAD));
+    }
+
+    #[test]
+    fn bad_hash() {
+        let mut reassembly = Reassembly::new(Duration::from_secs(60));
+        let author = author();
+        add(&mut reassembly, author, 0, 2).unwrap();
+        let e = reassembly
+            .add(
+                author,
+                &chunk(1, 2),
+                Some(Magic::BITCOIN),
+                None,
+                b"something else".to_vec(),
+            )
+            .unwrap_err();
+        assert!(e.to_string().contains("don't hash to its id"));
+
+        // the package is dropped, its chunks can come again
+        add(&mut reassembly, author, 0, 2).unwrap();
+        let progress = add(&mut reassembly, author, 1, 2).unwrap();
+        assert!(matches!(progress, Progress::Complete(_)));
+    }
+
+    #[test]
+    fn expire() {
+        let mut reassembly = Reassembly::new(Duration::ZERO);
+        add(&mut reassembly, author(), 0, 2).unwrap();
+        assert_eq!(reassembly.expire(), 1);
+        assert_eq!(reassembly.expire(), 0);
+    }
+}
@@ -0,0 +1,356 @@
+//! Reconnecting to relays that drop, waiting twice as long after each failed attempt, and
+//! resubscribing from the last event received so no events are missed in between. Relays that
+//! go quiet are pinged, and dropped when they stay silent, as a dead socket can look connected.
+//! Relays scoring too low are demoted: disconnected for a while.
+
+use nostr::Timestamp;
+use nostr_sdk::relay::RelayStatus;
+use std::time::{Duration, Instant};
+
+use crate::scoring::{RelayStats, ScoringConfig};
+
+/// Configuration of the reconnection to relays
+#[derive(Debug, Clone, Copy)]
+pub struct ReconnectConfig {
+    /// Wait before the first attempt to reconnect, doubled after each failed attempt
+    pub initial_delay: Duration,
+    /// Longest wait between attempts
+    pub max_delay: Duration,
+    /// Ping relays that didn't send anything for this long, `None` to trust the connection
+    pub ping_interval: Option<Duration>,
+    /// Drop relays that didn't send anything for this long, pings included
+    pub silence_timeout: Duration,
+}
+
+/// What to do about a relay after checking its status
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    /// Nothing changed
+    None,
+    /// The relay (re)connected
+    Connected,
+    /// The relay dropped, or the attempt to connect failed when `was_connected` is false: take it
+    /// out of the pool and try again after `delay`
+    Disconnected {
+        /// Whether the relay was connected before
+        was_connected: bool,
+        /// Wait before the next attempt
+        delay: Duration,
+    },
+    /// Connect to the relay again, subscribing from `since`
+    Reconnect {
+        /// Where the new subscription continues
+        since: Timestamp,
+        /// Whether the relay was demoted, its statistics are reset
+        demoted: bool,
+    },
+}
+
+/// What to do about a connected relay that went quiet
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Keepalive {
+    /// Ask the relay for something, to tell whether the connection is alive
+    Ping,
+    /// The relay hasn't sent anything for this long, pings unanswered: the connection is dead
+    Silent(Duration),
+}
+
+/// The state of the connection to a relay
+#[derive(Debug)]
+pub struct RelayConnection {
+    connected: bool,
+    failures: u32,
+    retry_at: Option<Instant>,
+    since: Timestamp,
+    demoted: bool,
+    stats: RelayStats,
+    /// When the relay last sent something
+    heard_at: Instant,
+    /// When the relay was last pinged
+    pinged_at: Option<Instant>,
+}
+
+impl RelayConnection {
+    /// A relay subscribed to from `since`
+    pub fn new(since: Timestamp) -> Self {
+        Self {
+            connected: false,
+            failures: 0,
+            retry_at: None,
+            since,
+            demoted: false,
+            stats: RelayStats::default(),
+            heard_at: Instant::now(),
+            pinged_at: None,
+        }
+    }
+
+    /// Record a message from the relay, of any kind
+    pub fn heard(&mut self) {
+        self.heard_at = Instant::now();
+    }
+
+    /// Whether the connected relay should be pinged or dropped for being silent
+    pub fn keepalive(&mut self, config: &ReconnectConfig) -> Option<Keepalive> {
+        let ping_interval = config.ping_interval?;
+        if !self.connected || self.demoted {
+            return None;
+        }
+        let silent = self.heard_at.elapsed();
+        if silent >= config.silence_timeout {
+            return Some(Keepalive::Silent(silent));
+        }
+        let last = self
+            .pinged_at
+            .map_or(self.heard_at, |at| at.max(self.heard_at));
+        if last.elapsed() < ping_interval {
+            return None;
+        }
+        self.pinged_at = Some(Instant::now());
+        Some(Keepalive::Ping)
+    }
+
+    /// What was observed of the relay since it was added or last demoted
+    pub fn stats(&self) -> &RelayStats {
+        &self.stats
+    }
+
+    /// Whether the relay is demoted
+    pub fn is_demoted(&self) -> bool {
+        self.demoted
+    }
+
+    /// Record an event created at `created_at` received from the relay, a `duplicate` when
+    /// another relay delivered it first. Events from the future don't move the subscription past
+    /// now
+    pub fn received(&mut self, created_at: Timestamp, duplicate: bool) {
+        let now = Timestamp::now();
+        let latency_secs = now.as_u64().saturating_sub(created_at.as_u64());
+        self.stats.event(latency_secs as f64, duplicate);
+        self.since = self.since.max(created_at.min(now));
+    }
+
+    /// Demote the relay when its score is too low, returning the score. It's tried again after
+    /// the demotion period, with fresh statistics
+    pub fn demote(&mut self, config: &ScoringConfig) -> Option<f64> {
+        let score = self.stats.score();
+        if self.demoted || !self.stats.is_significant() || score >= config.min_score {
+            return None;
+        }
+        self.demoted = true;
+        self.connected = false;
+        self.retry_at = Some(Instant::now() + config.demotion);
+        Some(score)
+    }
+
+    /// Follow the `status` of the relay
+    pub fn update(&mut self, status: RelayStatus, config: &ReconnectConfig) -> Action {
+        match status {
+            RelayStatus::Connected => {
+                if self.demoted || std::mem::replace(&mut self.connected, true) {
+                    return Action::None;
+                }
+                self.failures = 0;
+                self.retry_at = None;
+                self.heard_at = Instant::now();
+                self.stats.connect_attempt(true);
+                Action::Connected
+            }
+            RelayStatus::Disconnected | RelayStatus::Terminated => match self.retry_at {
+                Some(at) if at <= Instant::now() => {
+                    self.retry_at = None;
+                    let demoted = std::mem::take(&mut self.demoted);
+                    if demoted {
+                        self.stats = RelayStats::default();
+                    }
+                    Action::Reconnect {
+                        since: self.since,
+                        demoted,
+                    }
+                }
+                Some(_) => Action::None,
+                None => {
+                    let delay = config
+                        .initial_delay
+                        .saturating_mul(2u32.saturating_pow(self.failures))
+                        .min(config.max_delay);
+                    self.failures += 1;
+                    self.retry_at = Some(Instant::now() + delay);
+                    let was_connected = std::mem::replace(&mut self.connected, false);
+                    if !was_connected {
+                        self.stats.connect_attempt(false);
+                    }
+                    Action::Disconnected {
+                        was_connected,
+                        delay,
+                    }
+                }
+            },
+            RelayStatus::Initialized | RelayStatus::Connecting => Action::None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> ReconnectConfig {
+        ReconnectConfig {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(5),
+            ping_interval: None,
+            silence_timeout: Duration::from_secs(60),
+        }
+    }
+
+    /// The delay of the next attempt after the relay dropped, retrying right away
+    fn disconnect(relay: &mut RelayConnection) -> Duration {
+        let Action::Disconnected { delay, .. } = relay.update(RelayStatus::Disconnected, &config())
+        else {
+            panic!("not disconnected");
+        };
+        // still waiting
+        assert_eq!(
+            relay.update(RelayStatus::Disconnected, &config()),
+            Action::None
+        );
+        relay.retry_at = Some(Instant::now());
+        delay
+    }
+
+    #[test]
+    fn backoff() {
+        let mut relay = RelayConnection::new(Timestamp::from(1_000));
+        let mut delays = vec![];
+        for _ in 0..5 {
+            delays.push(disconnect(&mut relay).as_secs());
+            assert_eq!(
+                relay.update(RelayStatus::Disconnected, &config()),
+                Action::Reconnect {
+                    since: Timestamp::from(1_000),
+                    demoted: false
+                }
+            );
+        }
+        // doubling, up to the longest
+        assert_eq!(delays, [1, 2, 4, 5, 5]);
+
+        // connecting starts over
+        assert_eq!(
+            relay.update(RelayStatus::Connected, &config()),
+            Action::Connected
+        );
+        assert_eq!(
+            relay.update(RelayStatus::Connected, &config()),
+            Action::None
+        );
+        let Action::Disconnected {
+            was_connected,
+            delay,
+        } = relay.update(RelayStatus::Disconnected, &config())
+        else {
+            panic!("not disconnected");
+        };
+        assert!(was_connected);
+        assert_eq!(delay, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn resubscribes_from_the_last_event() {
+        let mut relay = RelayConnection::new(Timestamp::from(1_000));
+        relay.update(RelayStatus::Connected, &config());
+        relay.received(Timestamp::from(2_000), false);
+        // older events don't move it back
+        relay.received(Timestamp::from(1_500), true);
+        disconnect(&mut relay);
+        assert_eq!(
+            relay.update(RelayStatus::Disconnected, &config()),
+            Action::Reconnect {
+                since: Timestamp::from(2_000),
+                demoted: false
+            }
+        );
+    }
+
+    #[test]
+    fn future_events_stop_at_now() {
+        let mut relay = RelayConnection::new(Timestamp::from(1_000));
+        let future = Timestamp::now().as_u64() + 3600;
+        relay.received(Timestamp::from(future), false);
+        assert!(relay.since <= Timestamp::now());
+    }
+
+    #[test]
+    fn keepalive() {
+        let config = ReconnectConfig {
+            ping_interval: Some(Duration::from_secs(10)),
+            ..config()
+        };
+        let mut relay = RelayConnection::new(Timestamp::from(1_000));
+        // not connected yet
+        assert_eq!(relay.keepalive(&config), None);
+        relay.update(RelayStatus::Connected, &config);
+        assert_eq!(relay.keepalive(&config), None);
+
+        relay.heard_at = Instant::now() - Duration::from_secs(15);
+        assert_eq!(relay.keepalive(&config), Some(Keepalive::Ping));
+        // one ping per interval
+        assert_eq!(relay.keepalive(&config), None);
+
+        relay.heard();
+        relay.pinged_at = None;
+        assert_eq!(relay.keepalive(&config), None);
+
+        relay.heard_at = Instant::now() - Duration::from_secs(61);
+        assert!(matches!(
+            relay.keepalive(&config),
+            Some(Keepalive::Silent(silent)) if silent >= Duration::from_secs(61)
+        ));
+
+        // without pings, the connection is trusted
+        assert_eq!(relay.keepalive(&self::config()), None);
+    }
+
+    #[test]
+    fn demotion() {
+        let scoring = ScoringConfig {
+            min_score: 0.5,
+            demotion: Duration::from_secs(600),
+        };
+        let mut relay = RelayConnection::new(Timestamp::from(1_000));
+        relay.update(RelayStatus::Connected, &config());
+        // the first drop isn't a failed attempt, the next ones are
+        for _ in 0..4 {
+            disconnect(&mut relay);
+            relay.update(RelayStatus::Disconnected, &config());
+        }
+        // three of four attempts failed, not enough to judge yet
+        assert_eq!(relay.demote(&scoring), None);
+        disconnect(&mut relay);
+        let score = relay.demote(&scoring).unwrap();
+        assert!(score < 0.5);
+        assert!(relay.is_demoted());
+        assert_eq!(relay.demote(&scoring), None);
+        // not connecting again until the demotion is over
+        assert_eq!(
+            relay.update(RelayStatus::Connected, &config()),
+            Action::None
+        );
+        assert_eq!(
+            relay.update(RelayStatus::Disconnected, &config()),
+            Action::None
+        );
+
+        relay.retry_at = Some(Instant::now());
+        assert_eq!(
+            relay.update(RelayStatus::Disconnected, &config()),
+            Action::Reconnect {
+                since: Timestamp::from(1_000),
+                demoted: true
+            }
+        );
+        assert!(!relay.is_demoted());
+        assert!(!relay.stats().is_significant());
+    }
+}
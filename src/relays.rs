@@ -0,0 +1,106 @@
+//! Relay urls, and lists of relays to connect to besides the ones given on the command line:
+//! read from a file, or the healthy public relays of a directory service such as nostr.watch.
+
+use anyhow::{anyhow, bail, Context};
+use nostr::Url;
+use rand::seq::SliceRandom;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// Directory of the public relays online right now
+pub const DIRECTORY: &str = "https://api.nostr.watch/v1/online";
+
+/// How long the directory may take to answer
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Parse the url of a relay, `ws://` or `wss://` with a host: a name, an IPv4 address, an IPv6
+/// address in brackets or a `.onion` address. The error tells what's wrong with `relay`
+pub fn parse_relay(relay: &str) -> anyhow::Result<Url> {
+    let trimmed = relay.trim();
+    let url = Url::parse(trimmed).map_err(|e| {
+        let hint = if !trimmed.contains("://") {
+            ", missing ws:// or wss://"
+        } else if trimmed.matches(':').count() > 2 && !trimmed.contains('[') {
+            ", put IPv6 addresses in brackets like ws://[::1]:7777"
+        } else {
+            ""
+        };
+        anyhow!("Invalid relay url {relay:?}: {e}{hint}")
+    })?;
+    if !matches!(url.scheme(), "ws" | "wss") {
+        bail!(
+            "Invalid relay url {relay:?}: scheme {} isn't ws or wss",
+            url.scheme()
+        );
+    }
+    if url.host().is_none() {
+        bail!("Invalid relay url {relay:?}: no host");
+    }
+    Ok(url)
+}
+
+/// Whether `url` is a Tor onion service, only reachable through a proxy
+pub fn is_onion(url: &Url) -> bool {
+    url.domain()
+        .is_some_and(|domain| domain.trim_end_matches('.').ends_with(".onion"))
+}
+
+/// Read a file with a relay url per line, skipping empty lines and `#` comments
+pub fn read_relays(path: impl AsRef<Path>) -> anyhow::Result<Vec<String>> {
+    let path = path.as_ref();
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Reading relay file {}", path.display()))?;
+    contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            parse_relay(line).with_context(|| format!("In relay file {}", path.display()))?;
+            Ok(line.to_string())
+        })
+        .collect()
+}
+
+/// The relays `directory` lists as online, a JSON array of urls, in random order so not every
+/// broadcaster picks the same ones
+pub async fn fetch_online(directory: &str) -> anyhow::Result<Vec<Url>> {
+    let body = reqwest::Client::new()
+        .get(directory)
+        .timeout(TIMEOUT)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    let urls: Vec<String> = serde_json::from_str(&body).context("Invalid relay directory")?;
+    let mut relays: Vec<Url> = urls
+        .iter()
+        .filter_map(|url| parse_relay(url).ok())
+        .collect();
+    relays.shuffle(&mut rand::thread_rng());
+    Ok(relays)
+}
+
+/// Add the relays of `urls` not in `relays` yet, "wss://nos.lol" and "wss://nos.lol/" being the
+/// same relay, until there are `max`, returning the added ones
+pub fn add_new(
+    relays: &mut Vec<String>,
+    urls: impl IntoIterator<Item = Url>,
+    max: usize,
+) -> Vec<Url> {
+    let mut added = vec![];
+    for url in urls {
+        if relays.len() >= max {
+            break;
+        }
+        if !relays
+            .iter()
+            .any(|relay| Url::parse(relay).ok().as_ref() == Some(&url))
+        {
+            relays.push(url.to_string());
+            added.push(url);
+        }
+    }
+    added
+}
@@ -0,0 +1,253 @@
+//! Reputation of senders: a score per author, raised by accepted transactions and lowered by
+//! invalid ones and policy rejects, decaying back to zero over time. Authors whose score drops
+//! too low are banned for a while, longer each time, unlike the permanent bans of the
+//! [`Denylist`](crate::pubkeys::Denylist).
+
+use nostr::prelude::XOnlyPublicKey;
+use nostr::Timestamp;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::error;
+
+use crate::store::Store;
+
+/// Highest score an author can build up, so a long history doesn't excuse a burst of garbage
+const MAX_SCORE: f64 = 10.0;
+
+/// How the reputation of authors is kept
+#[derive(Debug, Clone)]
+pub struct ReputationConfig {
+    /// Authors are banned when their score drops to minus this
+    pub ban_threshold: f64,
+    /// How long the first ban lasts, each next one lasts twice as long
+    pub ban_duration: Duration,
+    /// The longest a ban lasts
+    pub max_ban_duration: Duration,
+    /// After how long scores, and the number of bans making the next one longer, halve
+    pub half_life: Duration,
+}
+
+/// What an author's submission turned out to be
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Conduct {
+    /// Undecodable, failing to decrypt or rejected by the backend
+    Invalid,
+    /// Breaking the policy of the broadcaster
+    PolicyReject,
+    /// Accepted into the mempool
+    Accepted,
+}
+
+impl Conduct {
+    fn points(self) -> f64 {
+        match self {
+            Conduct::Invalid => -1.0,
+            Conduct::PolicyReject => -0.5,
+            Conduct::Accepted => 1.0,
+        }
+    }
+}
+
+/// The reputation of an author, as of `updated_at`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Standing {
+    /// Score, negative for authors submitting garbage
+    pub score: f64,
+    /// How many times the author was banned, decayed like the score
+    pub bans: f64,
+    /// Until when the author is banned, if at all
+    pub banned_until: Option<Timestamp>,
+    /// When the score was last updated
+    pub updated_at: Timestamp,
+}
+
+impl Standing {
+    /// Decay the score and bans until `now`
+    fn decay(&mut self, now: Timestamp, half_life: Duration) {
+        let elapsed = now.as_u64().saturating_sub(self.updated_at.as_u64()) as f64;
+        let factor = 0.5f64.powf(elapsed / half_life.as_secs().max(1) as f64);
+        self.score *= factor;
+        self.bans *= factor;
+        self.updated_at = now;
+    }
+}
+
+/// Scores of the authors, persisted in the store when there is one
+#[derive(Debug)]
+pub struct Reputation {
+    config: ReputationConfig,
+    store: Option<Store>,
+    standings: Mutex<HashMap<XOnlyPublicKey, Standing>>,
+}
+
+impl Reputation {
+    /// Reputation loading and saving the standings of authors in `store`
+    pub fn new(config: ReputationConfig, store: Option<Store>) -> anyhow::Result<Self> {
+        let standings = match &store {
+            Some(store) => store.reputation()?.into_iter().collect(),
+            None => HashMap::new(),
+        };
+        Ok(Self {
+            config,
+            store,
+            standings: Mutex::new(standings),
+        })
+    }
+
+    /// Until when `author` is banned, if it is now
+    pub fn banned_until(&self, author: &XOnlyPublicKey) -> Option<Timestamp> {
+        let standings = self.standings.lock().unwrap();
+        let until = standings.get(author)?.banned_until?;
+        (until > Timestamp::now()).then_some(until)
+    }
+
+    /// Account for the `conduct` of `author`, returning until when it got banned if this was one
+    /// submission too many
+    pub fn record(&self, author: XOnlyPublicKey, conduct: Conduct) -> Option<Timestamp> {
+        self.record_at(author, conduct, Timestamp::now())
+    }
+
+    fn record_at(
+        &self,
+        author: XOnlyPublicKey,
+        conduct: Conduct,
+        now: Timestamp,
+    ) -> Option<Timestamp> {
+        let (standing, banned) = {
+            let mut standings = self.standings.lock().unwrap();
+            let standing = standings.entry(author).or_insert(Standing {
+                score: 0.0,
+                bans: 0.0,
+                banned_until: None,
+                updated_at: now,
+            });
+            standing.decay(now, self.config.half_life);
+            standing.score = (standing.score + conduct.points()).min(MAX_SCORE);
+
+            let mut banned = None;
+            if standing.score <= -self.config.ban_threshold {
+                let duration = self
+                    .config
+                    .ban_duration
+                    .mul_f64(2f64.powf(standing.bans.round().min(32.0)))
+                    .min(self.config.max_ban_duration);
+                let until = now + duration;
+                standing.banned_until = Some(until);
+                standing.bans += 1.0;
+                // a clean slate once the ban is over
+                standing.score = 0.0;
+                banned = Some(until);
+            }
+            (*standing, banned)
+        };
+
+        if let Some(store) = &self.store {
+            if let Err(e) = store.save_standing(author, &standing) {
+                error!("Error writing store: {e}");
+            }
+        }
+        banned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr::Keys;
+
+    fn config() -> ReputationConfig {
+        ReputationConfig {
+            ban_threshold: 2.0,
+            ban_duration: Duration::from_secs(60),
+            max_ban_duration: Duration::from_secs(200),
+            half_life: Duration::from_secs(1000),
+        }
+    }
+
+    #[test]
+    fn decay() {
+        let mut standing = Standing {
+            score: 8.0,
+            bans: 2.0,
+            banned_until: None,
+            updated_at: Timestamp::from(1_000),
+        };
+        standing.decay(Timestamp::from(2_000), Duration::from_secs(1000));
+        assert_eq!((standing.score, standing.bans), (4.0, 1.0));
+        standing.decay(Timestamp::from(4_000), Duration::from_secs(1000));
+        assert_eq!((standing.score, standing.bans), (1.0, 0.25));
+        assert_eq!(standing.updated_at, Timestamp::from(4_000));
+        // time going backwards changes nothing
+        standing.decay(Timestamp::from(3_000), Duration::from_secs(1000));
+        assert_eq!(standing.score, 1.0);
+    }
+
+    #[test]
+    fn score_is_capped() {
+        let reputation = Reputation::new(config(), None).unwrap();
+        let author = Keys::generate().public_key();
+        let now = Timestamp::from(1_000_000);
+        for _ in 0..20 {
+            reputation.record_at(author, Conduct::Accepted, now);
+        }
+        // the cap, not the 20 points, is what it takes to get banned
+        for _ in 0..11 {
+            assert_eq!(reputation.record_at(author, Conduct::Invalid, now), None);
+        }
+        assert!(reputation
+            .record_at(author, Conduct::Invalid, now)
+            .is_some());
+    }
+
+    #[test]
+    fn ban_escalation() {
+        let reputation = Reputation::new(config(), None).unwrap();
+        let author = Keys::generate().public_key();
+        let now = Timestamp::from(1_000_000);
+        let mut bans = vec![];
+        for _ in 0..4 {
+            assert_eq!(reputation.record_at(author, Conduct::Invalid, now), None);
+            bans.push(reputation.record_at(author, Conduct::Invalid, now).unwrap());
+        }
+        let durations: Vec<u64> = bans
+            .iter()
+            .map(|until| until.as_u64() - now.as_u64())
+            .collect();
+        // doubling, up to the longest
+        assert_eq!(durations, [60, 120, 200, 200]);
+
+        // policy rejects count half
+        let other = Keys::generate().public_key();
+        for _ in 0..3 {
+            assert_eq!(
+                reputation.record_at(other, Conduct::PolicyReject, now),
+                None
+            );
+        }
+        assert!(reputation
+            .record_at(other, Conduct::PolicyReject, now)
+            .is_some());
+    }
+
+    #[test]
+    fn standing_persists() {
+        let path = std::env::temp_dir().join(format!("reputation-{}.db", std::process::id()));
+        let author = Keys::generate().public_key();
+        let store = Store::open(&path).unwrap();
+        let reputation = Reputation::new(config(), Some(store)).unwrap();
+        reputation.record(author, Conduct::Invalid);
+        let until = reputation.record(author, Conduct::Invalid).unwrap();
+        assert_eq!(reputation.banned_until(&author), Some(until));
+
+        let reputation = Reputation::new(config(), Some(Store::open(&path).unwrap())).unwrap();
+        assert_eq!(reputation.banned_until(&author), Some(until));
+        let standing = reputation.standings.lock().unwrap()[&author];
+        assert_eq!((standing.score, standing.bans), (0.0, 1.0));
+        assert_eq!(
+            reputation.banned_until(&Keys::generate().public_key()),
+            None
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
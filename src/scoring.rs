@@ -0,0 +1,142 @@
+//! Scoring relays by how well they serve the listener, to stop using the ones that are
+//! persistently unhealthy.
+
+use std::time::Duration;
+
+/// Connection attempts or events after which a relay's score is trusted
+const MIN_ATTEMPTS: u32 = 5;
+const MIN_EVENTS: u64 = 20;
+
+/// Delay between the creation of an event and its arrival that doesn't lower the score
+const LATENCY_TARGET_SECS: f64 = 5.0;
+
+/// Weight of the latest event in the average latency
+const LATENCY_WEIGHT: f64 = 0.1;
+
+/// When relays are demoted
+#[derive(Debug, Clone, Copy)]
+pub struct ScoringConfig {
+    /// Relays scoring lower are demoted, as long as another relay is connected
+    pub min_score: f64,
+    /// How long a demoted relay isn't used before it's tried again with a clean slate
+    pub demotion: Duration,
+}
+
+/// What the listener observed of a relay
+#[derive(Debug, Clone, Default)]
+pub struct RelayStats {
+    attempts: u32,
+    failures: u32,
+    events: u64,
+    duplicates: u64,
+    latency_secs: Option<f64>,
+}
+
+impl RelayStats {
+    /// Record an attempt to connect to the relay
+    pub fn connect_attempt(&mut self, connected: bool) {
+        self.attempts += 1;
+        if !connected {
+            self.failures += 1;
+        }
+    }
+
+    /// Record an event received `latency_secs` after its creation, a `duplicate` when another
+    /// relay delivered it first
+    pub fn event(&mut self, latency_secs: f64, duplicate: bool) {
+        self.events += 1;
+        if duplicate {
+            self.duplicates += 1;
+        }
+        self.latency_secs = Some(match self.latency_secs {
+            Some(average) => average + LATENCY_WEIGHT * (latency_secs - average),
+            None => latency_secs,
+        });
+    }
+
+    /// Failed attempts to connect
+    pub fn connect_failures(&self) -> u32 {
+        self.failures
+    }
+
+    /// Average delay in seconds between the creation of the events and their arrival
+    pub fn latency_secs(&self) -> Option<f64> {
+        self.latency_secs
+    }
+
+    /// Share of the events that another relay delivered first
+    pub fn duplicate_ratio(&self) -> f64 {
+        if self.events == 0 {
+            return 0.0;
+        }
+        self.duplicates as f64 / self.events as f64
+    }
+
+    /// Between 0 and 1: the share of successful connection attempts, halved for a relay that
+    /// only delivers duplicates, and lowered for events arriving later than a few seconds
+    pub fn score(&self) -> f64 {
+        let connecting = if self.attempts == 0 {
+            1.0
+        } else {
+            (self.attempts - self.failures) as f64 / self.attempts as f64
+        };
+        let unique = 1.0 - self.duplicate_ratio() / 2.0;
+        let latency = match self.latency_secs {
+            Some(latency) if latency > LATENCY_TARGET_SECS => LATENCY_TARGET_SECS / latency,
+            _ => 1.0,
+        };
+        connecting * unique * latency
+    }
+
+    /// Whether enough was observed to judge the relay
+    pub fn is_significant(&self) -> bool {
+        self.attempts >= MIN_ATTEMPTS || self.events >= MIN_EVENTS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fresh_relay() {
+        let stats = RelayStats::default();
+        assert_eq!(stats.score(), 1.0);
+        assert_eq!(stats.duplicate_ratio(), 0.0);
+        assert!(!stats.is_significant());
+    }
+
+    #[test]
+    fn connection_failures() {
+        let mut stats = RelayStats::default();
+        for connected in [true, false, false, true] {
+            stats.connect_attempt(connected);
+        }
+        assert_eq!(stats.connect_failures(), 2);
+        assert_eq!(stats.score(), 0.5);
+        assert!(!stats.is_significant());
+        stats.connect_attempt(false);
+        assert!(stats.is_significant());
+        assert_eq!(stats.score(), 0.4);
+    }
+
+    #[test]
+    fn duplicates_and_latency() {
+        let mut stats = RelayStats::default();
+        for _ in 0..MIN_EVENTS {
+            stats.event(1.0, true);
+        }
+        assert!(stats.is_significant());
+        assert_eq!(stats.duplicate_ratio(), 1.0);
+        // only duplicates halve the score, latency under the target doesn't count
+        assert_eq!(stats.score(), 0.5);
+
+        let mut stats = RelayStats::default();
+        stats.event(10.0, false);
+        assert_eq!(stats.latency_secs(), Some(10.0));
+        assert_eq!(stats.score(), 0.5);
+        // a moving average
+        stats.event(20.0, false);
+        assert_eq!(stats.latency_secs(), Some(11.0));
+    }
+}
@@ -0,0 +1,41 @@
+//! Remembering recently seen events and transactions, as relays deliver the same event
+//! multiple times and several relays deliver the same events.
+
+use bitcoin::{Transaction, Wtxid};
+use lru::LruCache;
+use nostr::EventId;
+use std::num::NonZeroUsize;
+
+/// Bounded cache of the most recently seen event ids and wtxids
+#[derive(Debug)]
+pub struct SeenCache {
+    events: LruCache<EventId, ()>,
+    txs: LruCache<Wtxid, ()>,
+}
+
+impl SeenCache {
+    /// Remember up to `capacity` events and as many transactions
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        Self {
+            events: LruCache::new(capacity),
+            txs: LruCache::new(capacity),
+        }
+    }
+
+    /// Record the event, returning whether it was seen before
+    pub fn seen_event(&mut self, event_id: EventId) -> bool {
+        self.events.put(event_id, ()).is_some()
+    }
+
+    /// Whether all the transactions were recorded before
+    pub fn seen_txs(&self, txs: &[Transaction]) -> bool {
+        !txs.is_empty() && txs.iter().all(|tx| self.txs.contains(&tx.wtxid()))
+    }
+
+    /// Record the transactions, once they're on their way to be broadcast
+    pub fn record_txs(&mut self, txs: &[Transaction]) {
+        for tx in txs {
+            self.txs.put(tx.wtxid(), ());
+        }
+    }
+}
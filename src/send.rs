@@ -0,0 +1,548 @@
+//! Publishing transactions as transaction events, and following what broadcasters do with them.
+
+use anyhow::bail;
+use bitcoin::network::Magic;
+use bitcoin::{Transaction, Txid};
+use nostr::prelude::*;
+use nostr_sdk::relay::pool::RelayPoolNotification;
+use nostr_sdk::{Client, Options};
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::process::ExitCode;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, info, warn};
+
+use crate::backend::Bitcoind;
+use crate::compression::Compression;
+use crate::config::NetworkConfig;
+use crate::package;
+use crate::protocol::{self, Announcement, TxEncoding, TxEventOptions};
+
+/// How long `send` waits for the relays to accept its events before publishing to more relays
+const QUORUM_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// How to publish transactions and what to wait for
+#[derive(Debug, Clone)]
+pub struct SendConfig {
+    /// Relays to publish to
+    pub relays: Vec<String>,
+    /// Relays to publish to as well, one at a time, while not enough relays accepted the events
+    pub spare_relays: Vec<String>,
+    /// SOCKS5 proxy the relay connections go through, such as Tor
+    pub proxy: Option<SocketAddr>,
+    /// Network of the transactions
+    pub magic: Magic,
+    /// Event kind of the transaction events
+    pub kind: u64,
+    /// Broadcaster to encrypt the transactions to, so only it can read and broadcast them
+    pub encrypt_to: Option<XOnlyPublicKey>,
+    /// Look up the broadcasters announcing themselves, failing when none takes the transactions
+    pub discover: bool,
+    /// Encrypt the transactions to the most recently announced of the discovered broadcasters
+    /// taking encrypted events
+    pub encrypt: bool,
+    /// Cashu token paying for the broadcast, sent only in encrypted events
+    pub cashu: Option<String>,
+    /// How to encode the transactions in the events
+    pub tx_encoding: TxEncoding,
+    /// How to compress the transactions, `None` not to
+    pub compression: Option<Compression>,
+    /// Size in bytes above which packages are split into several events
+    pub chunk_size: Option<NonZeroUsize>,
+    /// How long relays keep the events (NIP-40), `None` for as long as they like
+    pub expiration: Option<Duration>,
+    /// Leading zero bits of the NIP-13 proof of work mined into the event ids
+    pub pow: u8,
+    /// Broadcaster to wait for the result of
+    pub wait_for: Option<XOnlyPublicKey>,
+    /// How long to wait for the relays to accept the events, and for the result of `wait_for`
+    pub timeout: Duration,
+    /// How many relays have to accept each event
+    pub min_relays: NonZeroUsize,
+    /// Bitcoin Core nodes to fetch the parents broadcasters ask for from, `None` when there are
+    /// none
+    pub node: Option<NetworkConfig>,
+}
+
+/// How far the published transactions got, each with its own exit code for scripts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sent {
+    /// A relay accepted every event, no broadcaster was waited for
+    Published,
+    /// The broadcaster waited for broadcast every transaction
+    Broadcast,
+    /// No relay accepted the events in time
+    TimedOut,
+    /// The relays accepted the events, but the broadcaster didn't reply in time
+    NoResult,
+    /// The broadcaster couldn't broadcast some transactions
+    NotBroadcast,
+}
+
+impl Sent {
+    /// The exit code of the send command
+    pub fn exit_code(self) -> ExitCode {
+        ExitCode::from(match self {
+            Sent::Broadcast => 0,
+            Sent::TimedOut => 2,
+            Sent::NoResult => 3,
+            Sent::NotBroadcast => 4,
+            Sent::Published => 5,
+        })
+    }
+}
+
+/// Publish `txs` as `config` asks for, and wait until the relays accepted them and the
+/// broadcaster of `config.wait_for` replied. The parents broadcasters ask for are published as
+/// well
+pub async fn run(keys: &Keys, config: &SendConfig, txs: &[Transaction]) -> anyhow::Result<Sent> {
+    if config.relays.is_empty() {
+        bail!("No relays to publish to, give them with --relays or leave out --no-default-relays");
+    }
+
+    // make sure the relays are up before publishing
+    let client = Client::with_opts(keys, Options::new().wait_for_connection(true));
+    for relay in &config.relays {
+        client.add_relay(relay.as_str(), config.proxy).await?;
+    }
+    client.connect().await;
+
+    let mut encrypt_to = config.encrypt_to;
+    let mut wait_for = config.wait_for;
+    if config.discover {
+        let broadcasters = discover(&client, config, txs).await?;
+        if config.encrypt {
+            let Some((broadcaster, announcement)) = broadcasters
+                .iter()
+                .find(|(_, announcement)| announcement.encrypted)
+            else {
+                bail!("None of the discovered broadcasters takes encrypted events");
+            };
+            info!(%broadcaster, "Encrypting to the discovered broadcaster");
+            encrypt_to = Some(*broadcaster);
+            // nobody else can broadcast the transactions
+            if announcement.reply {
+                wait_for = wait_for.or(Some(*broadcaster));
+            }
+        }
+    }
+    if config.cashu.is_some() && encrypt_to.is_none() {
+        bail!("--cashu needs --encrypt-to or --encrypt");
+    }
+    if config.pow > 0 {
+        info!(difficulty = config.pow, "Mining proof of work");
+    }
+    let events = tx_events(keys, config, encrypt_to, txs, config.cashu.as_deref())?;
+    let event_ids: Vec<EventId> = events.iter().map(|event| event.id).collect();
+
+    // listening before publishing, not to miss a quick answer
+    let mut notifications = client.notifications();
+    subscribe_answers(&client, wait_for, &event_ids).await;
+    for event in &events {
+        client.send_event(event.clone()).await?;
+    }
+    for tx in txs {
+        println!("Published tx: {}", tx.txid());
+    }
+    for event_id in &event_ids {
+        println!("Event id: {event_id}");
+    }
+
+    // the relays that accepted each event
+    let mut accepted: HashMap<EventId, HashSet<Url>> = HashMap::new();
+    let quorum = |accepted: &HashMap<EventId, HashSet<Url>>| {
+        event_ids
+            .iter()
+            .map(|event_id| accepted.get(event_id).map_or(0, HashSet::len))
+            .min()
+            .unwrap_or(0)
+    };
+    // the relays not used yet, to publish to when the others don't make the quorum
+    let mut spares = config
+        .spare_relays
+        .iter()
+        .filter(|relay| !config.relays.contains(relay));
+    let mut rejections = vec![];
+    // the txs whose parents were published on request, the events publishing them whose result
+    // is still awaited, and whether each tx was broadcast so far
+    let mut healing: HashSet<Txid> = HashSet::new();
+    let mut pending: HashSet<EventId> = HashSet::new();
+    let mut outcomes: HashMap<Txid, bool> = HashMap::new();
+    let deadline = tokio::time::sleep(config.timeout);
+    tokio::pin!(deadline);
+    let start = tokio::time::Instant::now() + QUORUM_RETRY_DELAY;
+    let mut retry = tokio::time::interval_at(start, QUORUM_RETRY_DELAY);
+    let sent = loop {
+        let notification = tokio::select! {
+            () = &mut deadline => break match quorum(&accepted) >= config.min_relays.get() {
+                true => Sent::NoResult,
+                false => Sent::TimedOut,
+            },
+            _ = retry.tick() => {
+                let missing = config.min_relays.get().saturating_sub(quorum(&accepted));
+                for relay in spares.by_ref().take(missing) {
+                    info!(relay, "Publishing to another relay to reach --min-relays");
+                    publish_to(&client, relay, config.proxy, &events).await;
+                }
+                continue;
+            }
+            notification = notifications.recv() => notification,
+        };
+        match notification {
+            Ok(RelayPoolNotification::Message(
+                relay,
+                RelayMessage::Ok {
+                    event_id,
+                    status,
+                    message,
+                },
+            )) if event_ids.contains(&event_id) => {
+                if status {
+                    info!(%relay, %event_id, "Relay accepted the event");
+                    accepted.entry(event_id).or_default().insert(relay);
+                } else {
+                    warn!(%relay, %event_id, "Relay rejected the event: {message}");
+                    rejections.push(format!("{relay}: {message}"));
+                }
+                if quorum(&accepted) >= config.min_relays.get() && wait_for.is_none() {
+                    break Sent::Published;
+                }
+            }
+            Ok(RelayPoolNotification::Event(_, event))
+                if Some(event.pubkey) == wait_for
+                    && event.kind == Kind::Custom(protocol::BROADCAST_RESULT_KIND) =>
+            {
+                let results = match protocol::results(&event) {
+                    Ok(results) => results,
+                    Err(e) => {
+                        warn!(event_id = %event.id, "Invalid result: {e}");
+                        continue;
+                    }
+                };
+                if let Some(bolt11) = protocol::invoice(&event) {
+                    println!("Pay to have the txs broadcast: {bolt11}");
+                    // paying takes a while
+                    deadline
+                        .as_mut()
+                        .reset(tokio::time::Instant::now() + config.timeout);
+                    continue;
+                }
+                for result in &results {
+                    match result.accepted {
+                        true => println!("Broadcast tx: {}", result.txid),
+                        false => println!(
+                            "Not broadcast tx: {}: {}",
+                            result.txid,
+                            result.error.as_deref().unwrap_or("unknown error")
+                        ),
+                    }
+                    outcomes.insert(result.txid, result.accepted);
+                }
+                let replied_to = event.tags.iter().find_map(|tag| match tag {
+                    Tag::Event(event_id, ..) => Some(*event_id),
+                    _ => None,
+                });
+                if let Some(event_id) = replied_to {
+                    pending.remove(&event_id);
+                }
+                // the result of the txs published again with their parents is still to come
+                if !pending.is_empty() {
+                    continue;
+                }
+                break match outcomes.values().all(|accepted| *accepted) {
+                    true => Sent::Broadcast,
+                    false => Sent::NotBroadcast,
+                };
+            }
+            Ok(RelayPoolNotification::Event(_, event))
+                if event.kind == Kind::Custom(protocol::PARENT_REQUEST_KIND) =>
+            {
+                let request = match protocol::parent_request(&event) {
+                    Ok(request) => request,
+                    Err(e) => {
+                        warn!(event_id = %event.id, "Invalid parent request: {e}");
+                        continue;
+                    }
+                };
+                let Some(tx) = txs.iter().find(|tx| tx.txid() == request.txid) else {
+                    continue;
+                };
+                if !healing.insert(request.txid) {
+                    continue;
+                }
+                let parents: Vec<String> = request.parents.iter().map(Txid::to_string).collect();
+                println!(
+                    "Broadcaster needs parent txs of {}: {}",
+                    request.txid,
+                    parents.join(", ")
+                );
+                let package = match fetch_ancestors(config, tx, &request.parents).await {
+                    Ok(parents) if parents.is_empty() => continue,
+                    Ok(mut package) => {
+                        package.push(tx.clone());
+                        package::sort(&package).unwrap_or(package)
+                    }
+                    Err(e) => {
+                        warn!(txid = %request.txid, "Can't publish the parent txs: {e}");
+                        continue;
+                    }
+                };
+                // the broadcaster took the payment with the first event, answers aren't charged
+                let answers = tx_events(keys, config, encrypt_to, &package, None)?;
+                if Some(event.pubkey) == wait_for {
+                    pending.extend(answers.iter().map(|answer| answer.id));
+                }
+                let mut awaited = event_ids.clone();
+                awaited.extend(pending.iter().copied());
+                subscribe_answers(&client, wait_for, &awaited).await;
+                for answer in answers {
+                    client.send_event(answer.clone()).await?;
+                    println!("Event id: {}", answer.id);
+                }
+                for parent in package.iter().filter(|parent| parent.txid() != tx.txid()) {
+                    println!("Published parent tx: {}", parent.txid());
+                }
+                // broadcasting takes another round trip
+                deadline
+                    .as_mut()
+                    .reset(tokio::time::Instant::now() + config.timeout);
+            }
+            Ok(_) => {}
+            Err(RecvError::Lagged(_)) => {}
+            Err(RecvError::Closed) => break Sent::TimedOut,
+        }
+    };
+    if sent == Sent::TimedOut && !rejections.is_empty() {
+        warn!(
+            "No relay accepted the events in time, rejected by {}",
+            rejections.join(", ")
+        );
+    }
+
+    client.disconnect().await?;
+    Ok(sent)
+}
+
+/// The Bitcoin Core node of `config` to fetch transactions from
+pub async fn fetching_node(
+    magic: Magic,
+    config: Option<&NetworkConfig>,
+) -> anyhow::Result<Bitcoind> {
+    match config {
+        Some(config) if !config.bitcoin_hosts.is_empty() => {
+            Bitcoind::from_config(magic, config).await
+        }
+        _ => {
+            bail!("Fetching txs needs the Bitcoin Core node to fetch them from, see --bitcoin-host")
+        }
+    }
+}
+
+/// The package of the transactions `txids` of `bitcoind`, parents first
+pub async fn fetch_package(
+    bitcoind: &Bitcoind,
+    txids: &[Txid],
+) -> anyhow::Result<Vec<Transaction>> {
+    let txs = package::sort(&fetch_txs(bitcoind, txids).await?)?;
+    let txids: HashSet<Txid> = txs.iter().map(Transaction::txid).collect();
+    for tx in &txs {
+        let spends_package = tx
+            .input
+            .iter()
+            .any(|input| txids.contains(&input.previous_output.txid));
+        let txid = tx.txid();
+        let spent_in_package = txs.iter().any(|other| {
+            other
+                .input
+                .iter()
+                .any(|input| input.previous_output.txid == txid)
+        });
+        if txs.len() > 1 && !spends_package && !spent_in_package {
+            warn!(%txid, "Tx is unrelated to the others of the package");
+        }
+    }
+    Ok(txs)
+}
+
+/// The transactions `txids` of `bitcoind`
+pub async fn fetch_txs(bitcoind: &Bitcoind, txids: &[Txid]) -> anyhow::Result<Vec<Transaction>> {
+    let mut txs = vec![];
+    for txid in txids {
+        txs.push(bitcoind.get_tx(*txid).await?);
+    }
+    Ok(txs)
+}
+
+/// The `requested` transactions that are ancestors of `tx`, from the Bitcoin Core node of
+/// `config`. Anyone can ask, so the others aren't fetched, not to hand out unrelated
+/// transactions of the node's wallet
+async fn fetch_ancestors(
+    config: &SendConfig,
+    tx: &Transaction,
+    requested: &[Txid],
+) -> anyhow::Result<Vec<Transaction>> {
+    let mut spent: HashSet<Txid> = tx
+        .input
+        .iter()
+        .map(|input| input.previous_output.txid)
+        .collect();
+    let mut ancestors: Vec<Transaction> = vec![];
+    let mut bitcoind = None;
+    // a parent found makes its own parents ancestors, whatever their order in the request
+    loop {
+        let next = requested.iter().find(|txid| {
+            spent.contains(*txid) && !ancestors.iter().any(|ancestor| ancestor.txid() == **txid)
+        });
+        let Some(&txid) = next else {
+            break;
+        };
+        if bitcoind.is_none() {
+            bitcoind = Some(fetching_node(config.magic, config.node.as_ref()).await?);
+        }
+        let parent = bitcoind.as_ref().unwrap().get_tx(txid).await?;
+        spent.extend(parent.input.iter().map(|input| input.previous_output.txid));
+        ancestors.push(parent);
+    }
+    for txid in requested {
+        if !ancestors.iter().any(|ancestor| ancestor.txid() == *txid) {
+            warn!(%txid, child = %tx.txid(), "Not publishing requested tx, it isn't an ancestor");
+        }
+    }
+    Ok(ancestors)
+}
+
+/// The events publishing `txs` as `config` asks for, encrypted to `encrypt_to` with the Cashu
+/// token `cashu` when given
+fn tx_events(
+    keys: &Keys,
+    config: &SendConfig,
+    encrypt_to: Option<XOnlyPublicKey>,
+    txs: &[Transaction],
+    cashu: Option<&str>,
+) -> anyhow::Result<Vec<Event>> {
+    let (kind, magic) = (config.kind, config.magic);
+    let options = TxEventOptions {
+        encoding: config.tx_encoding,
+        compression: config.compression,
+        expiration: config
+            .expiration
+            .map(|expiration| Timestamp::now() + expiration),
+    };
+    let events = match (encrypt_to, config.chunk_size) {
+        (Some(to), _) => vec![protocol::encrypted_tx_event(
+            keys, to, kind, magic, txs, options, cashu,
+        )?],
+        (None, Some(size)) => protocol::chunked_tx_events(kind, magic, txs, options, size.get())?,
+        (None, None) => vec![protocol::tx_event(kind, magic, txs, options)?],
+    };
+    // mining keeps every core busy
+    tokio::task::block_in_place(|| {
+        events
+            .into_iter()
+            .map(|event| protocol::mine(event, keys, config.pow))
+            .collect()
+    })
+}
+
+/// Subscribe to requests for the parents of `event_ids` by any broadcaster, and to their results by
+/// `wait_for`
+async fn subscribe_answers(
+    client: &Client,
+    wait_for: Option<XOnlyPublicKey>,
+    event_ids: &[EventId],
+) {
+    let mut filters = vec![Filter::new()
+        .kind(Kind::Custom(protocol::PARENT_REQUEST_KIND))
+        .events(event_ids.to_vec())];
+    if let Some(broadcaster) = wait_for {
+        filters.push(
+            Filter::new()
+                .kind(Kind::Custom(protocol::BROADCAST_RESULT_KIND))
+                .author(broadcaster.to_string())
+                .events(event_ids.to_vec()),
+        );
+    }
+    client.subscribe(filters).await;
+}
+
+/// The broadcasters announcing themselves on the relays of `client` that take `txs` as events of
+/// the kind and network of `config`, the most recently announced first
+async fn discover(
+    client: &Client,
+    config: &SendConfig,
+    txs: &[Transaction],
+) -> anyhow::Result<Vec<(XOnlyPublicKey, Announcement)>> {
+    let filter = Filter::new().kind(Kind::Custom(protocol::ANNOUNCEMENT_KIND));
+    let mut events = client
+        .get_events_of(vec![filter], Some(config.timeout))
+        .await?;
+    events.sort_by_key(|event| std::cmp::Reverse(event.created_at));
+
+    let now = Timestamp::now();
+    let weight: u64 = txs.iter().map(|tx| tx.weight().to_wu()).sum();
+    let mut broadcasters: Vec<(XOnlyPublicKey, Announcement)> = vec![];
+    for event in events {
+        // relays may still have older announcements of a broadcaster
+        if broadcasters
+            .iter()
+            .any(|(pubkey, _)| *pubkey == event.pubkey)
+            || protocol::expiration(&event).is_some_and(|expiration| expiration <= now)
+        {
+            continue;
+        }
+        let announcement = match protocol::announcement(&event) {
+            Ok(announcement) => announcement,
+            Err(e) => {
+                debug!(broadcaster = %event.pubkey, "Invalid announcement: {e}");
+                continue;
+            }
+        };
+        let fits = announcement.supports(config.magic)
+            && announcement.kinds.contains(&config.kind)
+            && announcement.min_pow <= config.pow
+            && announcement
+                .max_tx_vsize
+                .is_none_or(|max| txs.iter().all(|tx| tx.vsize() as u64 <= max))
+            && announcement
+                .max_package_weight
+                .is_none_or(|max| weight <= max);
+        if !fits {
+            debug!(broadcaster = %event.pubkey, "Discovered broadcaster doesn't take the txs");
+            continue;
+        }
+        info!(broadcaster = %event.pubkey, announced = %event.created_at, "Discovered broadcaster");
+        broadcasters.push((event.pubkey, announcement));
+    }
+    if broadcasters.is_empty() {
+        bail!(
+            "No broadcaster announced itself for network {} taking the txs",
+            config.magic
+        );
+    }
+    Ok(broadcasters)
+}
+
+/// Connect to `relay` and publish `events` to it in the background
+async fn publish_to(client: &Client, relay: &str, proxy: Option<SocketAddr>, events: &[Event]) {
+    if let Err(e) = client.add_relay(relay, proxy).await {
+        warn!(relay, "Error adding relay: {e}");
+        return;
+    }
+    let Ok(url) = Url::parse(relay) else {
+        return;
+    };
+    let Some(relay) = client.relays().await.remove(&url) else {
+        return;
+    };
+    let events = events.to_vec();
+    tokio::spawn(async move {
+        relay.connect(true).await;
+        for event in events {
+            if let Err(e) = relay.send_msg(ClientMessage::new_event(event), false).await {
+                warn!(relay = %relay.url(), "Error publishing: {e}");
+            }
+        }
+    });
+}
@@ -0,0 +1,390 @@
+//! Persistent state in sqlite: the received events, their transactions and the outcome of every
+//! broadcast, so restarts don't broadcast old transactions again and operators can audit what
+//! happened.
+
+use bitcoin::consensus::encode::serialize_hex;
+use bitcoin::{Transaction, Txid};
+use nostr::prelude::XOnlyPublicKey;
+use nostr::{Event, EventId, Timestamp, Url};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::protocol::parse_tx_hex;
+use crate::reputation::Standing;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS events (
+        id TEXT PRIMARY KEY,
+        author TEXT NOT NULL,
+        relay TEXT NOT NULL,
+        received_at INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS txs (
+        wtxid TEXT PRIMARY KEY,
+        txid TEXT NOT NULL,
+        event_id TEXT NOT NULL,
+        raw TEXT NOT NULL,
+        received_at INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS broadcasts (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        event_id TEXT NOT NULL,
+        txid TEXT NOT NULL,
+        error TEXT,
+        broadcast_at INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS broadcasts_txid ON broadcasts (txid);
+    CREATE TABLE IF NOT EXISTS seen_events (
+        id TEXT PRIMARY KEY,
+        created_at INTEGER NOT NULL,
+        seen_at INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS seen_events_seen_at ON seen_events (seen_at);
+    CREATE TABLE IF NOT EXISTS quotas (
+        author TEXT NOT NULL,
+        day INTEGER NOT NULL,
+        txs INTEGER NOT NULL,
+        PRIMARY KEY (author, day)
+    );
+    CREATE TABLE IF NOT EXISTS reputation (
+        author TEXT PRIMARY KEY,
+        score REAL NOT NULL,
+        bans REAL NOT NULL,
+        banned_until INTEGER,
+        updated_at INTEGER NOT NULL
+    );
+";
+
+/// Handle to the sqlite database
+#[derive(Debug, Clone)]
+pub struct Store {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl Store {
+    /// Open or create the database at `path`
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// Record a received event and its transactions, returning whether the event is new
+    pub fn record_event(
+        &self,
+        event: &Event,
+        relay: &Url,
+        txs: &[Transaction],
+    ) -> anyhow::Result<bool> {
+        let now = Timestamp::now().as_u64();
+        let mut conn = self.conn.lock().unwrap();
+        let db = conn.transaction()?;
+
+        let inserted = db.execute(
+            "INSERT OR IGNORE INTO events (id, author, relay, received_at) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                event.id.to_hex(),
+                event.pubkey.to_string(),
+                relay.to_string(),
+                now
+            ],
+        )?;
+        for tx in txs {
+            db.execute(
+                "INSERT OR IGNORE INTO txs (wtxid, txid, event_id, raw, received_at)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    tx.wtxid().to_string(),
+                    tx.txid().to_string(),
+                    event.id.to_hex(),
+                    serialize_hex(tx),
+                    now
+                ],
+            )?;
+        }
+
+        db.commit()?;
+        Ok(inserted > 0)
+    }
+
+    /// The transaction `txid` received in an event before, `None` when it wasn't
+    pub fn raw_tx(&self, txid: &Txid) -> anyhow::Result<Option<Transaction>> {
+        let conn = self.conn.lock().unwrap();
+        let raw: Option<String> = conn
+            .query_row(
+                "SELECT raw FROM txs WHERE txid = ?1 LIMIT 1",
+                [txid.to_string()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        raw.map(|raw| parse_tx_hex(&raw)).transpose()
+    }
+
+    /// Whether all of `txs` were broadcast successfully before
+    pub fn all_broadcast(&self, txs: &[Transaction]) -> anyhow::Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let mut query = conn
+            .prepare_cached("SELECT 1 FROM broadcasts WHERE txid = ?1 AND error IS NULL LIMIT 1")?;
+        for tx in txs {
+            let found = query
+                .query_row([tx.txid().to_string()], |_| Ok(()))
+                .optional()?;
+            if found.is_none() {
+                return Ok(false);
+            }
+        }
+        Ok(!txs.is_empty())
+    }
+
+    /// Record the outcome of broadcasting `txs`, `error` being `None` on success
+    pub fn record_broadcast(
+        &self,
+        event_id: EventId,
+        txs: &[Transaction],
+        error: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let now = Timestamp::now().as_u64();
+        let conn = self.conn.lock().unwrap();
+        for tx in txs {
+            conn.execute(
+                "INSERT INTO broadcasts (event_id, txid, error, broadcast_at) VALUES (?1, ?2, ?3, ?4)",
+                params![event_id.to_hex(), tx.txid().to_string(), error, now],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Remember that the event was handled, forgetting the events handled longer than `window` ago
+    pub fn record_seen(&self, event: &Event, window: Duration) -> anyhow::Result<()> {
+        let now = Timestamp::now().as_u64();
+        // events dated in the future would make catching up skip the events in between
+        let created_at = event.created_at.as_u64().min(now);
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR IGNORE INTO seen_events (id, created_at, seen_at) VALUES (?1, ?2, ?3)",
+            params![event.id.to_hex(), created_at, now],
+        )?;
+        conn.execute(
+            "DELETE FROM seen_events WHERE seen_at < ?1",
+            [now.saturating_sub(window.as_secs())],
+        )?;
+        Ok(())
+    }
+
+    /// Creation time of the newest handled event, where catching up after a restart starts
+    pub fn last_seen(&self) -> anyhow::Result<Option<Timestamp>> {
+        let conn = self.conn.lock().unwrap();
+        let created_at: Option<u64> =
+            conn.query_row("SELECT MAX(created_at) FROM seen_events", [], |row| {
+                row.get(0)
+            })?;
+        Ok(created_at.map(Timestamp::from))
+    }
+
+    /// The last `limit` events handled within `window`, oldest first
+    pub fn recent_events(&self, window: Duration, limit: usize) -> anyhow::Result<Vec<EventId>> {
+        let since = Timestamp::now().as_u64().saturating_sub(window.as_secs());
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM seen_events WHERE seen_at < ?1", [since])?;
+        let mut query =
+            conn.prepare("SELECT id FROM seen_events ORDER BY seen_at DESC, rowid DESC LIMIT ?1")?;
+        let mut ids = query
+            .query_map([limit as i64], |row| row.get::<_, String>(0))?
+            .map(|id| Ok(EventId::from_str(&id?)?))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        ids.reverse();
+        Ok(ids)
+    }
+
+    /// The reputation of every author with one
+    pub fn reputation(&self) -> anyhow::Result<Vec<(XOnlyPublicKey, Standing)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut query =
+            conn.prepare("SELECT author, score, bans, banned_until, updated_at FROM reputation")?;
+        let rows = query.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                Standing {
+                    score: row.get(1)?,
+                    bans: row.get(2)?,
+                    banned_until: row.get::<_, Option<u64>>(3)?.map(Timestamp::from),
+                    updated_at: Timestamp::from(row.get::<_, u64>(4)?),
+                },
+            ))
+        })?;
+        rows.map(|row| {
+            let (author, standing) = row?;
+            Ok((XOnlyPublicKey::from_str(&author)?, standing))
+        })
+        .collect()
+    }
+
+    /// Save the reputation of `author`
+    pub fn save_standing(&self, author: XOnlyPublicKey, standing: &Standing) -> anyhow::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO reputation (author, score, bans, banned_until, updated_at)
+            VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                author.to_string(),
+                standing.score,
+                standing.bans,
+                standing.banned_until.map(|until| until.as_u64()),
+                standing.updated_at.as_u64()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// How many transactions `author` submitted on `day`, counted in days since the epoch
+    pub fn quota_used(&self, author: XOnlyPublicKey, day: u64) -> anyhow::Result<u32> {
+        let conn = self.conn.lock().unwrap();
+        let txs = conn
+            .query_row(
+                "SELECT txs FROM quotas WHERE author = ?1 AND day = ?2",
+                params![author.to_string(), day],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(txs.unwrap_or(0))
+    }
+
+    /// Count `txs` more transactions of `author` on `day`, forgetting the days before
+    pub fn add_quota_used(&self, author: XOnlyPublicKey, day: u64, txs: u32) -> anyhow::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO quotas (author, day, txs) VALUES (?1, ?2, ?3)
+            ON CONFLICT (author, day) DO UPDATE SET txs = txs + excluded.txs",
+            params![author.to_string(), day, txs],
+        )?;
+        conn.execute("DELETE FROM quotas WHERE day < ?1", [day])?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{OutPoint, ScriptBuf, TxIn, TxOut};
+    use nostr::{EventBuilder, Keys, Kind};
+
+    const WINDOW: Duration = Duration::from_secs(3600);
+
+    fn memory() -> Store {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(SCHEMA).unwrap();
+        Store {
+            conn: Arc::new(Mutex::new(conn)),
+        }
+    }
+
+    /// A transaction spending a made up output, told apart from others by `id`
+    fn tx(id: u32) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: LockTime::from_consensus(id),
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::all_zeros(), id),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: 10_000,
+                script_pubkey: ScriptBuf::new(),
+            }],
+        }
+    }
+
+    fn event(content: &str) -> Event {
+        EventBuilder::new(Kind::from(crate::protocol::BITCOIN_TX_KIND), content, &[])
+            .to_event(&Keys::generate())
+            .unwrap()
+    }
+
+    #[test]
+    fn record_event() {
+        let store = memory();
+        let relay = Url::parse("ws://127.0.0.1:7777").unwrap();
+        let event = event("");
+        assert!(store.record_event(&event, &relay, &[tx(1)]).unwrap());
+        assert!(!store.record_event(&event, &relay, &[tx(1)]).unwrap());
+        assert_eq!(store.raw_tx(&tx(1).txid()).unwrap(), Some(tx(1)));
+        assert_eq!(store.raw_tx(&tx(2).txid()).unwrap(), None);
+    }
+
+    #[test]
+    fn all_broadcast() {
+        let store = memory();
+        let event_id = event("").id;
+        assert!(!store.all_broadcast(&[]).unwrap());
+        assert!(!store.all_broadcast(&[tx(1)]).unwrap());
+
+        store
+            .record_broadcast(event_id, &[tx(1)], Some("missing-inputs"))
+            .unwrap();
+        assert!(!store.all_broadcast(&[tx(1)]).unwrap());
+
+        store.record_broadcast(event_id, &[tx(1)], None).unwrap();
+        assert!(store.all_broadcast(&[tx(1)]).unwrap());
+        assert!(!store.all_broadcast(&[tx(1), tx(2)]).unwrap());
+    }
+
+    #[test]
+    fn record_seen_clamps_the_future() {
+        let store = memory();
+        assert_eq!(store.last_seen().unwrap(), None);
+
+        let mut future = event("");
+        future.created_at = Timestamp::from(Timestamp::now().as_u64() + 86_400);
+        store.record_seen(&future, WINDOW).unwrap();
+        let last_seen = store.last_seen().unwrap().unwrap();
+        assert!(last_seen <= Timestamp::now());
+    }
+
+    #[test]
+    fn recent_events() {
+        let store = memory();
+        let events = [event("a"), event("b"), event("c")];
+        for event in &events {
+            store.record_seen(event, WINDOW).unwrap();
+        }
+        // seen again, still in the order they were first seen
+        store.record_seen(&events[0], WINDOW).unwrap();
+
+        assert_eq!(
+            store.recent_events(WINDOW, 10).unwrap(),
+            events.iter().map(|event| event.id).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            store.recent_events(WINDOW, 2).unwrap(),
+            [events[1].id, events[2].id]
+        );
+    }
+
+    #[test]
+    fn quota_used() {
+        let store = memory();
+        let author = Keys::generate().public_key();
+        assert_eq!(store.quota_used(author, 100).unwrap(), 0);
+        store.add_quota_used(author, 100, 2).unwrap();
+        store.add_quota_used(author, 100, 3).unwrap();
+        assert_eq!(store.quota_used(author, 100).unwrap(), 5);
+        assert_eq!(
+            store
+                .quota_used(Keys::generate().public_key(), 100)
+                .unwrap(),
+            0
+        );
+
+        // a new day forgets the ones before
+        store.add_quota_used(author, 101, 1).unwrap();
+        assert_eq!(store.quota_used(author, 101).unwrap(), 1);
+        assert_eq!(store.quota_used(author, 100).unwrap(), 0);
+    }
+}
@@ -0,0 +1,43 @@
+//! Telling systemd how a `Type=notify` service is doing, over the socket in `NOTIFY_SOCKET`, as
+//! `sd_notify` does, without linking libsystemd.
+
+use std::env;
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+
+/// Send `state`, e.g. `READY=1`, returning false when not running under systemd
+pub fn notify(state: &str) -> anyhow::Result<bool> {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return Ok(false);
+    };
+    let socket = UnixDatagram::unbound()?;
+    let path = path.to_string_lossy();
+    match path.strip_prefix('@') {
+        // a socket in the abstract namespace
+        Some(name) => {
+            #[cfg(target_os = "linux")]
+            {
+                use std::os::linux::net::SocketAddrExt;
+                let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+                socket.send_to_addr(state.as_bytes(), &addr)?;
+            }
+            #[cfg(not(target_os = "linux"))]
+            anyhow::bail!("Abstract socket {name} is only supported on Linux");
+        }
+        None => {
+            socket.send_to(state.as_bytes(), path.as_ref())?;
+        }
+    }
+    Ok(true)
+}
+
+/// How often systemd expects `WATCHDOG=1`, if it watches this process
+pub fn watchdog() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse() != Ok(std::process::id()) {
+            return None;
+        }
+    }
+    let usec = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    Some(Duration::from_micros(usec))
+}
@@ -0,0 +1,658 @@
+//! Following broadcast transactions until they're confirmed, rebroadcasting the ones that drop
+//! out of the mempool, e.g. evicted by a feerate purge or taken out of a block by a reorg, and
+//! reporting the ones it gives up on. The transactions are checked
+//! every interval, when the tip of the chain changes, or when bitcoind's ZMQ notifications tell a
+//! block arrived or a followed transaction left the mempool.
+
+use anyhow::bail;
+use bitcoin::network::Magic;
+use bitcoin::{BlockHash, Transaction, Txid};
+use nostr::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tracing::{debug, info, warn};
+
+use crate::backend::{self, Backends, PackageTxResult, TxStatus};
+use crate::zmq::{self, Subscriber};
+
+/// How often the tip of the chain is polled for new blocks and reorgs, without ZMQ
+const TIP_POLL: Duration = Duration::from_secs(10);
+
+/// Configuration of the [`Tracker`]
+#[derive(Debug, Clone)]
+pub struct TrackerConfig {
+    /// How often the transactions are checked
+    pub interval: Duration,
+    /// How many times a transaction is rebroadcast before giving up on it
+    pub max_attempts: u32,
+    /// Confirmations after which a transaction is no longer followed
+    pub depth: u32,
+    /// bitcoind's ZMQ `hashblock` endpoint, to check the transactions on every block instead of
+    /// every interval
+    pub zmq_block: Option<String>,
+    /// bitcoind's ZMQ `sequence` endpoint, to check the transactions as soon as one of them
+    /// leaves the mempool
+    pub zmq_tx: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct Tracked {
+    tx: Transaction,
+    magic: Magic,
+    event_id: EventId,
+    author: XOnlyPublicKey,
+    /// Position in the event, to rebroadcast packages in order
+    index: usize,
+    attempts: u32,
+    /// The block it was reported confirmed in, until a reorg takes it out
+    block: Option<BlockHash>,
+    /// Why the last rebroadcast failed
+    last_error: Option<String>,
+}
+
+/// A followed transaction made it into a block
+#[derive(Debug, Clone)]
+pub struct Confirmation {
+    /// The transaction
+    pub txid: Txid,
+    /// Event that carried it
+    pub event_id: EventId,
+    /// Author of that event
+    pub author: XOnlyPublicKey,
+    /// Block the transaction is in
+    pub block_hash: BlockHash,
+    /// Height of that block
+    pub height: u32,
+}
+
+/// A followed transaction left the mempool and didn't make it back in, the tracker gave up on it
+#[derive(Debug, Clone)]
+pub struct Failure {
+    /// The transaction
+    pub txid: Txid,
+    /// Event that carried it
+    pub event_id: EventId,
+    /// Author of that event
+    pub author: XOnlyPublicKey,
+    /// Why it couldn't be rebroadcast
+    pub reason: String,
+}
+
+/// What happened to a followed transaction, for its author to hear
+#[derive(Debug, Clone)]
+pub enum Update {
+    /// It got its first confirmation, or one in another block after a reorg
+    Confirmed(Confirmation),
+    /// It's not followed anymore without being confirmed
+    Failed(Failure),
+}
+
+/// A transaction the tracker follows
+#[derive(Debug, Clone)]
+pub struct TrackedTx {
+    /// The transaction
+    pub txid: Txid,
+    /// Network magic of the transaction
+    pub magic: Magic,
+    /// Event that carried it
+    pub event_id: EventId,
+    /// How many times it was rebroadcast
+    pub attempts: u32,
+    /// Whether it has a confirmation
+    pub confirmed: bool,
+}
+
+/// Follows broadcast transactions until they're buried deep enough
+#[derive(Debug)]
+pub struct Tracker {
+    config: TrackerConfig,
+    backends: Backends,
+    txs: Mutex<HashMap<Txid, Tracked>>,
+    /// Wakes up the checks
+    checks: Notify,
+    /// Whether the block notifications arrive, the checks don't poll then
+    following_blocks: AtomicBool,
+}
+
+impl Tracker {
+    /// Follow transactions on `backends`
+    pub fn new(config: TrackerConfig, backends: Backends) -> Self {
+        Self {
+            config,
+            backends,
+            txs: Mutex::new(HashMap::new()),
+            checks: Notify::new(),
+            following_blocks: AtomicBool::new(false),
+        }
+    }
+
+    /// Start following the broadcast transactions of an event
+    pub fn track(
+        &self,
+        event_id: EventId,
+        author: XOnlyPublicKey,
+        magic: Magic,
+        txs: &[Transaction],
+    ) {
+        let mut tracked = self.txs.lock().unwrap();
+        for (index, tx) in txs.iter().enumerate() {
+            tracked.entry(tx.txid()).or_insert_with(|| Tracked {
+                tx: tx.clone(),
+                magic,
+                event_id,
+                author,
+                index,
+                attempts: 0,
+                block: None,
+                last_error: None,
+            });
+        }
+    }
+
+    /// The transactions followed
+    pub fn tracked(&self) -> Vec<TrackedTx> {
+        self.txs
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(txid, tracked)| TrackedTx {
+                txid: *txid,
+                magic: tracked.magic,
+                event_id: tracked.event_id,
+                attempts: tracked.attempts,
+                confirmed: tracked.block.is_some(),
+            })
+            .collect()
+    }
+
+    /// Rebroadcast the followed transaction `txid` right away, with the other transactions of its
+    /// event so a package goes out together. Doesn't count as one of its attempts
+    pub async fn rebroadcast_txid(&self, txid: &Txid) -> anyhow::Result<Vec<PackageTxResult>> {
+        let mut package: Vec<Tracked> = {
+            let txs = self.txs.lock().unwrap();
+            let Some(tracked) = txs.get(txid) else {
+                bail!("Not following tx {txid}");
+            };
+            txs.values()
+                .filter(|other| other.event_id == tracked.event_id)
+                .cloned()
+                .collect()
+        };
+        package.sort_by_key(|tracked| tracked.index);
+        let magic = package[0].magic;
+        let Some(backend) = self.backends.get(&magic) else {
+            bail!("No backend for network {magic}");
+        };
+        info!(%txid, event_id = %package[0].event_id, txs = package.len(), "Rebroadcasting txs on request");
+        let txs = package.into_iter().map(|tracked| tracked.tx).collect();
+        backend::broadcast_txs(backend.as_ref(), txs).await
+    }
+
+    /// Check the transactions every interval, or on the ZMQ notifications, forever, calling
+    /// `on_update` when one of them gets a confirmation or is given up on
+    pub async fn run<F, Fut>(&self, on_update: F)
+    where
+        F: Fn(Update) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let checks = async {
+            loop {
+                let polling = !self.following_blocks.load(Ordering::Relaxed);
+                tokio::select! {
+                    _ = self.checks.notified() => {}
+                    _ = tokio::time::sleep(self.config.interval), if polling => {}
+                }
+                for update in self.check().await {
+                    on_update(update).await;
+                }
+            }
+        };
+        tokio::join!(
+            checks,
+            self.follow_tips(),
+            self.follow_blocks(),
+            self.follow_mempool()
+        );
+    }
+
+    /// Check when the tip of the chain of a followed transaction changes, a new block or a reorg,
+    /// unless the ZMQ block notifications already tell
+    async fn follow_tips(&self) {
+        let mut tips: HashMap<Magic, BlockHash> = HashMap::new();
+        loop {
+            tokio::time::sleep(TIP_POLL).await;
+            if self.following_blocks.load(Ordering::Relaxed) {
+                continue;
+            }
+            let magics: HashSet<Magic> = self
+                .txs
+                .lock()
+                .unwrap()
+                .values()
+                .map(|tracked| tracked.magic)
+                .collect();
+            for magic in magics {
+                let Some(backend) = self.backends.get(&magic) else {
+                    continue;
+                };
+                match backend.best_block_hash().await {
+                    Ok(Some(tip)) => {
+                        if tips
+                            .insert(magic, tip)
+                            .is_some_and(|previous| previous != tip)
+                        {
+                            debug!(%magic, %tip, "New tip");
+                            self.checks.notify_one();
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => debug!(backend = backend.name(), "Error getting the tip: {e}"),
+                }
+            }
+        }
+    }
+
+    /// Check on every block announced at the `zmq_block` endpoint, polling while it's unreachable
+    async fn follow_blocks(&self) {
+        let Some(endpoint) = &self.config.zmq_block else {
+            return;
+        };
+        loop {
+            let result: anyhow::Result<()> = async {
+                let mut subscriber = Subscriber::connect(endpoint, "hashblock").await?;
+                info!(endpoint, "Following blocks through ZMQ");
+                self.following_blocks.store(true, Ordering::Relaxed);
+                loop {
+                    subscriber.recv().await?;
+                    self.checks.notify_one();
+                }
+            }
+            .await;
+            if let Err(e) = result {
+                warn!(
+                    endpoint,
+                    "Error following blocks through ZMQ, polling instead: {e}"
+                );
+            }
+            self.following_blocks.store(false, Ordering::Relaxed);
+            self.checks.notify_one();
+            tokio::time::sleep(self.config.interval).await;
+        }
+    }
+
+    /// Check when the `zmq_tx` endpoint announces that a followed transaction left the mempool
+    async fn follow_mempool(&self) {
+        let Some(endpoint) = &self.config.zmq_tx else {
+            return;
+        };
+        loop {
+            let result: anyhow::Result<()> = async {
+                let mut subscriber = Subscriber::connect(endpoint, "sequence").await?;
+                info!(endpoint, "Following the mempool through ZMQ");
+                loop {
+                    let Some(txid) = zmq::removed_tx(&subscriber.recv().await?)? else {
+                        continue;
+                    };
+                    if self.txs.lock().unwrap().contains_key(&txid) {
+                        info!(%txid, "Tx left the mempool");
+                        self.checks.notify_one();
+                    }
+                }
+            }
+            .await;
+            if let Err(e) = result {
+                warn!(endpoint, "Error following the mempool through ZMQ: {e}");
+            }
+            tokio::time::sleep(self.config.interval).await;
+        }
+    }
+
+    async fn check(&self) -> Vec<Update> {
+        let mut updates = vec![];
+        let tracked: Vec<Tracked> = self.txs.lock().unwrap().values().cloned().collect();
+
+        // the missing ones, grouped by event so packages go out together
+        let mut missing: HashMap<EventId, Vec<Tracked>> = HashMap::new();
+        for mut tracked in tracked {
+            let txid = tracked.tx.txid();
+            let Some(backend) = self.backends.get(&tracked.magic) else {
+                continue;
+            };
+            match backend.locate_tx(&tracked.tx).await {
+                Ok(Some(TxStatus::Confirmed {
+                    block_hash,
+                    height,
+                    confirmations: depth,
+                })) => {
+                    if tracked.block != Some(block_hash) {
+                        match tracked.block {
+                            Some(_) => {
+                                info!(%txid, event_id = %tracked.event_id, height, "Tx confirmed in another block after a reorg")
+                            }
+                            None => {
+                                info!(%txid, event_id = %tracked.event_id, height, "Tx confirmed")
+                            }
+                        }
+                        updates.push(Update::Confirmed(Confirmation {
+                            txid,
+                            event_id: tracked.event_id,
+                            author: tracked.author,
+                            block_hash,
+                            height,
+                        }));
+                        if let Some(tracked) = self.txs.lock().unwrap().get_mut(&txid) {
+                            tracked.block = Some(block_hash);
+                        }
+                    }
+                    if depth >= self.config.depth {
+                        self.untrack(&txid);
+                    }
+                }
+                // the block it was in isn't in the best chain anymore
+                Ok(Some(TxStatus::InMempool | TxStatus::Missing)) if tracked.block.is_some() => {
+                    let block_hash = tracked.block.take().unwrap();
+                    warn!(%txid, event_id = %tracked.event_id, %block_hash, "Tx unconfirmed by a reorg, rebroadcasting");
+                    if let Some(tracked) = self.txs.lock().unwrap().get_mut(&txid) {
+                        tracked.block = None;
+                        tracked.attempts = 0;
+                    }
+                    tracked.attempts = 0;
+                    missing.entry(tracked.event_id).or_default().push(tracked);
+                }
+                Ok(Some(TxStatus::InMempool)) => {}
+                Ok(Some(TxStatus::Missing)) => {
+                    info!(%txid, event_id = %tracked.event_id, attempts = tracked.attempts, "Tx not in the mempool");
+                    missing.entry(tracked.event_id).or_default().push(tracked);
+                }
+                // the backend can't follow transactions
+                Ok(None) => self.untrack(&txid),
+                Err(e) => warn!(%txid, backend = backend.name(), "Error checking tx: {e}"),
+            }
+        }
+
+        for (event_id, mut txs) in missing {
+            txs.sort_by_key(|tracked| tracked.index);
+            for failure in self.rebroadcast(event_id, txs).await {
+                updates.push(Update::Failed(failure));
+            }
+        }
+
+        updates
+    }
+
+    /// Rebroadcast the missing transactions of an event, returning the ones out of attempts
+    async fn rebroadcast(&self, event_id: EventId, txs: Vec<Tracked>) -> Vec<Failure> {
+        let mut failures = vec![];
+        let mut retry = vec![];
+        for tracked in txs {
+            let txid = tracked.tx.txid();
+            if tracked.attempts >= self.config.max_attempts {
+                let reason = match &tracked.last_error {
+                    Some(e) => format!("dropped from the mempool and rebroadcasting failed: {e}"),
+                    None => "dropped from the mempool and not relayed back".to_string(),
+                };
+                warn!(%txid, event_id = %event_id, attempts = tracked.attempts, %reason, "Giving up on tx");
+                self.untrack(&txid);
+                failures.push(Failure {
+                    txid,
+                    event_id,
+                    author: tracked.author,
+                    reason,
+                });
+            } else {
+                if let Some(tracked) = self.txs.lock().unwrap().get_mut(&txid) {
+                    tracked.attempts += 1;
+                }
+                retry.push(tracked);
+            }
+        }
+
+        let Some(first) = retry.first() else {
+            return failures;
+        };
+        let Some(backend) = self.backends.get(&first.magic) else {
+            return failures;
+        };
+
+        info!(event_id = %event_id, txs = retry.len(), "Rebroadcasting txs");
+        let txs: Vec<Transaction> = retry.into_iter().map(|tracked| tracked.tx).collect();
+        // rejected ones are tried again next time, until they run out of attempts
+        let errors: Vec<(Txid, Option<String>)> = match backend::broadcast_txs(
+            backend.as_ref(),
+            txs.clone(),
+        )
+        .await
+        {
+            Ok(results) => results
+                .into_iter()
+                .map(|result| (result.txid, result.error))
+                .collect(),
+            Err(e) => {
+                warn!(event_id = %event_id, backend = backend.name(), "Error rebroadcasting txs: {e}");
+                txs.iter()
+                    .map(|tx| (tx.txid(), Some(e.to_string())))
+                    .collect()
+            }
+        };
+        let mut tracked = self.txs.lock().unwrap();
+        for (txid, error) in errors {
+            if let Some(tracked) = tracked.get_mut(&txid) {
+                tracked.last_error = error;
+            }
+        }
+        failures
+    }
+
+    fn untrack(&self, txid: &Txid) {
+        self.txs.lock().unwrap().remove(txid);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::TxBackend;
+    use async_trait::async_trait;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{OutPoint, ScriptBuf, TxIn, TxOut};
+    use std::sync::Arc;
+
+    /// A backend telling the status set for each transaction, recording what's submitted to it
+    #[derive(Debug, Default)]
+    struct Node {
+        status: Mutex<HashMap<Txid, TxStatus>>,
+        submitted: Mutex<Vec<Txid>>,
+        /// Why it rejects submitted transactions, if it does
+        reject: Mutex<Option<String>>,
+    }
+
+    impl Node {
+        fn set(&self, txid: Txid, status: TxStatus) {
+            self.status.lock().unwrap().insert(txid, status);
+        }
+
+        fn submitted(&self) -> Vec<Txid> {
+            std::mem::take(&mut self.submitted.lock().unwrap())
+        }
+    }
+
+    #[async_trait]
+    impl TxBackend for Node {
+        fn name(&self) -> &str {
+            "node"
+        }
+
+        async fn submit_tx(&self, tx: &Transaction) -> anyhow::Result<Txid> {
+            self.submitted.lock().unwrap().push(tx.txid());
+            match &*self.reject.lock().unwrap() {
+                Some(reason) => bail!("{reason}"),
+                None => Ok(tx.txid()),
+            }
+        }
+
+        async fn tx_status(&self, txid: &Txid) -> anyhow::Result<Option<TxStatus>> {
+            Ok(Some(
+                self.status
+                    .lock()
+                    .unwrap()
+                    .get(txid)
+                    .copied()
+                    .unwrap_or(TxStatus::Missing),
+            ))
+        }
+    }
+
+    /// A transaction spending a made up output, told apart from others by `id`
+    fn tx(id: u32) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: LockTime::from_consensus(id),
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::all_zeros(), id),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: 10_000,
+                script_pubkey: ScriptBuf::new(),
+            }],
+        }
+    }
+
+    /// A tracker on a [`Node`], following `txs` as one event
+    fn tracker(txs: &[Transaction]) -> (Tracker, Arc<Node>) {
+        let node = Arc::new(Node::default());
+        let config = TrackerConfig {
+            interval: Duration::from_secs(60),
+            max_attempts: 2,
+            depth: 6,
+            zmq_block: None,
+            zmq_tx: None,
+        };
+        let backends = Backends::from([(Magic::BITCOIN, node.clone() as Arc<dyn TxBackend>)]);
+        let tracker = Tracker::new(config, backends);
+        let keys = Keys::generate();
+        tracker.track(
+            EventId::from_slice(&[0; 32]).unwrap(),
+            keys.public_key(),
+            Magic::BITCOIN,
+            txs,
+        );
+        (tracker, node)
+    }
+
+    fn confirmed(block: u8, confirmations: u32) -> TxStatus {
+        TxStatus::Confirmed {
+            block_hash: BlockHash::from_byte_array([block; 32]),
+            height: 100,
+            confirmations,
+        }
+    }
+
+    #[tokio::test]
+    async fn reorg() {
+        let (parent, child) = (tx(1), tx(2));
+        let (tracker, node) = tracker(&[parent.clone(), child.clone()]);
+        node.set(parent.txid(), confirmed(1, 1));
+        node.set(child.txid(), TxStatus::InMempool);
+        let updates = tracker.check().await;
+        assert!(matches!(&updates[..], [Update::Confirmed(c)] if c.txid == parent.txid()));
+        assert!(tracker.check().await.is_empty(), "reported once");
+
+        // the block is reorged out, the parent goes out again with its package
+        node.set(parent.txid(), TxStatus::Missing);
+        node.set(child.txid(), TxStatus::Missing);
+        assert!(tracker.check().await.is_empty());
+        assert_eq!(node.submitted(), [parent.txid(), child.txid()]);
+        let parent_tracked = tracker
+            .tracked()
+            .into_iter()
+            .find(|tracked| tracked.txid == parent.txid())
+            .unwrap();
+        assert!(!parent_tracked.confirmed);
+        assert_eq!(
+            parent_tracked.attempts, 1,
+            "attempts start over after a reorg"
+        );
+
+        // mined in another block
+        node.set(parent.txid(), confirmed(2, 1));
+        let updates = tracker.check().await;
+        assert!(matches!(
+            &updates[..],
+            [Update::Confirmed(c)] if c.block_hash == BlockHash::from_byte_array([2; 32])
+        ));
+
+        // buried deep enough
+        node.set(parent.txid(), confirmed(2, 6));
+        node.set(child.txid(), confirmed(2, 6));
+        tracker.check().await;
+        assert!(tracker.tracked().is_empty());
+    }
+
+    #[tokio::test]
+    async fn eviction() {
+        let tx = tx(1);
+        let (tracker, node) = tracker(std::slice::from_ref(&tx));
+        node.set(tx.txid(), TxStatus::InMempool);
+        assert!(tracker.check().await.is_empty());
+        assert!(node.submitted().is_empty());
+
+        // evicted, rebroadcast until out of attempts
+        node.set(tx.txid(), TxStatus::Missing);
+        for attempt in 1..=2 {
+            assert!(tracker.check().await.is_empty());
+            assert_eq!(node.submitted(), [tx.txid()]);
+            assert_eq!(tracker.tracked()[0].attempts, attempt);
+        }
+        let updates = tracker.check().await;
+        assert!(node.submitted().is_empty());
+        let [Update::Failed(failure)] = &updates[..] else {
+            panic!("expected a failure, got {updates:?}");
+        };
+        assert_eq!(failure.txid, tx.txid());
+        assert_eq!(
+            failure.reason,
+            "dropped from the mempool and not relayed back"
+        );
+        assert!(tracker.tracked().is_empty());
+    }
+
+    #[tokio::test]
+    async fn eviction_rejected() {
+        let tx = tx(1);
+        let (tracker, node) = tracker(std::slice::from_ref(&tx));
+        *node.reject.lock().unwrap() = Some("mempool min fee not met".to_string());
+        for _ in 0..2 {
+            assert!(tracker.check().await.is_empty());
+        }
+        let updates = tracker.check().await;
+        let [Update::Failed(failure)] = &updates[..] else {
+            panic!("expected a failure, got {updates:?}");
+        };
+        assert_eq!(
+            failure.reason,
+            "dropped from the mempool and rebroadcasting failed: mempool min fee not met"
+        );
+    }
+
+    #[tokio::test]
+    async fn back_in_the_mempool() {
+        let tx = tx(1);
+        let (tracker, node) = tracker(std::slice::from_ref(&tx));
+        tracker.check().await;
+        assert_eq!(node.submitted(), [tx.txid()]);
+
+        // relayed back, left alone until it confirms
+        node.set(tx.txid(), TxStatus::InMempool);
+        for _ in 0..3 {
+            assert!(tracker.check().await.is_empty());
+        }
+        assert!(node.submitted().is_empty());
+        assert_eq!(tracker.tracked().len(), 1);
+    }
+}
@@ -0,0 +1,139 @@
+//! A minimal ZeroMQ subscriber for the notifications bitcoind publishes with `-zmqpub*`,
+//! speaking ZMTP 3.0 without security so libzmq isn't needed.
+
+use anyhow::{anyhow, bail};
+use bitcoin::hashes::Hash;
+use bitcoin::Txid;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Largest frame accepted from the publisher
+const MAX_FRAME_SIZE: u64 = 4_000_000;
+
+const FLAG_MORE: u8 = 0x01;
+const FLAG_LONG: u8 = 0x02;
+const FLAG_COMMAND: u8 = 0x04;
+
+/// A SUB socket connected to a single publisher
+#[derive(Debug)]
+pub struct Subscriber {
+    stream: TcpStream,
+}
+
+impl Subscriber {
+    /// Connect to `endpoint` (tcp://host:port) and subscribe to `topic`, e.g. `hashblock`
+    pub async fn connect(endpoint: &str, topic: &str) -> anyhow::Result<Self> {
+        let Some(addr) = endpoint.strip_prefix("tcp://") else {
+            bail!("Unsupported ZMQ endpoint {endpoint}, expected tcp://host:port");
+        };
+        let stream = TcpStream::connect(addr).await?;
+        let mut subscriber = Self { stream };
+        subscriber.handshake().await?;
+
+        // before ZMTP 3.1 subscriptions are messages starting with 1
+        let mut subscription = vec![1];
+        subscription.extend_from_slice(topic.as_bytes());
+        subscriber.send_frame(0, &subscription).await?;
+        Ok(subscriber)
+    }
+
+    /// The frames of the next message: the topic, the body and a sequence number for bitcoind
+    pub async fn recv(&mut self) -> anyhow::Result<Vec<Vec<u8>>> {
+        let mut frames = vec![];
+        loop {
+            let (flags, frame) = self.read_frame().await?;
+            if flags & FLAG_COMMAND != 0 {
+                continue;
+            }
+            frames.push(frame);
+            if flags & FLAG_MORE == 0 {
+                return Ok(frames);
+            }
+        }
+    }
+
+    async fn handshake(&mut self) -> anyhow::Result<()> {
+        // signature, version 3.0, the NULL mechanism, as client
+        let mut greeting = [0u8; 64];
+        greeting[0] = 0xff;
+        greeting[9] = 0x7f;
+        greeting[10] = 3;
+        greeting[12..16].copy_from_slice(b"NULL");
+        self.stream.write_all(&greeting).await?;
+
+        let mut peer = [0u8; 64];
+        self.stream.read_exact(&mut peer).await?;
+        if peer[0] != 0xff || peer[9] != 0x7f || peer[10] < 3 {
+            bail!("Not a ZMTP 3 publisher");
+        }
+        if &peer[12..16] != b"NULL" {
+            bail!("Publisher requires a security mechanism");
+        }
+
+        let mut ready = vec![5];
+        ready.extend_from_slice(b"READY");
+        ready.push(11);
+        ready.extend_from_slice(b"Socket-Type");
+        ready.extend_from_slice(&3u32.to_be_bytes());
+        ready.extend_from_slice(b"SUB");
+        self.send_frame(FLAG_COMMAND, &ready).await?;
+
+        loop {
+            let (flags, frame) = self.read_frame().await?;
+            if flags & FLAG_COMMAND != 0 && frame.get(1..6) == Some(b"READY") {
+                return Ok(());
+            }
+            if flags & FLAG_COMMAND != 0 && frame.get(1..6) == Some(b"ERROR") {
+                let reason = String::from_utf8_lossy(frame.get(7..).unwrap_or_default());
+                bail!("Publisher refused: {reason}");
+            }
+        }
+    }
+
+    async fn send_frame(&mut self, flags: u8, body: &[u8]) -> anyhow::Result<()> {
+        let mut frame = vec![];
+        match u8::try_from(body.len()) {
+            Ok(len) => frame.extend_from_slice(&[flags, len]),
+            Err(_) => {
+                frame.push(flags | FLAG_LONG);
+                frame.extend_from_slice(&(body.len() as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(body);
+        self.stream.write_all(&frame).await?;
+        Ok(())
+    }
+
+    async fn read_frame(&mut self) -> anyhow::Result<(u8, Vec<u8>)> {
+        let flags = self.stream.read_u8().await?;
+        let len = if flags & FLAG_LONG != 0 {
+            self.stream.read_u64().await?
+        } else {
+            self.stream.read_u8().await? as u64
+        };
+        if len > MAX_FRAME_SIZE {
+            bail!("Frame of {len} bytes too large");
+        }
+        let mut frame = vec![0; len as usize];
+        self.stream.read_exact(&mut frame).await?;
+        Ok((flags, frame))
+    }
+}
+
+/// The transaction of a `sequence` notification that left the mempool for another reason than
+/// being mined: evicted, replaced or expired
+pub fn removed_tx(frames: &[Vec<u8>]) -> anyhow::Result<Option<Txid>> {
+    let body = frames
+        .get(1)
+        .ok_or_else(|| anyhow!("Notification without body"))?;
+    if body.len() < 33 {
+        bail!("Sequence notification of {} bytes", body.len());
+    }
+    if body[32] != b'R' {
+        return Ok(None);
+    }
+    // hashes are sent in the byte order they're displayed in
+    let mut hash: [u8; 32] = body[..32].try_into().unwrap();
+    hash.reverse();
+    Ok(Some(Txid::from_byte_array(hash)))
+}